@@ -98,6 +98,9 @@ class CachingTeamSerializer(serializers.ModelSerializer):
             "heatmaps_opt_in",
             "capture_dead_clicks",
             "flags_persistence_default",
+            # Read by capture to compute cookieless distinct_ids
+            "timezone",
+            "cookieless_server_hash_mode",
         ]
         read_only_fields = fields
 
@@ -2,6 +2,7 @@ use std::collections::HashMap;
 
 use anyhow::Error;
 use chrono::{DateTime, Utc};
+use common_types::mixpanel::map_event_names;
 use common_types::{CapturedEvent, InternallyCapturedEvent, RawEvent};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
@@ -90,12 +91,3 @@ impl MixpanelEvent {
         }
     }
 }
-
-// Maps mixpanel event names to posthog event names
-pub fn map_event_names(event: String) -> String {
-    // TODO - add more as you find them
-    match event.as_str() {
-        "$mp_web_page_view" => "$pageview".to_string(),
-        _ => event,
-    }
-}
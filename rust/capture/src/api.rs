@@ -1,9 +1,14 @@
-use axum::http::StatusCode;
+use std::time::Duration;
+
+use axum::http::{header, StatusCode};
 use axum::response::{IntoResponse, Response};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use thiserror::Error;
+use uuid::Uuid;
 
 use crate::token::InvalidTokenReason;
+use crate::v0_request::DataType;
 
 #[derive(Debug, PartialEq, Eq, Deserialize, Serialize)]
 pub enum CaptureResponseCode {
@@ -18,6 +23,59 @@ pub struct CaptureResponse {
     pub quota_limited: Option<Vec<String>>,
 }
 
+/// Response of the v1 batch endpoint, holding one result per submitted event,
+/// in the same order as the request's batch.
+#[derive(Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct BatchResponse {
+    pub status: CaptureResponseCode,
+    pub accepted: usize,
+    pub rejected: usize,
+    pub dropped: usize,
+    pub results: Vec<EventResult>,
+}
+
+#[derive(Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum EventResult {
+    Accepted,
+    Rejected { reason: String },
+    // Valid events that capture drops on purpose, not to be resent
+    Dropped { reason: String },
+}
+
+/// Response of the validation endpoint, holding the events that would be sent to the sink,
+/// and the rejected ones. Nothing is sent to the sink.
+#[derive(Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ValidationResponse {
+    pub status: CaptureResponseCode,
+    pub token: String,
+    pub events: Vec<ValidatedEvent>,
+    pub rejected: Vec<RejectedEvent>,
+    // Valid events that capture drops on purpose
+    pub dropped: Vec<RejectedEvent>,
+}
+
+#[derive(Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ValidatedEvent {
+    pub uuid: Uuid,
+    pub event: String,
+    pub distinct_id: String,
+    pub data_type: DataType,
+    // Unset if the sink does not produce to topics
+    pub topic: Option<String>,
+    pub partition_key: Option<String>,
+    pub now: String,
+    pub sent_at: Option<String>,
+    // Event timestamp as computed by ingestion, from the timestamps above
+    pub timestamp: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct RejectedEvent {
+    pub payload: Value,
+    pub reason: String,
+}
+
 #[derive(Clone, Error, Debug)]
 pub enum CaptureError {
     #[error("failed to decode request: {0}")]
@@ -27,12 +85,16 @@ pub enum CaptureError {
 
     #[error("request holds no event")]
     EmptyBatch,
+    #[error("failed to parse event: {0}")]
+    InvalidEvent(String),
     #[error("event submitted with an empty event name")]
     MissingEventName,
     #[error("event submitted without a distinct_id")]
     MissingDistinctId,
     #[error("event submitted with invalid cookieless mode")]
     InvalidCookielessMode,
+    #[error("invalid exception: {0}")]
+    InvalidException(String),
     #[error("replay event submitted without snapshot data")]
     MissingSnapshotData,
     #[error("replay event submitted without session id")]
@@ -53,6 +115,8 @@ pub enum CaptureError {
     RetryableSinkError,
     #[error("maximum event size exceeded")]
     EventTooBig,
+    #[error("request limit exceeded: {0}")]
+    LimitExceeded(String),
     #[error("invalid event could not be processed")]
     NonRetryableSinkError,
 
@@ -60,7 +124,7 @@ pub enum CaptureError {
     BillingLimit,
 
     #[error("rate limited")]
-    RateLimited,
+    RateLimited(Duration), // Time to wait before retrying
 }
 
 impl From<serde_json::Error> for CaptureError {
@@ -75,9 +139,11 @@ impl IntoResponse for CaptureError {
             CaptureError::RequestDecodingError(_)
             | CaptureError::RequestParsingError(_)
             | CaptureError::EmptyBatch
+            | CaptureError::InvalidEvent(_)
             | CaptureError::MissingEventName
             | CaptureError::MissingDistinctId
             | CaptureError::InvalidCookielessMode
+            | CaptureError::InvalidException(_)
             | CaptureError::EventTooBig
             | CaptureError::NonRetryableSinkError
             | CaptureError::MissingSessionId
@@ -91,8 +157,19 @@ impl IntoResponse for CaptureError {
 
             CaptureError::RetryableSinkError => (StatusCode::SERVICE_UNAVAILABLE, self.to_string()),
 
-            CaptureError::BillingLimit | CaptureError::RateLimited => {
-                (StatusCode::TOO_MANY_REQUESTS, self.to_string())
+            CaptureError::LimitExceeded(_) => (StatusCode::PAYLOAD_TOO_LARGE, self.to_string()),
+
+            CaptureError::BillingLimit => (StatusCode::TOO_MANY_REQUESTS, self.to_string()),
+
+            CaptureError::RateLimited(retry_after) => {
+                // Retry-After is in whole seconds, round up to not invite early retries
+                let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+                return (
+                    StatusCode::TOO_MANY_REQUESTS,
+                    [(header::RETRY_AFTER, seconds.max(1).to_string())],
+                    self.to_string(),
+                )
+                    .into_response();
             }
         }
         .into_response()
@@ -0,0 +1,101 @@
+//! Replays the objects written by the S3 fallback sink into Kafka.
+//!
+//! Events are produced through `KafkaSink`, with the same topic and partition key routing
+//! as capture. Objects are processed in key order, which is chronological within a
+//! partition, and can optionally be deleted once all their events were acked.
+
+use envconfig::Envconfig;
+use health::HealthRegistry;
+use tracing::level_filters::LevelFilter;
+
+use capture::config::KafkaConfig;
+use capture::sinks::kafka::KafkaSink;
+use capture::sinks::s3::{create_client, decode_object};
+use capture::sinks::Event;
+
+const SEND_BATCH_SIZE: usize = 1000;
+
+#[derive(Envconfig)]
+struct Config {
+    s3_fallback_bucket: String,
+    s3_fallback_endpoint: Option<String>,
+
+    // Only replay the objects under this prefix, eg. `{s3_fallback_prefix}2024/01/02/`
+    #[envconfig(default = "")]
+    s3_replay_prefix: String,
+
+    #[envconfig(default = "false")]
+    s3_replay_delete: bool,
+
+    #[envconfig(nested = true)]
+    kafka: KafkaConfig,
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    tracing_subscriber::fmt()
+        .with_max_level(LevelFilter::INFO)
+        .init();
+    let config = Config::init_from_env()?;
+
+    let liveness = HealthRegistry::new("liveness");
+    let sink_liveness = liveness
+        .register("rdkafka".to_string(), time::Duration::seconds(30))
+        .await;
+    // No overflow: replayed events are not representative of live traffic
+    let sink = KafkaSink::new(config.kafka, sink_liveness, None, None).await?;
+    let client = create_client(config.s3_fallback_endpoint).await;
+
+    let mut keys = Vec::new();
+    let mut pages = client
+        .list_objects_v2()
+        .bucket(&config.s3_fallback_bucket)
+        .prefix(&config.s3_replay_prefix)
+        .into_paginator()
+        .send();
+    while let Some(page) = pages.next().await {
+        keys.extend(
+            page?
+                .contents()
+                .iter()
+                .filter_map(|o| o.key().map(String::from)),
+        );
+    }
+    keys.sort();
+    tracing::info!("replaying {} objects", keys.len());
+
+    let mut replayed = 0;
+    for key in keys {
+        let object = client
+            .get_object()
+            .bucket(&config.s3_fallback_bucket)
+            .key(&key)
+            .send()
+            .await?;
+        let body = object.body.collect().await?.into_bytes();
+        let events = decode_object(&key, &body)?;
+
+        let count = events.len();
+        let mut events = events.into_iter().peekable();
+        while events.peek().is_some() {
+            let batch: Vec<_> = events.by_ref().take(SEND_BATCH_SIZE).collect();
+            sink.send_batch(batch)
+                .await
+                .map_err(|e| anyhow::anyhow!("failed to replay {}: {}", key, e))?;
+        }
+        replayed += count;
+        tracing::info!("replayed {} events from {}", count, key);
+
+        if config.s3_replay_delete {
+            client
+                .delete_object()
+                .bucket(&config.s3_fallback_bucket)
+                .key(&key)
+                .send()
+                .await?;
+        }
+    }
+
+    tracing::info!("replayed {} events", replayed);
+    Ok(())
+}
@@ -0,0 +1,398 @@
+//! Binary encodings of the v0 requests, for clients that can't afford JSON encoding.
+//!
+//! The encoding is picked from the request's `Content-Type`:
+//!   - `application/msgpack`: MessagePack, with the same shapes as the JSON payloads,
+//!   - `application/x-protobuf; version=1`: the `posthog.capture.v1.BatchedRequest`
+//!     message, see `proto/posthog/capture/v1/batch.proto`.
+//!
+//! Both are decoded into the same `RawRequest` types as JSON payloads.
+
+use std::collections::HashMap;
+
+use common_types::RawEvent;
+use prost::Message;
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::api::CaptureError;
+use crate::v0_request::{BatchedRequest, InvalidEvent, RawRequest};
+
+// Schema version used when the Content-Type does not set one
+const DEFAULT_PROTOBUF_VERSION: &str = "1";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryFormat {
+    MessagePack,
+    Protobuf,
+}
+
+impl BinaryFormat {
+    /// Returns `None` for the text content types, handled as JSON.
+    pub fn from_content_type(content_type: &str) -> Result<Option<Self>, CaptureError> {
+        let mut parts = content_type.split(';');
+        let mime = parts.next().unwrap_or_default().trim().to_ascii_lowercase();
+        match mime.as_str() {
+            "application/msgpack" | "application/x-msgpack" | "application/vnd.msgpack" => {
+                Ok(Some(BinaryFormat::MessagePack))
+            }
+            "application/x-protobuf" | "application/protobuf" => {
+                let version = parts
+                    .filter_map(|param| param.split_once('='))
+                    .find(|(key, _)| key.trim().eq_ignore_ascii_case("version"))
+                    .map_or(DEFAULT_PROTOBUF_VERSION, |(_, value)| {
+                        value.trim().trim_matches('"')
+                    });
+                match version {
+                    "1" => Ok(Some(BinaryFormat::Protobuf)),
+                    version => Err(CaptureError::RequestDecodingError(format!(
+                        "unsupported protobuf schema version: {}",
+                        version
+                    ))),
+                }
+            }
+            _ => Ok(None),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BinaryFormat::MessagePack => "msgpack",
+            BinaryFormat::Protobuf => "protobuf",
+        }
+    }
+}
+
+/// Messages of the `posthog.capture.v1` package.
+pub mod v1 {
+    use std::collections::HashMap;
+
+    use prost::{Message, Oneof};
+
+    #[derive(Clone, PartialEq, Message)]
+    pub struct BatchedRequest {
+        #[prost(string, tag = "1")]
+        pub token: String,
+        #[prost(bool, optional, tag = "2")]
+        pub historical_migration: Option<bool>,
+        #[prost(string, optional, tag = "3")]
+        pub sent_at: Option<String>,
+        #[prost(message, repeated, tag = "4")]
+        pub batch: Vec<Event>,
+    }
+
+    #[derive(Clone, PartialEq, Message)]
+    pub struct Event {
+        #[prost(string, tag = "1")]
+        pub event: String,
+        #[prost(string, optional, tag = "2")]
+        pub distinct_id: Option<String>,
+        #[prost(string, optional, tag = "3")]
+        pub uuid: Option<String>,
+        #[prost(string, optional, tag = "4")]
+        pub timestamp: Option<String>,
+        #[prost(int64, optional, tag = "5")]
+        pub offset: Option<i64>,
+        #[prost(map = "string, message", tag = "6")]
+        pub properties: HashMap<String, PropertyValue>,
+        #[prost(map = "string, message", tag = "7")]
+        pub set: HashMap<String, PropertyValue>,
+        #[prost(map = "string, message", tag = "8")]
+        pub set_once: HashMap<String, PropertyValue>,
+    }
+
+    #[derive(Clone, PartialEq, Message)]
+    pub struct PropertyValue {
+        #[prost(oneof = "Kind", tags = "1, 2, 3, 4, 5, 6")]
+        pub kind: Option<Kind>,
+    }
+
+    #[derive(Clone, PartialEq, Oneof)]
+    pub enum Kind {
+        #[prost(bool, tag = "1")]
+        BoolValue(bool),
+        #[prost(int64, tag = "2")]
+        IntValue(i64),
+        #[prost(double, tag = "3")]
+        DoubleValue(f64),
+        #[prost(string, tag = "4")]
+        StringValue(String),
+        #[prost(message, tag = "5")]
+        ListValue(ListValue),
+        #[prost(message, tag = "6")]
+        MapValue(MapValue),
+    }
+
+    #[derive(Clone, PartialEq, Message)]
+    pub struct ListValue {
+        #[prost(message, repeated, tag = "1")]
+        pub values: Vec<PropertyValue>,
+    }
+
+    #[derive(Clone, PartialEq, Message)]
+    pub struct MapValue {
+        #[prost(map = "string, message", tag = "1")]
+        pub values: HashMap<String, PropertyValue>,
+    }
+}
+
+/// Decodes a `posthog.capture.v1.BatchedRequest`. Like JSON batches, events with an invalid
+/// uuid are returned separately instead of failing the whole request.
+pub fn decode_protobuf_request(
+    payload: &[u8],
+) -> Result<(RawRequest, Vec<InvalidEvent>), CaptureError> {
+    let request = v1::BatchedRequest::decode(payload).map_err(|e| {
+        tracing::error!("failed to decode protobuf payload: {}", e);
+        CaptureError::RequestDecodingError(String::from("invalid protobuf data"))
+    })?;
+
+    let mut batch = Vec::with_capacity(request.batch.len());
+    let mut invalid = Vec::new();
+    for event in request.batch {
+        match raw_event(event) {
+            Ok(event) => batch.push(event),
+            Err(event) => invalid.push(event),
+        }
+    }
+
+    let request = BatchedRequest {
+        token: request.token,
+        historical_migration: request.historical_migration,
+        sent_at: request.sent_at,
+        batch,
+    };
+    Ok((RawRequest::Batch(request), invalid))
+}
+
+fn raw_event(event: v1::Event) -> Result<RawEvent, InvalidEvent> {
+    // Proto3 maps can't be told apart from unset ones when empty
+    let non_empty = |map: HashMap<String, v1::PropertyValue>| match map.is_empty() {
+        true => None,
+        false => Some(json_map(map)),
+    };
+    let mut raw = RawEvent {
+        token: None,
+        distinct_id: event.distinct_id.map(Value::String),
+        uuid: None,
+        event: event.event,
+        properties: json_map(event.properties),
+        timestamp: event.timestamp,
+        offset: event.offset,
+        set: non_empty(event.set),
+        set_once: non_empty(event.set_once),
+    };
+
+    match event.uuid.as_deref() {
+        None | Some("") => Ok(raw),
+        Some(uuid) => match Uuid::parse_str(uuid) {
+            Ok(uuid) => {
+                raw.uuid = Some(uuid);
+                Ok(raw)
+            }
+            Err(e) => {
+                let mut payload = serde_json::to_value(&raw).unwrap_or_default();
+                if let Value::Object(fields) = &mut payload {
+                    fields.insert("uuid".to_string(), Value::from(uuid));
+                }
+                Err(InvalidEvent {
+                    payload,
+                    error: CaptureError::InvalidEvent(format!("invalid uuid: {}", e)),
+                })
+            }
+        },
+    }
+}
+
+fn json_map(map: HashMap<String, v1::PropertyValue>) -> HashMap<String, Value> {
+    map.into_iter()
+        .map(|(key, value)| (key, json_value(value)))
+        .collect()
+}
+
+fn json_value(value: v1::PropertyValue) -> Value {
+    match value.kind {
+        None => Value::Null,
+        Some(v1::Kind::BoolValue(b)) => Value::from(b),
+        Some(v1::Kind::IntValue(i)) => Value::from(i),
+        Some(v1::Kind::DoubleValue(d)) => Value::from(d), // NaN and infinities become null
+        Some(v1::Kind::StringValue(s)) => Value::from(s),
+        Some(v1::Kind::ListValue(list)) => list.values.into_iter().map(json_value).collect(),
+        Some(v1::Kind::MapValue(map)) => Value::Object(
+            map.values
+                .into_iter()
+                .map(|(key, value)| (key, json_value(value)))
+                .collect(),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use prost::Message;
+    use serde_json::json;
+
+    use super::{decode_protobuf_request, v1, BinaryFormat};
+    use crate::api::CaptureError;
+    use crate::v0_request::RawRequest;
+
+    fn string_value(value: &str) -> v1::PropertyValue {
+        v1::PropertyValue {
+            kind: Some(v1::Kind::StringValue(value.to_string())),
+        }
+    }
+
+    #[test]
+    fn content_types() {
+        assert_eq!(
+            Some(BinaryFormat::MessagePack),
+            BinaryFormat::from_content_type("application/msgpack").unwrap()
+        );
+        assert_eq!(
+            Some(BinaryFormat::Protobuf),
+            BinaryFormat::from_content_type("application/x-protobuf").unwrap()
+        );
+        assert_eq!(
+            Some(BinaryFormat::Protobuf),
+            BinaryFormat::from_content_type("application/x-protobuf; version=1").unwrap()
+        );
+        assert!(BinaryFormat::from_content_type("application/x-protobuf; version=2").is_err());
+        assert_eq!(
+            None,
+            BinaryFormat::from_content_type("application/json").unwrap()
+        );
+    }
+
+    #[test]
+    fn decodes_protobuf_batch() {
+        let request = v1::BatchedRequest {
+            token: "my_token".to_string(),
+            historical_migration: Some(true),
+            sent_at: None,
+            batch: vec![
+                v1::Event {
+                    event: "reading".to_string(),
+                    distinct_id: Some("device1".to_string()),
+                    uuid: Some("01912c5a-2a9b-7d4c-9f5e-2b7a1c3d4e5f".to_string()),
+                    properties: HashMap::from([
+                        (
+                            "temperature".to_string(),
+                            v1::PropertyValue {
+                                kind: Some(v1::Kind::DoubleValue(21.5)),
+                            },
+                        ),
+                        (
+                            "tags".to_string(),
+                            v1::PropertyValue {
+                                kind: Some(v1::Kind::ListValue(v1::ListValue {
+                                    values: vec![string_value("a"), v1::PropertyValue::default()],
+                                })),
+                            },
+                        ),
+                    ]),
+                    set: HashMap::from([("model".to_string(), string_value("x1"))]),
+                    ..Default::default()
+                },
+                v1::Event {
+                    event: "reading".to_string(),
+                    uuid: Some("not-a-uuid".to_string()),
+                    ..Default::default()
+                },
+            ],
+        };
+
+        let (request, invalid) =
+            decode_protobuf_request(&request.encode_to_vec()).expect("failed to decode");
+        assert_eq!("my_token", request.extract_and_verify_token().unwrap());
+        assert!(request.historical_migration());
+        assert!(matches!(request, RawRequest::Batch(_)));
+
+        let events = request.events();
+        assert_eq!(1, events.len());
+        assert_eq!(Some(json!("device1")), events[0].distinct_id);
+        assert!(events[0].uuid.is_some());
+        assert_eq!(json!(21.5), events[0].properties["temperature"]);
+        assert_eq!(json!(["a", null]), events[0].properties["tags"]);
+        assert_eq!(
+            Some(&json!("x1")),
+            events[0].set.as_ref().and_then(|set| set.get("model"))
+        );
+        assert_eq!(None, events[0].set_once);
+
+        assert_eq!(1, invalid.len());
+        assert_eq!(json!("not-a-uuid"), invalid[0].payload["uuid"]);
+        assert!(matches!(invalid[0].error, CaptureError::InvalidEvent(_)));
+
+        assert!(matches!(
+            decode_protobuf_request(b"\xff\xff\xff"),
+            Err(CaptureError::RequestDecodingError(_))
+        ));
+    }
+
+    #[test]
+    fn decodes_message_encoded_from_schema() {
+        // Encoded by protoc from batch.proto, see testdata/batch.txtpb. Fields missing from the
+        // Rust types would be skipped as unknown, failing the comparison.
+        let payload = include_bytes!("../proto/posthog/capture/v1/testdata/batch.binpb");
+        let value = |kind: v1::Kind| v1::PropertyValue { kind: Some(kind) };
+        let expected = v1::BatchedRequest {
+            token: "my_token".to_string(),
+            historical_migration: Some(true),
+            sent_at: Some("2024-01-01T00:00:00Z".to_string()),
+            batch: vec![
+                v1::Event {
+                    event: "reading".to_string(),
+                    distinct_id: Some("device1".to_string()),
+                    uuid: Some("01912c5a-2a9b-7d4c-9f5e-2b7a1c3d4e5f".to_string()),
+                    timestamp: Some("2024-01-01T00:00:00Z".to_string()),
+                    offset: Some(1500),
+                    properties: HashMap::from([
+                        ("on".to_string(), value(v1::Kind::BoolValue(true))),
+                        ("count".to_string(), value(v1::Kind::IntValue(-3))),
+                        (
+                            "temperature".to_string(),
+                            value(v1::Kind::DoubleValue(21.5)),
+                        ),
+                        ("unit".to_string(), string_value("celsius")),
+                        (
+                            "tags".to_string(),
+                            value(v1::Kind::ListValue(v1::ListValue {
+                                values: vec![string_value("a"), v1::PropertyValue::default()],
+                            })),
+                        ),
+                        (
+                            "location".to_string(),
+                            value(v1::Kind::MapValue(v1::MapValue {
+                                values: HashMap::from([(
+                                    "room".to_string(),
+                                    string_value("kitchen"),
+                                )]),
+                            })),
+                        ),
+                    ]),
+                    set: HashMap::from([("model".to_string(), string_value("x1"))]),
+                    set_once: HashMap::from([(
+                        "first_seen".to_string(),
+                        string_value("2024-01-01"),
+                    )]),
+                },
+                v1::Event {
+                    event: "reading".to_string(),
+                    uuid: Some("not-a-uuid".to_string()),
+                    ..Default::default()
+                },
+            ],
+        };
+        assert_eq!(
+            expected,
+            v1::BatchedRequest::decode(&payload[..]).expect("failed to decode")
+        );
+
+        let (request, invalid) = decode_protobuf_request(payload).expect("failed to decode");
+        let events = request.events();
+        assert_eq!(1, events.len());
+        assert_eq!(Some(1500), events[0].offset);
+        assert_eq!(json!({"room": "kitchen"}), events[0].properties["location"]);
+        assert_eq!(1, invalid.len());
+    }
+}
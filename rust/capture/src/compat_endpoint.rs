@@ -0,0 +1,307 @@
+//! Endpoints compatible with the Segment and Mixpanel ingestion APIs, so that their SDKs
+//! can be pointed to capture. Payloads are translated into `RawEvent`s, see `segment_request`
+//! and `mixpanel_request`, then go through the same pipeline as v0 events.
+
+use std::collections::HashSet;
+use std::ops::Deref;
+
+use axum::extract::{MatchedPath, Query, State};
+use axum::http::{header, HeaderMap, Method};
+use axum::response::{IntoResponse, Response};
+use axum::{debug_handler, Json};
+use axum_client_ip::InsecureClientIp;
+use base64::Engine;
+use bytes::Bytes;
+use common_types::RawEvent;
+use metrics::counter;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tracing::instrument;
+
+use crate::api::CaptureError;
+use crate::mixpanel_request::{mixpanel_events, parse_data, MixpanelEndpoint, MixpanelFormData};
+use crate::prometheus::report_dropped_events;
+use crate::router;
+use crate::segment_request::{message_write_key, segment_events, SegmentBatch, SegmentMessageType};
+use crate::token::validate_token;
+use crate::v0_endpoint::process_events;
+use crate::v0_request::{decompress_payload, Compression, InvalidEvent, ProcessingContext};
+
+#[derive(Deserialize, Default)]
+pub struct MixpanelQuery {
+    data: Option<String>,
+    verbose: Option<String>,
+}
+
+/// Segment HTTP tracking API: `/v1/track`, `/v1/identify`, `/v1/page` and `/v1/batch`.
+///
+/// The write key, used as project token, is read from the basic auth username, or else
+/// from the payload's `writeKey` field.
+#[instrument(
+    skip_all,
+    fields(path, token, batch_size, user_agent, content_encoding)
+)]
+#[debug_handler]
+pub async fn segment(
+    state: State<router::State>,
+    InsecureClientIp(ip): InsecureClientIp,
+    headers: HeaderMap,
+    path: MatchedPath,
+    body: Bytes,
+) -> Result<Json<Value>, CaptureError> {
+    let path = path.as_str().trim_end_matches('/');
+    tracing::Span::current().record("path", path);
+    let body_bytes = body.len();
+
+    let auth_token = basic_auth_username(&headers);
+    let payload = decompress_body(&state, &headers, auth_token.as_deref(), path, body)?;
+    let decompressed_bytes = payload.len();
+    let payload: Value = serde_json::from_str(&payload)?;
+    let (write_key, messages, default_type) = match path.rsplit('/').next() {
+        Some("batch") => {
+            let batch = SegmentBatch::deserialize(&payload)?;
+            (batch.write_key, batch.batch, None)
+        }
+        Some(endpoint) => (
+            message_write_key(&payload),
+            vec![payload],
+            SegmentMessageType::parse(endpoint),
+        ),
+        None => return Err(CaptureError::RequestParsingError(path.to_string())),
+    };
+    let token = auth_token.or(write_key).ok_or(CaptureError::NoTokenError)?;
+
+    let (events, invalid) = segment_events(messages, default_type);
+    process_translated_events(
+        &state,
+        "segment",
+        ip.to_string(),
+        &headers,
+        path,
+        body_bytes,
+        decompressed_bytes,
+        token,
+        events,
+        invalid,
+    )
+    .await?;
+
+    Ok(Json(json!({"success": true})))
+}
+
+/// Mixpanel ingestion API, `/track` and `/engage` served under `/mixpanel`.
+///
+/// The payload is read from the `data` query parameter or form field, or else from the
+/// JSON body. Mixpanel SDKs expect `1` on success, or a status object in verbose mode.
+#[instrument(
+    skip_all,
+    fields(path, token, batch_size, user_agent, content_encoding)
+)]
+#[debug_handler]
+pub async fn mixpanel(
+    state: State<router::State>,
+    InsecureClientIp(ip): InsecureClientIp,
+    meta: Query<MixpanelQuery>,
+    headers: HeaderMap,
+    method: Method,
+    path: MatchedPath,
+    body: Bytes,
+) -> Result<Response, CaptureError> {
+    let path = path.as_str().trim_end_matches('/');
+    tracing::Span::current().record("path", path);
+    let body_bytes = body.len();
+    let endpoint = match path.ends_with("engage") {
+        true => MixpanelEndpoint::Engage,
+        false => MixpanelEndpoint::Track,
+    };
+
+    let content_type = headers
+        .get("content-type")
+        .map_or("", |v| v.to_str().unwrap_or(""));
+    let data = match &meta.data {
+        Some(data) => data.clone(),
+        None if method == Method::GET => return Err(CaptureError::EmptyBatch),
+        None if content_type.starts_with("application/x-www-form-urlencoded") => {
+            let input: MixpanelFormData =
+                serde_urlencoded::from_bytes(body.deref()).map_err(|e| {
+                    tracing::error!("failed to decode body: {}", e);
+                    CaptureError::RequestDecodingError(String::from("invalid form data"))
+                })?;
+            input.data.ok_or(CaptureError::EmptyBatch)?
+        }
+        None => decompress_body(&state, &headers, None, path, body)?,
+    };
+    let decompressed_bytes = data.len();
+
+    let (token, events, invalid) = mixpanel_events(parse_data(&data)?, endpoint)?;
+    process_translated_events(
+        &state,
+        "mixpanel",
+        ip.to_string(),
+        &headers,
+        path,
+        body_bytes,
+        decompressed_bytes,
+        token,
+        events,
+        invalid,
+    )
+    .await?;
+
+    Ok(match meta.verbose.as_deref() {
+        Some("1") => Json(json!({"status": 1, "error": null})).into_response(),
+        _ => "1".into_response(),
+    })
+}
+
+/// Decompresses the body within the limit of the token, if already known, see
+/// `TokenLimits::decompress`.
+fn decompress_body(
+    state: &router::State,
+    headers: &HeaderMap,
+    token: Option<&str>,
+    path: &str,
+    body: Bytes,
+) -> Result<String, CaptureError> {
+    let content_encoding = headers
+        .get("content-encoding")
+        .map_or("unknown", |v| v.to_str().unwrap_or("unknown"));
+    tracing::Span::current().record("content_encoding", content_encoding);
+
+    let compression = Compression::from_content_encoding(content_encoding).unwrap_or_default();
+    state.token_limits.decompress(
+        token,
+        router::default_body_limit(path),
+        state.event_size_limit,
+        |limit| decompress_payload(body, limit, compression),
+    )
+}
+
+fn basic_auth_username(headers: &HeaderMap) -> Option<String> {
+    let value = headers.get(header::AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, credentials) = value.trim().split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("basic") {
+        return None;
+    }
+    let decoded = base64::engine::general_purpose::STANDARD
+        .decode(credentials.trim())
+        .ok()?;
+    let decoded = String::from_utf8(decoded).ok()?;
+    let username = decoded.split(':').next().unwrap_or_default();
+    match username.is_empty() {
+        true => None,
+        false => Some(username.to_string()),
+    }
+}
+
+/// Applies the same checks as `handle_common` to translated events, before `process_events`.
+#[allow(clippy::too_many_arguments)]
+async fn process_translated_events(
+    state: &router::State,
+    source: &'static str,
+    client_ip: String,
+    headers: &HeaderMap,
+    path: &str,
+    body_bytes: usize,
+    decompressed_bytes: usize,
+    token: String,
+    events: Vec<RawEvent>,
+    invalid: Vec<InvalidEvent>,
+) -> Result<(), CaptureError> {
+    let user_agent = headers
+        .get("user-agent")
+        .map_or("unknown", |v| v.to_str().unwrap_or("unknown"));
+    tracing::Span::current().record("user_agent", user_agent);
+
+    let count = events.len() + invalid.len();
+    if let Err(err) = validate_token(&token) {
+        report_dropped_events("token_shape_invalid", count as u64);
+        return Err(err.into());
+    }
+    tracing::Span::current().record("token", &token);
+    tracing::Span::current().record("batch_size", count);
+
+    if count == 0 {
+        tracing::log::warn!("rejected empty batch");
+        return Err(CaptureError::EmptyBatch);
+    }
+
+    counter!("capture_events_received_total").increment(count as u64);
+    counter!("capture_compat_events_received_total", "source" => source).increment(count as u64);
+
+    if let Err(err) = state.token_limits.check(
+        &token,
+        body_bytes,
+        decompressed_bytes,
+        router::default_body_limit(path),
+        &events,
+    ) {
+        report_dropped_events("token_limit_exceeded", count as u64);
+        return Err(err);
+    }
+
+    let context = ProcessingContext {
+        lib_version: None,
+        sent_at: None,
+        token,
+        now: state.timesource.current_time(),
+        client_ip,
+        historical_migration: false,
+        user_agent: Some(user_agent.to_string()),
+        server_hashed: HashSet::new(),
+    };
+
+    if let Some(rate_limiter) = &state.rate_limiter {
+        if let Some(retry_after) = rate_limiter.check(&context.token, &context.client_ip).await {
+            report_dropped_events("rate_limited", count as u64);
+            return Err(CaptureError::RateLimited(retry_after));
+        }
+    }
+
+    // Like v0, over quota events are dropped without an error, so that SDKs don't retry them
+    if state
+        .billing_limiter
+        .is_limited(context.token.as_str())
+        .await
+    {
+        report_dropped_events("over_quota", count as u64);
+        return Ok(());
+    }
+
+    process_events(
+        state.sink.clone(),
+        state.token_dropper.clone(),
+        state.event_rules.clone(),
+        state.deduplicator.clone(),
+        &events,
+        invalid,
+        &context,
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{header, HeaderMap, HeaderValue};
+
+    use super::basic_auth_username;
+
+    #[test]
+    fn reads_write_key_from_basic_auth() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(None, basic_auth_username(&headers));
+
+        // base64("phc_123:")
+        headers.insert(
+            header::AUTHORIZATION,
+            HeaderValue::from_static("Basic cGhjXzEyMzo="),
+        );
+        assert_eq!(Some("phc_123".to_string()), basic_auth_username(&headers));
+
+        headers.insert(
+            header::AUTHORIZATION,
+            HeaderValue::from_static("Bearer phc_123"),
+        );
+        assert_eq!(None, basic_auth_username(&headers));
+    }
+}
@@ -2,6 +2,9 @@ use std::{net::SocketAddr, num::NonZeroU32};
 
 use envconfig::Envconfig;
 use health::HealthStrategy;
+use limiters::request_rate::RateLimit;
+
+use crate::sinks::dual::RoutingPolicies;
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum CaptureMode {
@@ -21,6 +24,33 @@ impl std::str::FromStr for CaptureMode {
     }
 }
 
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum S3Compression {
+    Gzip,
+    Zstd,
+}
+
+impl S3Compression {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            S3Compression::Gzip => "gz",
+            S3Compression::Zstd => "zst",
+        }
+    }
+}
+
+impl std::str::FromStr for S3Compression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_ref() {
+            "gzip" => Ok(S3Compression::Gzip),
+            "zstd" => Ok(S3Compression::Zstd),
+            _ => Err(format!("Unknown S3 compression: {s}")),
+        }
+    }
+}
+
 #[derive(Envconfig, Clone)]
 pub struct Config {
     #[envconfig(default = "false")]
@@ -41,12 +71,22 @@ pub struct Config {
     #[envconfig(default = "1000")]
     pub overflow_burst_limit: NonZeroU32,
 
+    // Both are also reloaded at runtime from redis sets, see `OVERFLOW_FORCED_KEYS_CACHE_KEY`
+    // and `TOKEN_DROPPER_CACHE_KEY`
     pub overflow_forced_keys: Option<String>, // Coma-delimited keys
     pub dropped_keys: Option<String>, // "<token>:<distinct_id or *>,<distinct_id or *>;<token>..."
 
     #[envconfig(nested = true)]
     pub kafka: KafkaConfig,
 
+    // Secondary Kafka cluster, sharing the other settings of the primary one. Events are routed
+    // per data type, see `sinks::dual`
+    pub kafka_secondary_hosts: Option<String>,
+    #[envconfig(default = "")]
+    pub kafka_secondary_policies: RoutingPolicies, // "<data_type>=<primary|mirror|failover|shadow:<percent>>,..."
+    #[envconfig(default = "1000")]
+    pub kafka_secondary_max_copies_in_flight: usize, // Copies to the secondary are dropped past it
+
     #[envconfig(default = "1.0")]
     pub otel_sampling_rate: f64,
 
@@ -71,8 +111,62 @@ pub struct Config {
     #[envconfig(default = "")]
     pub s3_fallback_prefix: String,
 
+    #[envconfig(default = "gzip")]
+    pub s3_fallback_compression: S3Compression,
+
+    // Split objects by token and hour, see `sinks::s3::object_key`
+    #[envconfig(default = "false")]
+    pub s3_fallback_partitioned: bool,
+
+    // Spill events to local disk while Kafka is unavailable, if no S3 fallback is configured
+    #[envconfig(default = "false")]
+    pub disk_spill_enabled: bool,
+    pub disk_spill_path: Option<String>,
+    #[envconfig(default = "1073741824")]
+    pub disk_spill_max_bytes: u64, // 1GiB, new events are rejected once reached
+    #[envconfig(default = "67108864")]
+    pub disk_spill_segment_bytes: u64, // 64MiB
+
     #[envconfig(default = "ALL")]
     pub healthcheck_strategy: HealthStrategy,
+
+    // Refuse requests over the per-token limit with a 429, limits are "<per_second>[:<burst>]".
+    // Per-token overrides are read from redis, see `limiters::request_rate`.
+    #[envconfig(default = "false")]
+    pub rate_limit_enabled: bool,
+    #[envconfig(default = "1000:10000")]
+    pub rate_limit_default: RateLimit,
+    pub rate_limit_per_ip: Option<RateLimit>,
+
+    // Compute the distinct_id of anonymous cookieless events, instead of the plugin-server
+    #[envconfig(default = "false")]
+    pub cookieless_enabled: bool,
+    #[envconfig(default = "false")]
+    pub cookieless_force_stateless_mode: bool,
+    pub cookieless_redis_url: Option<String>, // Defaults to redis_url
+
+    // Drop events whose uuid was already captured in the last window, see `dedup`
+    #[envconfig(default = "false")]
+    pub dedup_enabled: bool,
+    #[envconfig(default = "600")]
+    pub dedup_window_secs: u64,
+    #[envconfig(default = "100000")]
+    pub dedup_local_cache_size: u64,
+
+    // Per-token body size, batch length and event size limits, as a JSON object mapping tokens
+    // to their limits. Also reloaded at runtime from a redis hash, see `token_limits`. Bodies
+    // are only allowed over the route's default limit up to the ceiling, and their decompressed
+    // size limit is scaled along.
+    pub token_limits: Option<String>, // {"<token>": {"max_body_bytes": ..., "max_batch_events": ..., "max_event_bytes": ...}}
+    #[envconfig(default = "0")]
+    pub token_limits_max_body_bytes: usize,
+
+    // On shutdown, time given to in-flight requests before aborting them, then to the sink
+    // to write out its buffered events. Should fit in the pod's termination grace period.
+    #[envconfig(default = "10")]
+    pub shutdown_drain_timeout_secs: u64,
+    #[envconfig(default = "15")]
+    pub shutdown_flush_timeout_secs: u64,
 }
 
 #[derive(Envconfig, Clone)]
@@ -98,6 +192,8 @@ pub struct KafkaConfig {
     pub kafka_exceptions_topic: String,
     #[envconfig(default = "events_plugin_ingestion")]
     pub kafka_heatmaps_topic: String,
+    #[envconfig(default = "logs_ingestion")]
+    pub kafka_logs_topic: String,
     #[envconfig(default = "session_recording_snapshot_item_overflow")]
     pub kafka_replay_overflow_topic: String,
     #[envconfig(default = "false")]
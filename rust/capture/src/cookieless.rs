@@ -0,0 +1,473 @@
+//! Server-side computation of cookieless distinct_ids.
+//!
+//! SDKs running in cookieless mode send the `$posthog_cookieless` sentinel as distinct_id,
+//! and flag their events with `$cookieless_mode`. This module replaces the sentinel with the
+//! daily-salted hash of the client IP, user agent and host, using the `common-cookieless`
+//! manager, so that the plugin-server does not have to. Like the plugin-server, it also
+//! assigns the `$device_id`, and the `$session_id` from the session state shared with it.
+//!
+//! Only anonymous events of teams in the stateful server hash mode are handled here: identify and
+//! post-identify events, events of teams in the disabled or stateless modes (or whose mode isn't
+//! in the team cache yet), and events we fail to process, are left untouched and keep being
+//! handled by the plugin-server.
+//! Processed events are no longer flagged with `$cookieless_mode`, so that the plugin-server
+//! skips them, and have the personal data used in the hash removed. Their uuids are returned,
+//! so that the client IP is not passed on either, see `ProcessingContext::server_hashed`.
+
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use common_cookieless::{
+    CookielessConfig, CookielessManager, CookielessManagerError, EventData, HashParams,
+    COOKIELESS_EXTRA_HASH_CONTENTS_PROPERTY, COOKIELESS_MODE_FLAG_PROPERTY,
+    COOKIELESS_SENTINEL_VALUE,
+};
+use common_redis::Client;
+use common_types::RawEvent;
+use metrics::counter;
+use serde::Deserialize;
+use serde_json::Value;
+use time::format_description::well_known::Iso8601;
+use time::OffsetDateTime;
+use tracing::instrument;
+use uuid::Uuid;
+
+use crate::utils::uuid_v7;
+use crate::v0_request::ProcessingContext;
+
+// TRICKY: This cache is written by django-redis, see posthog/models/team/team_caching.py
+pub const TEAM_TOKEN_CACHE_PREFIX: &str = "posthog:1:team_token:";
+
+/// Set on events processed by capture, so that ingestion knows not to expect a client IP.
+/// Only capture sets it: the values sent by clients are removed in `process_single_event`.
+pub const COOKIELESS_SERVER_HASHED_PROPERTY: &str = "$cookieless_server_hashed";
+
+// Events that cannot be handled in cookieless mode, the plugin-server drops them
+const UNSUPPORTED_EVENTS: [&str; 3] = ["$identify", "$create_alias", "$merge_dangerously"];
+
+// Same values as `CookielessServerHashMode` in django, 0 and 1 being the disabled and stateless modes
+const SERVER_HASH_MODE_STATEFUL: i16 = 2;
+
+#[derive(Deserialize)]
+struct CachedTeam {
+    id: u64,
+    // Missing from the entries cached before django added them
+    #[serde(default)]
+    cookieless_server_hash_mode: Option<i16>,
+    #[serde(default)]
+    timezone: Option<String>,
+}
+
+pub struct CookielessProcessor {
+    manager: CookielessManager,
+    team_redis: Arc<dyn Client + Send + Sync>,
+    // The plugin-server also forces the stateless mode when this is set
+    force_stateless_mode: bool,
+}
+
+impl CookielessProcessor {
+    /// `team_redis` must point to the django cache, `salt_redis` to the redis instance
+    /// holding the salts and identify counts shared with the plugin-server.
+    pub fn new(
+        config: CookielessConfig,
+        team_redis: Arc<dyn Client + Send + Sync>,
+        salt_redis: Arc<dyn Client + Send + Sync>,
+    ) -> Self {
+        Self {
+            force_stateless_mode: config.force_stateless_mode,
+            manager: CookielessManager::new(config, salt_redis),
+            team_redis,
+        }
+    }
+
+    /// Replaces the sentinel distinct_id of anonymous cookieless events, in place, and returns
+    /// the uuids of the processed events, assigning one to those without.
+    /// The team is only resolved if the batch holds candidate events.
+    #[instrument(skip_all)]
+    pub async fn process_events<'a>(
+        &self,
+        events: impl Iterator<Item = &'a mut RawEvent>,
+        context: &ProcessingContext,
+    ) -> HashSet<Uuid> {
+        let mut processed = HashSet::new();
+        let mut resolved_team = None;
+        for event in events.filter(|e| is_candidate(e)) {
+            let team = match resolved_team {
+                Some(ref team) => team,
+                None => match self.resolve_team(&context.token).await {
+                    Some(team) => resolved_team.insert(team),
+                    None => {
+                        counter!("capture_cookieless_events_total", "outcome" => "unknown_team")
+                            .increment(1);
+                        return processed;
+                    }
+                },
+            };
+            // The plugin-server drops the events of disabled teams, and stateless events
+            // get a session_id derived from the hash, which isn't computed here
+            if !self.is_stateful(team) {
+                counter!("capture_cookieless_events_total", "outcome" => "not_stateful")
+                    .increment(1);
+                return processed;
+            }
+
+            let outcome = match self.process_event(team, event, context).await {
+                Ok(uuid) => {
+                    processed.insert(uuid);
+                    "processed"
+                }
+                Err(CookielessManagerError::MissingProperty(_)) => "missing_property",
+                Err(err) => {
+                    tracing::warn!("failed to compute cookieless distinct_id: {}", err);
+                    "error"
+                }
+            };
+            counter!("capture_cookieless_events_total", "outcome" => outcome).increment(1);
+        }
+        processed
+    }
+
+    fn is_stateful(&self, team: &CachedTeam) -> bool {
+        !self.force_stateless_mode
+            && team.cookieless_server_hash_mode == Some(SERVER_HASH_MODE_STATEFUL)
+            && team.timezone.is_some()
+    }
+
+    async fn resolve_team(&self, token: &str) -> Option<CachedTeam> {
+        let serialized = self
+            .team_redis
+            .get(format!("{TEAM_TOKEN_CACHE_PREFIX}{token}"))
+            .await
+            .inspect_err(|e| tracing::debug!("failed to fetch team from cache: {}", e))
+            .ok()?;
+        serde_json::from_str::<CachedTeam>(&serialized)
+            .inspect_err(|e| tracing::error!("failed to parse cached team: {}", e))
+            .ok()
+    }
+
+    async fn process_event(
+        &self,
+        team: &CachedTeam,
+        event: &mut RawEvent,
+        context: &ProcessingContext,
+    ) -> Result<Uuid, CookielessManagerError> {
+        let team_id = team.id;
+        let property = |key: &str| event.properties.get(key).and_then(Value::as_str);
+
+        let timestamp_ms = event_timestamp_ms(event.timestamp.as_deref(), context)
+            .ok_or_else(|| CookielessManagerError::InvalidTimestamp(context.now.clone()))?;
+        let data = EventData {
+            ip: property("$ip").unwrap_or(&context.client_ip),
+            timestamp_ms,
+            host: property("$host").unwrap_or_default(),
+            user_agent: property("$raw_user_agent")
+                .or(context.user_agent.as_deref())
+                .unwrap_or_default(),
+            event_time_zone: property("$timezone"),
+            hash_extra: property(COOKIELESS_EXTRA_HASH_CONTENTS_PROPERTY),
+            team_id,
+            team_time_zone: team.timezone.as_deref(),
+        };
+
+        let hash = self.manager.compute_cookieless_hash(data.clone()).await?;
+        // Like the plugin-server, the device_id ignores the identify count
+        let base_hash = self
+            .manager
+            .do_hash_for_day(HashParams {
+                timestamp_ms: data.timestamp_ms,
+                event_time_zone: data.event_time_zone,
+                team_time_zone: data.team_time_zone,
+                team_id,
+                ip: data.ip,
+                host: data.host,
+                user_agent: data.user_agent,
+                n: 0,
+                hash_extra: data.hash_extra.unwrap_or_default(),
+            })
+            .await?;
+        let device_id = CookielessManager::hash_to_distinct_id(&base_hash);
+        let session_id = self
+            .manager
+            .get_session_id(&hash, team_id, timestamp_ms)
+            .await?;
+
+        event.distinct_id = Some(Value::String(CookielessManager::hash_to_distinct_id(&hash)));
+        event.properties.remove("distinct_id");
+        event.properties.remove("$ip");
+        event.properties.remove("$raw_user_agent");
+        event
+            .properties
+            .remove(COOKIELESS_EXTRA_HASH_CONTENTS_PROPERTY);
+        event.properties.remove(COOKIELESS_MODE_FLAG_PROPERTY);
+        event
+            .properties
+            .insert("$device_id".to_string(), Value::String(device_id));
+        event.properties.insert(
+            "$session_id".to_string(),
+            Value::String(session_id.to_string()),
+        );
+        event.properties.insert(
+            COOKIELESS_SERVER_HASHED_PROPERTY.to_string(),
+            Value::Bool(true),
+        );
+        Ok(*event.uuid.get_or_insert_with(uuid_v7))
+    }
+}
+
+/// Builds the manager config from the capture config, see `Config::cookieless_enabled`.
+pub fn cookieless_config(force_stateless_mode: bool) -> CookielessConfig {
+    CookielessConfig {
+        force_stateless_mode,
+        ..CookielessConfig::default()
+    }
+}
+
+fn is_candidate(event: &RawEvent) -> bool {
+    event.extract_is_cookieless_mode() == Some(true)
+        && event.extract_distinct_id().as_deref() == Some(COOKIELESS_SENTINEL_VALUE)
+        && !UNSUPPORTED_EVENTS.contains(&event.event.as_str())
+        // The plugin-server drops these, let it report them
+        && !event.properties.contains_key("$session_id")
+        && !event.properties.contains_key("$device_id")
+}
+
+/// Same precedence as the plugin-server: event timestamp, then sent_at, then now.
+fn event_timestamp_ms(timestamp: Option<&str>, context: &ProcessingContext) -> Option<u64> {
+    let parse = |value: &str| OffsetDateTime::parse(value, &Iso8601::DEFAULT).ok();
+    let timestamp = match timestamp {
+        Some(value) => parse(value)?,
+        None => match context.sent_at {
+            Some(sent_at) => sent_at,
+            None => parse(&context.now)?,
+        },
+    };
+    u64::try_from(timestamp.unix_timestamp_nanos() / 1_000_000).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, HashSet};
+    use std::sync::Arc;
+
+    use chrono::Utc;
+    use common_cookieless::{do_hash, get_redis_sessions_key, CookielessManager};
+    use common_redis::{CustomRedisError, MockRedisClient};
+    use common_types::RawEvent;
+    use serde_json::{json, Value};
+
+    use super::{
+        cookieless_config, event_timestamp_ms, CookielessProcessor,
+        COOKIELESS_SERVER_HASHED_PROPERTY, TEAM_TOKEN_CACHE_PREFIX,
+    };
+    use crate::v0_request::ProcessingContext;
+
+    fn context() -> ProcessingContext {
+        ProcessingContext {
+            lib_version: None,
+            user_agent: Some("Mozilla/5.0".to_string()),
+            sent_at: None,
+            token: "my_token".to_string(),
+            now: "2024-01-01T00:00:00Z".to_string(),
+            client_ip: "1.2.3.4".to_string(),
+            historical_migration: false,
+            server_hashed: HashSet::new(),
+        }
+    }
+
+    fn cookieless_event(name: &str, properties: Value) -> RawEvent {
+        let mut properties: HashMap<String, Value> =
+            serde_json::from_value(properties).expect("invalid properties");
+        properties.insert("$cookieless_mode".to_string(), Value::Bool(true));
+        RawEvent {
+            event: name.to_string(),
+            distinct_id: Some(Value::String("$posthog_cookieless".to_string())),
+            properties,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn timestamp_precedence() {
+        let mut context = context();
+        assert_eq!(
+            Some(1704067200000),
+            event_timestamp_ms(None, &context),
+            "falls back to now"
+        );
+        assert_eq!(
+            Some(1704070800000),
+            event_timestamp_ms(Some("2024-01-01T01:00:00Z"), &context)
+        );
+        context.sent_at = Some(time::macros::datetime!(2024-01-01 02:00 UTC));
+        assert_eq!(Some(1704074400000), event_timestamp_ms(None, &context));
+        assert_eq!(None, event_timestamp_ms(Some("yesterday"), &context));
+    }
+
+    #[tokio::test]
+    async fn leaves_events_untouched_for_unknown_teams() {
+        let redis = Arc::new(MockRedisClient::new().get_ret(
+            &format!("{TEAM_TOKEN_CACHE_PREFIX}my_token"),
+            Err(CustomRedisError::NotFound),
+        ));
+        let processor = CookielessProcessor::new(cookieless_config(false), redis.clone(), redis);
+
+        let mut events = vec![cookieless_event(
+            "$pageview",
+            json!({"$host": "example.com", "$raw_user_agent": "Mozilla/5.0"}),
+        )];
+        let processed = processor
+            .process_events(events.iter_mut(), &context())
+            .await;
+
+        assert!(processed.is_empty());
+        assert_eq!(
+            Some("$posthog_cookieless".to_string()),
+            events[0].extract_distinct_id()
+        );
+        assert_eq!(Some(true), events[0].extract_is_cookieless_mode());
+    }
+
+    #[tokio::test]
+    async fn leaves_events_untouched_for_teams_not_in_stateful_mode() {
+        let cases = [
+            (json!({"id": 1}), false),
+            (
+                json!({"id": 1, "cookieless_server_hash_mode": null, "timezone": "UTC"}),
+                false,
+            ),
+            (
+                json!({"id": 1, "cookieless_server_hash_mode": 0, "timezone": "UTC"}),
+                false,
+            ),
+            (
+                json!({"id": 1, "cookieless_server_hash_mode": 1, "timezone": "UTC"}),
+                false,
+            ),
+            (
+                json!({"id": 1, "cookieless_server_hash_mode": 2, "timezone": null}),
+                false,
+            ),
+            (
+                json!({"id": 1, "cookieless_server_hash_mode": 2, "timezone": "UTC"}),
+                true,
+            ),
+        ];
+        for (team, force_stateless_mode) in cases {
+            let redis = Arc::new(MockRedisClient::new().get_ret(
+                &format!("{TEAM_TOKEN_CACHE_PREFIX}my_token"),
+                Ok(team.to_string()),
+            ));
+            let processor = CookielessProcessor::new(
+                cookieless_config(force_stateless_mode),
+                redis.clone(),
+                redis,
+            );
+
+            let mut events = vec![cookieless_event(
+                "$pageview",
+                json!({"$host": "example.com", "$raw_user_agent": "Mozilla/5.0"}),
+            )];
+            let processed = processor
+                .process_events(events.iter_mut(), &context())
+                .await;
+
+            assert!(processed.is_empty(), "{team}");
+            assert_eq!(
+                Some("$posthog_cookieless".to_string()),
+                events[0].extract_distinct_id(),
+                "{team}"
+            );
+            assert_eq!(Some(true), events[0].extract_is_cookieless_mode());
+        }
+    }
+
+    #[tokio::test]
+    async fn skips_unsupported_events() {
+        // No team in the mock: looking it up would leave a call trace
+        let redis = Arc::new(MockRedisClient::new());
+        let processor =
+            CookielessProcessor::new(cookieless_config(false), redis.clone(), redis.clone());
+
+        let mut events = vec![
+            cookieless_event("$identify", json!({"$host": "example.com"})),
+            cookieless_event(
+                "$pageview",
+                json!({"$host": "example.com", "$session_id": "s1"}),
+            ),
+            RawEvent {
+                event: "$pageview".to_string(),
+                distinct_id: Some(Value::String("$posthog_cookieless".to_string())),
+                ..Default::default()
+            },
+        ];
+        let processed = processor
+            .process_events(events.iter_mut(), &context())
+            .await;
+
+        assert!(processed.is_empty());
+        assert!(redis.get_calls().is_empty());
+    }
+
+    #[tokio::test]
+    async fn hashes_events_of_teams_in_stateful_mode() {
+        // Salts are only valid around the current date
+        let now = Utc::now();
+        let salt = [0u8; 16];
+        let hash = do_hash(&salt, 1, "1.2.3.4", "example.com", "Mozilla/5.0", 0, "").unwrap();
+        let redis = Arc::new(
+            MockRedisClient::new()
+                .get_ret(
+                    &format!("{TEAM_TOKEN_CACHE_PREFIX}my_token"),
+                    Ok(
+                        json!({"id": 1, "cookieless_server_hash_mode": 2, "timezone": "UTC"})
+                            .to_string(),
+                    ),
+                )
+                .get_ret(
+                    &format!("cookieless_salt:{}", now.format("%Y-%m-%d")),
+                    Ok("AAAAAAAAAAAAAAAAAAAAAA==".to_string()),
+                )
+                .set_ret(&get_redis_sessions_key(&hash, 1), Ok(())),
+        );
+        let processor =
+            CookielessProcessor::new(cookieless_config(false), redis.clone(), redis.clone());
+
+        let mut event = cookieless_event(
+            "$pageview",
+            json!({"$host": "example.com", "$raw_user_agent": "Mozilla/5.0", "$ip": "1.2.3.4"}),
+        );
+        event.timestamp = Some(now.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string());
+        let mut events = vec![event];
+        let processed = processor
+            .process_events(events.iter_mut(), &context())
+            .await;
+
+        let event = &events[0];
+        // The event had no uuid, it got one to be tracked with
+        assert_eq!(Some(&event.uuid.unwrap()), processed.iter().next());
+        let distinct_id = CookielessManager::hash_to_distinct_id(&hash);
+        assert_eq!(Some(distinct_id.clone()), event.extract_distinct_id());
+        // No identify yet, the device_id is the distinct_id
+        assert_eq!(
+            Some(&Value::String(distinct_id)),
+            event.properties.get("$device_id")
+        );
+        let session_id = event.properties["$session_id"].as_str().unwrap();
+        assert_eq!(
+            7,
+            uuid::Uuid::parse_str(session_id).unwrap().get_version_num()
+        );
+        assert!(redis
+            .get_calls()
+            .iter()
+            .any(|call| call.op == "set_bytes" && call.key == get_redis_sessions_key(&hash, 1)));
+
+        assert_eq!(
+            Some(&Value::Bool(true)),
+            event.properties.get(COOKIELESS_SERVER_HASHED_PROPERTY)
+        );
+        for property in ["$cookieless_mode", "$ip", "$raw_user_agent"] {
+            assert!(!event.properties.contains_key(property), "{property}");
+        }
+    }
+}
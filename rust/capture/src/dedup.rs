@@ -0,0 +1,223 @@
+//! Short-window deduplication of events on their client-provided `uuid`.
+//!
+//! Clients retrying a request after a timeout send the same events again. The uuids of a batch
+//! are claimed for the window with pipelined `SET NX EX`s in redis, shared by all capture
+//! instances, and events whose uuid was already claimed are dropped. Recently claimed uuids are also kept in a local cache,
+//! sparing the redis round-trip for in-batch duplicates and quick retries on the same instance.
+//!
+//! Events without a uuid get a fresh one in `process_single_event`, and are never deduplicated.
+//! If redis is unavailable, events are let through.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+
+use common_redis::Client;
+use common_types::RawEvent;
+use metrics::counter;
+use moka::sync::Cache;
+use tracing::instrument;
+use uuid::Uuid;
+
+pub const DEDUP_KEY_PREFIX: &str = "@posthog/capture-dedup:";
+
+pub struct EventDeduplicator {
+    redis: Arc<dyn Client + Send + Sync>,
+    window: Duration,
+    key_prefix: String,
+    // Keys claimed by this instance, or known to be claimed by another one
+    seen: Cache<String, ()>,
+}
+
+impl EventDeduplicator {
+    pub fn new(
+        redis: Arc<dyn Client + Send + Sync>,
+        window: Duration,
+        local_cache_size: u64,
+        redis_key_prefix: Option<String>,
+    ) -> Self {
+        Self {
+            redis,
+            window,
+            key_prefix: format!("{}{DEDUP_KEY_PREFIX}", redis_key_prefix.unwrap_or_default()),
+            seen: Cache::builder()
+                .max_capacity(local_cache_size)
+                .time_to_live(window)
+                .build(),
+        }
+    }
+
+    /// Claims the uuids of the events for the window, and drops the events whose uuid was
+    /// already claimed. Returns the remaining events, and the uuids claimed by this call, to
+    /// be released if the events could not be sent.
+    pub async fn filter_duplicates<'a>(
+        &self,
+        token: &str,
+        events: &'a [RawEvent],
+    ) -> (Vec<&'a RawEvent>, Vec<Uuid>) {
+        let events: Vec<&RawEvent> = events.iter().collect();
+        let (unique, claimed) = self.check_duplicates(token, &events).await;
+        let events = events
+            .into_iter()
+            .zip(unique)
+            .filter_map(|(event, unique)| unique.then_some(event))
+            .collect();
+        (events, claimed)
+    }
+
+    /// Same as `filter_duplicates`, telling whether each event is unique instead of dropping
+    /// the duplicates, for callers reporting on every event.
+    #[instrument(skip_all, fields(events = events.len()))]
+    pub async fn check_duplicates(
+        &self,
+        token: &str,
+        events: &[&RawEvent],
+    ) -> (Vec<bool>, Vec<Uuid>) {
+        // Keys missing from the local cache are claimed in redis, once per batch
+        let mut batch_keys = HashSet::new();
+        let to_claim: Vec<String> = events
+            .iter()
+            .filter_map(|event| event.uuid.map(|uuid| self.key(token, &uuid)))
+            .filter(|key| !self.seen.contains_key(key) && batch_keys.insert(key.clone()))
+            .collect();
+        let mut claim_outcomes: HashMap<String, &'static str> = if to_claim.is_empty() {
+            HashMap::new()
+        } else {
+            match self
+                .redis
+                .set_nx_ex_many(to_claim.clone(), "1".to_string(), self.window.as_secs())
+                .await
+            {
+                Ok(set) => to_claim
+                    .into_iter()
+                    .zip(set)
+                    .map(|(key, set)| (key, if set { "unique" } else { "duplicate" }))
+                    .collect(),
+                Err(err) => {
+                    tracing::debug!("failed to check event uuids in redis: {}", err);
+                    to_claim.into_iter().map(|key| (key, "error")).collect()
+                }
+            }
+        };
+
+        let mut unique = Vec::with_capacity(events.len());
+        let mut claimed = Vec::new();
+        for event in events {
+            let Some(uuid) = event.uuid else {
+                unique.push(true);
+                continue;
+            };
+
+            // Only the first event of the batch with the uuid gets the redis outcome
+            let key = self.key(token, &uuid);
+            let outcome = claim_outcomes.remove(&key).unwrap_or("duplicate_local");
+            self.seen.insert(key, ());
+            counter!("capture_dedup_events_total", "outcome" => outcome).increment(1);
+
+            match outcome {
+                "duplicate" | "duplicate_local" => unique.push(false),
+                _ => {
+                    unique.push(true);
+                    claimed.push(uuid);
+                }
+            }
+        }
+        (unique, claimed)
+    }
+
+    /// Releases uuids claimed by `filter_duplicates`, so that their retries are accepted.
+    pub async fn release(&self, token: &str, uuids: &[Uuid]) {
+        if uuids.is_empty() {
+            return;
+        }
+        let keys: Vec<String> = uuids.iter().map(|uuid| self.key(token, uuid)).collect();
+        for key in &keys {
+            self.seen.invalidate(key);
+        }
+        if let Err(err) = self.redis.del_many(keys).await {
+            tracing::warn!("failed to release event uuids: {}", err);
+        }
+    }
+
+    // Keyed by token too, as uuids are only unique within a project
+    fn key(&self, token: &str, uuid: &Uuid) -> String {
+        format!("{}{}:{}", self.key_prefix, token, uuid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use common_redis::{CustomRedisError, MockRedisClient};
+    use common_types::RawEvent;
+    use uuid::Uuid;
+
+    use super::{EventDeduplicator, DEDUP_KEY_PREFIX};
+
+    fn event(uuid: Option<Uuid>) -> RawEvent {
+        RawEvent {
+            event: "e".to_string(),
+            uuid,
+            ..Default::default()
+        }
+    }
+
+    fn key(uuid: Uuid) -> String {
+        format!("{DEDUP_KEY_PREFIX}token:{uuid}")
+    }
+
+    #[tokio::test]
+    async fn drops_duplicates() {
+        let (new, retried) = (Uuid::now_v7(), Uuid::now_v7());
+        let redis = MockRedisClient::new()
+            .set_nx_ex_ret(&key(new), Ok(true))
+            .set_nx_ex_ret(&key(retried), Ok(false));
+        let dedup =
+            EventDeduplicator::new(Arc::new(redis.clone()), Duration::from_secs(600), 100, None);
+
+        let events = vec![
+            event(Some(new)),
+            event(Some(retried)),
+            event(None),
+            event(Some(new)), // Duplicated in the batch, only claimed once
+        ];
+        let (unique, claimed) = dedup.filter_duplicates("token", &events).await;
+
+        let uuids: Vec<Option<Uuid>> = unique.iter().map(|e| e.uuid).collect();
+        assert_eq!(vec![Some(new), None], uuids);
+        assert_eq!(vec![new], claimed);
+        let (unique, _) = dedup
+            .check_duplicates("token", &events.iter().collect::<Vec<_>>())
+            .await;
+        assert_eq!(vec![false, false, true, false], unique);
+        // The batch is claimed in a single pipeline
+        assert_eq!(1, redis.get_calls().len());
+
+        // Already claimed uuids are caught by the local cache
+        let (unique, _) = dedup.filter_duplicates("token", &events[..1]).await;
+        assert!(unique.is_empty());
+        assert_eq!(1, redis.get_calls().len());
+
+        // Released uuids are checked against redis again
+        dedup.release("token", &claimed).await;
+        assert_eq!(2, redis.get_calls().len());
+        let (unique, _) = dedup.filter_duplicates("token", &events[..1]).await;
+        assert_eq!(1, unique.len());
+    }
+
+    #[tokio::test]
+    async fn lets_events_through_if_redis_fails() {
+        let (first, second) = (Uuid::now_v7(), Uuid::now_v7());
+        let redis = MockRedisClient::new()
+            .set_nx_ex_ret(&key(first), Ok(true))
+            .set_nx_ex_ret(&key(second), Err(CustomRedisError::Timeout));
+        let dedup = EventDeduplicator::new(Arc::new(redis), Duration::from_secs(600), 100, None);
+
+        let events = vec![event(Some(first)), event(Some(second))];
+        let (unique, claimed) = dedup.filter_duplicates("token", &events).await;
+        assert_eq!(2, unique.len());
+        assert_eq!(vec![first, second], claimed);
+    }
+}
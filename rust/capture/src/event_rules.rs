@@ -0,0 +1,397 @@
+//! Per-token sampling and drop rules, applied to events before they reach the sink.
+//!
+//! Rules are loaded from a redis hash, mapping each token to a JSON list of rules:
+//!
+//! ```json
+//! [
+//!     {"id": "sample-pageviews", "event": "$pageview", "sample_rate": 0.1},
+//!     {"id": "drop-staging", "property": "$current_url", "matches": "^https?://staging\\."},
+//!     {"id": "drop-autocapture", "event": "$autocapture"}
+//! ]
+//! ```
+//!
+//! A rule matches events with the given `event` name, if set, and whose `property` value
+//! matches the `matches` regex, if set. Matching events are dropped, or kept with the
+//! probability given by `sample_rate`. The first matching rule decides, other events are kept.
+//!
+//! Sampling is deterministic on the rule id and the event's distinct_id, so that all the events
+//! of a person are either kept or dropped, on any capture instance. Events without one are
+//! sampled on their uuid, or else at random.
+//!
+//! Rule ids are used as metric labels, so they must be short slugs of lowercase letters,
+//! digits, `-` and `_`, and only the first few rules of each token are loaded.
+//!
+//! Invalid rules are skipped, and the last loaded rules are kept if redis is unavailable.
+
+use std::collections::HashMap;
+use std::hash::Hasher;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use common_redis::{Client, CustomRedisError};
+use common_types::RawEvent;
+use metrics::{counter, gauge};
+use regex::Regex;
+use serde::Deserialize;
+use serde_json::Value;
+use siphasher::sip::SipHasher13;
+
+pub const EVENT_RULES_CACHE_KEY: &str = "@posthog/capture-event-rules";
+
+const MAX_RULES_PER_TOKEN: usize = 10;
+const MAX_RULE_ID_LENGTH: usize = 32;
+
+#[derive(Debug, Deserialize)]
+struct RuleConfig {
+    id: String,
+    event: Option<String>,
+    property: Option<String>,
+    matches: Option<String>,
+    sample_rate: Option<f64>,
+}
+
+#[derive(Debug)]
+struct PropertyMatch {
+    property: String,
+    pattern: Regex,
+}
+
+#[derive(Debug)]
+struct EventRule {
+    id: String,
+    event: Option<String>,
+    property: Option<PropertyMatch>,
+    // Share of the matching events to keep, none are kept if unset
+    sample_rate: Option<f64>,
+}
+
+impl EventRule {
+    fn parse(config: RuleConfig) -> Result<Self, String> {
+        if !is_valid_id(&config.id) {
+            return Err(format!(
+                "id must be a slug of at most {} lowercase letters, digits, - or _",
+                MAX_RULE_ID_LENGTH
+            ));
+        }
+        let property = match (config.property, config.matches) {
+            (Some(property), Some(matches)) => Some(PropertyMatch {
+                property,
+                pattern: Regex::new(&matches).map_err(|e| e.to_string())?,
+            }),
+            (None, None) => None,
+            _ => return Err(String::from("property and matches must be set together")),
+        };
+        if config.event.is_none() && property.is_none() {
+            return Err(String::from("rule would match all events"));
+        }
+        if let Some(rate) = config.sample_rate {
+            if !(0.0..=1.0).contains(&rate) {
+                return Err(format!("sample_rate must be between 0 and 1, got {}", rate));
+            }
+        }
+        Ok(Self {
+            id: config.id,
+            event: config.event,
+            property,
+            sample_rate: config.sample_rate,
+        })
+    }
+
+    fn matches(&self, event: &RawEvent) -> bool {
+        if self.event.as_ref().is_some_and(|name| *name != event.event) {
+            return false;
+        }
+        let Some(PropertyMatch { property, pattern }) = &self.property else {
+            return true;
+        };
+        match event.properties.get(property) {
+            Some(Value::String(value)) => pattern.is_match(value),
+            Some(Value::Null) | None => false,
+            Some(value) => pattern.is_match(&value.to_string()),
+        }
+    }
+
+    fn keep(&self, event: &RawEvent) -> bool {
+        let Some(rate) = self.sample_rate else {
+            return false;
+        };
+        let mut hasher = SipHasher13::new();
+        hasher.write(self.id.as_bytes());
+        hasher.write_u8(b'.');
+        match (&event.distinct_id, event.uuid) {
+            (Some(Value::String(distinct_id)), _) => hasher.write(distinct_id.as_bytes()),
+            (Some(distinct_id), _) if !distinct_id.is_null() => {
+                hasher.write(distinct_id.to_string().as_bytes())
+            }
+            (_, Some(uuid)) => hasher.write(uuid.as_bytes()),
+            _ => return rand::random::<f64>() < rate,
+        }
+        // The top 53 bits, as a float in [0, 1)
+        let sample = (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64;
+        sample < rate
+    }
+}
+
+fn is_valid_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_RULE_ID_LENGTH
+        && id
+            .bytes()
+            .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-' || b == b'_')
+}
+
+#[derive(Default, Clone)]
+pub struct EventRules {
+    rules: Arc<RwLock<HashMap<String, Arc<Vec<EventRule>>>>>,
+}
+
+impl EventRules {
+    /// Parses the rules of a token, skipping the invalid ones and those over the limit.
+    fn parse_rules(token: &str, rules: &str) -> Vec<EventRule> {
+        let mut configs: Vec<RuleConfig> = match serde_json::from_str(rules) {
+            Ok(configs) => configs,
+            Err(e) => {
+                tracing::warn!("invalid event rules for token {}: {}", token, e);
+                return Vec::new();
+            }
+        };
+        if configs.len() > MAX_RULES_PER_TOKEN {
+            tracing::warn!(
+                "token {} has {} event rules, only the first {} are applied",
+                token,
+                configs.len(),
+                MAX_RULES_PER_TOKEN
+            );
+            configs.truncate(MAX_RULES_PER_TOKEN);
+        }
+        configs
+            .into_iter()
+            .filter_map(|config| {
+                let id = config.id.clone();
+                EventRule::parse(config)
+                    .inspect_err(|e| {
+                        tracing::warn!("invalid event rule {} for token {}: {}", id, token, e)
+                    })
+                    .ok()
+            })
+            .collect()
+    }
+
+    fn rules_for(&self, token: &str) -> Option<Arc<Vec<EventRule>>> {
+        match self.rules.read() {
+            Ok(rules) => rules.get(token).cloned(),
+            Err(poisoned) => poisoned.into_inner().get(token).cloned(),
+        }
+    }
+
+    /// Returns the events to keep, after applying the rules of the token.
+    pub fn filter<'a>(&self, token: &str, events: Vec<&'a RawEvent>) -> Vec<&'a RawEvent> {
+        let Some(rules) = self.rules_for(token) else {
+            return events;
+        };
+        events
+            .into_iter()
+            .filter(|event| Self::apply(&rules, event))
+            .collect()
+    }
+
+    /// Same as `filter`, for a single event.
+    pub fn keep(&self, token: &str, event: &RawEvent) -> bool {
+        match self.rules_for(token) {
+            Some(rules) => Self::apply(&rules, event),
+            None => true,
+        }
+    }
+
+    /// Same as `keep` without reporting to the metrics, for dry-runs: returns the id of the
+    /// rule dropping the event, if any.
+    pub fn dropping_rule(&self, token: &str, event: &RawEvent) -> Option<String> {
+        let rules = self.rules_for(token)?;
+        let rule = rules.iter().find(|rule| rule.matches(event))?;
+        (!rule.keep(event)).then(|| rule.id.clone())
+    }
+
+    fn apply(rules: &[EventRule], event: &RawEvent) -> bool {
+        let Some(rule) = rules.iter().find(|rule| rule.matches(event)) else {
+            return true;
+        };
+        let keep = rule.keep(event);
+        let outcome = match keep {
+            true => "kept",
+            false => "dropped",
+        };
+        counter!("capture_event_rules_total", "rule" => rule.id.clone(), "outcome" => outcome)
+            .increment(1);
+        keep
+    }
+
+    /// Reloads the rules from the given redis hash every `interval`, needs to be spawned
+    /// in a separate task.
+    pub async fn refresh(
+        &self,
+        redis: Arc<dyn Client + Send + Sync>,
+        key: String,
+        interval: Duration,
+    ) {
+        let mut interval = tokio::time::interval(interval);
+        loop {
+            interval.tick().await;
+
+            let entries = match redis.hgetall(key.clone()).await {
+                Ok(entries) => entries,
+                Err(CustomRedisError::NotFound) => HashMap::new(),
+                Err(e) => {
+                    tracing::error!("Failed to update event rules from Redis: {:?}", e);
+                    continue;
+                }
+            };
+            let loaded: HashMap<String, Arc<Vec<EventRule>>> = entries
+                .iter()
+                .map(|(token, rules)| (token.clone(), Self::parse_rules(token, rules)))
+                .filter(|(_, rules)| !rules.is_empty())
+                .map(|(token, rules)| (token, Arc::new(rules)))
+                .collect();
+            gauge!("capture_event_rules_loaded_tokens").set(loaded.len() as f64);
+            match self.rules.write() {
+                Ok(mut rules) => *rules = loaded,
+                Err(poisoned) => *poisoned.into_inner() = loaded,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use common_redis::MockRedisClient;
+    use common_types::RawEvent;
+    use serde_json::json;
+
+    use super::{EventRule, EventRules, RuleConfig, EVENT_RULES_CACHE_KEY};
+
+    fn event(name: &str, url: &str) -> RawEvent {
+        RawEvent {
+            event: name.to_string(),
+            properties: HashMap::from([("$current_url".to_string(), json!(url))]),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn skips_invalid_rules() {
+        let rules = json!([
+            {"id": "valid", "event": "$autocapture"},
+            {"id": "all-events", "sample_rate": 0.5},
+            {"id": "bad-regex", "property": "$current_url", "matches": "("},
+            {"id": "no-pattern", "property": "$current_url"},
+            {"id": "bad-rate", "event": "$pageview", "sample_rate": 2.0},
+            {"id": "Not a slug!", "event": "$pageview"},
+            {"id": "", "event": "$pageview"},
+            {"id": "a".repeat(33), "event": "$pageview"},
+        ]);
+        let parsed = EventRules::parse_rules("token", &rules.to_string());
+        assert_eq!(1, parsed.len());
+        assert_eq!("valid", parsed[0].id);
+
+        assert!(EventRules::parse_rules("token", "not json").is_empty());
+    }
+
+    #[test]
+    fn limits_rules_per_token() {
+        let rules: Vec<_> = (0..15)
+            .map(|i| json!({"id": format!("rule-{}", i), "event": "$pageview"}))
+            .collect();
+        let parsed = EventRules::parse_rules("token", &json!(rules).to_string());
+        assert_eq!(10, parsed.len());
+        assert_eq!("rule-9", parsed[9].id);
+    }
+
+    #[tokio::test]
+    async fn applies_rules_from_redis() {
+        let rules = json!([
+            {"id": "keep-pageviews", "event": "$pageview", "sample_rate": 1.0},
+            {"id": "drop-staging", "property": "$current_url", "matches": "^https://staging\\."},
+            {"id": "drop-autocapture", "event": "$autocapture"},
+        ]);
+        let client = MockRedisClient::new().hgetall_ret(
+            EVENT_RULES_CACHE_KEY,
+            HashMap::from([("token".to_string(), rules.to_string())]),
+        );
+
+        let event_rules = EventRules::default();
+        let refreshed = event_rules.clone();
+        tokio::spawn(async move {
+            refreshed
+                .refresh(
+                    Arc::new(client),
+                    EVENT_RULES_CACHE_KEY.to_string(),
+                    Duration::from_secs(60),
+                )
+                .await;
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let events = [
+            event("$pageview", "https://staging.example.com"),
+            event("$autocapture", "https://example.com"),
+            event("clicked", "https://staging.example.com"),
+            event("clicked", "https://example.com"),
+        ];
+        let kept: Vec<&str> = event_rules
+            .filter("token", events.iter().collect())
+            .iter()
+            .map(|e| e.event.as_str())
+            .collect();
+        // The first matching rule wins, keeping the staging pageview
+        assert_eq!(vec!["$pageview", "clicked"], kept);
+        assert!(!event_rules.keep("token", &events[1]));
+        assert!(event_rules.keep("token", &events[3]));
+        assert_eq!(
+            Some("drop-autocapture".to_string()),
+            event_rules.dropping_rule("token", &events[1])
+        );
+        assert_eq!(None, event_rules.dropping_rule("token", &events[0]));
+
+        // Other tokens are not affected
+        assert_eq!(
+            4,
+            event_rules.filter("other", events.iter().collect()).len()
+        );
+    }
+
+    #[test]
+    fn samples_on_distinct_id() {
+        let rule = |id: &str| {
+            EventRule::parse(RuleConfig {
+                id: id.to_string(),
+                event: Some("$pageview".to_string()),
+                property: None,
+                matches: None,
+                sample_rate: Some(0.5),
+            })
+            .unwrap()
+        };
+        let (pageviews, other_rule) = (rule("sample-pageviews"), rule("other-rule"));
+        let pageview = |distinct_id: String| RawEvent {
+            event: "$pageview".to_string(),
+            distinct_id: Some(json!(distinct_id)),
+            ..Default::default()
+        };
+
+        let events: Vec<RawEvent> = (0..1000).map(|i| pageview(format!("user-{}", i))).collect();
+        let kept: Vec<bool> = events.iter().map(|e| pageviews.keep(e)).collect();
+        // The same persons are kept every time, about half of them
+        assert_eq!(
+            kept,
+            events.iter().map(|e| pageviews.keep(e)).collect::<Vec<_>>()
+        );
+        let kept_count = kept.iter().filter(|keep| **keep).count();
+        assert!((400..600).contains(&kept_count), "kept {}", kept_count);
+        // While each rule samples its own persons
+        let other_kept: Vec<bool> = events.iter().map(|e| other_rule.keep(e)).collect();
+        assert_ne!(kept, other_kept);
+    }
+}
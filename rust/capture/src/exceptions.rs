@@ -1,6 +1,23 @@
-pub trait Exception {
-    fn fingerprint(&self) -> String;
-}
+//! Structural validation of `$exception` events, for the dedicated exception endpoint.
+//!
+//! The `$exception_list` property is checked against the shapes cymbal processes: each
+//! exception has a `type` and a `value`, and its optional stack trace holds frames tagged
+//! with a supported `platform` and carrying that platform's required fields. Payloads are
+//! also bounded in size, so that broken SDKs get a clear error instead of failing in cymbal.
+
+use common_types::RawEvent;
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::api::CaptureError;
+
+pub const EXCEPTION_EVENT: &str = "$exception";
+pub const EXCEPTION_LIST_PROPERTY: &str = "$exception_list";
+
+const MAX_EXCEPTIONS: usize = 32; // Chained exceptions, from the outermost cause
+const MAX_FRAMES: usize = 1024;
+const MAX_TYPE_BYTES: usize = 1024;
+const MAX_VALUE_BYTES: usize = 64 * 1024;
 
 pub trait Stacked {
     type Frame;
@@ -9,3 +26,231 @@ pub trait Stacked {
     fn stack(&self) -> Vec<Self::Frame>;
     fn lang_hint(&self) -> String;
 }
+
+#[derive(Debug, Deserialize)]
+pub struct RawException {
+    #[serde(rename = "type")]
+    pub exception_type: String,
+    #[serde(rename = "value")]
+    pub exception_message: String,
+    #[serde(rename = "stacktrace")]
+    pub stack: Option<RawStacktrace>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum RawStacktrace {
+    Raw { frames: Vec<RawFrame> },
+    Resolved { frames: Vec<Value> }, // Already resolved by the SDK, passed through by cymbal
+}
+
+// Same platform tags as cymbal's frames, with their required fields
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "platform")]
+pub enum RawFrame {
+    #[serde(rename = "python")]
+    Python(ServerFrame),
+    #[serde(rename = "node:javascript")]
+    JavaScriptNode(ServerFrame),
+    #[serde(rename = "web:javascript", alias = "javascript")]
+    JavaScriptWeb(WebFrame),
+}
+
+impl RawFrame {
+    fn platform(&self) -> &'static str {
+        match self {
+            RawFrame::Python(_) => "python",
+            RawFrame::JavaScriptNode(_) => "node:javascript",
+            RawFrame::JavaScriptWeb(_) => "web:javascript",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ServerFrame {
+    pub filename: String,
+    pub function: String,
+    pub lineno: Option<u32>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct WebFrame {
+    pub filename: Option<String>,
+    pub function: String,
+    pub in_app: bool,
+    pub lineno: Option<u32>,
+    pub colno: Option<u32>,
+}
+
+impl Stacked for RawException {
+    type Frame = RawFrame;
+
+    fn raw_ident(&self) -> String {
+        self.exception_type.clone()
+    }
+
+    fn stack(&self) -> Vec<Self::Frame> {
+        match &self.stack {
+            Some(RawStacktrace::Raw { frames }) => frames.clone(),
+            _ => Vec::new(),
+        }
+    }
+
+    fn lang_hint(&self) -> String {
+        match &self.stack {
+            Some(RawStacktrace::Raw { frames }) => frames.first().map(RawFrame::platform),
+            _ => None,
+        }
+        .unwrap_or("unknown")
+        .to_string()
+    }
+}
+
+/// Checks that the event is an `$exception` with a well-formed `$exception_list`, and
+/// returns the parsed exceptions.
+pub fn validate_exception(event: &RawEvent) -> Result<Vec<RawException>, CaptureError> {
+    let invalid = CaptureError::InvalidException;
+    if event.event != EXCEPTION_EVENT {
+        return Err(invalid(format!(
+            "expected an {} event, got {}",
+            EXCEPTION_EVENT, event.event
+        )));
+    }
+    let list = match event.properties.get(EXCEPTION_LIST_PROPERTY) {
+        Some(Value::Array(list)) => list,
+        Some(_) => return Err(invalid(format!("{EXCEPTION_LIST_PROPERTY} is not a list"))),
+        None => return Err(invalid(format!("missing {EXCEPTION_LIST_PROPERTY}"))),
+    };
+    if list.is_empty() || list.len() > MAX_EXCEPTIONS {
+        return Err(invalid(format!(
+            "{EXCEPTION_LIST_PROPERTY} must hold between 1 and {MAX_EXCEPTIONS} exceptions, got {}",
+            list.len()
+        )));
+    }
+
+    let mut exceptions = Vec::with_capacity(list.len());
+    for (i, exception) in list.iter().enumerate() {
+        let exception = RawException::deserialize(exception)
+            .map_err(|e| invalid(format!("exception {i}: {e}")))?;
+        if exception.exception_type.len() > MAX_TYPE_BYTES {
+            return Err(invalid(format!(
+                "exception {i}: type is longer than {MAX_TYPE_BYTES} bytes"
+            )));
+        }
+        if exception.exception_message.len() > MAX_VALUE_BYTES {
+            return Err(invalid(format!(
+                "exception {i}: value is longer than {MAX_VALUE_BYTES} bytes"
+            )));
+        }
+        let frames = match &exception.stack {
+            Some(RawStacktrace::Raw { frames }) => frames.len(),
+            Some(RawStacktrace::Resolved { frames }) => frames.len(),
+            None => 0,
+        };
+        if frames > MAX_FRAMES {
+            return Err(invalid(format!(
+                "exception {i}: stack trace has more than {MAX_FRAMES} frames"
+            )));
+        }
+        exceptions.push(exception);
+    }
+    Ok(exceptions)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use common_types::RawEvent;
+    use serde_json::{json, Value};
+
+    use super::{validate_exception, Stacked};
+    use crate::api::CaptureError;
+
+    fn exception_event(exception_list: Value) -> RawEvent {
+        RawEvent {
+            event: "$exception".to_string(),
+            properties: HashMap::from([("$exception_list".to_string(), exception_list)]),
+            ..Default::default()
+        }
+    }
+
+    fn error_message(exception_list: Value) -> String {
+        match validate_exception(&exception_event(exception_list)) {
+            Err(CaptureError::InvalidException(reason)) => reason,
+            other => panic!("expected an invalid exception, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn accepts_valid_exceptions() {
+        let exceptions = validate_exception(&exception_event(json!([
+            {
+                "type": "TypeError",
+                "value": "undefined is not a function",
+                "mechanism": {"handled": false},
+                "stacktrace": {"type": "raw", "frames": [
+                    {"platform": "web:javascript", "filename": "https://example.com/app.js",
+                     "function": "onClick", "in_app": true, "lineno": 1, "colno": 42},
+                    {"platform": "javascript", "function": "dispatch", "in_app": false},
+                ]},
+            },
+            {
+                "type": "ValueError",
+                "value": "bad value",
+                "stacktrace": {"type": "raw", "frames": [
+                    {"platform": "python", "filename": "app.py", "function": "main", "lineno": 3},
+                ]},
+            },
+            {"type": "Error", "value": "no stack"},
+        ])))
+        .expect("exceptions should be valid");
+
+        assert_eq!(3, exceptions.len());
+        assert_eq!("TypeError", exceptions[0].raw_ident());
+        assert_eq!(2, exceptions[0].stack().len());
+        assert_eq!("web:javascript", exceptions[0].lang_hint());
+        assert_eq!("python", exceptions[1].lang_hint());
+        assert_eq!("unknown", exceptions[2].lang_hint());
+    }
+
+    #[test]
+    fn rejects_malformed_exceptions() {
+        assert!(error_message(json!([])).contains("between 1 and 32"));
+        assert!(error_message(json!({"type": "Error"})).contains("not a list"));
+        assert!(error_message(json!([{"value": "no type"}])).contains("missing field `type`"));
+
+        let frame = |frame: Value| {
+            json!([{
+                "type": "Error",
+                "value": "message",
+                "stacktrace": {"type": "raw", "frames": [frame]},
+            }])
+        };
+        assert!(
+            error_message(frame(json!({"platform": "ruby", "function": "f"})))
+                .contains("unknown variant `ruby`")
+        );
+        assert!(
+            error_message(frame(json!({"platform": "python", "function": "f"})))
+                .contains("missing field `filename`")
+        );
+        assert!(error_message(frame(
+            json!({"platform": "web:javascript", "function": "f", "in_app": "yes"})
+        ))
+        .contains("exception 0"));
+
+        let long_value = "x".repeat(64 * 1024 + 1);
+        assert!(
+            error_message(json!([{"type": "Error", "value": long_value}]))
+                .contains("value is longer")
+        );
+
+        let mut event = exception_event(json!([{"type": "Error", "value": "message"}]));
+        event.event = "$pageview".to_string();
+        assert!(validate_exception(&event).is_err());
+        event.properties.clear();
+        event.event = "$exception".to_string();
+        assert!(validate_exception(&event).is_err());
+    }
+}
@@ -0,0 +1,172 @@
+//! Ingestion warnings for the events dropped by capture.
+//!
+//! Invalid events are dropped one by one instead of failing their whole request. Each of them
+//! is replaced by a `$$client_ingestion_warning` event, routed to the ingestion warnings topic,
+//! holding the reason and a truncated copy of the payload. The plugin-server then surfaces it
+//! as an ingestion warning to the project's users.
+
+use std::collections::HashMap;
+
+use common_types::{CapturedEvent, RawEvent};
+use serde_json::Value;
+
+use crate::api::CaptureError;
+use crate::utils::uuid_v7;
+use crate::v0_request::{DataType, ProcessedEvent, ProcessedEventMetadata, ProcessingContext};
+
+pub const INGESTION_WARNING_EVENT: &str = "$$client_ingestion_warning";
+
+// Used when the dropped event does not hold a usable distinct_id
+const FALLBACK_DISTINCT_ID: &str = "$capture_ingestion_warning";
+
+const MAX_PAYLOAD_CHARS: usize = 1024;
+
+/// Short reason for dropping an event, also used as the `cause` of the dropped events metric.
+pub fn drop_reason(err: &CaptureError) -> &'static str {
+    match err {
+        CaptureError::MissingDistinctId => "missing_distinct_id",
+        CaptureError::MissingEventName => "missing_event_name",
+        CaptureError::InvalidCookielessMode => "invalid_cookieless_mode",
+        CaptureError::InvalidEvent(_) => "invalid_event",
+        CaptureError::InvalidException(_) => "invalid_exception",
+        _ => "process_events_error",
+    }
+}
+
+/// Builds the ingestion warning reporting that the event in `payload` was dropped because of `err`.
+pub fn dropped_event_warning(
+    err: &CaptureError,
+    payload: &Value,
+    context: &ProcessingContext,
+) -> Result<ProcessedEvent, CaptureError> {
+    let distinct_id = payload_distinct_id(payload);
+    let truncated_payload = truncate(payload.to_string(), MAX_PAYLOAD_CHARS);
+
+    let properties = HashMap::from([
+        (
+            "$$client_ingestion_warning_message".to_string(),
+            Value::String(format!("Event dropped by capture: {}", err)),
+        ),
+        (
+            "$$capture_drop_reason".to_string(),
+            Value::String(drop_reason(err).to_string()),
+        ),
+        (
+            "$$capture_dropped_payload".to_string(),
+            Value::String(truncated_payload),
+        ),
+    ]);
+    let warning = RawEvent {
+        event: INGESTION_WARNING_EVENT.to_string(),
+        distinct_id: Some(Value::String(distinct_id.clone())),
+        properties,
+        ..Default::default()
+    };
+    let data = serde_json::to_string(&warning).map_err(|e| {
+        tracing::error!("failed to encode ingestion warning: {}", e);
+        CaptureError::NonRetryableSinkError
+    })?;
+
+    Ok(ProcessedEvent {
+        metadata: ProcessedEventMetadata {
+            data_type: DataType::ClientIngestionWarning,
+            session_id: None,
+        },
+        event: CapturedEvent {
+            uuid: uuid_v7(),
+            distinct_id,
+            ip: context.client_ip.clone(),
+            data,
+            now: context.now.clone(),
+            sent_at: context.sent_at,
+            token: context.token.clone(),
+            is_cookieless_mode: false,
+        },
+    })
+}
+
+fn payload_distinct_id(payload: &Value) -> String {
+    let value = ["distinct_id", "$distinct_id"]
+        .iter()
+        .find_map(|key| payload.get(key))
+        .or_else(|| payload.get("properties")?.get("distinct_id"));
+    let distinct_id = match value {
+        None | Some(Value::Null) => return FALLBACK_DISTINCT_ID.to_string(),
+        Some(Value::String(id)) => id.clone(),
+        Some(id) => id.to_string(),
+    };
+    match distinct_id.is_empty() {
+        true => FALLBACK_DISTINCT_ID.to_string(),
+        false => truncate(distinct_id, 200),
+    }
+}
+
+fn truncate(value: String, max_chars: usize) -> String {
+    match value.char_indices().nth(max_chars) {
+        None => value,
+        Some((index, _)) => value[..index].to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use common_types::RawEvent;
+    use serde_json::{json, Value};
+
+    use super::{dropped_event_warning, INGESTION_WARNING_EVENT};
+    use crate::api::CaptureError;
+    use crate::v0_request::{DataType, ProcessingContext};
+
+    fn context() -> ProcessingContext {
+        ProcessingContext {
+            lib_version: None,
+            user_agent: None,
+            sent_at: None,
+            token: "my_token".to_string(),
+            now: "2024-01-01T00:00:00Z".to_string(),
+            client_ip: "127.0.0.1".to_string(),
+            historical_migration: false,
+            server_hashed: HashSet::new(),
+        }
+    }
+
+    #[test]
+    fn builds_warning() {
+        let payload = json!({"event": "e", "distinct_id": "id1", "uuid": "not-a-uuid", "properties": {"big": "a".repeat(2000)}});
+        let warning = dropped_event_warning(
+            &CaptureError::InvalidEvent("invalid uuid".to_string()),
+            &payload,
+            &context(),
+        )
+        .expect("failed to build warning");
+
+        assert_eq!(DataType::ClientIngestionWarning, warning.metadata.data_type);
+        assert_eq!("my_token", warning.event.token);
+        assert_eq!("id1", warning.event.distinct_id);
+
+        let data: RawEvent = serde_json::from_str(&warning.event.data).expect("invalid data");
+        assert_eq!(INGESTION_WARNING_EVENT, data.event);
+        assert_eq!(
+            Some(&Value::String("invalid_event".to_string())),
+            data.properties.get("$$capture_drop_reason")
+        );
+        let dropped = data.properties["$$capture_dropped_payload"]
+            .as_str()
+            .expect("missing payload");
+        assert_eq!(1024, dropped.chars().count());
+        assert!(dropped.starts_with('{'));
+    }
+
+    #[test]
+    fn falls_back_on_missing_distinct_id() {
+        let warning = dropped_event_warning(
+            &CaptureError::MissingDistinctId,
+            &json!({"event": "e"}),
+            &context(),
+        )
+        .expect("failed to build warning");
+        assert_eq!("$capture_ingestion_warning", warning.event.distinct_id);
+    }
+}
@@ -1,12 +1,27 @@
 pub mod api;
+pub mod binary_request;
+pub mod compat_endpoint;
 pub mod config;
+pub mod cookieless;
+pub mod dedup;
+pub mod event_rules;
+pub mod exceptions;
+pub mod ingestion_warnings;
+pub mod lzstring;
+pub mod mixpanel_request;
+pub mod otlp_endpoint;
+pub mod otlp_request;
 pub mod prometheus;
 pub mod router;
+pub mod segment_request;
 pub mod server;
 pub mod sinks;
 pub mod test_endpoint;
 pub mod time;
 pub mod token;
+pub mod token_limits;
 pub mod utils;
 pub mod v0_endpoint;
 pub mod v0_request;
+pub mod v1_endpoint;
+pub mod v1_request;
@@ -0,0 +1,172 @@
+//! Decoder for the `lz64` compression used by older posthog-js versions: the payload is
+//! compressed with lz-string (https://github.com/pieroxy/lz-string) and encoded with its
+//! `compressToBase64` variant.
+//!
+//! lz-string works on JS strings, we therefore decode to UTF-16 code units, and convert
+//! back to utf8 at the end.
+use crate::api::CaptureError;
+
+const BASE64_ALPHABET: &[u8; 65] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/=";
+
+/// Reads the compressed bitstream, each input character holding `log2(reset_value) + 1` bits.
+struct BitReader {
+    input: Vec<u32>,
+    reset_value: u32,
+    value: u32,
+    position: u32,
+    index: usize,
+}
+
+impl BitReader {
+    fn new(input: Vec<u32>, reset_value: u32) -> Self {
+        let value = input.first().copied().unwrap_or_default();
+        Self {
+            input,
+            reset_value,
+            value,
+            position: reset_value,
+            index: 1,
+        }
+    }
+
+    fn read_bits(&mut self, count: u32) -> u32 {
+        let mut bits = 0;
+        for power in 0..count {
+            let bit = self.value & self.position;
+            self.position >>= 1;
+            if self.position == 0 {
+                self.position = self.reset_value;
+                // Reading past the end yields zeroes, like JS' `charAt` does
+                self.value = self.input.get(self.index).copied().unwrap_or_default();
+                self.index += 1;
+            }
+            if bit > 0 {
+                bits |= 1 << power;
+            }
+        }
+        bits
+    }
+
+    fn exhausted(&self) -> bool {
+        self.index > self.input.len()
+    }
+}
+
+/// Equivalent of lz-string's `decompressFromBase64`. Fails if the input is invalid, or
+/// if the decompressed payload exceeds `limit` bytes.
+pub fn decompress_from_base64(input: &str, limit: usize) -> Result<String, CaptureError> {
+    let invalid = || CaptureError::RequestDecodingError(String::from("invalid lz64 data"));
+
+    // Query-string decoding turns `+` into spaces, put them back
+    let input = input
+        .trim()
+        .chars()
+        .map(|c| if c == ' ' { '+' } else { c })
+        .map(|c| {
+            BASE64_ALPHABET
+                .iter()
+                .position(|a| *a as char == c)
+                .map(|p| p as u32)
+        })
+        .collect::<Option<Vec<u32>>>()
+        .ok_or_else(invalid)?;
+    if input.is_empty() {
+        return Err(invalid());
+    }
+
+    let mut reader = BitReader::new(input, 32);
+    let mut dictionary: Vec<Vec<u16>> = vec![vec![], vec![], vec![]];
+    let mut enlarge_in: u32 = 4;
+    let mut num_bits: u32 = 3;
+    let mut result: Vec<u16> = Vec::new();
+
+    let first = match reader.read_bits(2) {
+        0 => reader.read_bits(8) as u16,
+        1 => reader.read_bits(16) as u16,
+        _ => return Err(invalid()),
+    };
+    dictionary.push(vec![first]);
+    let mut previous = vec![first];
+    result.push(first);
+
+    loop {
+        if reader.exhausted() {
+            return Err(invalid());
+        }
+
+        let mut code = reader.read_bits(num_bits) as usize;
+        match code {
+            0 | 1 => {
+                let char_bits = if code == 0 { 8 } else { 16 };
+                let c = reader.read_bits(char_bits) as u16;
+                dictionary.push(vec![c]);
+                code = dictionary.len() - 1;
+                enlarge_in -= 1;
+            }
+            2 => break,
+            _ => {}
+        }
+
+        if enlarge_in == 0 {
+            enlarge_in = 1 << num_bits;
+            num_bits += 1;
+        }
+
+        let entry = if code < dictionary.len() {
+            dictionary[code].clone()
+        } else if code == dictionary.len() {
+            let mut entry = previous.clone();
+            entry.push(previous[0]);
+            entry
+        } else {
+            return Err(invalid());
+        };
+        result.extend_from_slice(&entry);
+        // There are never more code units than utf8 bytes, bail early on large payloads
+        if result.len() > limit {
+            tracing::error!("LZ64 decompression limit reached");
+            return Err(CaptureError::EventTooBig);
+        }
+
+        let mut new_entry = previous;
+        new_entry.push(entry[0]);
+        dictionary.push(new_entry);
+        enlarge_in -= 1;
+        previous = entry;
+
+        if enlarge_in == 0 {
+            enlarge_in = 1 << num_bits;
+            num_bits += 1;
+        }
+    }
+
+    let decoded = String::from_utf16(&result).map_err(|_| invalid())?;
+    if decoded.len() > limit {
+        tracing::error!("LZ64 decompression limit reached");
+        return Err(CaptureError::EventTooBig);
+    }
+    Ok(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::decompress_from_base64;
+    use crate::api::CaptureError;
+
+    #[test]
+    fn decompress_invalid_input() {
+        assert!(matches!(
+            decompress_from_base64("", 1024),
+            Err(CaptureError::RequestDecodingError(_))
+        ));
+        assert!(matches!(
+            decompress_from_base64("foo", 1024),
+            Err(CaptureError::RequestDecodingError(_))
+        ));
+        assert!(matches!(
+            decompress_from_base64("not base64!", 1024),
+            Err(CaptureError::RequestDecodingError(_))
+        ));
+    }
+}
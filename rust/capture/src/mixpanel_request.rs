@@ -0,0 +1,288 @@
+//! Translation of Mixpanel ingestion API payloads into PostHog events.
+//!
+//! `/track` events keep their properties, minus the `token`, `distinct_id` and `time` fields
+//! that are mapped to the event itself. Event names are mapped like in the Mixpanel importer.
+//!
+//! `/engage` profile updates become `$set` events: `$set` and `$set_once` are passed through,
+//! `$unset` is passed as the `$unset` property. Other operations are not supported, and these
+//! updates are dropped as invalid events.
+
+use std::collections::HashMap;
+
+use base64::Engine;
+use common_types::mixpanel::map_event_names;
+use common_types::RawEvent;
+use serde::Deserialize;
+use serde_json::Value;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+use crate::api::CaptureError;
+use crate::v0_request::InvalidEvent;
+
+// Timestamps after this are in milliseconds (year 2286 in seconds)
+const MAX_SECONDS_TIMESTAMP: f64 = 10_000_000_000.0;
+
+const SUPPORTED_ENGAGE_OPERATIONS: [&str; 3] = ["$set", "$set_once", "$unset"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MixpanelEndpoint {
+    Track,
+    Engage,
+}
+
+#[derive(Deserialize)]
+pub struct MixpanelFormData {
+    pub data: Option<String>,
+}
+
+/// Parses the `data` payload, sent as JSON or base64 encoded JSON, into a list of messages.
+pub fn parse_data(data: &str) -> Result<Vec<Value>, CaptureError> {
+    let data = data.trim();
+    let json = match data.starts_with('[') || data.starts_with('{') {
+        true => data.as_bytes().to_vec(),
+        false => base64::engine::general_purpose::STANDARD
+            .decode(data)
+            .map_err(|e| {
+                tracing::error!("failed to decode mixpanel data: {}", e);
+                CaptureError::RequestDecodingError(String::from("invalid base64 data"))
+            })?,
+    };
+    match serde_json::from_slice(&json)? {
+        Value::Array(messages) => Ok(messages),
+        message @ Value::Object(_) => Ok(vec![message]),
+        _ => Err(CaptureError::RequestParsingError(String::from(
+            "expected a mixpanel message or a list of messages",
+        ))),
+    }
+}
+
+/// Translates the messages of a request, and returns the project token they hold.
+/// Invalid messages are returned separately, but all messages must hold the same token.
+pub fn mixpanel_events(
+    messages: Vec<Value>,
+    endpoint: MixpanelEndpoint,
+) -> Result<(String, Vec<RawEvent>, Vec<InvalidEvent>), CaptureError> {
+    let mut token: Option<String> = None;
+    let mut events = Vec::with_capacity(messages.len());
+    let mut invalid = Vec::new();
+    for payload in messages {
+        let message_token = match endpoint {
+            MixpanelEndpoint::Track => payload.get("properties").and_then(|p| p.get("token")),
+            MixpanelEndpoint::Engage => payload.get("$token"),
+        };
+        match (message_token.and_then(Value::as_str), &token) {
+            (Some(t), Some(token)) if t != token => return Err(CaptureError::MultipleTokensError),
+            (Some(t), None) => token = Some(t.to_string()),
+            _ => {}
+        }
+
+        let event = match endpoint {
+            MixpanelEndpoint::Track => track_event(&payload),
+            MixpanelEndpoint::Engage => engage_event(&payload),
+        };
+        match event {
+            Ok(event) => events.push(event),
+            Err(error) => invalid.push(InvalidEvent { payload, error }),
+        }
+    }
+    let token = token.ok_or(CaptureError::NoTokenError)?;
+    Ok((token, events, invalid))
+}
+
+#[derive(Deserialize)]
+struct MixpanelTrackEvent {
+    event: String,
+    #[serde(default)]
+    properties: HashMap<String, Value>,
+}
+
+fn track_event(payload: &Value) -> Result<RawEvent, CaptureError> {
+    let MixpanelTrackEvent {
+        event,
+        mut properties,
+    } = MixpanelTrackEvent::deserialize(payload)
+        .map_err(|e| CaptureError::InvalidEvent(e.to_string()))?;
+
+    properties.remove("token");
+    let distinct_id = properties.remove("distinct_id");
+    let timestamp = properties.remove("time").map(parse_time).transpose()?;
+
+    Ok(RawEvent {
+        distinct_id,
+        event: map_event_names(event),
+        properties,
+        timestamp,
+        ..Default::default()
+    })
+}
+
+fn engage_event(payload: &Value) -> Result<RawEvent, CaptureError> {
+    let Value::Object(update) = payload else {
+        return Err(CaptureError::InvalidEvent(String::from(
+            "expected a profile update object",
+        )));
+    };
+
+    let mut event = RawEvent {
+        event: "$set".to_string(),
+        distinct_id: update.get("$distinct_id").cloned(),
+        timestamp: update.get("$time").cloned().map(parse_time).transpose()?,
+        ..Default::default()
+    };
+    for (key, value) in update {
+        if !key.starts_with('$') || !is_operation(key) {
+            continue;
+        }
+        if !SUPPORTED_ENGAGE_OPERATIONS.contains(&key.as_str()) {
+            return Err(CaptureError::InvalidEvent(format!(
+                "unsupported profile operation: {}",
+                key
+            )));
+        }
+        let properties = || {
+            serde_json::from_value::<HashMap<String, Value>>(value.clone())
+                .map_err(|e| CaptureError::InvalidEvent(e.to_string()))
+        };
+        match key.as_str() {
+            "$set" => event.set = Some(properties()?),
+            "$set_once" => event.set_once = Some(properties()?),
+            _ => {
+                event.properties.insert(key.clone(), value.clone());
+            }
+        }
+    }
+    if let Some(ip) = update.get("$ip") {
+        event.properties.insert("$ip".to_string(), ip.clone());
+    }
+    Ok(event)
+}
+
+// Profile updates hold a single operation, next to the `$token`, `$distinct_id`,
+// `$ip`, `$time` and `$ignore_time` fields
+fn is_operation(key: &str) -> bool {
+    !matches!(
+        key,
+        "$token" | "$distinct_id" | "$ip" | "$time" | "$ignore_time" | "$ignore_alias"
+    )
+}
+
+// Mixpanel timestamps are in seconds, or milliseconds, since the epoch
+fn parse_time(value: Value) -> Result<String, CaptureError> {
+    let invalid = || CaptureError::InvalidEvent(format!("invalid time: {}", value));
+    let time = match &value {
+        Value::Number(n) => n.as_f64(),
+        Value::String(s) => s.parse::<f64>().ok(),
+        _ => None,
+    }
+    .ok_or_else(invalid)?;
+    // Rounded to the millisecond, to not carry floating point noise
+    let millis = match time < MAX_SECONDS_TIMESTAMP {
+        true => (time * 1000.0).round(),
+        false => time.round(),
+    };
+    OffsetDateTime::from_unix_timestamp_nanos(millis as i128 * 1_000_000)
+        .ok()
+        .and_then(|t| t.format(&Rfc3339).ok())
+        .ok_or_else(invalid)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::{mixpanel_events, parse_data, MixpanelEndpoint};
+    use crate::api::CaptureError;
+
+    #[test]
+    fn parses_data() {
+        let message = json!({"event": "e", "properties": {"token": "t"}});
+        let encoded = base64::Engine::encode(
+            &base64::engine::general_purpose::STANDARD,
+            message.to_string(),
+        );
+        assert_eq!(vec![message.clone()], parse_data(&encoded).unwrap());
+        assert_eq!(
+            vec![message.clone()],
+            parse_data(&json!([message]).to_string()).unwrap()
+        );
+        assert!(parse_data("not base64!").is_err());
+        assert!(parse_data("1").is_err());
+    }
+
+    #[test]
+    fn translates_track_events() {
+        let (token, events, invalid) = mixpanel_events(
+            vec![
+                json!({"event": "$mp_web_page_view", "properties": {
+                    "token": "my_token", "distinct_id": "user1", "time": 1704067200, "plan": "pro",
+                }}),
+                json!({"event": "clicked", "properties": {
+                    "token": "my_token", "distinct_id": "user1", "time": 1704067200123_i64,
+                }}),
+                json!({"properties": {"token": "my_token"}}),
+            ],
+            MixpanelEndpoint::Track,
+        )
+        .expect("failed to translate events");
+
+        assert_eq!("my_token", token);
+        assert_eq!(2, events.len());
+        assert_eq!("$pageview", events[0].event);
+        assert_eq!(Some(json!("user1")), events[0].distinct_id);
+        assert_eq!(
+            Some("2024-01-01T00:00:00Z".to_string()),
+            events[0].timestamp
+        );
+        assert_eq!(Some(&json!("pro")), events[0].properties.get("plan"));
+        assert_eq!(None, events[0].properties.get("token"));
+        assert_eq!(
+            Some("2024-01-01T00:00:00.123Z".to_string()),
+            events[1].timestamp
+        );
+        assert_eq!(1, invalid.len());
+
+        assert!(matches!(
+            mixpanel_events(
+                vec![
+                    json!({"event": "e", "properties": {"token": "a"}}),
+                    json!({"event": "e", "properties": {"token": "b"}}),
+                ],
+                MixpanelEndpoint::Track
+            ),
+            Err(CaptureError::MultipleTokensError)
+        ));
+        assert!(matches!(
+            mixpanel_events(vec![json!({"event": "e"})], MixpanelEndpoint::Track),
+            Err(CaptureError::NoTokenError)
+        ));
+    }
+
+    #[test]
+    fn translates_profile_updates() {
+        let (token, events, invalid) = mixpanel_events(
+            vec![
+                json!({"$token": "my_token", "$distinct_id": "user1", "$set": {"plan": "pro"}}),
+                json!({"$token": "my_token", "$distinct_id": "user1", "$set_once": {"first": "a"}}),
+                json!({"$token": "my_token", "$distinct_id": "user1", "$unset": ["plan"]}),
+                json!({"$token": "my_token", "$distinct_id": "user1", "$add": {"count": 1}}),
+            ],
+            MixpanelEndpoint::Engage,
+        )
+        .expect("failed to translate updates");
+
+        assert_eq!("my_token", token);
+        assert_eq!(3, events.len());
+        assert!(events.iter().all(|e| e.event == "$set"));
+        assert_eq!(
+            Some(&json!("pro")),
+            events[0].set.as_ref().and_then(|set| set.get("plan"))
+        );
+        assert_eq!(
+            Some(&json!("a")),
+            events[1].set_once.as_ref().and_then(|set| set.get("first"))
+        );
+        assert_eq!(Some(&json!(["plan"])), events[2].properties.get("$unset"));
+        assert_eq!(1, invalid.len());
+    }
+}
@@ -0,0 +1,165 @@
+use std::collections::HashSet;
+
+use axum::debug_handler;
+use axum::extract::{MatchedPath, Query, State};
+use axum::http::{header, HeaderMap};
+use axum::response::{IntoResponse, Response};
+use axum_client_ip::InsecureClientIp;
+use bytes::Bytes;
+use metrics::counter;
+use serde::Deserialize;
+use tracing::instrument;
+
+use crate::api::CaptureError;
+use crate::otlp_request::{decode_logs_request, log_record_count, logs_to_events, OtlpEncoding};
+use crate::prometheus::report_dropped_events;
+use crate::router;
+use crate::token::{request_token, validate_token};
+use crate::v0_request::{decompress_bytes, Compression, ProcessingContext};
+
+#[derive(Deserialize, Default)]
+pub struct OtlpQuery {
+    #[serde(alias = "api_key")]
+    token: Option<String>,
+}
+
+/// OTLP/HTTP logs endpoint, taking protobuf or JSON `ExportLogsServiceRequest` payloads.
+///
+/// OTLP exporters can't add the token to the payload, so it is read from an
+/// `Authorization: Bearer <token>` header, or else from the `token` query parameter.
+/// Every log record is sent to the logs topic as a `$log` event, see `otlp_request`, unless
+/// dropped by the token's event rules or the token dropper.
+#[instrument(
+    skip_all,
+    fields(
+        token,
+        batch_size,
+        user_agent,
+        content_encoding,
+        content_type,
+        compression
+    )
+)]
+#[debug_handler]
+pub async fn logs(
+    state: State<router::State>,
+    InsecureClientIp(ip): InsecureClientIp,
+    meta: Query<OtlpQuery>,
+    headers: HeaderMap,
+    path: MatchedPath,
+    body: Bytes,
+) -> Result<Response, CaptureError> {
+    let user_agent = headers
+        .get("user-agent")
+        .map_or("unknown", |v| v.to_str().unwrap_or("unknown"));
+    let content_encoding = headers
+        .get("content-encoding")
+        .map_or("unknown", |v| v.to_str().unwrap_or("unknown"));
+    let content_type = headers
+        .get("content-type")
+        .map_or("", |v| v.to_str().unwrap_or(""));
+
+    tracing::Span::current().record("user_agent", user_agent);
+    tracing::Span::current().record("content_encoding", content_encoding);
+    tracing::Span::current().record("content_type", content_type);
+
+    let encoding = OtlpEncoding::from_content_type(content_type).ok_or_else(|| {
+        CaptureError::RequestDecodingError(format!("unsupported content type: {}", content_type))
+    })?;
+    let compression = Compression::from_content_encoding(content_encoding).unwrap_or_default();
+    tracing::Span::current().record("compression", compression.as_str());
+
+    let token = request_token(&headers, meta.token.as_deref())
+        .ok_or(CaptureError::NoTokenError)?
+        .to_string();
+
+    // Before decoding the payload, the request is counted as a single dropped event
+    if let Err(err) = validate_token(&token) {
+        report_dropped_events("token_shape_invalid", 1);
+        return Err(err.into());
+    }
+
+    let body_bytes = body.len();
+    let payload = state.token_limits.decompress(
+        Some(token.as_str()),
+        router::default_body_limit(path.as_str()),
+        state.event_size_limit,
+        |limit| decompress_bytes(body, limit, compression),
+    )?;
+    let decompressed_bytes = payload.len();
+    let request = decode_logs_request(&payload, encoding)?;
+    let record_count = log_record_count(&request) as u64;
+
+    tracing::Span::current().record("token", &token);
+    tracing::Span::current().record("batch_size", record_count);
+
+    counter!("capture_otlp_log_records_received_total").increment(record_count);
+
+    let context = ProcessingContext {
+        lib_version: None,
+        sent_at: None,
+        historical_migration: false,
+        token,
+        now: state.timesource.current_time(),
+        client_ip: ip.to_string(),
+        user_agent: Some(user_agent.to_string()),
+        server_hashed: HashSet::new(),
+    };
+
+    if let Some(rate_limiter) = &state.rate_limiter {
+        if let Some(retry_after) = rate_limiter.check(&context.token, &context.client_ip).await {
+            report_dropped_events("rate_limited", record_count);
+            return Err(CaptureError::RateLimited(retry_after));
+        }
+    }
+
+    // Like v0, so that exporters don't retry: OTLP treats 429s as retryable
+    if state
+        .billing_limiter
+        .is_limited(context.token.as_str())
+        .await
+    {
+        report_dropped_events("over_quota", record_count);
+        return Ok(success_response(encoding));
+    }
+
+    let mut events = logs_to_events(request, &context, &state.event_rules)?;
+    if events.len() < record_count as usize {
+        report_dropped_events("event_rule", record_count - events.len() as u64);
+    }
+    if let Err(err) = state.token_limits.check(
+        &context.token,
+        body_bytes,
+        decompressed_bytes,
+        router::default_body_limit(path.as_str()),
+        &events,
+    ) {
+        report_dropped_events("token_limit_exceeded", events.len() as u64);
+        return Err(err);
+    }
+    events.retain(|e| {
+        let drop = state
+            .token_dropper
+            .should_drop(&e.event.token, &e.event.distinct_id);
+        if drop {
+            report_dropped_events("token_dropper", 1);
+        }
+        !drop
+    });
+
+    match events.len() {
+        0 => {}
+        1 => state.sink.send(events.pop().unwrap()).await?,
+        _ => state.sink.send_batch(events).await?,
+    }
+
+    Ok(success_response(encoding))
+}
+
+fn success_response(encoding: OtlpEncoding) -> Response {
+    (
+        [(header::CONTENT_TYPE, encoding.content_type())],
+        encoding.success_body(),
+    )
+        .into_response()
+}
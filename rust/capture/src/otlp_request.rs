@@ -0,0 +1,665 @@
+//! Decoding of OpenTelemetry OTLP/HTTP log exports.
+//!
+//! Exports are accepted in both OTLP encodings: binary protobuf, and the protobuf JSON mapping.
+//! The JSON payloads are converted to the protobuf types, so that every log record goes through
+//! the same mapping into a `$log` event:
+//!   - log attributes become event properties,
+//!   - resource attributes become `resource.<key>` properties,
+//!   - the body, severity, trace context and scope are stored in `$log_*` properties.
+
+use std::collections::HashMap;
+
+use base64::engine::general_purpose;
+use base64::Engine;
+use common_types::{CapturedEvent, RawEvent};
+use opentelemetry_proto::tonic::collector::logs::v1::ExportLogsServiceRequest;
+use opentelemetry_proto::tonic::common::v1::{
+    any_value, AnyValue, ArrayValue, InstrumentationScope, KeyValue, KeyValueList,
+};
+use opentelemetry_proto::tonic::logs::v1::{LogRecord, ResourceLogs, ScopeLogs};
+use opentelemetry_proto::tonic::resource::v1::Resource;
+use prost::Message;
+use serde::{Deserialize, Deserializer};
+use serde_json::Value;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+use crate::api::CaptureError;
+use crate::event_rules::EventRules;
+use crate::utils::uuid_v7;
+use crate::v0_request::{DataType, ProcessedEvent, ProcessedEventMetadata, ProcessingContext};
+
+pub const LOG_EVENT: &str = "$log";
+
+// Attributes holding the PostHog distinct_id, looked up in the log then resource attributes
+const DISTINCT_ID_ATTRIBUTES: [&str; 2] = ["posthog.distinct_id", "distinct_id"];
+const SERVICE_NAME_ATTRIBUTE: &str = "service.name";
+// Used when neither a distinct_id nor a service name is set
+const FALLBACK_DISTINCT_ID: &str = "$otel_logs";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OtlpEncoding {
+    Protobuf,
+    Json,
+}
+
+impl OtlpEncoding {
+    /// Maps the request's Content-Type to the OTLP encoding, ignoring parameters like charset.
+    pub fn from_content_type(content_type: &str) -> Option<Self> {
+        let mime = content_type.split(';').next().unwrap_or_default().trim();
+        match mime.to_ascii_lowercase().as_str() {
+            "application/x-protobuf" | "application/protobuf" => Some(OtlpEncoding::Protobuf),
+            "application/json" => Some(OtlpEncoding::Json),
+            _ => None,
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            OtlpEncoding::Protobuf => "application/x-protobuf",
+            OtlpEncoding::Json => "application/json",
+        }
+    }
+
+    /// Encoded empty `ExportLogsServiceResponse`, for a fully accepted export.
+    pub fn success_body(&self) -> &'static [u8] {
+        match self {
+            OtlpEncoding::Protobuf => b"",
+            OtlpEncoding::Json => b"{}",
+        }
+    }
+}
+
+pub fn decode_logs_request(
+    payload: &[u8],
+    encoding: OtlpEncoding,
+) -> Result<ExportLogsServiceRequest, CaptureError> {
+    match encoding {
+        OtlpEncoding::Protobuf => ExportLogsServiceRequest::decode(payload).map_err(|e| {
+            tracing::error!("failed to decode otlp protobuf payload: {}", e);
+            CaptureError::RequestDecodingError(String::from("invalid otlp protobuf data"))
+        }),
+        OtlpEncoding::Json => {
+            let request: JsonExportLogsServiceRequest = serde_json::from_slice(payload)?;
+            request.into_proto()
+        }
+    }
+}
+
+/// Number of log records in the export.
+pub fn log_record_count(request: &ExportLogsServiceRequest) -> usize {
+    request
+        .resource_logs
+        .iter()
+        .flat_map(|r| &r.scope_logs)
+        .map(|s| s.log_records.len())
+        .sum()
+}
+
+/// Maps every log record of the export to a `$log` event, routed to the logs topic.
+/// Records dropped by the token's event rules are skipped, see `event_rules`.
+pub fn logs_to_events(
+    request: ExportLogsServiceRequest,
+    context: &ProcessingContext,
+    event_rules: &EventRules,
+) -> Result<Vec<ProcessedEvent>, CaptureError> {
+    let mut events = Vec::with_capacity(log_record_count(&request));
+    for resource_logs in request.resource_logs {
+        let resource_attributes = resource_logs
+            .resource
+            .map(|r| r.attributes)
+            .unwrap_or_default();
+        let resource_properties: Vec<(String, Value)> = resource_attributes
+            .iter()
+            .map(|kv| {
+                (
+                    format!("resource.{}", kv.key),
+                    any_value_to_json(kv.value.as_ref()),
+                )
+            })
+            .collect();
+
+        for scope_logs in resource_logs.scope_logs {
+            let scope = scope_logs.scope.unwrap_or_default();
+            for record in scope_logs.log_records {
+                let distinct_id = distinct_id(&record.attributes, &resource_attributes);
+                let properties = log_properties(&record, &scope, &resource_properties);
+                let log = RawEvent {
+                    event: LOG_EVENT.to_string(),
+                    distinct_id: Some(Value::String(distinct_id.clone())),
+                    timestamp: log_timestamp(&record),
+                    properties,
+                    ..Default::default()
+                };
+                if !event_rules.keep(&context.token, &log) {
+                    continue;
+                }
+                let data = serde_json::to_string(&log).map_err(|e| {
+                    tracing::error!("failed to encode log event: {}", e);
+                    CaptureError::NonRetryableSinkError
+                })?;
+
+                events.push(ProcessedEvent {
+                    metadata: ProcessedEventMetadata {
+                        data_type: DataType::LogMain,
+                        session_id: None,
+                    },
+                    event: CapturedEvent {
+                        uuid: uuid_v7(),
+                        distinct_id,
+                        ip: context.client_ip.clone(),
+                        data,
+                        now: context.now.clone(),
+                        sent_at: context.sent_at,
+                        token: context.token.clone(),
+                        is_cookieless_mode: false,
+                    },
+                });
+            }
+        }
+    }
+    Ok(events)
+}
+
+fn log_properties(
+    record: &LogRecord,
+    scope: &InstrumentationScope,
+    resource_properties: &[(String, Value)],
+) -> HashMap<String, Value> {
+    let mut properties: HashMap<String, Value> = resource_properties.iter().cloned().collect();
+    for kv in &record.attributes {
+        properties.insert(kv.key.clone(), any_value_to_json(kv.value.as_ref()));
+    }
+
+    let mut set = |key: &str, value: Value| {
+        properties.insert(key.to_string(), value);
+    };
+    set("$log_body", any_value_to_json(record.body.as_ref()));
+    set("$log_severity_number", Value::from(record.severity_number));
+    if !record.severity_text.is_empty() {
+        set(
+            "$log_severity_text",
+            Value::from(record.severity_text.clone()),
+        );
+    }
+    if !record.trace_id.is_empty() {
+        set("$log_trace_id", Value::from(to_hex(&record.trace_id)));
+    }
+    if !record.span_id.is_empty() {
+        set("$log_span_id", Value::from(to_hex(&record.span_id)));
+    }
+    if !scope.name.is_empty() {
+        set("$log_scope_name", Value::from(scope.name.clone()));
+    }
+    if !scope.version.is_empty() {
+        set("$log_scope_version", Value::from(scope.version.clone()));
+    }
+    properties
+}
+
+fn distinct_id(attributes: &[KeyValue], resource_attributes: &[KeyValue]) -> String {
+    let lookup = |keys: &[&str]| {
+        attributes
+            .iter()
+            .chain(resource_attributes)
+            .filter(|kv| keys.contains(&kv.key.as_str()))
+            .find_map(|kv| match any_value_to_json(kv.value.as_ref()) {
+                Value::Null => None,
+                Value::String(id) if id.is_empty() => None,
+                Value::String(id) => Some(id),
+                id => Some(id.to_string()),
+            })
+    };
+    lookup(&DISTINCT_ID_ATTRIBUTES)
+        .or_else(|| lookup(&[SERVICE_NAME_ATTRIBUTE]))
+        .unwrap_or_else(|| FALLBACK_DISTINCT_ID.to_string())
+}
+
+// Records without a time are timestamped when observed by the collector, if known
+fn log_timestamp(record: &LogRecord) -> Option<String> {
+    [record.time_unix_nano, record.observed_time_unix_nano]
+        .into_iter()
+        .find(|nanos| *nanos > 0)
+        .and_then(|nanos| OffsetDateTime::from_unix_timestamp_nanos(i128::from(nanos)).ok())
+        .and_then(|timestamp| timestamp.format(&Rfc3339).ok())
+}
+
+fn any_value_to_json(value: Option<&AnyValue>) -> Value {
+    let Some(value) = value.and_then(|v| v.value.as_ref()) else {
+        return Value::Null;
+    };
+    match value {
+        any_value::Value::StringValue(s) => Value::from(s.clone()),
+        any_value::Value::BoolValue(b) => Value::from(*b),
+        any_value::Value::IntValue(i) => Value::from(*i),
+        any_value::Value::DoubleValue(d) => Value::from(*d), // NaN and infinities become null
+        any_value::Value::ArrayValue(array) => array
+            .values
+            .iter()
+            .map(|v| any_value_to_json(Some(v)))
+            .collect(),
+        any_value::Value::KvlistValue(list) => Value::Object(
+            list.values
+                .iter()
+                .map(|kv| (kv.key.clone(), any_value_to_json(kv.value.as_ref())))
+                .collect(),
+        ),
+        any_value::Value::BytesValue(bytes) => Value::from(general_purpose::STANDARD.encode(bytes)),
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(value: &str) -> Result<Vec<u8>, CaptureError> {
+    let invalid = || CaptureError::RequestParsingError(format!("invalid otlp id: {}", value));
+    if value.len() % 2 != 0 {
+        return Err(invalid());
+    }
+    (0..value.len())
+        .step_by(2)
+        .map(|i| {
+            value
+                .get(i..i + 2)
+                .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+                .ok_or_else(invalid)
+        })
+        .collect()
+}
+
+// OTLP/JSON payloads, following the protobuf JSON mapping: lowerCamelCase field names, hex
+// encoded trace and span ids, and 64 bits integers as either numbers or strings.
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct JsonExportLogsServiceRequest {
+    #[serde(default)]
+    resource_logs: Vec<JsonResourceLogs>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct JsonResourceLogs {
+    resource: Option<JsonResource>,
+    #[serde(default)]
+    scope_logs: Vec<JsonScopeLogs>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct JsonResource {
+    #[serde(default)]
+    attributes: Vec<JsonKeyValue>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct JsonScopeLogs {
+    scope: Option<JsonInstrumentationScope>,
+    #[serde(default)]
+    log_records: Vec<JsonLogRecord>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct JsonInstrumentationScope {
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    version: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct JsonLogRecord {
+    #[serde(default, deserialize_with = "int_or_string")]
+    time_unix_nano: u64,
+    #[serde(default, deserialize_with = "int_or_string")]
+    observed_time_unix_nano: u64,
+    #[serde(default)]
+    severity_number: i32,
+    #[serde(default)]
+    severity_text: String,
+    body: Option<JsonAnyValue>,
+    #[serde(default)]
+    attributes: Vec<JsonKeyValue>,
+    #[serde(default)]
+    trace_id: String,
+    #[serde(default)]
+    span_id: String,
+}
+
+#[derive(Deserialize)]
+struct JsonKeyValue {
+    key: String,
+    value: Option<JsonAnyValue>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+enum JsonAnyValue {
+    StringValue(String),
+    BoolValue(bool),
+    #[serde(deserialize_with = "int_or_string")]
+    IntValue(i64),
+    DoubleValue(f64),
+    ArrayValue {
+        #[serde(default)]
+        values: Vec<JsonAnyValue>,
+    },
+    KvlistValue {
+        #[serde(default)]
+        values: Vec<JsonKeyValue>,
+    },
+    BytesValue(String), // base64
+}
+
+fn int_or_string<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de> + std::str::FromStr,
+    T::Err: std::fmt::Display,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum IntOrString<T> {
+        Int(T),
+        String(String),
+    }
+
+    match IntOrString::<T>::deserialize(deserializer)? {
+        IntOrString::Int(value) => Ok(value),
+        IntOrString::String(value) => value.parse().map_err(serde::de::Error::custom),
+    }
+}
+
+impl JsonExportLogsServiceRequest {
+    fn into_proto(self) -> Result<ExportLogsServiceRequest, CaptureError> {
+        let resource_logs = self
+            .resource_logs
+            .into_iter()
+            .map(|resource_logs| {
+                Ok(ResourceLogs {
+                    resource: resource_logs
+                        .resource
+                        .map(|resource| -> Result<Resource, CaptureError> {
+                            Ok(Resource {
+                                attributes: key_values_into_proto(resource.attributes)?,
+                                ..Default::default()
+                            })
+                        })
+                        .transpose()?,
+                    scope_logs: resource_logs
+                        .scope_logs
+                        .into_iter()
+                        .map(JsonScopeLogs::into_proto)
+                        .collect::<Result<_, _>>()?,
+                    ..Default::default()
+                })
+            })
+            .collect::<Result<_, CaptureError>>()?;
+        Ok(ExportLogsServiceRequest { resource_logs })
+    }
+}
+
+impl JsonScopeLogs {
+    fn into_proto(self) -> Result<ScopeLogs, CaptureError> {
+        Ok(ScopeLogs {
+            scope: self.scope.map(|scope| InstrumentationScope {
+                name: scope.name,
+                version: scope.version,
+                ..Default::default()
+            }),
+            log_records: self
+                .log_records
+                .into_iter()
+                .map(JsonLogRecord::into_proto)
+                .collect::<Result<_, _>>()?,
+            ..Default::default()
+        })
+    }
+}
+
+impl JsonLogRecord {
+    fn into_proto(self) -> Result<LogRecord, CaptureError> {
+        Ok(LogRecord {
+            time_unix_nano: self.time_unix_nano,
+            observed_time_unix_nano: self.observed_time_unix_nano,
+            severity_number: self.severity_number,
+            severity_text: self.severity_text,
+            body: self.body.map(JsonAnyValue::into_proto).transpose()?,
+            attributes: key_values_into_proto(self.attributes)?,
+            trace_id: from_hex(&self.trace_id)?,
+            span_id: from_hex(&self.span_id)?,
+            ..Default::default()
+        })
+    }
+}
+
+impl JsonAnyValue {
+    fn into_proto(self) -> Result<AnyValue, CaptureError> {
+        let value = match self {
+            JsonAnyValue::StringValue(s) => any_value::Value::StringValue(s),
+            JsonAnyValue::BoolValue(b) => any_value::Value::BoolValue(b),
+            JsonAnyValue::IntValue(i) => any_value::Value::IntValue(i),
+            JsonAnyValue::DoubleValue(d) => any_value::Value::DoubleValue(d),
+            JsonAnyValue::ArrayValue { values } => any_value::Value::ArrayValue(ArrayValue {
+                values: values
+                    .into_iter()
+                    .map(JsonAnyValue::into_proto)
+                    .collect::<Result<_, _>>()?,
+            }),
+            JsonAnyValue::KvlistValue { values } => any_value::Value::KvlistValue(KeyValueList {
+                values: key_values_into_proto(values)?,
+            }),
+            JsonAnyValue::BytesValue(b) => {
+                any_value::Value::BytesValue(general_purpose::STANDARD.decode(b).map_err(|e| {
+                    CaptureError::RequestParsingError(format!("invalid otlp bytes value: {}", e))
+                })?)
+            }
+        };
+        Ok(AnyValue { value: Some(value) })
+    }
+}
+
+fn key_values_into_proto(values: Vec<JsonKeyValue>) -> Result<Vec<KeyValue>, CaptureError> {
+    values
+        .into_iter()
+        .map(|kv| {
+            Ok(KeyValue {
+                key: kv.key,
+                value: kv.value.map(JsonAnyValue::into_proto).transpose()?,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use common_types::RawEvent;
+    use opentelemetry_proto::tonic::collector::logs::v1::ExportLogsServiceRequest;
+    use opentelemetry_proto::tonic::common::v1::{any_value, AnyValue, KeyValue};
+    use opentelemetry_proto::tonic::logs::v1::{LogRecord, ResourceLogs, ScopeLogs};
+    use opentelemetry_proto::tonic::resource::v1::Resource;
+    use prost::Message;
+    use serde_json::json;
+
+    use super::{decode_logs_request, logs_to_events, OtlpEncoding, LOG_EVENT};
+    use crate::api::CaptureError;
+    use crate::event_rules::EventRules;
+    use crate::v0_request::{DataType, ProcessingContext};
+
+    fn context() -> ProcessingContext {
+        ProcessingContext {
+            lib_version: None,
+            user_agent: None,
+            sent_at: None,
+            token: "my_token".to_string(),
+            now: "2024-01-01T00:00:00Z".to_string(),
+            client_ip: "127.0.0.1".to_string(),
+            historical_migration: false,
+            server_hashed: HashSet::new(),
+        }
+    }
+
+    fn string_attribute(key: &str, value: &str) -> KeyValue {
+        KeyValue {
+            key: key.to_string(),
+            value: Some(AnyValue {
+                value: Some(any_value::Value::StringValue(value.to_string())),
+            }),
+        }
+    }
+
+    fn data(event: &crate::v0_request::ProcessedEvent) -> RawEvent {
+        serde_json::from_str(&event.event.data).expect("invalid data")
+    }
+
+    #[test]
+    fn content_types() {
+        assert_eq!(
+            Some(OtlpEncoding::Protobuf),
+            OtlpEncoding::from_content_type("application/x-protobuf")
+        );
+        assert_eq!(
+            Some(OtlpEncoding::Json),
+            OtlpEncoding::from_content_type("application/json; charset=utf-8")
+        );
+        assert_eq!(None, OtlpEncoding::from_content_type("text/plain"));
+    }
+
+    #[test]
+    fn maps_protobuf_logs() {
+        let request = ExportLogsServiceRequest {
+            resource_logs: vec![ResourceLogs {
+                resource: Some(Resource {
+                    attributes: vec![string_attribute("service.name", "checkout")],
+                    ..Default::default()
+                }),
+                scope_logs: vec![ScopeLogs {
+                    log_records: vec![
+                        LogRecord {
+                            time_unix_nano: 1_704_067_200_000_000_000,
+                            severity_number: 9,
+                            severity_text: "INFO".to_string(),
+                            body: Some(AnyValue {
+                                value: Some(any_value::Value::StringValue("paid".to_string())),
+                            }),
+                            attributes: vec![string_attribute("order", "42")],
+                            trace_id: vec![0xab; 16],
+                            span_id: vec![0x01; 8],
+                            ..Default::default()
+                        },
+                        LogRecord {
+                            attributes: vec![string_attribute("distinct_id", "user1")],
+                            ..Default::default()
+                        },
+                    ],
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+        };
+        let payload = request.encode_to_vec();
+
+        let request =
+            decode_logs_request(&payload, OtlpEncoding::Protobuf).expect("failed to decode");
+        let events = logs_to_events(request, &context(), &EventRules::default())
+            .expect("failed to map logs");
+        assert_eq!(2, events.len());
+
+        assert_eq!(DataType::LogMain, events[0].metadata.data_type);
+        assert_eq!("my_token", events[0].event.token);
+        assert_eq!("checkout", events[0].event.distinct_id);
+        let log = data(&events[0]);
+        assert_eq!(LOG_EVENT, log.event);
+        assert_eq!(Some("2024-01-01T00:00:00Z".to_string()), log.timestamp);
+        assert_eq!(json!("paid"), log.properties["$log_body"]);
+        assert_eq!(json!("INFO"), log.properties["$log_severity_text"]);
+        assert_eq!(json!(9), log.properties["$log_severity_number"]);
+        assert_eq!(json!("ab".repeat(16)), log.properties["$log_trace_id"]);
+        assert_eq!(json!("0101010101010101"), log.properties["$log_span_id"]);
+        assert_eq!(json!("42"), log.properties["order"]);
+        assert_eq!(json!("checkout"), log.properties["resource.service.name"]);
+
+        // The distinct_id attribute takes precedence over the service name
+        assert_eq!("user1", events[1].event.distinct_id);
+        assert_eq!(None, data(&events[1]).timestamp);
+    }
+
+    #[test]
+    fn maps_json_logs() {
+        let payload = json!({
+            "resourceLogs": [{
+                "resource": {"attributes": [{"key": "host.name", "value": {"stringValue": "web-1"}}]},
+                "scopeLogs": [{
+                    "scope": {"name": "my.logger", "version": "1.0"},
+                    "logRecords": [{
+                        "timeUnixNano": "1704067200000000000",
+                        "severityNumber": 17,
+                        "body": {"kvlistValue": {"values": [
+                            {"key": "count", "value": {"intValue": "3"}},
+                            {"key": "ratio", "value": {"doubleValue": 0.5}},
+                        ]}},
+                        "attributes": [
+                            {"key": "tags", "value": {"arrayValue": {"values": [{"boolValue": true}]}}},
+                            {"key": "raw", "value": {"bytesValue": "aGk="}},
+                        ],
+                        "traceId": "5b8efff798038103d269b633813fc60c",
+                        "spanId": "eee19b7ec3c1b174",
+                    }],
+                }],
+            }],
+        });
+
+        let request = decode_logs_request(payload.to_string().as_bytes(), OtlpEncoding::Json)
+            .expect("failed to decode");
+        let events = logs_to_events(request, &context(), &EventRules::default())
+            .expect("failed to map logs");
+        assert_eq!(1, events.len());
+        assert_eq!("$otel_logs", events[0].event.distinct_id);
+
+        let log = data(&events[0]);
+        assert_eq!(Some("2024-01-01T00:00:00Z".to_string()), log.timestamp);
+        assert_eq!(
+            json!({"count": 3, "ratio": 0.5}),
+            log.properties["$log_body"]
+        );
+        assert_eq!(json!([true]), log.properties["tags"]);
+        assert_eq!(json!("aGk="), log.properties["raw"]);
+        assert_eq!(json!("web-1"), log.properties["resource.host.name"]);
+        assert_eq!(json!("my.logger"), log.properties["$log_scope_name"]);
+        assert_eq!(json!("1.0"), log.properties["$log_scope_version"]);
+        assert_eq!(
+            json!("5b8efff798038103d269b633813fc60c"),
+            log.properties["$log_trace_id"]
+        );
+        assert_eq!(None, log.properties.get("$log_severity_text"));
+    }
+
+    #[test]
+    fn rejects_invalid_payloads() {
+        assert!(matches!(
+            decode_logs_request(b"\xff\xff\xff", OtlpEncoding::Protobuf),
+            Err(CaptureError::RequestDecodingError(_))
+        ));
+        let invalid_id =
+            json!({"resourceLogs": [{"scopeLogs": [{"logRecords": [{"traceId": "xyz"}]}]}]});
+        assert!(matches!(
+            decode_logs_request(invalid_id.to_string().as_bytes(), OtlpEncoding::Json),
+            Err(CaptureError::RequestParsingError(_))
+        ));
+        assert!(matches!(
+            decode_logs_request(b"not json", OtlpEncoding::Json),
+            Err(CaptureError::RequestParsingError(_))
+        ));
+
+        let empty = decode_logs_request(b"{}", OtlpEncoding::Json).expect("failed to decode");
+        assert!(logs_to_events(empty, &context(), &EventRules::default())
+            .expect("failed to map logs")
+            .is_empty());
+    }
+}
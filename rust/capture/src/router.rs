@@ -1,8 +1,10 @@
 use std::future::ready;
 use std::sync::Arc;
 
-use axum::extract::DefaultBodyLimit;
-use axum::http::Method;
+use axum::extract::{DefaultBodyLimit, Request};
+use axum::http::{header, Method};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
 use axum::{
     routing::{get, post},
     Router,
@@ -12,10 +14,18 @@ use tower::limit::ConcurrencyLimitLayer;
 use tower_http::cors::{AllowHeaders, AllowOrigin, CorsLayer};
 use tower_http::trace::TraceLayer;
 
+use crate::cookieless::CookielessProcessor;
+use crate::dedup::EventDeduplicator;
+use crate::event_rules::EventRules;
+use crate::prometheus::report_dropped_events;
 use crate::test_endpoint;
-use crate::{sinks, time::TimeSource, v0_endpoint};
+use crate::token::request_token;
+use crate::token_limits::TokenLimits;
+use crate::v0_request::EventQuery;
+use crate::{compat_endpoint, otlp_endpoint, sinks, time::TimeSource, v0_endpoint, v1_endpoint};
 use common_redis::Client;
 use limiters::redis::RedisLimiter;
+use limiters::request_rate::RequestRateLimiter;
 use limiters::token_dropper::TokenDropper;
 
 use crate::config::CaptureMode;
@@ -23,7 +33,17 @@ use crate::prometheus::{setup_metrics_recorder, track_metrics};
 
 const EVENT_BODY_SIZE: usize = 2 * 1024 * 1024; // 2MB
 pub const BATCH_BODY_SIZE: usize = 20 * 1024 * 1024; // 20MB, up from the default 2MB used for normal event payloads
-const RECORDING_BODY_SIZE: usize = 25 * 1024 * 1024; // 25MB, up from the default 2MB used for normal event payloads
+pub const RECORDING_BODY_SIZE: usize = 25 * 1024 * 1024; // 25MB, up from the default 2MB used for normal event payloads
+
+/// Body size limit of the route, unless the token has its own. The route layers only enforce
+/// the largest limit that may be allowed to a token, see `TokenLimits`.
+pub fn default_body_limit(path: &str) -> usize {
+    match path.trim_end_matches('/') {
+        "/e" | "/i/v0/e" | "/i/v0/exception" => EVENT_BODY_SIZE,
+        "/s" => RECORDING_BODY_SIZE,
+        _ => BATCH_BODY_SIZE,
+    }
+}
 
 #[derive(Clone)]
 pub struct State {
@@ -31,14 +51,47 @@ pub struct State {
     pub timesource: Arc<dyn TimeSource + Send + Sync>,
     pub redis: Arc<dyn Client + Send + Sync>,
     pub billing_limiter: RedisLimiter,
+    pub rate_limiter: Option<RequestRateLimiter>,
     pub token_dropper: Arc<TokenDropper>,
+    pub event_rules: Arc<EventRules>,
+    pub token_limits: Arc<TokenLimits>,
+    pub cookieless: Option<Arc<CookielessProcessor>>,
+    pub deduplicator: Option<Arc<EventDeduplicator>>,
     pub event_size_limit: usize,
+    pub replay_message_max_bytes: usize,
 }
 
 async fn index() -> &'static str {
     "capture"
 }
 
+/// Rejects the requests over their token's body size limit from their Content-Length, before
+/// the body is read, if the token is sent outside of the payload, see `token::request_token`.
+/// The handlers check the other requests once decoded, see `TokenLimits::check`.
+async fn reject_oversized_bodies(
+    axum::extract::State(token_limits): axum::extract::State<Arc<TokenLimits>>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let query: EventQuery =
+        serde_urlencoded::from_str(req.uri().query().unwrap_or_default()).unwrap_or_default();
+    let body_bytes = req
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<usize>().ok());
+    if let (Some(token), Some(body_bytes)) =
+        (request_token(req.headers(), query.token()), body_bytes)
+    {
+        let default_body_bytes = default_body_limit(req.uri().path());
+        if let Err(err) = token_limits.check_body(token, body_bytes, default_body_bytes) {
+            report_dropped_events("token_limit_exceeded", 1);
+            return err.into_response();
+        }
+    }
+    next.run(req).await
+}
+
 #[allow(clippy::too_many_arguments)]
 pub fn router<
     TZ: TimeSource + Send + Sync + 'static,
@@ -50,19 +103,31 @@ pub fn router<
     sink: S,
     redis: Arc<R>,
     billing_limiter: RedisLimiter,
+    rate_limiter: Option<RequestRateLimiter>,
     token_dropper: TokenDropper,
+    event_rules: EventRules,
+    token_limits: TokenLimits,
+    cookieless: Option<CookielessProcessor>,
+    deduplicator: Option<EventDeduplicator>,
     metrics: bool,
     capture_mode: CaptureMode,
     concurrency_limit: Option<usize>,
     event_size_limit: usize,
+    replay_message_max_bytes: usize,
 ) -> Router {
     let state = State {
         sink: Arc::new(sink),
         timesource: Arc::new(timesource),
         redis,
         billing_limiter,
+        rate_limiter,
         event_size_limit,
+        replay_message_max_bytes,
         token_dropper: Arc::new(token_dropper),
+        event_rules: Arc::new(event_rules),
+        token_limits: Arc::new(token_limits.clone()),
+        cookieless: cookieless.map(Arc::new),
+        deduplicator: deduplicator.map(Arc::new),
     };
 
     // Very permissive CORS policy, as old SDK versions
@@ -86,7 +151,9 @@ pub fn router<
                 .get(test_endpoint::test_black_hole)
                 .options(v0_endpoint::options),
         )
-        .layer(DefaultBodyLimit::max(BATCH_BODY_SIZE));
+        .layer(DefaultBodyLimit::max(
+            token_limits.route_body_limit(BATCH_BODY_SIZE),
+        ));
 
     let batch_router = Router::new()
         .route(
@@ -101,7 +168,67 @@ pub fn router<
                 .get(v0_endpoint::event)
                 .options(v0_endpoint::options),
         )
-        .layer(DefaultBodyLimit::max(BATCH_BODY_SIZE)); // Have to use this, rather than RequestBodyLimitLayer, because we use `Bytes` in the handler (this limit applies specifically to Bytes body types)
+        .route(
+            "/i/v1/batch",
+            post(v1_endpoint::batch).options(v0_endpoint::options),
+        )
+        .route(
+            "/i/v1/batch/",
+            post(v1_endpoint::batch).options(v0_endpoint::options),
+        )
+        .route(
+            "/i/v0/validate",
+            post(v0_endpoint::validate).options(v0_endpoint::options),
+        )
+        .route(
+            "/i/v0/validate/",
+            post(v0_endpoint::validate).options(v0_endpoint::options),
+        )
+        .route(
+            "/i/v1/logs",
+            post(otlp_endpoint::logs).options(v0_endpoint::options),
+        )
+        .route(
+            "/i/v1/logs/",
+            post(otlp_endpoint::logs).options(v0_endpoint::options),
+        )
+        .layer(DefaultBodyLimit::max(
+            token_limits.route_body_limit(BATCH_BODY_SIZE),
+        )); // Have to use this, rather than RequestBodyLimitLayer, because we use `Bytes` in the handler (this limit applies specifically to Bytes body types)
+
+    // Segment and Mixpanel SDKs pointed to capture. Segment's paths don't clash with django's,
+    // but `/track` and `/engage` are already aliases of the PostHog event endpoint there, so
+    // Mixpanel gets its own prefix.
+    let mut compat_router = Router::new();
+    for path in ["/v1/track", "/v1/identify", "/v1/page", "/v1/batch"] {
+        compat_router = compat_router
+            .route(
+                path,
+                post(compat_endpoint::segment).options(v0_endpoint::options),
+            )
+            .route(
+                &format!("{path}/"),
+                post(compat_endpoint::segment).options(v0_endpoint::options),
+            );
+    }
+    for path in ["/mixpanel/track", "/mixpanel/engage"] {
+        compat_router = compat_router
+            .route(
+                path,
+                post(compat_endpoint::mixpanel)
+                    .get(compat_endpoint::mixpanel)
+                    .options(v0_endpoint::options),
+            )
+            .route(
+                &format!("{path}/"),
+                post(compat_endpoint::mixpanel)
+                    .get(compat_endpoint::mixpanel)
+                    .options(v0_endpoint::options),
+            );
+    }
+    let compat_router = compat_router.layer(DefaultBodyLimit::max(
+        token_limits.route_body_limit(BATCH_BODY_SIZE),
+    ));
 
     let event_router = Router::new()
         .route(
@@ -128,7 +255,17 @@ pub fn router<
                 .get(v0_endpoint::event)
                 .options(v0_endpoint::options),
         )
-        .layer(DefaultBodyLimit::max(EVENT_BODY_SIZE));
+        .route(
+            "/i/v0/exception",
+            post(v0_endpoint::exception).options(v0_endpoint::options),
+        )
+        .route(
+            "/i/v0/exception/",
+            post(v0_endpoint::exception).options(v0_endpoint::options),
+        )
+        .layer(DefaultBodyLimit::max(
+            token_limits.route_body_limit(EVENT_BODY_SIZE),
+        ));
 
     let status_router = Router::new()
         .route("/", get(index))
@@ -148,16 +285,23 @@ pub fn router<
                 .get(v0_endpoint::recording)
                 .options(v0_endpoint::options),
         )
-        .layer(DefaultBodyLimit::max(RECORDING_BODY_SIZE));
+        .layer(DefaultBodyLimit::max(
+            token_limits.route_body_limit(RECORDING_BODY_SIZE),
+        ));
 
     let mut router = match capture_mode {
         CaptureMode::Events => Router::new()
             .merge(batch_router)
+            .merge(compat_router)
             .merge(event_router)
             .merge(test_router),
         CaptureMode::Recordings => Router::new().merge(recordings_router),
     };
 
+    router = router.layer(axum::middleware::from_fn_with_state(
+        state.token_limits.clone(),
+        reject_oversized_bodies,
+    ));
     if let Some(limit) = concurrency_limit {
         router = router.layer(ConcurrencyLimitLayer::new(limit));
     }
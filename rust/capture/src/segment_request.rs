@@ -0,0 +1,266 @@
+//! Translation of Segment HTTP tracking API payloads into PostHog events.
+//!
+//! Supported message types:
+//!   - `track` keeps its event name and properties,
+//!   - `identify` becomes an `$identify` event, with the traits as `$set`,
+//!   - `page` becomes a `$pageview`, with the page url, path and referrer mapped.
+//!
+//! Other message types are dropped as invalid events. Segment's `messageId` is used as the
+//! event uuid when it is a valid uuid.
+
+use std::collections::HashMap;
+
+use common_types::RawEvent;
+use serde::Deserialize;
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::api::CaptureError;
+use crate::v0_request::InvalidEvent;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SegmentMessageType {
+    Track,
+    Identify,
+    Page,
+}
+
+impl SegmentMessageType {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "track" => Some(SegmentMessageType::Track),
+            "identify" => Some(SegmentMessageType::Identify),
+            "page" => Some(SegmentMessageType::Page),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SegmentBatch {
+    pub write_key: Option<String>,
+    #[serde(default)]
+    pub batch: Vec<Value>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SegmentMessage {
+    #[serde(rename = "type")]
+    message_type: Option<String>,
+    user_id: Option<Value>,
+    anonymous_id: Option<Value>,
+    event: Option<String>,
+    name: Option<String>,
+    properties: Option<HashMap<String, Value>>,
+    traits: Option<HashMap<String, Value>>,
+    context: Option<SegmentContext>,
+    timestamp: Option<String>,
+    message_id: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SegmentContext {
+    ip: Option<String>,
+    user_agent: Option<String>,
+    library: Option<SegmentLibrary>,
+    page: Option<SegmentPage>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct SegmentLibrary {
+    name: Option<String>,
+    version: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct SegmentPage {
+    url: Option<String>,
+    path: Option<String>,
+    referrer: Option<String>,
+}
+
+/// Reads the write key from a single message, for requests not using basic auth.
+pub fn message_write_key(message: &Value) -> Option<String> {
+    message
+        .get("writeKey")
+        .and_then(Value::as_str)
+        .map(String::from)
+}
+
+/// Translates the messages of a request. `default_type` is the type implied by the endpoint,
+/// used for messages without a `type` field. Invalid messages are returned separately.
+pub fn segment_events(
+    messages: Vec<Value>,
+    default_type: Option<SegmentMessageType>,
+) -> (Vec<RawEvent>, Vec<InvalidEvent>) {
+    let mut events = Vec::with_capacity(messages.len());
+    let mut invalid = Vec::new();
+    for payload in messages {
+        match segment_event(&payload, default_type) {
+            Ok(event) => events.push(event),
+            Err(error) => invalid.push(InvalidEvent { payload, error }),
+        }
+    }
+    (events, invalid)
+}
+
+fn segment_event(
+    payload: &Value,
+    default_type: Option<SegmentMessageType>,
+) -> Result<RawEvent, CaptureError> {
+    let message = SegmentMessage::deserialize(payload)
+        .map_err(|e| CaptureError::InvalidEvent(e.to_string()))?;
+    let message_type = match &message.message_type {
+        Some(message_type) => SegmentMessageType::parse(message_type).ok_or_else(|| {
+            CaptureError::InvalidEvent(format!("unsupported segment type: {}", message_type))
+        })?,
+        None => default_type.ok_or_else(|| {
+            CaptureError::InvalidEvent(String::from("segment message without a type"))
+        })?,
+    };
+
+    let mut properties = message.properties.unwrap_or_default();
+    let context = message.context.unwrap_or_default();
+    let mut set_default = |key: &str, value: Option<String>| {
+        if let Some(value) = value {
+            properties
+                .entry(key.to_string())
+                .or_insert(Value::String(value));
+        }
+    };
+    set_default("$ip", context.ip);
+    set_default("$raw_user_agent", context.user_agent);
+    let library = context.library.unwrap_or_default();
+    set_default("$lib", library.name);
+    set_default("$lib_version", library.version);
+    let page = context.page.unwrap_or_default();
+    set_default("$current_url", page.url);
+    set_default("$pathname", page.path);
+    set_default("$referrer", page.referrer);
+
+    let (event, set) = match message_type {
+        SegmentMessageType::Track => (message.event.unwrap_or_default(), None),
+        SegmentMessageType::Identify => {
+            // Identified users are merged with their anonymous id, like posthog-js does
+            if let (Some(_), Some(anonymous_id)) = (&message.user_id, &message.anonymous_id) {
+                properties.insert("$anon_distinct_id".to_string(), anonymous_id.clone());
+            }
+            ("$identify".to_string(), message.traits)
+        }
+        SegmentMessageType::Page => {
+            for (from, to) in [
+                ("url", "$current_url"),
+                ("path", "$pathname"),
+                ("referrer", "$referrer"),
+            ] {
+                if let Some(value) = properties.get(from).cloned() {
+                    properties.insert(to.to_string(), value);
+                }
+            }
+            if let Some(name) = message.name {
+                properties.entry("name".to_string()).or_insert(name.into());
+            }
+            ("$pageview".to_string(), None)
+        }
+    };
+
+    Ok(RawEvent {
+        token: None,
+        distinct_id: message.user_id.or(message.anonymous_id),
+        uuid: message.message_id.and_then(|id| Uuid::parse_str(&id).ok()),
+        event,
+        properties,
+        timestamp: message.timestamp,
+        offset: None,
+        set,
+        set_once: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use uuid::Uuid;
+
+    use super::{segment_events, SegmentMessageType};
+    use crate::api::CaptureError;
+
+    #[test]
+    fn translates_messages() {
+        let message_id = Uuid::now_v7();
+        let (events, invalid) = segment_events(
+            vec![
+                json!({
+                    "type": "track",
+                    "event": "Order Completed",
+                    "userId": "user1",
+                    "properties": {"revenue": 42},
+                    "context": {"ip": "1.2.3.4", "library": {"name": "analytics-node", "version": "1.0"}},
+                    "timestamp": "2024-01-01T00:00:00Z",
+                    "messageId": message_id.to_string(),
+                }),
+                json!({
+                    "type": "identify",
+                    "userId": "user1",
+                    "anonymousId": "anon1",
+                    "traits": {"email": "user@example.com"},
+                    "messageId": "ajs-not-a-uuid",
+                }),
+                json!({
+                    "type": "page",
+                    "anonymousId": "anon1",
+                    "name": "Home",
+                    "properties": {"url": "https://example.com/", "path": "/"},
+                }),
+                json!({"type": "group", "userId": "user1", "groupId": "g1"}),
+            ],
+            None,
+        );
+
+        assert_eq!(3, events.len());
+        let track = &events[0];
+        assert_eq!("Order Completed", track.event);
+        assert_eq!(Some(json!("user1")), track.distinct_id);
+        assert_eq!(Some(message_id), track.uuid);
+        assert_eq!(Some("2024-01-01T00:00:00Z".to_string()), track.timestamp);
+        assert_eq!(json!(42), track.properties["revenue"]);
+        assert_eq!(json!("1.2.3.4"), track.properties["$ip"]);
+        assert_eq!(json!("analytics-node"), track.properties["$lib"]);
+
+        let identify = &events[1];
+        assert_eq!("$identify", identify.event);
+        assert_eq!(None, identify.uuid);
+        assert_eq!(json!("anon1"), identify.properties["$anon_distinct_id"]);
+        assert_eq!(
+            Some(&json!("user@example.com")),
+            identify.set.as_ref().and_then(|set| set.get("email"))
+        );
+
+        let page = &events[2];
+        assert_eq!("$pageview", page.event);
+        assert_eq!(Some(json!("anon1")), page.distinct_id);
+        assert_eq!(
+            json!("https://example.com/"),
+            page.properties["$current_url"]
+        );
+        assert_eq!(json!("/"), page.properties["$pathname"]);
+        assert_eq!(json!("Home"), page.properties["name"]);
+
+        assert_eq!(1, invalid.len());
+        assert!(matches!(invalid[0].error, CaptureError::InvalidEvent(_)));
+    }
+
+    #[test]
+    fn uses_endpoint_type() {
+        let message = json!({"event": "clicked", "anonymousId": "anon1"});
+        let (events, _) = segment_events(vec![message.clone()], Some(SegmentMessageType::Track));
+        assert_eq!("clicked", events[0].event);
+
+        let (events, invalid) = segment_events(vec![message], None);
+        assert!(events.is_empty());
+        assert_eq!(1, invalid.len());
+    }
+}
@@ -1,29 +1,70 @@
-use std::future::Future;
+use std::future::{Future, IntoFuture};
 use std::net::SocketAddr;
 use std::sync::Arc;
 
+use axum::extract::{Request, State};
+use axum::http::StatusCode;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
 use common_redis::RedisClient;
 use health::{ComponentStatus, HealthRegistry};
 use limiters::redis::ServiceName;
 use time::Duration;
 use tokio::net::TcpListener;
+use tokio::sync::{watch, RwLock};
+use tokio::time::{sleep_until, Instant};
 
 use crate::config::CaptureMode;
-use crate::config::Config;
+use crate::config::{Config, KafkaConfig};
+use crate::cookieless::{cookieless_config, CookielessProcessor};
+use crate::dedup::EventDeduplicator;
+use crate::event_rules::{EventRules, EVENT_RULES_CACHE_KEY};
 
-use limiters::overflow::OverflowLimiter;
+use limiters::overflow::{OverflowLimiter, OVERFLOW_FORCED_KEYS_CACHE_KEY};
 use limiters::redis::{
     QuotaResource, RedisLimiter, OVERFLOW_LIMITER_CACHE_KEY, QUOTA_LIMITER_CACHE_KEY,
 };
+use limiters::request_rate::RequestRateLimiter;
 
 use crate::router;
-use crate::router::BATCH_BODY_SIZE;
+use crate::router::{BATCH_BODY_SIZE, RECORDING_BODY_SIZE};
+use crate::sinks::disk::DiskSink;
+use crate::sinks::dual::DualSink;
 use crate::sinks::fallback::FallbackSink;
 use crate::sinks::kafka::KafkaSink;
 use crate::sinks::print::PrintSink;
 use crate::sinks::s3::S3Sink;
 use crate::sinks::Event;
-use limiters::token_dropper::TokenDropper;
+use crate::token_limits::{TokenLimits, TOKEN_LIMITS_CACHE_KEY};
+use limiters::token_dropper::{TokenDropper, TOKEN_DROPPER_CACHE_KEY};
+
+// How often the dropped keys and forced overflow keys are reloaded from redis
+const DYNAMIC_KEYS_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Requests being handled. Dropping the server future on shutdown doesn't stop the tasks of its
+/// connections, so the requests still running once the drain timeout is reached are aborted
+/// through `aborted`, and each holds a read guard of `lock` until it returned.
+#[derive(Clone)]
+struct InFlightRequests {
+    lock: Arc<RwLock<()>>,
+    aborted: watch::Receiver<bool>,
+}
+
+/// Handles the request, unless it's aborted on shutdown, answering 503 so that it's retried.
+async fn abort_on_shutdown(
+    State(requests): State<InFlightRequests>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let _in_flight = requests.lock.read_owned().await;
+    let mut aborted = requests.aborted;
+    tokio::select! {
+        response = next.run(req) => response,
+        Ok(_) = aborted.wait_for(|aborted| *aborted) => {
+            StatusCode::SERVICE_UNAVAILABLE.into_response()
+        }
+    }
+}
 
 async fn create_sink(
     config: &Config,
@@ -65,6 +106,19 @@ async fn create_sink(
                         partition.clean_state().await;
                     });
                 }
+                {
+                    let partition = partition.clone();
+                    let redis_client = redis_client.clone();
+                    let key = format!(
+                        "{}{OVERFLOW_FORCED_KEYS_CACHE_KEY}",
+                        config.redis_key_prefix.clone().unwrap_or_default()
+                    );
+                    tokio::spawn(async move {
+                        partition
+                            .refresh_forced_keys(redis_client, key, DYNAMIC_KEYS_INTERVAL)
+                            .await;
+                    });
+                }
                 Some(partition)
             }
         };
@@ -93,6 +147,37 @@ async fn create_sink(
         .await
         .expect("failed to start Kafka sink");
 
+        let kafka_sink: Arc<dyn Event + Send + Sync> = match &config.kafka_secondary_hosts {
+            None => Arc::new(kafka_sink),
+            Some(hosts) => {
+                // Not part of the liveness checks, so that secondary outages don't restart us
+                let secondary_liveness = HealthRegistry::new("secondary_kafka")
+                    .register("rdkafka".to_string(), Duration::seconds(30))
+                    .await;
+                // Overflow is only decided for the primary cluster
+                let secondary_sink = KafkaSink::new(
+                    KafkaConfig {
+                        kafka_hosts: hosts.clone(),
+                        ..config.kafka.clone()
+                    },
+                    secondary_liveness,
+                    None,
+                    None,
+                )
+                .await
+                .expect("failed to start secondary Kafka sink");
+
+                Arc::new(DualSink::new_with_health(
+                    kafka_sink,
+                    secondary_sink,
+                    config.kafka_secondary_policies.clone(),
+                    config.kafka_secondary_max_copies_in_flight,
+                    liveness.clone(),
+                    "rdkafka".to_string(),
+                ))
+            }
+        };
+
         if config.s3_fallback_enabled {
             let sink_liveness = liveness
                 .register("s3".to_string(), Duration::seconds(30))
@@ -105,6 +190,8 @@ async fn create_sink(
                     .expect("S3 bucket required when fallback enabled"),
                 config.s3_fallback_prefix.clone(),
                 config.s3_fallback_endpoint.clone(),
+                config.s3_fallback_compression,
+                config.s3_fallback_partitioned,
                 sink_liveness,
             )
             .await
@@ -116,6 +203,33 @@ async fn create_sink(
                 liveness.clone(),
                 "rdkafka".to_string(),
             )))
+        } else if config.disk_spill_enabled {
+            let sink_liveness = liveness
+                .register("disk_spill".to_string(), Duration::seconds(30))
+                .await;
+
+            let disk_sink = DiskSink::new(
+                config
+                    .disk_spill_path
+                    .clone()
+                    .expect("disk spill path required when spill enabled")
+                    .into(),
+                config.disk_spill_max_bytes,
+                config.disk_spill_segment_bytes,
+                kafka_sink.clone(),
+                liveness.clone(),
+                "rdkafka".to_string(),
+                sink_liveness,
+            )
+            .await
+            .expect("failed to create disk spill sink");
+
+            Ok(Box::new(FallbackSink::new_with_health(
+                kafka_sink,
+                disk_sink,
+                liveness.clone(),
+                "rdkafka".to_string(),
+            )))
         } else {
             Ok(Box::new(kafka_sink))
         }
@@ -146,46 +260,197 @@ where
     )
     .expect("failed to create billing limiter");
 
+    let rate_limiter = config.rate_limit_enabled.then(|| {
+        RequestRateLimiter::new(
+            config.rate_limit_default,
+            config.rate_limit_per_ip,
+            redis_client.clone(),
+            config.redis_key_prefix.clone(),
+            std::time::Duration::from_secs(30),
+        )
+    });
+
     let token_dropper = config
         .dropped_keys
         .clone()
         .map(|k| TokenDropper::new(&k))
         .unwrap_or_default();
+    {
+        let token_dropper = token_dropper.clone();
+        let redis_client = redis_client.clone();
+        let key = format!(
+            "{}{TOKEN_DROPPER_CACHE_KEY}",
+            config.redis_key_prefix.clone().unwrap_or_default()
+        );
+        tokio::spawn(async move {
+            token_dropper
+                .refresh(redis_client, key, DYNAMIC_KEYS_INTERVAL)
+                .await;
+        });
+    }
+
+    let event_rules = EventRules::default();
+    {
+        let event_rules = event_rules.clone();
+        let redis_client = redis_client.clone();
+        let key = format!(
+            "{}{EVENT_RULES_CACHE_KEY}",
+            config.redis_key_prefix.clone().unwrap_or_default()
+        );
+        tokio::spawn(async move {
+            event_rules
+                .refresh(redis_client, key, DYNAMIC_KEYS_INTERVAL)
+                .await;
+        });
+    }
 
     // In Recordings capture mode, we unpack a batch of events, and then pack them back up into
-    // a big blob and send to kafka all at once - so we should abort unpacking a batch if the data
-    // size crosses the kafka limit. In the Events mode, we can unpack the batch and send each
-    // event individually, so we should instead allow for some small multiple of our max compressed
-    // body size to be unpacked. If a single event is still too big, we'll drop it at kafka send time.
-    let event_max_bytes = match config.capture_mode {
-        CaptureMode::Events => BATCH_BODY_SIZE * 5,
-        CaptureMode::Recordings => config.kafka.kafka_producer_message_max_bytes as usize,
+    // blobs split under the kafka limit, so we allow for the max compressed body size to be
+    // unpacked. In the Events mode, we can unpack the batch and send each event individually,
+    // so we should instead allow for some small multiple of our max compressed body size to be
+    // unpacked. If a single event is still too big, we'll drop it at kafka send time.
+    // Both are scaled along the body size limits of the tokens that have one.
+    let (body_bytes, decompression_ratio) = match config.capture_mode {
+        CaptureMode::Events => (BATCH_BODY_SIZE, 5),
+        CaptureMode::Recordings => (RECORDING_BODY_SIZE, 1),
     };
+    let token_limits = TokenLimits::new(
+        config.token_limits.as_deref(),
+        config.token_limits_max_body_bytes,
+        body_bytes * decompression_ratio,
+    )
+    .expect("invalid token limits");
+    {
+        let token_limits = token_limits.clone();
+        let redis_client = redis_client.clone();
+        let key = format!(
+            "{}{TOKEN_LIMITS_CACHE_KEY}",
+            config.redis_key_prefix.clone().unwrap_or_default()
+        );
+        tokio::spawn(async move {
+            token_limits
+                .refresh(redis_client, key, DYNAMIC_KEYS_INTERVAL)
+                .await;
+        });
+    }
 
-    let sink = create_sink(&config, redis_client.clone(), &liveness)
-        .await
-        .expect("failed to create sink");
+    let cookieless = match config.cookieless_enabled {
+        false => None,
+        true => {
+            let salt_redis = match &config.cookieless_redis_url {
+                None => redis_client.clone(),
+                Some(url) => Arc::new(
+                    RedisClient::new(url.clone())
+                        .expect("failed to create cookieless redis client"),
+                ),
+            };
+            Some(CookielessProcessor::new(
+                cookieless_config(config.cookieless_force_stateless_mode),
+                redis_client.clone(),
+                salt_redis,
+            ))
+        }
+    };
+
+    let deduplicator = config.dedup_enabled.then(|| {
+        EventDeduplicator::new(
+            redis_client.clone(),
+            std::time::Duration::from_secs(config.dedup_window_secs),
+            config.dedup_local_cache_size,
+            config.redis_key_prefix.clone(),
+        )
+    });
+
+    // Largest decompressed size allowed to a token, see `TokenLimits::decompress`
+    let event_max_bytes = token_limits.route_body_limit(body_bytes) * decompression_ratio;
+
+    // Shared with the router, to be flushed once the server stopped
+    let sink: Arc<Box<dyn Event + Send + Sync>> = Arc::new(
+        create_sink(&config, redis_client.clone(), &liveness)
+            .await
+            .expect("failed to create sink"),
+    );
 
     let app = router::router(
         crate::time::SystemTime {},
         liveness,
-        sink,
+        sink.clone(),
         redis_client,
         billing_limiter,
+        rate_limiter,
         token_dropper,
+        event_rules,
+        token_limits,
+        cookieless,
+        deduplicator,
         config.export_prometheus,
         config.capture_mode,
         config.concurrency_limit,
         event_max_bytes,
+        config.kafka.kafka_producer_message_max_bytes as usize,
     );
+    let (abort_tx, aborted) = watch::channel(false);
+    let requests = InFlightRequests {
+        lock: Arc::new(RwLock::new(())),
+        aborted,
+    };
+    let in_flight_lock = requests.lock.clone();
+    let app = app.layer(axum::middleware::from_fn_with_state(
+        requests,
+        abort_on_shutdown,
+    ));
+
+    // Record when the shutdown signal was received, to bound the time spent draining requests
+    let (signal_tx, mut signal_rx) = watch::channel(None);
+    let shutdown = async move {
+        shutdown.await;
+        tracing::info!("draining in-flight requests");
+        drop(signal_tx.send(Some(Instant::now())));
+    };
+    let drain_timeout = std::time::Duration::from_secs(config.shutdown_drain_timeout_secs);
+    let drain_deadline = async move {
+        let signal = match signal_rx.wait_for(Option::is_some).await {
+            Ok(signal) => *signal,
+            Err(_) => None,
+        };
+        match signal {
+            Some(signal) => sleep_until(signal + drain_timeout).await,
+            None => std::future::pending().await,
+        }
+    };
 
     // run our app with hyper
     tracing::info!("listening on {:?}", listener.local_addr().unwrap());
-    axum::serve(
+    let server = axum::serve(
         listener,
         app.into_make_service_with_connect_info::<SocketAddr>(),
     )
     .with_graceful_shutdown(shutdown)
-    .await
-    .unwrap()
+    .into_future();
+
+    let timed_out = tokio::select! {
+        result = server => {
+            result.unwrap();
+            false
+        }
+        _ = drain_deadline => true,
+    };
+    if timed_out {
+        tracing::warn!(
+            "timed out draining requests after {:?}, aborting them",
+            drain_timeout
+        );
+        drop(abort_tx.send(true));
+        // Wait for the aborted requests to return, so that none of them writes to the sink while
+        // it's flushed. Requests arriving from now on wait for the lock, and are never handled.
+        let _aborted = in_flight_lock.write().await;
+    }
+
+    // Write out the events still queued by the sink, eg. from requests which were aborted
+    // after sending some of their events
+    let flush_timeout = std::time::Duration::from_secs(config.shutdown_flush_timeout_secs);
+    match sink.flush(flush_timeout).await {
+        Ok(()) => tracing::info!("flushed sink, exiting"),
+        Err(err) => tracing::error!("failed to flush sink on shutdown: {}", err),
+    }
 }
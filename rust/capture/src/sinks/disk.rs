@@ -0,0 +1,550 @@
+use std::collections::VecDeque;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use health::{HealthHandle, HealthRegistry};
+use metrics::{counter, gauge};
+use tokio::fs::{self, File, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+use tokio::task;
+use tokio::time::sleep;
+use tracing::instrument;
+use tracing::log::{error, info, warn};
+
+use crate::api::CaptureError;
+use crate::prometheus::report_dropped_events;
+use crate::sinks::Event;
+use crate::v0_request::ProcessedEvent;
+
+const REPLAY_INTERVAL: Duration = Duration::from_secs(1);
+const REPLAY_BATCH_SIZE: usize = 500;
+const SEGMENT_EXTENSION: &str = "log";
+
+struct Segment {
+    id: u64,
+    bytes: u64,
+}
+
+/// Append-only log, split in numbered segment files holding one JSON event per line.
+/// Events are appended to the active segment, only closed segments are replayed.
+struct Log {
+    dir: PathBuf,
+    max_bytes: u64,
+    segment_bytes: u64,
+    closed: VecDeque<Segment>, // Oldest first
+    active: Segment,
+    active_file: File,
+    total_bytes: u64,
+}
+
+impl Log {
+    async fn open(dir: PathBuf, max_bytes: u64, segment_bytes: u64) -> anyhow::Result<Log> {
+        fs::create_dir_all(&dir).await?;
+
+        // Segments left over by a previous process are replayed first
+        let mut closed = Vec::new();
+        let mut entries = fs::read_dir(&dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if path.extension().and_then(|e| e.to_str()) != Some(SEGMENT_EXTENSION) {
+                continue;
+            }
+            let Some(id) = path
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .and_then(|s| s.parse::<u64>().ok())
+            else {
+                continue;
+            };
+            closed.push(Segment {
+                id,
+                bytes: entry.metadata().await?.len(),
+            });
+        }
+        closed.sort_by_key(|s| s.id);
+
+        let total_bytes = closed.iter().map(|s| s.bytes).sum();
+        let active_id = closed.last().map_or(0, |s| s.id + 1);
+        let active_file = create_segment(&dir, active_id).await?;
+        if !closed.is_empty() {
+            info!(
+                "found {} spilled segments ({} bytes) in {:?}",
+                closed.len(),
+                total_bytes,
+                dir
+            );
+        }
+
+        Ok(Log {
+            dir,
+            max_bytes,
+            segment_bytes,
+            closed: closed.into(),
+            active: Segment {
+                id: active_id,
+                bytes: 0,
+            },
+            active_file,
+            total_bytes,
+        })
+    }
+
+    async fn append(&mut self, payload: &[u8]) -> Result<(), CaptureError> {
+        let len = payload.len() as u64;
+        if self.total_bytes + len > self.max_bytes {
+            return Err(CaptureError::RetryableSinkError);
+        }
+        if self.active.bytes > 0 && self.active.bytes + len > self.segment_bytes {
+            self.rotate().await?;
+        }
+
+        self.active_file
+            .write_all(payload)
+            .await
+            .map_err(io_error)?;
+        // This is our last copy of the events, make sure they survive a crash
+        self.active_file.sync_data().await.map_err(io_error)?;
+
+        self.active.bytes += len;
+        self.total_bytes += len;
+        gauge!("capture_disk_spill_bytes").set(self.total_bytes as f64);
+        Ok(())
+    }
+
+    /// Closes the active segment, and opens the next one.
+    async fn rotate(&mut self) -> Result<(), CaptureError> {
+        let next_id = self.active.id + 1;
+        self.active_file = create_segment(&self.dir, next_id).await.map_err(io_error)?;
+        let closed = std::mem::replace(
+            &mut self.active,
+            Segment {
+                id: next_id,
+                bytes: 0,
+            },
+        );
+        self.closed.push_back(closed);
+        Ok(())
+    }
+
+    /// Returns the oldest segment holding events, closing the active one if needed.
+    async fn oldest_segment(&mut self) -> Result<Option<u64>, CaptureError> {
+        if self.closed.is_empty() && self.active.bytes > 0 {
+            self.rotate().await?;
+        }
+        Ok(self.closed.front().map(|s| s.id))
+    }
+
+    async fn remove_segment(&mut self, id: u64) -> Result<(), CaptureError> {
+        if let Some(position) = self.closed.iter().position(|s| s.id == id) {
+            if let Some(segment) = self.closed.remove(position) {
+                self.total_bytes -= segment.bytes;
+            }
+        }
+        gauge!("capture_disk_spill_bytes").set(self.total_bytes as f64);
+        match fs::remove_file(segment_path(&self.dir, id)).await {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(io_error(e)),
+            _ => Ok(()),
+        }
+    }
+}
+
+struct Inner {
+    log: Mutex<Log>,
+    target: Arc<dyn Event + Send + Sync>,
+    health_registry: HealthRegistry,
+    target_component_name: String,
+    liveness: HealthHandle,
+    write_failed: AtomicBool,
+}
+
+/// DiskSink spills events to a bounded on-disk log, to be used as the fallback of a
+/// `FallbackSink` when no S3 bucket is available. A background task replays the log
+/// into the `target` sink, in order, while its health component reports healthy.
+///
+/// Delivery is at-least-once: events of a partially replayed segment are sent again
+/// if capture restarts before the segment is fully replayed.
+pub struct DiskSink {
+    inner: Arc<Inner>,
+}
+
+impl DiskSink {
+    pub async fn new(
+        path: PathBuf,
+        max_bytes: u64,
+        segment_bytes: u64,
+        target: Arc<dyn Event + Send + Sync>,
+        health_registry: HealthRegistry,
+        target_component_name: String,
+        liveness: HealthHandle,
+    ) -> anyhow::Result<DiskSink> {
+        info!("Initializing disk spill sink in {:?}", path);
+        let log = Log::open(path, max_bytes, segment_bytes).await?;
+        liveness.report_healthy().await;
+
+        let inner = Arc::new(Inner {
+            log: Mutex::new(log),
+            target,
+            health_registry,
+            target_component_name,
+            liveness,
+            write_failed: AtomicBool::new(false),
+        });
+
+        // Create weak reference for background task
+        let inner_weak = Arc::downgrade(&inner);
+
+        task::spawn(async move {
+            // Number of lines of the oldest segment already sent to the target
+            let mut replayed: (u64, usize) = (0, 0);
+            loop {
+                sleep(REPLAY_INTERVAL).await;
+
+                // Exit loop if the DiskSink was dropped
+                let Some(inner) = inner_weak.upgrade() else {
+                    break;
+                };
+                if !inner.write_failed.load(Ordering::Relaxed) {
+                    inner.liveness.report_healthy().await;
+                }
+                if inner.target_is_healthy() {
+                    if let Err(e) = inner.replay(&mut replayed).await {
+                        warn!("failed to replay spilled events, will retry: {}", e);
+                    }
+                }
+            }
+        });
+
+        Ok(DiskSink { inner })
+    }
+}
+
+impl Inner {
+    fn target_is_healthy(&self) -> bool {
+        self.health_registry
+            .get_status()
+            .components
+            .get(&self.target_component_name)
+            .map(|c| c.is_healthy())
+            .unwrap_or(false)
+    }
+
+    async fn append(&self, events: Vec<ProcessedEvent>) -> Result<(), CaptureError> {
+        let count = events.len() as u64;
+        let mut payload = Vec::new();
+        for event in events {
+            serde_json::to_writer(&mut payload, &event)?;
+            payload.push(b'\n');
+        }
+
+        let result = self.log.lock().await.append(&payload).await;
+        match &result {
+            Ok(_) => {
+                self.write_failed.store(false, Ordering::Relaxed);
+                counter!("capture_disk_spill_events_written_total").increment(count);
+            }
+            Err(CaptureError::RetryableSinkError) => {
+                error!("disk spill log is full, rejecting events");
+                report_dropped_events("disk_spill_full", count);
+            }
+            Err(_) => {
+                self.write_failed.store(true, Ordering::Relaxed);
+                report_dropped_events("disk_spill_error", count);
+            }
+        }
+        result
+    }
+
+    /// Replays segments, oldest first, until the log is empty or the target fails.
+    /// `replayed` holds the id of the segment being replayed, and how many of its
+    /// lines were already sent, so that retries don't send them again.
+    /// Events the target rejects as invalid are dropped, as they would fail every retry.
+    async fn replay(&self, replayed: &mut (u64, usize)) -> Result<(), CaptureError> {
+        loop {
+            let (id, dir) = {
+                let mut log = self.log.lock().await;
+                match log.oldest_segment().await? {
+                    None => return Ok(()),
+                    Some(id) => (id, log.dir.clone()),
+                }
+            };
+            if replayed.0 != id {
+                *replayed = (id, 0);
+            }
+
+            // Closed segments are not written to anymore, no need to hold the lock
+            let content = fs::read_to_string(segment_path(&dir, id))
+                .await
+                .map_err(io_error)?;
+            let lines: Vec<&str> = content.lines().skip(replayed.1).collect();
+
+            for chunk in lines.chunks(REPLAY_BATCH_SIZE) {
+                if !self.target_is_healthy() {
+                    return Err(CaptureError::RetryableSinkError);
+                }
+                let events: Vec<ProcessedEvent> = chunk
+                    .iter()
+                    .filter_map(|line| match serde_json::from_str(line) {
+                        Ok(event) => Some(event),
+                        Err(e) => {
+                            // Most likely a torn write, when the previous process crashed
+                            error!("skipping unreadable spilled event: {}", e);
+                            report_dropped_events("disk_spill_corrupted", 1);
+                            None
+                        }
+                    })
+                    .collect();
+
+                match self.target.send_batch(events.clone()).await {
+                    Ok(()) => counter!("capture_disk_spill_events_replayed_total")
+                        .increment(events.len() as u64),
+                    Err(e) if is_rejected(&e) => self.replay_one_by_one(events).await?,
+                    Err(e) => return Err(e),
+                }
+                replayed.1 += chunk.len();
+            }
+
+            self.log.lock().await.remove_segment(id).await?;
+            *replayed = (id + 1, 0);
+        }
+    }
+
+    /// Sends the events of a batch the target rejected one by one, to only drop the invalid ones.
+    /// Events of the batch the target already wrote are sent again, like on any replay retry.
+    async fn replay_one_by_one(&self, events: Vec<ProcessedEvent>) -> Result<(), CaptureError> {
+        for event in events {
+            match self.target.send(event).await {
+                Ok(()) => counter!("capture_disk_spill_events_replayed_total").increment(1),
+                Err(e) if is_rejected(&e) => {
+                    error!("dropping spilled event rejected by the target: {}", e);
+                    report_dropped_events("disk_spill_rejected", 1);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Event for DiskSink {
+    #[instrument(skip_all)]
+    async fn send(&self, event: ProcessedEvent) -> Result<(), CaptureError> {
+        self.inner.append(vec![event]).await
+    }
+
+    #[instrument(skip_all)]
+    async fn send_batch(&self, events: Vec<ProcessedEvent>) -> Result<(), CaptureError> {
+        self.inner.append(events).await
+    }
+}
+
+fn segment_path(dir: &Path, id: u64) -> PathBuf {
+    dir.join(format!("{id:020}.{SEGMENT_EXTENSION}"))
+}
+
+async fn create_segment(dir: &Path, id: u64) -> std::io::Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(segment_path(dir, id))
+        .await
+}
+
+fn is_rejected(e: &CaptureError) -> bool {
+    matches!(
+        e,
+        CaptureError::EventTooBig | CaptureError::NonRetryableSinkError
+    )
+}
+
+fn io_error(e: std::io::Error) -> CaptureError {
+    error!("disk spill io error: {}", e);
+    CaptureError::RetryableSinkError
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::uuid_v7;
+    use crate::v0_request::{DataType, ProcessedEventMetadata};
+    use common_types::CapturedEvent;
+    use std::sync::Mutex as StdMutex;
+    use tempfile::TempDir;
+
+    #[derive(Default)]
+    struct MemorySink {
+        events: StdMutex<Vec<ProcessedEvent>>,
+        // Events with this distinct_id are rejected, failing their whole batch
+        poison: Option<&'static str>,
+    }
+
+    impl MemorySink {
+        fn is_poison(&self, event: &ProcessedEvent) -> bool {
+            self.poison == Some(event.event.distinct_id.as_str())
+        }
+    }
+
+    #[async_trait]
+    impl Event for MemorySink {
+        async fn send(&self, event: ProcessedEvent) -> Result<(), CaptureError> {
+            if self.is_poison(&event) {
+                return Err(CaptureError::EventTooBig);
+            }
+            self.events.lock().unwrap().push(event);
+            Ok(())
+        }
+        async fn send_batch(&self, events: Vec<ProcessedEvent>) -> Result<(), CaptureError> {
+            if events.iter().any(|e| self.is_poison(e)) {
+                return Err(CaptureError::EventTooBig);
+            }
+            self.events.lock().unwrap().extend(events);
+            Ok(())
+        }
+    }
+
+    fn event(distinct_id: &str) -> ProcessedEvent {
+        ProcessedEvent {
+            event: CapturedEvent {
+                uuid: uuid_v7(),
+                distinct_id: distinct_id.to_string(),
+                ip: "127.0.0.1".to_string(),
+                data: "test data".to_string(),
+                now: "2024-01-01T00:00:00Z".to_string(),
+                sent_at: None,
+                token: "test_token".to_string(),
+                is_cookieless_mode: false,
+            },
+            metadata: ProcessedEventMetadata {
+                data_type: DataType::AnalyticsMain,
+                session_id: None,
+            },
+        }
+    }
+
+    async fn sink(
+        dir: &TempDir,
+        max_bytes: u64,
+        target: Arc<MemorySink>,
+    ) -> (DiskSink, HealthHandle) {
+        let registry = HealthRegistry::new("liveness");
+        let target_health = registry
+            .register("target".to_string(), time::Duration::seconds(30))
+            .await;
+        let liveness = registry
+            .register("disk".to_string(), time::Duration::seconds(30))
+            .await;
+        let sink = DiskSink::new(
+            dir.path().to_path_buf(),
+            max_bytes,
+            256,
+            target,
+            registry,
+            "target".to_string(),
+            liveness,
+        )
+        .await
+        .expect("failed to create sink");
+        (sink, target_health)
+    }
+
+    #[tokio::test]
+    async fn replays_in_order_once_target_is_healthy() {
+        let dir = TempDir::new().unwrap();
+        let target = Arc::new(MemorySink::default());
+        let (sink, target_health) = sink(&dir, 1024 * 1024, target.clone()).await;
+
+        for i in 0..10 {
+            sink.send(event(&format!("id{i}"))).await.unwrap();
+        }
+        sink.send_batch(vec![event("id10"), event("id11")])
+            .await
+            .unwrap();
+
+        // Target is unhealthy, nothing is replayed
+        sleep(REPLAY_INTERVAL * 2).await;
+        assert!(target.events.lock().unwrap().is_empty());
+
+        target_health.report_healthy().await;
+        sleep(REPLAY_INTERVAL * 2).await;
+
+        let replayed: Vec<String> = target
+            .events
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|e| e.event.distinct_id.clone())
+            .collect();
+        let expected: Vec<String> = (0..12).map(|i| format!("id{i}")).collect();
+        assert_eq!(expected, replayed);
+        assert_eq!(0, sink.inner.log.lock().await.total_bytes);
+    }
+
+    #[tokio::test]
+    async fn drops_events_rejected_by_target() {
+        let dir = TempDir::new().unwrap();
+        let target = Arc::new(MemorySink {
+            poison: Some("poison"),
+            ..Default::default()
+        });
+        let (sink, target_health) = sink(&dir, 1024 * 1024, target.clone()).await;
+
+        sink.send_batch(vec![event("id0"), event("poison"), event("id1")])
+            .await
+            .unwrap();
+        sink.send(event("id2")).await.unwrap();
+
+        target_health.report_healthy().await;
+        sleep(REPLAY_INTERVAL * 2).await;
+
+        let replayed: Vec<String> = target
+            .events
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|e| e.event.distinct_id.clone())
+            .collect();
+        assert_eq!(vec!["id0", "id1", "id2"], replayed);
+        assert_eq!(0, sink.inner.log.lock().await.total_bytes);
+    }
+
+    #[tokio::test]
+    async fn rejects_events_when_full() {
+        let dir = TempDir::new().unwrap();
+        let target = Arc::new(MemorySink::default());
+        let (sink, _) = sink(&dir, 512, target).await;
+
+        let mut written = 0;
+        while sink.send(event("id")).await.is_ok() {
+            written += 1;
+        }
+        assert!(written > 0);
+        assert!(matches!(
+            sink.send(event("id")).await,
+            Err(CaptureError::RetryableSinkError)
+        ));
+    }
+
+    #[tokio::test]
+    async fn reopens_existing_segments() {
+        let dir = TempDir::new().unwrap();
+        {
+            let target = Arc::new(MemorySink::default());
+            let (sink, _) = sink(&dir, 1024 * 1024, target).await;
+            sink.send_batch(vec![event("id1"), event("id2")])
+                .await
+                .unwrap();
+        }
+
+        let log = Log::open(dir.path().to_path_buf(), 1024 * 1024, 256)
+            .await
+            .unwrap();
+        assert_eq!(1, log.closed.len());
+        assert!(log.total_bytes > 0);
+        assert_eq!(log.closed[0].id + 1, log.active.id);
+    }
+}
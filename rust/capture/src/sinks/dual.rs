@@ -0,0 +1,462 @@
+//! Sink wrapping a primary and a secondary Kafka cluster, to move traffic between clusters
+//! without a risky cut-over.
+//!
+//! Each data type is routed with one of these policies:
+//!   - `primary`: only sent to the primary cluster, the default,
+//!   - `mirror`: sent to both clusters,
+//!   - `failover`: sent to the secondary cluster while the primary one is unhealthy,
+//!   - `shadow:<percent>`: sent to the primary cluster, and a random share to both.
+//!
+//! Copies sent to the secondary cluster are best-effort: they're sent in the background, so that
+//! a slow secondary doesn't delay requests, and dropped with a warning while more than
+//! `KAFKA_SECONDARY_MAX_COPIES_IN_FLIGHT` sends are in flight. Only the primary's result is
+//! returned.
+//!
+//! Policies are configured as `<data_type>=<policy>,...`, with the snake_case `DataType`
+//! names, for example `analytics_main=shadow:10,exception_main=mirror`.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use health::HealthRegistry;
+use metrics::counter;
+use serde_json::Value;
+use tokio::sync::{oneshot, Semaphore};
+use tracing::instrument;
+use tracing::log::warn;
+
+use crate::api::CaptureError;
+use crate::sinks::fallback::watch_primary_health;
+use crate::sinks::{Destination, Event};
+use crate::v0_request::{DataType, ProcessedEvent};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RoutingPolicy {
+    Primary,
+    Mirror,
+    Failover,
+    Shadow(f64), // Percentage of events copied to the secondary cluster
+}
+
+impl std::str::FromStr for RoutingPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let policy = s.trim().to_lowercase();
+        match policy.split_once(':') {
+            None => match policy.as_ref() {
+                "primary" => Ok(RoutingPolicy::Primary),
+                "mirror" => Ok(RoutingPolicy::Mirror),
+                "failover" => Ok(RoutingPolicy::Failover),
+                _ => Err(format!("Unknown routing policy: {s}")),
+            },
+            Some(("shadow", percent)) => match percent.trim().parse::<f64>() {
+                Ok(percent) if (0.0..=100.0).contains(&percent) => {
+                    Ok(RoutingPolicy::Shadow(percent))
+                }
+                _ => Err(format!("Invalid shadow percentage: {s}")),
+            },
+            Some(_) => Err(format!("Unknown routing policy: {s}")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RoutingPolicies(HashMap<DataType, RoutingPolicy>);
+
+impl RoutingPolicies {
+    pub fn get(&self, data_type: DataType) -> RoutingPolicy {
+        self.0
+            .get(&data_type)
+            .copied()
+            .unwrap_or(RoutingPolicy::Primary)
+    }
+}
+
+impl std::str::FromStr for RoutingPolicies {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut policies = HashMap::new();
+        for pair in s.split(',').filter(|pair| !pair.trim().is_empty()) {
+            let (data_type, policy) = pair
+                .split_once('=')
+                .ok_or_else(|| format!("Invalid routing policy: {pair}"))?;
+            let data_type: DataType =
+                serde_json::from_value(Value::String(data_type.trim().to_string()))
+                    .map_err(|_| format!("Unknown data type: {data_type}"))?;
+            policies.insert(data_type, policy.parse()?);
+        }
+        Ok(RoutingPolicies(policies))
+    }
+}
+
+enum Target {
+    Primary,
+    Secondary,
+    Both,
+}
+
+pub struct DualSink {
+    primary: Arc<dyn Event + Send + Sync>,
+    secondary: Arc<dyn Event + Send + Sync>,
+    policies: RoutingPolicies,
+    primary_is_healthy: Arc<AtomicBool>,
+    copy_permits: Arc<Semaphore>,
+    max_copies_in_flight: usize,
+    shutdown_tx: Option<oneshot::Sender<()>>,
+}
+
+impl DualSink {
+    pub fn new<P, S>(
+        primary: P,
+        secondary: S,
+        policies: RoutingPolicies,
+        max_copies_in_flight: usize,
+    ) -> Self
+    where
+        P: Event + Send + Sync + 'static,
+        S: Event + Send + Sync + 'static,
+    {
+        Self {
+            primary: Arc::new(primary),
+            secondary: Arc::new(secondary),
+            policies,
+            primary_is_healthy: Arc::new(AtomicBool::new(true)),
+            copy_permits: Arc::new(Semaphore::new(max_copies_in_flight)),
+            max_copies_in_flight,
+            shutdown_tx: None,
+        }
+    }
+
+    /// The `failover` policy is only applied when the primary's health is tracked.
+    pub fn new_with_health<P, S>(
+        primary: P,
+        secondary: S,
+        policies: RoutingPolicies,
+        max_copies_in_flight: usize,
+        health_registry: HealthRegistry,
+        primary_component_name: String,
+    ) -> Self
+    where
+        P: Event + Send + Sync + 'static,
+        S: Event + Send + Sync + 'static,
+    {
+        let (primary_is_healthy, shutdown_tx) =
+            watch_primary_health(health_registry, primary_component_name);
+        Self {
+            primary: Arc::new(primary),
+            secondary: Arc::new(secondary),
+            policies,
+            primary_is_healthy,
+            copy_permits: Arc::new(Semaphore::new(max_copies_in_flight)),
+            max_copies_in_flight,
+            shutdown_tx: Some(shutdown_tx),
+        }
+    }
+
+    fn target(&self, event: &ProcessedEvent) -> Target {
+        match self.policies.get(event.metadata.data_type) {
+            RoutingPolicy::Primary => Target::Primary,
+            RoutingPolicy::Mirror => Target::Both,
+            RoutingPolicy::Failover => match self.primary_is_healthy.load(Ordering::Relaxed) {
+                true => Target::Primary,
+                false => Target::Secondary,
+            },
+            RoutingPolicy::Shadow(percent) => match rand::random::<f64>() * 100.0 < percent {
+                true => Target::Both,
+                false => Target::Primary,
+            },
+        }
+    }
+
+    /// Sends copies to the secondary in a detached task, off the request path
+    fn spawn_copies(&self, events: Vec<ProcessedEvent>) {
+        let count = events.len();
+        let Ok(permit) = self.copy_permits.clone().try_acquire_owned() else {
+            warn!(
+                "dropped {} copies to the secondary sink, {} sends already in flight",
+                count, self.max_copies_in_flight
+            );
+            counter!("capture_dual_sink_copies_total", "outcome" => "dropped")
+                .increment(count as u64);
+            return;
+        };
+        let secondary = self.secondary.clone();
+        tokio::spawn(async move {
+            Self::report_copies(send_all(&secondary, events).await, count);
+            drop(permit);
+        });
+    }
+
+    fn report_copies(result: Result<(), CaptureError>, count: usize) {
+        let outcome = match result {
+            Ok(()) => "success",
+            Err(err) => {
+                warn!("failed to copy events to the secondary sink: {}", err);
+                "failure"
+            }
+        };
+        counter!("capture_dual_sink_copies_total", "outcome" => outcome).increment(count as u64);
+    }
+}
+
+async fn send_all(
+    sink: &Arc<dyn Event + Send + Sync>,
+    mut events: Vec<ProcessedEvent>,
+) -> Result<(), CaptureError> {
+    match events.len() {
+        0 => Ok(()),
+        1 => sink.send(events.pop().unwrap()).await,
+        _ => sink.send_batch(events).await,
+    }
+}
+
+#[async_trait]
+impl Event for DualSink {
+    #[instrument(skip_all)]
+    async fn send(&self, event: ProcessedEvent) -> Result<(), CaptureError> {
+        match self.target(&event) {
+            Target::Primary => self.primary.send(event).await,
+            Target::Secondary => {
+                counter!("capture_dual_sink_failovers_total").increment(1);
+                self.secondary.send(event).await
+            }
+            Target::Both => {
+                self.spawn_copies(vec![event.clone()]);
+                self.primary.send(event).await
+            }
+        }
+    }
+
+    #[instrument(skip_all)]
+    async fn send_batch(&self, events: Vec<ProcessedEvent>) -> Result<(), CaptureError> {
+        let mut primary = Vec::with_capacity(events.len());
+        let mut failover = Vec::new();
+        let mut copies = Vec::new();
+        for event in events {
+            match self.target(&event) {
+                Target::Primary => primary.push(event),
+                Target::Secondary => failover.push(event),
+                Target::Both => {
+                    copies.push(event.clone());
+                    primary.push(event);
+                }
+            }
+        }
+        if !failover.is_empty() {
+            counter!("capture_dual_sink_failovers_total").increment(failover.len() as u64);
+        }
+
+        if !copies.is_empty() {
+            self.spawn_copies(copies);
+        }
+        let (primary, failover) = tokio::join!(
+            send_all(&self.primary, primary),
+            send_all(&self.secondary, failover)
+        );
+        primary.and(failover)
+    }
+
+    fn destination(&self, event: &ProcessedEvent) -> Option<Destination> {
+        match self.target(event) {
+            Target::Secondary => self.secondary.destination(event),
+            Target::Primary | Target::Both => self.primary.destination(event),
+        }
+    }
+
+    async fn flush(&self, timeout: Duration) -> Result<(), CaptureError> {
+        // Waits for the copies in flight, which are then flushed with the secondary
+        let _ = tokio::time::timeout(
+            timeout,
+            self.copy_permits
+                .acquire_many(self.max_copies_in_flight as u32),
+        )
+        .await;
+        let (primary, secondary) =
+            tokio::join!(self.primary.flush(timeout), self.secondary.flush(timeout));
+        primary.and(secondary)
+    }
+}
+
+impl Drop for DualSink {
+    fn drop(&mut self) {
+        if let Some(shutdown_tx) = self.shutdown_tx.take() {
+            drop(shutdown_tx);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use common_types::CapturedEvent;
+
+    use super::*;
+    use crate::utils::uuid_v7;
+    use crate::v0_request::ProcessedEventMetadata;
+
+    #[derive(Clone, Default)]
+    struct MemorySink {
+        events: Arc<Mutex<Vec<ProcessedEvent>>>,
+    }
+
+    impl MemorySink {
+        fn len(&self) -> usize {
+            self.events.lock().unwrap().len()
+        }
+    }
+
+    // Never completes a send
+    struct StuckSink;
+
+    #[async_trait]
+    impl Event for StuckSink {
+        async fn send(&self, _: ProcessedEvent) -> Result<(), CaptureError> {
+            std::future::pending().await
+        }
+        async fn send_batch(&self, _: Vec<ProcessedEvent>) -> Result<(), CaptureError> {
+            std::future::pending().await
+        }
+    }
+
+    // Copies are sent in the background
+    async fn wait_for_copies(sink: &MemorySink, len: usize) {
+        for _ in 0..100 {
+            if sink.len() >= len {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    #[async_trait]
+    impl Event for MemorySink {
+        async fn send(&self, event: ProcessedEvent) -> Result<(), CaptureError> {
+            self.events.lock().unwrap().push(event);
+            Ok(())
+        }
+        async fn send_batch(&self, events: Vec<ProcessedEvent>) -> Result<(), CaptureError> {
+            self.events.lock().unwrap().extend(events);
+            Ok(())
+        }
+    }
+
+    fn event(data_type: DataType) -> ProcessedEvent {
+        ProcessedEvent {
+            event: CapturedEvent {
+                uuid: uuid_v7(),
+                distinct_id: "test_id".to_string(),
+                ip: "127.0.0.1".to_string(),
+                data: "test data".to_string(),
+                now: "2024-01-01T00:00:00Z".to_string(),
+                sent_at: None,
+                token: "test_token".to_string(),
+                is_cookieless_mode: false,
+            },
+            metadata: ProcessedEventMetadata {
+                data_type,
+                session_id: None,
+            },
+        }
+    }
+
+    #[test]
+    fn parses_policies() {
+        let policies: RoutingPolicies =
+            "analytics_main=shadow:10, exception_main=mirror,heatmap_main=failover"
+                .parse()
+                .unwrap();
+        assert_eq!(
+            RoutingPolicy::Shadow(10.0),
+            policies.get(DataType::AnalyticsMain)
+        );
+        assert_eq!(RoutingPolicy::Mirror, policies.get(DataType::ExceptionMain));
+        assert_eq!(RoutingPolicy::Failover, policies.get(DataType::HeatmapMain));
+        assert_eq!(RoutingPolicy::Primary, policies.get(DataType::SnapshotMain));
+
+        assert_eq!(RoutingPolicies::default(), "".parse().unwrap());
+        assert!("analytics_main".parse::<RoutingPolicies>().is_err());
+        assert!("unknown=mirror".parse::<RoutingPolicies>().is_err());
+        assert!("analytics_main=shadow:200"
+            .parse::<RoutingPolicies>()
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn routes_per_data_type() {
+        let (primary, secondary) = (MemorySink::default(), MemorySink::default());
+        let policies = "exception_main=mirror,heatmap_main=shadow:100,client_ingestion_warning=shadow:0,analytics_main=failover"
+            .parse()
+            .unwrap();
+        let sink = DualSink::new(primary.clone(), secondary.clone(), policies, 1000);
+
+        sink.send(event(DataType::ExceptionMain)).await.unwrap();
+        wait_for_copies(&secondary, 1).await;
+        assert_eq!((1, 1), (primary.len(), secondary.len()));
+
+        sink.send_batch(vec![
+            event(DataType::AnalyticsMain), // Primary is healthy
+            event(DataType::AnalyticsHistorical),
+            event(DataType::HeatmapMain),
+            event(DataType::ClientIngestionWarning),
+        ])
+        .await
+        .unwrap();
+        wait_for_copies(&secondary, 2).await;
+        assert_eq!((5, 2), (primary.len(), secondary.len()));
+
+        // Fails over to the secondary while the primary is unhealthy
+        sink.primary_is_healthy.store(false, Ordering::Relaxed);
+        sink.send(event(DataType::AnalyticsMain)).await.unwrap();
+        sink.send(event(DataType::AnalyticsHistorical))
+            .await
+            .unwrap();
+        assert_eq!((6, 3), (primary.len(), secondary.len()));
+    }
+
+    #[tokio::test]
+    async fn does_not_wait_for_copies() {
+        let primary = MemorySink::default();
+        let policies = "analytics_main=mirror".parse().unwrap();
+        let sink = DualSink::new(primary.clone(), StuckSink, policies, 1000);
+
+        let sent = tokio::time::timeout(Duration::from_secs(1), async {
+            sink.send(event(DataType::AnalyticsMain)).await.unwrap();
+            sink.send_batch(vec![
+                event(DataType::AnalyticsMain),
+                event(DataType::AnalyticsMain),
+            ])
+            .await
+            .unwrap();
+        })
+        .await;
+        assert!(sent.is_ok());
+        assert_eq!(3, primary.len());
+    }
+
+    #[tokio::test]
+    async fn drops_copies_over_the_bound() {
+        let (primary, secondary) = (MemorySink::default(), MemorySink::default());
+        let policies = "analytics_main=mirror".parse().unwrap();
+        let stuck = DualSink::new(primary.clone(), StuckSink, policies, 1);
+
+        stuck.send(event(DataType::AnalyticsMain)).await.unwrap();
+        stuck.send(event(DataType::AnalyticsMain)).await.unwrap();
+        assert_eq!(0, stuck.copy_permits.available_permits());
+        assert_eq!(2, primary.len());
+
+        // Permits are given back once the copies are sent
+        let policies = "analytics_main=mirror".parse().unwrap();
+        let sink = DualSink::new(primary.clone(), secondary.clone(), policies, 1);
+        sink.send(event(DataType::AnalyticsMain)).await.unwrap();
+        wait_for_copies(&secondary, 1).await;
+        sink.send(event(DataType::AnalyticsMain)).await.unwrap();
+        wait_for_copies(&secondary, 2).await;
+        assert_eq!(2, secondary.len());
+    }
+}
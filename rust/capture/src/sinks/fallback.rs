@@ -1,5 +1,5 @@
 use crate::api::CaptureError;
-use crate::sinks::Event;
+use crate::sinks::{Destination, Event};
 use crate::v0_request::ProcessedEvent;
 use health::HealthRegistry;
 use std::time::Duration;
@@ -46,50 +46,8 @@ impl FallbackSink {
         P: Event + Send + Sync + 'static,
         F: Event + Send + Sync + 'static,
     {
-        if !health_registry
-            .get_status()
-            .components
-            .contains_key(&primary_component_name)
-        {
-            panic!(
-                "health registry does not contain primary component {}",
-                primary_component_name
-            )
-        }
-
-        let (shutdown_tx, mut shutdown_rx) = oneshot::channel();
-        let primary_is_healthy = Arc::new(AtomicBool::new(true));
-        let thread_healthy = primary_is_healthy.clone();
-        gauge!("capture_primary_sink_health").set(1.0);
-
-        // Asynchronously update primary health status every 10 seconds
-        // this means if the primary starts failing we'll stop trying to send to it until it recovers.
-        task::spawn(async move {
-            loop {
-                tokio::select! {
-                    _ = sleep(Duration::from_millis(10000)) => {
-                        let is_healthy = health_registry
-                            .get_status()
-                            .components
-                            .get(&primary_component_name)
-                            .map(|c| c.is_healthy())
-                            .unwrap_or(false);
-                        let was_healthy = thread_healthy.load(Ordering::Relaxed);
-                        if was_healthy && !is_healthy {
-                            error!("primary sink has become unhealthy");
-                            gauge!("capture_primary_sink_health").set(0.0);
-                        } else if !was_healthy && is_healthy {
-                            warn!("primary sink has recovered");
-                            gauge!("capture_primary_sink_health").set(1.0);
-                        }
-                        thread_healthy.store(is_healthy, Ordering::Relaxed);
-                    }
-                    _ = &mut shutdown_rx => {
-                        break;
-                    }
-                }
-            }
-        });
+        let (primary_is_healthy, shutdown_tx) =
+            watch_primary_health(health_registry, primary_component_name);
 
         Self {
             primary: Arc::new(Box::new(primary)),
@@ -100,8 +58,66 @@ impl FallbackSink {
     }
 }
 
+/// Tracks the health of the primary sink's component in the registry, polled every 10 seconds
+/// until the returned sender is dropped.
+pub(crate) fn watch_primary_health(
+    health_registry: HealthRegistry,
+    primary_component_name: String,
+) -> (Arc<AtomicBool>, oneshot::Sender<()>) {
+    if !health_registry
+        .get_status()
+        .components
+        .contains_key(&primary_component_name)
+    {
+        panic!(
+            "health registry does not contain primary component {}",
+            primary_component_name
+        )
+    }
+
+    let (shutdown_tx, mut shutdown_rx) = oneshot::channel();
+    let primary_is_healthy = Arc::new(AtomicBool::new(true));
+    let thread_healthy = primary_is_healthy.clone();
+    gauge!("capture_primary_sink_health").set(1.0);
+
+    // Asynchronously update primary health status every 10 seconds
+    // this means if the primary starts failing we'll stop trying to send to it until it recovers.
+    task::spawn(async move {
+        loop {
+            tokio::select! {
+                _ = sleep(Duration::from_millis(10000)) => {
+                    let is_healthy = health_registry
+                        .get_status()
+                        .components
+                        .get(&primary_component_name)
+                        .map(|c| c.is_healthy())
+                        .unwrap_or(false);
+                    let was_healthy = thread_healthy.load(Ordering::Relaxed);
+                    if was_healthy && !is_healthy {
+                        error!("primary sink has become unhealthy");
+                        gauge!("capture_primary_sink_health").set(0.0);
+                    } else if !was_healthy && is_healthy {
+                        warn!("primary sink has recovered");
+                        gauge!("capture_primary_sink_health").set(1.0);
+                    }
+                    thread_healthy.store(is_healthy, Ordering::Relaxed);
+                }
+                _ = &mut shutdown_rx => {
+                    break;
+                }
+            }
+        }
+    });
+
+    (primary_is_healthy, shutdown_tx)
+}
+
 #[async_trait]
 impl Event for FallbackSink {
+    fn destination(&self, event: &ProcessedEvent) -> Option<Destination> {
+        self.primary.destination(event)
+    }
+
     #[instrument(skip_all)]
     async fn send(&self, event: ProcessedEvent) -> Result<(), CaptureError> {
         if self.primary_is_healthy.load(Ordering::Relaxed) {
@@ -137,6 +153,12 @@ impl Event for FallbackSink {
             self.fallback.send_batch(events).await
         }
     }
+
+    async fn flush(&self, timeout: Duration) -> Result<(), CaptureError> {
+        let (primary, fallback) =
+            tokio::join!(self.primary.flush(timeout), self.fallback.flush(timeout));
+        primary.and(fallback)
+    }
 }
 
 impl Drop for FallbackSink {
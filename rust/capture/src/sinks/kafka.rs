@@ -1,7 +1,7 @@
 use crate::api::CaptureError;
 use crate::config::KafkaConfig;
 use crate::prometheus::report_dropped_events;
-use crate::sinks::Event;
+use crate::sinks::{Destination, Event};
 use crate::v0_request::{DataType, ProcessedEvent};
 use async_trait::async_trait;
 use health::HealthHandle;
@@ -117,6 +117,7 @@ pub struct KafkaSink {
     client_ingestion_warning_topic: String,
     exceptions_topic: String,
     heatmaps_topic: String,
+    logs_topic: String,
     replay_overflow_limiter: Option<RedisLimiter>,
     replay_overflow_topic: String,
 }
@@ -197,14 +198,35 @@ impl KafkaSink {
             client_ingestion_warning_topic: config.kafka_client_ingestion_warning_topic,
             exceptions_topic: config.kafka_exceptions_topic,
             heatmaps_topic: config.kafka_heatmaps_topic,
+            logs_topic: config.kafka_logs_topic,
             replay_overflow_topic: config.kafka_replay_overflow_topic,
             replay_overflow_limiter,
         })
     }
 
-    pub fn flush(&self) -> Result<(), KafkaError> {
-        // TODO: hook it up on shutdown
-        self.producer.flush(Duration::new(30, 0))
+    // Topic and partition key of an event, before overflow rerouting
+    fn route<'a>(
+        &'a self,
+        data_type: DataType,
+        event_key: &'a str,
+        token: &'a str,
+        session_id: Option<&'a str>,
+    ) -> Result<(&'a str, Option<&'a str>), CaptureError> {
+        let route: (&str, Option<&str>) = match data_type {
+            DataType::AnalyticsHistorical => (&self.historical_topic, Some(event_key)), // We never trigger overflow on historical events
+            DataType::AnalyticsMain => (&self.main_topic, Some(event_key)),
+            DataType::ClientIngestionWarning => {
+                (&self.client_ingestion_warning_topic, Some(event_key))
+            }
+            DataType::HeatmapMain => (&self.heatmaps_topic, Some(event_key)),
+            DataType::ExceptionMain => (&self.exceptions_topic, Some(event_key)),
+            DataType::LogMain => (&self.logs_topic, Some(token)),
+            DataType::SnapshotMain => (
+                &self.main_topic,
+                Some(session_id.ok_or(CaptureError::MissingSessionId)?),
+            ),
+        };
+        Ok(route)
     }
 
     async fn kafka_send(&self, event: ProcessedEvent) -> Result<DeliveryFuture, CaptureError> {
@@ -223,8 +245,9 @@ impl KafkaSink {
 
         drop(event); // Events can be EXTREMELY memory hungry
 
-        let (topic, partition_key): (&str, Option<&str>) = match data_type {
-            DataType::AnalyticsHistorical => (&self.historical_topic, Some(event_key.as_str())), // We never trigger overflow on historical events
+        let (mut topic, mut partition_key) =
+            self.route(data_type, &event_key, &token, session_id.as_deref())?;
+        match data_type {
             DataType::AnalyticsMain => {
                 // TODO: deprecate capture-led overflow or move logic in handler
                 let is_limited = match &self.partition {
@@ -232,33 +255,20 @@ impl KafkaSink {
                     Some(partition) => partition.is_limited(&event_key),
                 };
                 if is_limited {
-                    (&self.main_topic, None) // Analytics overflow goes to the main topic without locality
-                } else {
-                    (&self.main_topic, Some(event_key.as_str()))
+                    partition_key = None; // Analytics overflow goes to the main topic without locality
                 }
             }
-            DataType::ClientIngestionWarning => (
-                &self.client_ingestion_warning_topic,
-                Some(event_key.as_str()),
-            ),
-            DataType::HeatmapMain => (&self.heatmaps_topic, Some(event_key.as_str())),
-            DataType::ExceptionMain => (&self.exceptions_topic, Some(event_key.as_str())),
             DataType::SnapshotMain => {
-                let session_id = session_id
-                    .as_deref()
-                    .ok_or(CaptureError::MissingSessionId)?;
-                let is_overflowing = match &self.replay_overflow_limiter {
-                    None => false,
-                    Some(limiter) => limiter.is_limited(session_id).await,
+                let is_overflowing = match (&self.replay_overflow_limiter, partition_key) {
+                    (Some(limiter), Some(session_id)) => limiter.is_limited(session_id).await,
+                    _ => false,
                 };
-
                 if is_overflowing {
-                    (&self.replay_overflow_topic, Some(session_id))
-                } else {
-                    (&self.main_topic, Some(session_id))
+                    topic = self.replay_overflow_topic.as_str();
                 }
             }
-        };
+            _ => {}
+        }
 
         match self.producer.send_result(FutureRecord {
             topic,
@@ -285,7 +295,7 @@ impl KafkaSink {
                     Err(CaptureError::EventTooBig)
                 }
                 _ => {
-                    // TODO(maybe someday): Don't drop them but write them somewhere and try again
+                    // Retryable, so that a FallbackSink can spill them to S3 or disk
                     report_dropped_events("kafka_write_error", 1);
                     error!("failed to produce event: {}", e);
                     Err(CaptureError::RetryableSinkError)
@@ -323,6 +333,22 @@ impl KafkaSink {
 
 #[async_trait]
 impl Event for KafkaSink {
+    fn destination(&self, event: &ProcessedEvent) -> Option<Destination> {
+        let event_key = event.event.key();
+        let (topic, partition_key) = self
+            .route(
+                event.metadata.data_type,
+                &event_key,
+                &event.event.token,
+                event.metadata.session_id.as_deref(),
+            )
+            .ok()?;
+        Some(Destination {
+            topic: topic.to_string(),
+            partition_key: partition_key.map(String::from),
+        })
+    }
+
     #[instrument(skip_all)]
     async fn send(&self, event: ProcessedEvent) -> Result<(), CaptureError> {
         let ack = self.kafka_send(event).await?;
@@ -332,6 +358,33 @@ impl Event for KafkaSink {
             .await
     }
 
+    async fn flush(&self, timeout: Duration) -> Result<(), CaptureError> {
+        // rdkafka's flush blocks until the queue is empty or the timeout expires
+        let producer = self.producer.clone();
+        let result = tokio::task::spawn_blocking(move || {
+            producer
+                .flush(timeout)
+                .map_err(|err| (err, producer.in_flight_count()))
+        })
+        .await
+        .map_err(|_| CaptureError::RetryableSinkError)?;
+
+        match result {
+            Ok(()) => {
+                info!("flushed the Kafka producer queue");
+                Ok(())
+            }
+            Err((err, in_flight)) => {
+                error!(
+                    "failed to flush the Kafka producer queue, {} messages lost: {}",
+                    in_flight, err
+                );
+                report_dropped_events("kafka_shutdown_flush", in_flight.max(0) as u64);
+                Err(CaptureError::RetryableSinkError)
+            }
+        }
+    }
+
     #[instrument(skip_all)]
     async fn send_batch(&self, events: Vec<ProcessedEvent>) -> Result<(), CaptureError> {
         let mut set = JoinSet::new();
@@ -414,6 +467,7 @@ mod tests {
             kafka_client_ingestion_warning_topic: "events_plugin_ingestion".to_string(),
             kafka_exceptions_topic: "events_plugin_ingestion".to_string(),
             kafka_heatmaps_topic: "events_plugin_ingestion".to_string(),
+            kafka_logs_topic: "logs_ingestion".to_string(),
             kafka_replay_overflow_topic: "session_recording_snapshot_item_overflow".to_string(),
             kafka_tls: false,
             kafka_client_id: "".to_string(),
@@ -1,15 +1,40 @@
+use std::sync::Arc;
+use std::time::Duration;
+
 use async_trait::async_trait;
 
 use crate::{api::CaptureError, v0_request::ProcessedEvent};
 
+pub mod disk;
+pub mod dual;
 pub mod fallback;
 pub mod kafka;
 pub mod print;
 pub mod s3;
+
+/// Where an event is produced to, see `Event::destination`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Destination {
+    pub topic: String,
+    pub partition_key: Option<String>,
+}
+
 #[async_trait]
 pub trait Event {
     async fn send(&self, event: ProcessedEvent) -> Result<(), CaptureError>;
     async fn send_batch(&self, events: Vec<ProcessedEvent>) -> Result<(), CaptureError>;
+
+    /// Topic and partition key the event would be sent to, for dry-runs. Overflow rerouting,
+    /// decided at send time, is not applied. Sinks without topics return `None`.
+    fn destination(&self, _event: &ProcessedEvent) -> Option<Destination> {
+        None
+    }
+
+    /// Called on shutdown, once all requests were handled: writes out the buffered events,
+    /// giving up after `timeout`. Events that could not be written are reported as dropped.
+    async fn flush(&self, _timeout: Duration) -> Result<(), CaptureError> {
+        Ok(())
+    }
 }
 
 #[async_trait]
@@ -21,6 +46,33 @@ impl<T: Event + ?Sized + Send + Sync> Event for Box<T> {
     async fn send_batch(&self, events: Vec<ProcessedEvent>) -> Result<(), CaptureError> {
         (**self).send_batch(events).await
     }
+
+    fn destination(&self, event: &ProcessedEvent) -> Option<Destination> {
+        (**self).destination(event)
+    }
+
+    async fn flush(&self, timeout: Duration) -> Result<(), CaptureError> {
+        (**self).flush(timeout).await
+    }
+}
+
+#[async_trait]
+impl<T: Event + ?Sized + Send + Sync> Event for Arc<T> {
+    async fn send(&self, event: ProcessedEvent) -> Result<(), CaptureError> {
+        (**self).send(event).await
+    }
+
+    async fn send_batch(&self, events: Vec<ProcessedEvent>) -> Result<(), CaptureError> {
+        (**self).send_batch(events).await
+    }
+
+    fn destination(&self, event: &ProcessedEvent) -> Option<Destination> {
+        (**self).destination(event)
+    }
+
+    async fn flush(&self, timeout: Duration) -> Result<(), CaptureError> {
+        (**self).flush(timeout).await
+    }
 }
 
 pub use fallback::FallbackSink;
@@ -1,11 +1,17 @@
-use crate::v0_request::ProcessedEvent;
+use crate::config::S3Compression;
+use crate::v0_request::{DataType, ProcessedEvent, ProcessedEventMetadata};
 use async_trait::async_trait;
 use aws_sdk_s3::config::Builder;
 use aws_sdk_s3::Client as S3Client;
-use chrono::{DateTime, Datelike, Utc};
+use chrono::{DateTime, Datelike, Timelike, Utc};
+use common_types::CapturedEvent;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
 use health::HealthHandle;
 use metrics::{counter, histogram};
+use std::collections::HashMap;
 use std::env;
+use std::io::{Read, Write};
 use std::sync::Arc;
 use std::time::{Duration, SystemTime};
 use tokio::sync::broadcast;
@@ -18,6 +24,7 @@ use tracing::instrument;
 use tracing::log::{debug, error, info};
 
 use crate::api::CaptureError;
+use crate::prometheus::report_dropped_events;
 use crate::sinks::Event;
 
 const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
@@ -28,6 +35,8 @@ struct Inner {
     client: S3Client,
     bucket: String,
     prefix: String,
+    compression: S3Compression,
+    partitioned: bool,
     buffer: Arc<Mutex<EventBuffer>>,
     liveness: HealthHandle,
 }
@@ -36,9 +45,17 @@ pub struct S3Sink {
     inner: Arc<Inner>,
 }
 
-struct EventBuffer {
+#[derive(Default)]
+struct Partition {
     event_bytes: Vec<u8>,
     event_count: usize,
+}
+
+struct EventBuffer {
+    // Keyed by token if the sink is partitioned, a single entry with an empty key otherwise
+    partitions: HashMap<String, Partition>,
+    size: usize,
+    event_count: usize,
     time_elapsed: Instant,
     tx: Sender<Result<(), CaptureError>>,
 }
@@ -48,57 +65,74 @@ impl EventBuffer {
         let (tx, _) = broadcast::channel(32);
 
         Self {
-            event_bytes: Vec::new(),
+            partitions: HashMap::new(),
+            size: 0,
             event_count: 0,
             time_elapsed: Instant::now(),
             tx,
         }
     }
 
-    fn add_event(&mut self, event: ProcessedEvent) -> Result<(), CaptureError> {
-        let json = serde_json::to_string(&event.event)?;
-        self.event_bytes.extend_from_slice(json.as_bytes());
-        self.event_bytes.push(b'\n');
+    fn add_event(&mut self, event: ProcessedEvent, partitioned: bool) -> Result<(), CaptureError> {
+        // Metadata is kept so that events can be replayed with the same routing
+        let json = serde_json::to_string(&event)?;
+        let key = match partitioned {
+            true => event.event.token,
+            false => String::new(),
+        };
+        let partition = self.partitions.entry(key).or_default();
+        partition.event_bytes.extend_from_slice(json.as_bytes());
+        partition.event_bytes.push(b'\n');
+        partition.event_count += 1;
+        self.size += json.len() + 1;
         self.event_count += 1;
         Ok(())
     }
 
     fn should_flush(&self) -> bool {
-        !self.event_bytes.is_empty()
-            && (self.event_bytes.len() >= MAX_BUFFER_SIZE
-                || self.time_elapsed.elapsed() >= FLUSH_INTERVAL)
+        self.size > 0
+            && (self.size >= MAX_BUFFER_SIZE || self.time_elapsed.elapsed() >= FLUSH_INTERVAL)
     }
 }
 
+/// Builds an S3 client, custom endpoints are used for local development.
+pub async fn create_client(s3_endpoint: Option<String>) -> S3Client {
+    // Load base config
+    let mut config_loader = aws_config::defaults(aws_config::BehaviorVersion::latest());
+
+    if let Some(s3_endpoint) = s3_endpoint.clone() {
+        config_loader = config_loader.endpoint_url(s3_endpoint);
+    }
+
+    let mut config = Builder::from(&config_loader.load().await);
+    if s3_endpoint.is_some() {
+        // custom s3 endpoints need force_path_style set
+        config = config.force_path_style(true);
+    }
+
+    S3Client::from_conf(config.build())
+}
+
 impl S3Sink {
     pub async fn new(
         bucket: String,
         prefix: String,
         s3_endpoint: Option<String>,
+        compression: S3Compression,
+        partitioned: bool,
         liveness: HealthHandle,
     ) -> anyhow::Result<S3Sink> {
         info!("Initializing S3 sink with bucket: {}", bucket);
 
-        // Load base config
-        let mut config_loader = aws_config::defaults(aws_config::BehaviorVersion::latest());
-
-        if let Some(s3_endpoint) = s3_endpoint.clone() {
-            config_loader = config_loader.endpoint_url(s3_endpoint);
-        }
-
-        let mut config = Builder::from(&config_loader.load().await);
-        if s3_endpoint.is_some() {
-            // custom s3 endpoints need force_path_style set
-            config = config.force_path_style(true);
-        }
-
-        let client = S3Client::from_conf(config.build());
+        let client = create_client(s3_endpoint).await;
         let buffer = Arc::new(Mutex::new(EventBuffer::new()));
 
         let inner = Arc::new(Inner {
             client,
             bucket,
             prefix,
+            compression,
+            partitioned,
             buffer,
             liveness,
         });
@@ -169,7 +203,7 @@ impl Inner {
     async fn flush_buffer(&self, buffer: &mut EventBuffer) -> Result<(), CaptureError> {
         let start = Instant::now();
         let events_count = buffer.event_count;
-        let batch_size = buffer.event_bytes.len();
+        let batch_size = buffer.size;
 
         match self.do_flush(buffer).await {
             Ok(_) => {
@@ -203,60 +237,137 @@ impl Inner {
         }
     }
 
+    /// Writes one object per partition. Partitions are removed from the buffer once
+    /// written, so that retries only write the remaining ones.
     async fn try_flush(&self, buffer: &mut EventBuffer) -> Result<(), CaptureError> {
-        if buffer.event_bytes.is_empty() {
-            return Ok(());
-        }
-
         let hostname = env::var("HOSTNAME").unwrap_or("unknown".to_string());
-
         let now: DateTime<Utc> = SystemTime::now().into();
-        let path = format!(
-            "{}{}/{:02}/{:02}/events_{}_{}.jsonl.gz",
-            self.prefix,
-            now.year(),
-            now.month(),
-            now.day(),
-            now.timestamp_millis(),
-            hostname
-        );
 
-        debug!(
-            "Flushing {} events to S3 path: {}",
-            buffer.event_count, path
-        );
+        let tokens: Vec<String> = buffer.partitions.keys().cloned().collect();
+        for token in tokens {
+            let Some(partition) = buffer.partitions.get(&token) else {
+                continue;
+            };
+            let path = object_key(
+                &self.prefix,
+                (!token.is_empty()).then_some(token.as_str()),
+                now,
+                &hostname,
+                self.compression,
+            );
+            debug!(
+                "Flushing {} events to S3 path: {}",
+                partition.event_count, path
+            );
+
+            let event_count = partition.event_count;
+            let written_bytes = partition.event_bytes.len();
+            let body = compress(&partition.event_bytes, self.compression).map_err(|e| {
+                error!("Failed to compress S3 batch: {}", e);
+                CaptureError::NonRetryableSinkError
+            })?;
+
+            match self
+                .client
+                .put_object()
+                .bucket(&self.bucket)
+                .key(&path)
+                .body(body.into())
+                .send()
+                .await
+            {
+                Ok(_) => {
+                    counter!("capture_s3_events_written_total").increment(event_count as u64);
+                    counter!("capture_s3_bytes_written_total").increment(written_bytes as u64);
+                    histogram!("capture_s3_batch_size").record(event_count as f64);
+                    buffer.partitions.remove(&token);
+                }
+                Err(err) => {
+                    error!("Failed to write to S3: {}", err);
+                    counter!("capture_s3_write_errors_total").increment(1);
+                    return Err(CaptureError::RetryableSinkError);
+                }
+            }
+        }
 
-        let event_count = buffer.event_count;
-        let written_bytes = buffer.event_bytes.len();
+        self.liveness.report_healthy().await;
+        Ok(())
+    }
+}
 
-        // take the event_bytes - this will implicitly reset to a new Vec
-        let event_bytes = std::mem::take(&mut buffer.event_bytes);
-        buffer.event_count = 0;
-        buffer.time_elapsed = Instant::now();
+/// Object keys are `{prefix}[{token}/]{year}/{month}/{day}/[{hour}/]events_{millis}_{host}.jsonl.{ext}`,
+/// the token and hour levels are only present if the sink is partitioned.
+fn object_key(
+    prefix: &str,
+    token: Option<&str>,
+    now: DateTime<Utc>,
+    hostname: &str,
+    compression: S3Compression,
+) -> String {
+    let mut key = String::from(prefix);
+    if let Some(token) = token {
+        key.push_str(token);
+        key.push('/');
+    }
+    key.push_str(&format!(
+        "{}/{:02}/{:02}/",
+        now.year(),
+        now.month(),
+        now.day()
+    ));
+    if token.is_some() {
+        key.push_str(&format!("{:02}/", now.hour()));
+    }
+    key.push_str(&format!(
+        "events_{}_{}.jsonl.{}",
+        now.timestamp_millis(),
+        hostname,
+        compression.extension()
+    ));
+    key
+}
 
-        match self
-            .client
-            .put_object()
-            .bucket(&self.bucket)
-            .key(&path)
-            .body(event_bytes.into())
-            .send()
-            .await
-        {
-            Ok(_) => {
-                counter!("capture_s3_events_written_total").increment(event_count as u64);
-                counter!("capture_s3_bytes_written_total").increment(written_bytes as u64);
-                histogram!("capture_s3_batch_size").record(event_count as f64);
-                self.liveness.report_healthy().await;
-                Ok(())
-            }
-            Err(err) => {
-                error!("Failed to write to S3: {}", err);
-                counter!("capture_s3_write_errors_total").increment(1);
-                Err(CaptureError::RetryableSinkError)
-            }
+fn compress(bytes: &[u8], compression: S3Compression) -> std::io::Result<Vec<u8>> {
+    match compression {
+        S3Compression::Gzip => {
+            let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(bytes)?;
+            encoder.finish()
         }
+        S3Compression::Zstd => zstd::encode_all(bytes, 0),
+    }
+}
+
+/// Decodes an object written by the sink, picking the decompression from its key.
+/// Objects written before metadata was included only hold the captured events, they
+/// are decoded as main analytics events.
+pub fn decode_object(key: &str, body: &[u8]) -> anyhow::Result<Vec<ProcessedEvent>> {
+    let mut content = String::new();
+    if key.ends_with(S3Compression::Zstd.extension()) {
+        zstd::stream::read::Decoder::new(body)?.read_to_string(&mut content)?;
+    } else if body.starts_with(&[0x1f, 0x8b]) {
+        GzDecoder::new(body).read_to_string(&mut content)?;
+    } else {
+        // Objects used to be written uncompressed, despite their .gz extension
+        content = String::from_utf8(body.to_vec())?;
     }
+
+    content
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(|line| -> anyhow::Result<ProcessedEvent> {
+            match serde_json::from_str::<ProcessedEvent>(line) {
+                Ok(event) => Ok(event),
+                Err(_) => Ok(ProcessedEvent {
+                    event: serde_json::from_str::<CapturedEvent>(line)?,
+                    metadata: ProcessedEventMetadata {
+                        data_type: DataType::AnalyticsMain,
+                        session_id: None,
+                    },
+                }),
+            }
+        })
+        .collect()
 }
 
 #[async_trait]
@@ -264,7 +375,7 @@ impl Event for S3Sink {
     #[instrument(skip_all)]
     async fn send(&self, event: ProcessedEvent) -> Result<(), CaptureError> {
         let mut buffer = self.inner.buffer.lock().await;
-        buffer.add_event(event)?;
+        buffer.add_event(event, self.inner.partitioned)?;
         let mut rx = buffer.tx.subscribe();
         drop(buffer);
         rx.recv()
@@ -276,7 +387,7 @@ impl Event for S3Sink {
     async fn send_batch(&self, events: Vec<ProcessedEvent>) -> Result<(), CaptureError> {
         let mut buffer = self.inner.buffer.lock().await;
         for event in events {
-            buffer.add_event(event)?;
+            buffer.add_event(event, self.inner.partitioned)?;
         }
         let mut rx = buffer.tx.subscribe();
         drop(buffer);
@@ -284,6 +395,27 @@ impl Event for S3Sink {
             .await
             .map_err(|_| CaptureError::NonRetryableSinkError)?
     }
+
+    async fn flush(&self, timeout: Duration) -> Result<(), CaptureError> {
+        let mut buffer = {
+            let mut buffer = self.inner.buffer.lock().await;
+            std::mem::replace(&mut *buffer, EventBuffer::new())
+        };
+        if buffer.event_count == 0 {
+            return Ok(());
+        }
+
+        let result = tokio::time::timeout(timeout, self.inner.flush_buffer(&mut buffer))
+            .await
+            .unwrap_or(Err(CaptureError::RetryableSinkError));
+        if result.is_err() {
+            let lost: usize = buffer.partitions.values().map(|p| p.event_count).sum();
+            error!("failed to flush the S3 buffer, {} events lost", lost);
+            report_dropped_events("s3_shutdown_flush", lost as u64);
+        }
+        drop(buffer.tx.send(result.clone()));
+        result
+    }
 }
 
 #[cfg(test)]
@@ -309,6 +441,8 @@ mod tests {
             "capture".to_string(),
             "".to_string(),
             Some("http://localhost:19000".to_string()),
+            S3Compression::Gzip,
+            false,
             handle,
         )
         .await
@@ -365,4 +499,50 @@ mod tests {
 
         sink.send(event).await.expect("Failed to send large event");
     }
+
+    #[test]
+    fn test_object_key() {
+        let now = DateTime::parse_from_rfc3339("2024-01-02T03:04:05Z")
+            .unwrap()
+            .with_timezone(&Utc);
+
+        assert_eq!(
+            "prefix/2024/01/02/events_1704164645000_host.jsonl.gz",
+            object_key("prefix/", None, now, "host", S3Compression::Gzip)
+        );
+        assert_eq!(
+            "prefix/phc_token/2024/01/02/03/events_1704164645000_host.jsonl.zst",
+            object_key(
+                "prefix/",
+                Some("phc_token"),
+                now,
+                "host",
+                S3Compression::Zstd
+            )
+        );
+    }
+
+    #[test]
+    fn test_decode_object() {
+        let event = create_test_event();
+        let mut lines = serde_json::to_vec(&event).unwrap();
+        lines.push(b'\n');
+
+        for compression in [S3Compression::Gzip, S3Compression::Zstd] {
+            let key = format!("events.jsonl.{}", compression.extension());
+            let body = compress(&lines, compression).unwrap();
+            let decoded = decode_object(&key, &body).expect("failed to decode");
+            assert_eq!(1, decoded.len());
+            assert_eq!(event.event, decoded[0].event);
+            assert_eq!(event.metadata.data_type, decoded[0].metadata.data_type);
+        }
+
+        // Legacy objects hold uncompressed events, without metadata
+        let legacy = serde_json::to_string(&event.event).unwrap() + "\n";
+        let decoded =
+            decode_object("events.jsonl.gz", legacy.as_bytes()).expect("failed to decode");
+        assert_eq!(1, decoded.len());
+        assert_eq!(event.event, decoded[0].event);
+        assert_eq!(DataType::AnalyticsMain, decoded[0].metadata.data_type);
+    }
 }
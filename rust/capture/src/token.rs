@@ -1,6 +1,8 @@
 use std::error::Error;
 use std::fmt::Display;
 
+use axum::http::{header, HeaderMap};
+
 /// Validate that a token is the correct shape
 
 #[derive(Clone, Debug, PartialEq)]
@@ -67,9 +69,26 @@ pub fn validate_token(token: &str) -> Result<(), InvalidTokenReason> {
     Ok(())
 }
 
+/// Token of a request sent outside of its payload, so known before the body is decoded: from
+/// an `Authorization: Bearer <token>` header, or else from the `token` query parameter.
+pub fn request_token<'a>(headers: &'a HeaderMap, query_token: Option<&'a str>) -> Option<&'a str> {
+    bearer_token(headers).or(query_token)
+}
+
+pub fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    let value = headers.get(header::AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, token) = value.trim().split_once(' ')?;
+    match scheme.eq_ignore_ascii_case("bearer") {
+        true => Some(token.trim()),
+        false => None,
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::token::{validate_token, InvalidTokenReason};
+    use axum::http::{header, HeaderMap, HeaderValue};
+
+    use crate::token::{bearer_token, request_token, validate_token, InvalidTokenReason};
 
     #[test]
     fn blocks_empty_tokens() {
@@ -111,4 +130,27 @@ mod tests {
         assert!(valid.is_err());
         assert_eq!(valid.unwrap_err(), InvalidTokenReason::NullByte);
     }
+
+    #[test]
+    fn reads_bearer_token() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(None, bearer_token(&headers));
+        assert_eq!(
+            Some("phc_query"),
+            request_token(&headers, Some("phc_query"))
+        );
+
+        headers.insert(
+            header::AUTHORIZATION,
+            HeaderValue::from_static("Bearer phc_123"),
+        );
+        assert_eq!(Some("phc_123"), bearer_token(&headers));
+        assert_eq!(Some("phc_123"), request_token(&headers, Some("phc_query")));
+
+        headers.insert(
+            header::AUTHORIZATION,
+            HeaderValue::from_static("Basic dXNlcg=="),
+        );
+        assert_eq!(None, bearer_token(&headers));
+    }
 }
@@ -0,0 +1,333 @@
+//! Per-token overrides of the request limits.
+//!
+//! By default, the compressed body size is limited per route (see `router`), and batches and
+//! events are only limited by the decompressed size. Tokens can be given their own limits:
+//!
+//! ```json
+//! {"max_body_bytes": 104857600, "max_batch_events": 5000, "max_event_bytes": 1048576}
+//! ```
+//!
+//! Overrides are read from the config, as a JSON object mapping tokens to their limits, and
+//! from a redis hash mapping tokens to the same JSON limits, refreshed in the background. The
+//! redis ones win. As the body size is first checked before the token is known, bodies larger
+//! than the route's limit are only accepted up to the configured `body_ceiling`.
+//!
+//! The decompressed size limit follows the token's body size limit: it is scaled along it
+//! relative to the route's default. Requests sending their token outside of the payload (see
+//! `token::request_token`) are held to their token's limits before their body is read or
+//! decompressed, the others once it is decoded.
+
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use common_redis::{Client, CustomRedisError};
+use metrics::gauge;
+use serde::{Deserialize, Serialize};
+
+use crate::api::CaptureError;
+
+pub const TOKEN_LIMITS_CACHE_KEY: &str = "@posthog/capture-token-limits";
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub struct Limits {
+    pub max_body_bytes: Option<usize>,
+    pub max_batch_events: Option<usize>,
+    pub max_event_bytes: Option<usize>,
+}
+
+#[derive(Default, Clone)]
+pub struct TokenLimits {
+    configured: HashMap<String, Limits>,
+    // Loaded from redis by `refresh`, taking precedence over the configured ones
+    dynamic: Arc<RwLock<HashMap<String, Limits>>>,
+    body_ceiling: usize,
+    decompressed_default: usize,
+}
+
+impl TokenLimits {
+    /// Takes a JSON object mapping tokens to their limits, the largest body size that can be
+    /// allowed to a token, and the decompressed size limit of the tokens without a body size one.
+    pub fn new(
+        config: Option<&str>,
+        body_ceiling: usize,
+        decompressed_default: usize,
+    ) -> Result<Self, serde_json::Error> {
+        let configured = match config {
+            None => HashMap::new(),
+            Some(config) if config.trim().is_empty() => HashMap::new(),
+            Some(config) => serde_json::from_str(config)?,
+        };
+        Ok(Self {
+            configured,
+            body_ceiling,
+            decompressed_default,
+            ..Default::default()
+        })
+    }
+
+    /// Body size limit of a route, raised to the ceiling for the tokens allowed larger bodies.
+    pub fn route_body_limit(&self, default: usize) -> usize {
+        default.max(self.body_ceiling)
+    }
+
+    pub fn get(&self, token: &str) -> Limits {
+        let dynamic = match self.dynamic.read() {
+            Ok(dynamic) => dynamic.get(token).copied(),
+            Err(poisoned) => poisoned.into_inner().get(token).copied(),
+        };
+        dynamic
+            .or_else(|| self.configured.get(token).copied())
+            .unwrap_or_default()
+    }
+
+    /// Decompressed size limit of the token's requests: the default one, scaled along the
+    /// token's body size limit relative to the route's `default_body_bytes`.
+    pub fn decompressed_limit(&self, token: &str, default_body_bytes: usize) -> usize {
+        match self.get(token).max_body_bytes {
+            None => self.decompressed_default,
+            Some(max_body_bytes) => {
+                let scaled = self.decompressed_default as u128 * max_body_bytes as u128
+                    / default_body_bytes.max(1) as u128;
+                usize::try_from(scaled).unwrap_or(usize::MAX)
+            }
+        }
+    }
+
+    /// Decompresses a request within the decompressed size limit of its token if it's already
+    /// known, or else within the `ceiling` of all tokens. Going over the token's own limit is a
+    /// `LimitExceeded` error, like in `check`.
+    pub fn decompress<T>(
+        &self,
+        token: Option<&str>,
+        default_body_bytes: usize,
+        ceiling: usize,
+        decompress: impl FnOnce(usize) -> Result<T, CaptureError>,
+    ) -> Result<T, CaptureError> {
+        let limit = match token {
+            Some(token) => self.decompressed_limit(token, default_body_bytes),
+            None => return decompress(ceiling),
+        };
+        if limit >= ceiling {
+            return decompress(ceiling);
+        }
+        decompress(limit).map_err(|err| match err {
+            CaptureError::EventTooBig => CaptureError::LimitExceeded(format!(
+                "decompressed request body is over the limit of {} bytes",
+                limit
+            )),
+            err => err,
+        })
+    }
+
+    /// Checks the compressed body size against the token's limit, `default_body_bytes` being
+    /// the route's default one.
+    pub fn check_body(
+        &self,
+        token: &str,
+        body_bytes: usize,
+        default_body_bytes: usize,
+    ) -> Result<(), CaptureError> {
+        let max_body_bytes = self.get(token).max_body_bytes.unwrap_or(default_body_bytes);
+        if body_bytes > max_body_bytes {
+            return Err(CaptureError::LimitExceeded(format!(
+                "request body of {} bytes is over the limit of {} bytes",
+                body_bytes, max_body_bytes
+            )));
+        }
+        Ok(())
+    }
+
+    /// Checks the compressed and decompressed body sizes, and the batch length and event sizes
+    /// if the token has limits for them. `default_body_bytes` is the route's default limit.
+    pub fn check<T: Serialize>(
+        &self,
+        token: &str,
+        body_bytes: usize,
+        decompressed_bytes: usize,
+        default_body_bytes: usize,
+        events: &[T],
+    ) -> Result<(), CaptureError> {
+        let limits = self.get(token);
+
+        self.check_body(token, body_bytes, default_body_bytes)?;
+        let max_decompressed_bytes = self.decompressed_limit(token, default_body_bytes);
+        if decompressed_bytes > max_decompressed_bytes {
+            return Err(CaptureError::LimitExceeded(format!(
+                "decompressed request body of {} bytes is over the limit of {} bytes",
+                decompressed_bytes, max_decompressed_bytes
+            )));
+        }
+        if let Some(max_batch_events) = limits.max_batch_events {
+            if events.len() > max_batch_events {
+                return Err(CaptureError::LimitExceeded(format!(
+                    "batch of {} events is over the limit of {} events",
+                    events.len(),
+                    max_batch_events
+                )));
+            }
+        }
+        if let Some(max_event_bytes) = limits.max_event_bytes {
+            for (i, event) in events.iter().enumerate() {
+                let event_bytes = serde_json::to_vec(event)?.len();
+                if event_bytes > max_event_bytes {
+                    return Err(CaptureError::LimitExceeded(format!(
+                        "event {} of {} bytes is over the limit of {} bytes",
+                        i, event_bytes, max_event_bytes
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Reloads the limits from the given redis hash every `interval`, needs to be spawned
+    /// in a separate task. The last loaded limits are kept if redis is unavailable.
+    pub async fn refresh(
+        &self,
+        redis: Arc<dyn Client + Send + Sync>,
+        key: String,
+        interval: Duration,
+    ) {
+        let mut interval = tokio::time::interval(interval);
+        loop {
+            interval.tick().await;
+
+            let entries = match redis.hgetall(key.clone()).await {
+                Ok(entries) => entries,
+                Err(CustomRedisError::NotFound) => HashMap::new(),
+                Err(e) => {
+                    tracing::error!("Failed to update token limits from Redis: {:?}", e);
+                    continue;
+                }
+            };
+            let loaded: HashMap<String, Limits> = entries
+                .into_iter()
+                .filter_map(|(token, limits)| match serde_json::from_str(&limits) {
+                    Ok(limits) => Some((token, limits)),
+                    Err(e) => {
+                        tracing::warn!("invalid limits for token {}: {}", token, e);
+                        None
+                    }
+                })
+                .collect();
+            gauge!("capture_token_limits_loaded_tokens").set(loaded.len() as f64);
+            match self.dynamic.write() {
+                Ok(mut dynamic) => *dynamic = loaded,
+                Err(poisoned) => *poisoned.into_inner() = loaded,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use common_redis::MockRedisClient;
+    use serde_json::json;
+
+    use super::{Limits, TokenLimits, TOKEN_LIMITS_CACHE_KEY};
+    use crate::api::CaptureError;
+
+    #[test]
+    fn checks_limits() {
+        let config = json!({
+            "large": {"max_body_bytes": 1000},
+            "strict": {"max_body_bytes": 10, "max_batch_events": 2, "max_event_bytes": 20},
+        });
+        let limits = TokenLimits::new(Some(&config.to_string()), 500, 400).unwrap();
+        assert_eq!(500, limits.route_body_limit(100));
+        assert_eq!(200, limits.route_body_limit(200));
+
+        let events = vec![json!({"event": "e"}), json!({"event": "e"})];
+        assert!(limits.check("other", 100, 400, 100, &events).is_ok());
+        assert!(limits.check("other", 101, 400, 100, &events).is_err());
+        assert!(limits.check("other", 100, 401, 100, &events).is_err());
+        assert!(limits.check("large", 1000, 4000, 100, &events).is_ok());
+
+        assert!(limits.check("strict", 10, 40, 100, &events).is_ok());
+        assert!(limits.check("strict", 11, 40, 100, &events).is_err());
+        assert!(limits.check("strict", 10, 41, 100, &events).is_err());
+        let three = vec![json!({"event": "e"}); 3];
+        assert!(matches!(
+            limits.check("strict", 10, 40, 100, &three),
+            Err(CaptureError::LimitExceeded(_))
+        ));
+        let large = vec![json!({"event": "e".repeat(20)})];
+        assert!(limits.check("strict", 10, 40, 100, &large).is_err());
+
+        assert!(TokenLimits::new(Some("not json"), 0, 0).is_err());
+        assert!(TokenLimits::new(None, 0, 0).is_ok());
+    }
+
+    #[test]
+    fn decompresses_within_token_limits() {
+        let config = json!({
+            "large": {"max_body_bytes": 1000},
+            "strict": {"max_body_bytes": 10},
+        });
+        let limits = TokenLimits::new(Some(&config.to_string()), 1000, 400).unwrap();
+        assert_eq!(400, limits.decompressed_limit("other", 100));
+        assert_eq!(4000, limits.decompressed_limit("large", 100));
+        assert_eq!(40, limits.decompressed_limit("strict", 100));
+
+        let decompress = |limit: usize| match limit >= 100 {
+            true => Ok(limit),
+            false => Err(CaptureError::EventTooBig),
+        };
+        assert!(matches!(
+            limits.decompress(None, 100, 2000, decompress),
+            Ok(2000)
+        ));
+        assert!(matches!(
+            limits.decompress(Some("other"), 100, 2000, decompress),
+            Ok(400)
+        ));
+        assert!(matches!(
+            limits.decompress(Some("large"), 100, 2000, decompress),
+            Ok(2000)
+        ));
+        assert!(matches!(
+            limits.decompress(Some("strict"), 100, 2000, decompress),
+            Err(CaptureError::LimitExceeded(_))
+        ));
+        assert!(matches!(
+            limits.decompress(None, 100, 10, decompress),
+            Err(CaptureError::EventTooBig)
+        ));
+    }
+
+    #[tokio::test]
+    async fn refreshes_from_redis() {
+        let limits =
+            TokenLimits::new(Some(r#"{"token1": {"max_batch_events": 1}}"#), 0, 0).unwrap();
+        let client = MockRedisClient::new().hgetall_ret(
+            TOKEN_LIMITS_CACHE_KEY,
+            HashMap::from([
+                (
+                    "token1".to_string(),
+                    r#"{"max_batch_events": 10}"#.to_string(),
+                ),
+                ("token2".to_string(), "invalid".to_string()),
+            ]),
+        );
+
+        let refreshed = limits.clone();
+        tokio::spawn(async move {
+            refreshed
+                .refresh(
+                    Arc::new(client),
+                    TOKEN_LIMITS_CACHE_KEY.to_string(),
+                    Duration::from_secs(60),
+                )
+                .await;
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        assert_eq!(Some(10), limits.get("token1").max_batch_events);
+        assert_eq!(Limits::default(), limits.get("token2"));
+    }
+}
@@ -1,3 +1,4 @@
+use std::collections::HashSet;
 use std::ops::Deref;
 use std::sync::Arc;
 
@@ -13,34 +14,149 @@ use limiters::token_dropper::TokenDropper;
 use metrics::counter;
 use serde_json::json;
 use serde_json::Value;
+use time::format_description::well_known::{Iso8601, Rfc3339};
+use time::OffsetDateTime;
 use tracing::instrument;
+use uuid::Uuid;
 
+use crate::binary_request::BinaryFormat;
+use crate::cookieless::COOKIELESS_SERVER_HASHED_PROPERTY;
+use crate::dedup::EventDeduplicator;
+use crate::event_rules::EventRules;
+use crate::exceptions::{validate_exception, Stacked};
+use crate::ingestion_warnings::{drop_reason, dropped_event_warning};
 use crate::prometheus::report_dropped_events;
+use crate::token::request_token;
 use crate::v0_request::{
-    Compression, DataType, ProcessedEvent, ProcessedEventMetadata, ProcessingContext, RawRequest,
+    decompress_bytes, decompress_payload, Compression, DataType, InvalidEvent, ProcessedEvent,
+    ProcessedEventMetadata, ProcessingContext, RawRequest,
 };
 use crate::{
-    api::{CaptureError, CaptureResponse, CaptureResponseCode},
+    api::{
+        CaptureError, CaptureResponse, CaptureResponseCode, RejectedEvent, ValidatedEvent,
+        ValidationResponse,
+    },
     router, sinks,
     utils::uuid_v7,
     v0_request::{EventFormData, EventQuery},
 };
 
+// Room left in replay messages for the Kafka headers and record overhead
+const REPLAY_MESSAGE_MARGIN_BYTES: usize = 4096;
+
 /// Flexible endpoint that targets wide compatibility with the wide range of requests
 /// currently processed by posthog-events (analytics events capture). Replay is out
 /// of scope and should be processed on a separate endpoint.
 ///
-/// Because it must accommodate several shapes, it is inefficient in places. A v1
-/// endpoint should be created, that only accepts the BatchedRequest payload shape.
+/// Because it must accommodate several shapes, it is inefficient in places. The v1
+/// batch endpoint (see `v1_endpoint`) only accepts the BatchedRequest payload shape.
+///
+/// Events that fail to parse are returned separately, so that the handlers can decide
+/// whether they fail the whole request.
 async fn handle_common(
     state: &State<router::State>,
-    InsecureClientIp(ip): &InsecureClientIp,
+    ip: &InsecureClientIp,
+    meta: &EventQuery,
+    headers: &HeaderMap,
+    method: &Method,
+    path: &MatchedPath,
+    body: Bytes,
+) -> Result<(ProcessingContext, Vec<RawEvent>, Vec<InvalidEvent>), CaptureError> {
+    let request = read_request(meta, headers, method, path, body)?;
+
+    // The payload of a token sent outside of it is only decompressed within its rate limit
+    let known_token = request.known_token.clone();
+    if let (Some(rate_limiter), Some(token)) = (&state.rate_limiter, &known_token) {
+        if let Some(retry_after) = rate_limiter.check(token, &ip.0.to_string()).await {
+            report_dropped_events("rate_limited", 1);
+            return Err(CaptureError::RateLimited(retry_after));
+        }
+    }
+
+    let ParsedRequest {
+        context,
+        events,
+        invalid,
+        body_bytes,
+        decompressed_bytes,
+    } = parse_request(state, ip, meta, headers, path, request)?;
+    let count = events.len() + invalid.len();
+    let context =
+        context.inspect_err(|_| report_dropped_events("token_shape_invalid", count as u64))?;
+
+    if count == 0 {
+        tracing::log::warn!("rejected empty batch");
+        return Err(CaptureError::EmptyBatch);
+    }
+
+    counter!("capture_events_received_total").increment(count as u64);
+
+    if let Err(err) = state.token_limits.check(
+        &context.token,
+        body_bytes,
+        decompressed_bytes,
+        router::default_body_limit(path.as_str()),
+        &events,
+    ) {
+        report_dropped_events("token_limit_exceeded", count as u64);
+        return Err(err);
+    }
+
+    // Unless already checked before decoding
+    if let Some(rate_limiter) = &state.rate_limiter {
+        if known_token.as_deref() != Some(context.token.as_str()) {
+            if let Some(retry_after) = rate_limiter.check(&context.token, &context.client_ip).await
+            {
+                report_dropped_events("rate_limited", count as u64);
+                return Err(CaptureError::RateLimited(retry_after));
+            }
+        }
+    }
+
+    let billing_limited = state
+        .billing_limiter
+        .is_limited(context.token.as_str())
+        .await;
+
+    if billing_limited {
+        report_dropped_events("over_quota", count as u64);
+
+        return Err(CaptureError::BillingLimit);
+    }
+
+    tracing::debug!(context=?context, events=?events, "decoded request");
+
+    Ok((context, events, invalid))
+}
+
+/// A decoded request, the context being an error if the token is invalid
+struct ParsedRequest {
+    context: Result<ProcessingContext, CaptureError>,
+    events: Vec<RawEvent>,
+    invalid: Vec<InvalidEvent>,
+    body_bytes: usize,
+    decompressed_bytes: usize,
+}
+
+/// A request body before decompression, with the token if it's sent outside of the payload
+struct EncodedRequest {
+    known_token: Option<String>,
+    payload: Bytes,
+    // Unset for JSON payloads
+    format: Option<BinaryFormat>,
+    compression: Compression,
+    body_bytes: usize,
+}
+
+/// Reads the payload out of the request body, without decompressing it, so that the token sent
+/// outside of the payload can be rate limited first, see `token::request_token`.
+fn read_request(
     meta: &EventQuery,
     headers: &HeaderMap,
     method: &Method,
     path: &MatchedPath,
     body: Bytes,
-) -> Result<(ProcessingContext, Vec<RawEvent>), CaptureError> {
+) -> Result<EncodedRequest, CaptureError> {
     let user_agent = headers
         .get("user-agent")
         .map_or("unknown", |v| v.to_str().unwrap_or("unknown"));
@@ -49,19 +165,25 @@ async fn handle_common(
         .map_or("unknown", |v| v.to_str().unwrap_or("unknown"));
 
     let comp = match meta.compression {
-        None => String::from("unknown"),
-        Some(Compression::Gzip) => String::from("gzip"),
-        Some(Compression::Unsupported) => String::from("unsupported"),
+        None => "unknown",
+        Some(compression) => compression.as_str(),
     };
+    // Like posthog-django, the query parameter wins over the Content-Encoding header
+    let compression = meta
+        .compression
+        .or_else(|| Compression::from_content_encoding(content_encoding))
+        .unwrap_or_default();
 
     tracing::Span::current().record("user_agent", user_agent);
     tracing::Span::current().record("content_encoding", content_encoding);
     tracing::Span::current().record("version", meta.lib_version.clone());
-    tracing::Span::current().record("compression", comp.as_str());
+    tracing::Span::current().record("compression", comp);
     tracing::Span::current().record("method", method.as_str());
     tracing::Span::current().record("path", path.as_str().trim_end_matches('/'));
 
-    let request = match headers
+    let known_token = request_token(headers, meta.token()).map(str::to_string);
+    let body_bytes = body.len();
+    match headers
         .get("content-type")
         .map_or("", |v| v.to_str().unwrap_or(""))
     {
@@ -72,44 +194,98 @@ async fn handle_common(
                 tracing::error!("failed to decode body: {}", e);
                 CaptureError::RequestDecodingError(String::from("invalid form data"))
             })?;
-            let payload = base64::engine::general_purpose::STANDARD
-                .decode(input.data)
-                .map_err(|e| {
-                    tracing::error!("failed to decode form data: {}", e);
-                    CaptureError::RequestDecodingError(String::from("missing data field"))
-                })?;
-            RawRequest::from_bytes(payload.into(), state.event_size_limit)
+            let known_token = known_token.or_else(|| input.token().map(str::to_string));
+            let payload = match compression {
+                // lz64 payloads use their own base64 flavour, decoded with the compressed data
+                Compression::LZ64 => input.data.into_bytes(),
+                _ => base64::engine::general_purpose::STANDARD
+                    .decode(input.data)
+                    .map_err(|e| {
+                        tracing::error!("failed to decode form data: {}", e);
+                        CaptureError::RequestDecodingError(String::from("missing data field"))
+                    })?,
+            };
+            Ok(EncodedRequest {
+                known_token,
+                payload: payload.into(),
+                format: None,
+                compression,
+                body_bytes,
+            })
         }
         ct => {
             tracing::Span::current().record("content_type", ct);
 
-            RawRequest::from_bytes(body, state.event_size_limit)
+            Ok(EncodedRequest {
+                known_token,
+                payload: body,
+                format: BinaryFormat::from_content_type(ct)?,
+                compression,
+                body_bytes,
+            })
+        }
+    }
+}
+
+/// Decodes the request, without any side effect besides tracing, so that the dry-run of
+/// `validate` can share it.
+fn parse_request(
+    state: &State<router::State>,
+    InsecureClientIp(ip): &InsecureClientIp,
+    meta: &EventQuery,
+    headers: &HeaderMap,
+    path: &MatchedPath,
+    request: EncodedRequest,
+) -> Result<ParsedRequest, CaptureError> {
+    let user_agent = headers
+        .get("user-agent")
+        .map_or("unknown", |v| v.to_str().unwrap_or("unknown"));
+
+    let EncodedRequest {
+        known_token,
+        payload,
+        format,
+        compression,
+        body_bytes,
+    } = request;
+    // Decompressed within the limit of the token, if already known, see `TokenLimits::decompress`
+    let default_body_bytes = router::default_body_limit(path.as_str());
+    let decompressed_bytes;
+    let (request, invalid) = match format {
+        Some(format) => {
+            let payload = state.token_limits.decompress(
+                known_token.as_deref(),
+                default_body_bytes,
+                state.event_size_limit,
+                |limit| decompress_bytes(payload, limit, compression),
+            )?;
+            decompressed_bytes = payload.len();
+            RawRequest::from_binary_payload_lenient(&payload, format)
+        }
+        None => {
+            let payload = state.token_limits.decompress(
+                known_token.as_deref(),
+                default_body_bytes,
+                state.event_size_limit,
+                |limit| decompress_payload(payload, limit, compression),
+            )?;
+            decompressed_bytes = payload.len();
+            RawRequest::from_payload_lenient(&payload)
         }
     }?;
 
     let sent_at = request.sent_at().or(meta.sent_at());
-    let token = match request.extract_and_verify_token() {
-        Ok(token) => token,
-        Err(err) => {
-            report_dropped_events("token_shape_invalid", request.events().len() as u64);
-            return Err(err);
-        }
-    };
     let historical_migration = request.historical_migration();
+    let token = request.extract_and_verify_token();
     let events = request.events(); // Takes ownership of request
 
-    tracing::Span::current().record("token", &token);
-    tracing::Span::current().record("historical_migration", historical_migration);
-    tracing::Span::current().record("batch_size", events.len());
-
-    if events.is_empty() {
-        tracing::log::warn!("rejected empty batch");
-        return Err(CaptureError::EmptyBatch);
+    if let Ok(token) = &token {
+        tracing::Span::current().record("token", token);
     }
+    tracing::Span::current().record("historical_migration", historical_migration);
+    tracing::Span::current().record("batch_size", events.len() + invalid.len());
 
-    counter!("capture_events_received_total").increment(events.len() as u64);
-
-    let context = ProcessingContext {
+    let context = token.map(|token| ProcessingContext {
         lib_version: meta.lib_version.clone(),
         sent_at,
         token,
@@ -117,22 +293,15 @@ async fn handle_common(
         client_ip: ip.to_string(),
         historical_migration,
         user_agent: Some(user_agent.to_string()),
-    };
-
-    let billing_limited = state
-        .billing_limiter
-        .is_limited(context.token.as_str())
-        .await;
-
-    if billing_limited {
-        report_dropped_events("over_quota", events.len() as u64);
-
-        return Err(CaptureError::BillingLimit);
-    }
-
-    tracing::debug!(context=?context, events=?events, "decoded request");
-
-    Ok((context, events))
+        server_hashed: HashSet::new(),
+    });
+    Ok(ParsedRequest {
+        context,
+        events,
+        invalid,
+        body_bytes,
+        decompressed_bytes,
+    })
 }
 
 #[instrument(
@@ -173,21 +342,23 @@ pub async fn event(
             }))
         }
         Err(err) => Err(err),
-        Ok((context, events)) => {
+        Ok((mut context, mut events, invalid)) => {
+            if let Some(cookieless) = &state.cookieless {
+                context.server_hashed =
+                    cookieless.process_events(events.iter_mut(), &context).await;
+            }
             if let Err(err) = process_events(
                 state.sink.clone(),
                 state.token_dropper.clone(),
+                state.event_rules.clone(),
+                state.deduplicator.clone(),
                 &events,
+                invalid,
                 &context,
             )
             .await
             {
-                let cause = match err {
-                    CaptureError::MissingDistinctId => "missing_distinct_id",
-                    CaptureError::MissingEventName => "missing_event_name",
-                    _ => "process_events_error",
-                };
-                report_dropped_events(cause, events.len() as u64);
+                report_dropped_events("process_events_error", events.len() as u64);
                 tracing::log::warn!("rejected invalid payload: {}", err);
                 return Err(err);
             }
@@ -230,9 +401,25 @@ pub async fn recording(
             quota_limited: Some(vec!["recordings".to_string()]),
         })),
         Err(err) => Err(err),
-        Ok((context, events)) => {
+        Ok((context, events, invalid)) => {
+            // Replay events are merged in a single message, one invalid event fails them all
+            if !invalid.is_empty() {
+                let count = events.len() + invalid.len();
+                report_dropped_events("invalid_event", count as u64);
+                let error = invalid.into_iter().next().map(|e| e.error);
+                let error = error.unwrap_or(CaptureError::EmptyBatch);
+                tracing::log::warn!("rejected invalid payload: {}", error);
+                return Err(error);
+            }
             let count = events.len() as u64;
-            if let Err(err) = process_replay_events(state.sink.clone(), events, &context).await {
+            if let Err(err) = process_replay_events(
+                state.sink.clone(),
+                events,
+                &context,
+                state.replay_message_max_bytes,
+            )
+            .await
+            {
                 let cause = match err {
                     CaptureError::MissingDistinctId => "missing_distinct_id",
                     CaptureError::MissingSessionId => "missing_session_id",
@@ -258,6 +445,205 @@ pub async fn recording(
     }
 }
 
+/// Dedicated endpoint for `$exception` events. Unlike the generic endpoints, events are only
+/// accepted if their `$exception_list` is well-formed, see `exceptions`, and malformed ones
+/// fail the whole request, so that SDK developers see the error.
+#[instrument(
+    skip_all,
+    fields(
+        path,
+        token,
+        batch_size,
+        user_agent,
+        content_encoding,
+        content_type,
+        version,
+        compression,
+        historical_migration
+    )
+)]
+#[debug_handler]
+pub async fn exception(
+    state: State<router::State>,
+    ip: InsecureClientIp,
+    meta: Query<EventQuery>,
+    headers: HeaderMap,
+    method: Method,
+    path: MatchedPath,
+    body: Bytes,
+) -> Result<Json<CaptureResponse>, CaptureError> {
+    let (mut context, mut events, invalid) =
+        match handle_common(&state, &ip, &meta, &headers, &method, &path, body).await {
+            // Like v0, so that SDKs don't retry
+            Err(CaptureError::BillingLimit) => {
+                return Ok(Json(CaptureResponse {
+                    status: CaptureResponseCode::Ok,
+                    quota_limited: None,
+                }))
+            }
+            Err(err) => return Err(err),
+            Ok(parsed) => parsed,
+        };
+
+    let count = (events.len() + invalid.len()) as u64;
+    if let Some(InvalidEvent { error, .. }) = invalid.into_iter().next() {
+        report_dropped_events("invalid_event", count);
+        tracing::log::warn!("rejected invalid payload: {}", error);
+        return Err(error);
+    }
+    for event in &events {
+        let exceptions = validate_exception(event).inspect_err(|err| {
+            report_dropped_events("invalid_exception", count);
+            tracing::log::warn!("rejected invalid exception: {}", err);
+        })?;
+        let lang = exceptions[0].lang_hint();
+        counter!("capture_exceptions_received_total", "lang" => lang).increment(1);
+    }
+
+    if let Some(cookieless) = &state.cookieless {
+        context.server_hashed = cookieless.process_events(events.iter_mut(), &context).await;
+    }
+
+    process_events(
+        state.sink.clone(),
+        state.token_dropper.clone(),
+        state.event_rules.clone(),
+        state.deduplicator.clone(),
+        &events,
+        Vec::new(),
+        &context,
+    )
+    .await
+    .inspect_err(|err| {
+        report_dropped_events("process_events_error", count);
+        tracing::log::warn!("rejected invalid payload: {}", err);
+    })?;
+
+    Ok(Json(CaptureResponse {
+        status: CaptureResponseCode::Ok,
+        quota_limited: None,
+    }))
+}
+
+/// Dry-run of the event endpoints: the request goes through the same parsing, token limits,
+/// event rules and token dropper, but instead of being sent to the sink, the resulting events
+/// are returned with their destination, and the rejected and dropped ones with the reason.
+/// SDK developers can check their payloads against it.
+///
+/// It has no side effect: requests are not counted, nor checked against the rate and billing
+/// limits, and cookieless distinct_ids are not computed, as that records sessions.
+#[instrument(
+    skip_all,
+    fields(
+        path,
+        token,
+        batch_size,
+        user_agent,
+        content_encoding,
+        content_type,
+        version,
+        compression,
+        historical_migration
+    )
+)]
+#[debug_handler]
+pub async fn validate(
+    state: State<router::State>,
+    ip: InsecureClientIp,
+    meta: Query<EventQuery>,
+    headers: HeaderMap,
+    method: Method,
+    path: MatchedPath,
+    body: Bytes,
+) -> Result<Json<ValidationResponse>, CaptureError> {
+    let ParsedRequest {
+        context,
+        events,
+        invalid,
+        body_bytes,
+        decompressed_bytes,
+    } = parse_request(
+        &state,
+        &ip,
+        &meta,
+        &headers,
+        &path,
+        read_request(&meta, &headers, &method, &path, body)?,
+    )?;
+    let context = context?;
+    if events.is_empty() && invalid.is_empty() {
+        return Err(CaptureError::EmptyBatch);
+    }
+    state.token_limits.check(
+        &context.token,
+        body_bytes,
+        decompressed_bytes,
+        router::default_body_limit(path.as_str()),
+        &events,
+    )?;
+
+    let mut rejected: Vec<RejectedEvent> = invalid
+        .into_iter()
+        .map(|InvalidEvent { payload, error }| RejectedEvent {
+            payload,
+            reason: error.to_string(),
+        })
+        .collect();
+    let mut dropped = Vec::new();
+    let mut validated = Vec::with_capacity(events.len());
+    for event in &events {
+        // Same order as `send_events`
+        if let Some(rule) = state.event_rules.dropping_rule(&context.token, event) {
+            dropped.push(RejectedEvent {
+                payload: serde_json::to_value(event)?,
+                reason: format!("dropped by event rule {}", rule),
+            });
+            continue;
+        }
+        let processed = match process_single_event(event, &context) {
+            Ok(processed) => processed,
+            Err(error) => {
+                rejected.push(RejectedEvent {
+                    payload: serde_json::to_value(event)?,
+                    reason: error.to_string(),
+                });
+                continue;
+            }
+        };
+        if state
+            .token_dropper
+            .should_drop(&processed.event.token, &processed.event.distinct_id)
+        {
+            dropped.push(RejectedEvent {
+                payload: serde_json::to_value(event)?,
+                reason: String::from("dropped by the token dropper"),
+            });
+            continue;
+        }
+        let destination = state.sink.destination(&processed);
+        let format = |time: OffsetDateTime| time.format(&Rfc3339).ok();
+        validated.push(ValidatedEvent {
+            uuid: processed.event.uuid,
+            event: event.event.clone(),
+            distinct_id: processed.event.distinct_id.clone(),
+            data_type: processed.metadata.data_type,
+            topic: destination.as_ref().map(|d| d.topic.clone()),
+            partition_key: destination.and_then(|d| d.partition_key),
+            now: context.now.clone(),
+            sent_at: context.sent_at.and_then(format),
+            timestamp: ingestion_timestamp(event, &context).and_then(format),
+        });
+    }
+
+    Ok(Json(ValidationResponse {
+        status: CaptureResponseCode::Ok,
+        token: context.token,
+        events: validated,
+        rejected,
+        dropped,
+    }))
+}
+
 pub async fn options() -> Result<Json<CaptureResponse>, CaptureError> {
     Ok(Json(CaptureResponse {
         status: CaptureResponseCode::Ok,
@@ -282,7 +668,24 @@ pub fn process_single_event(
         (_, false) => DataType::AnalyticsMain,
     };
 
-    let data = serde_json::to_string(&event).map_err(|e| {
+    let server_hashed = event
+        .uuid
+        .is_some_and(|uuid| context.server_hashed.contains(&uuid));
+    // Only capture marks events as server hashed, don't pass on the marks sent by clients
+    let client_marked = !server_hashed
+        && event
+            .properties
+            .contains_key(COOKIELESS_SERVER_HASHED_PROPERTY);
+    let data = match client_marked {
+        true => serde_json::to_value(event).and_then(|mut value| {
+            if let Some(properties) = value["properties"].as_object_mut() {
+                properties.remove(COOKIELESS_SERVER_HASHED_PROPERTY);
+            }
+            serde_json::to_string(&value)
+        }),
+        false => serde_json::to_string(&event),
+    }
+    .map_err(|e| {
         tracing::error!("failed to encode data field: {}", e);
         CaptureError::NonRetryableSinkError
     })?;
@@ -297,7 +700,11 @@ pub fn process_single_event(
         distinct_id: event
             .extract_distinct_id()
             .ok_or(CaptureError::MissingDistinctId)?,
-        ip: context.client_ip.clone(),
+        // The IP was only needed to compute the cookieless distinct_id, don't pass it on
+        ip: match server_hashed {
+            true => String::new(),
+            false => context.client_ip.clone(),
+        },
         data,
         now: context.now.clone(),
         sent_at: context.sent_at,
@@ -309,19 +716,95 @@ pub fn process_single_event(
     Ok(ProcessedEvent { metadata, event })
 }
 
+/// Invalid events, either from `invalid` or failing `process_single_event`, are dropped and
+/// replaced by an ingestion warning, instead of failing the whole batch.
+///
+/// If a deduplicator is set, events already captured in its window are dropped first. Their
+/// uuids are released if the batch can't be sent, or if the events are turned into warnings,
+/// so that the client's retry is not dropped.
+/// The token's sampling and drop rules are then applied, see `event_rules`.
 #[instrument(skip_all, fields(events = events.len()))]
 pub async fn process_events<'a>(
     sink: Arc<dyn sinks::Event + Send + Sync>,
     dropper: Arc<TokenDropper>,
+    event_rules: Arc<EventRules>,
+    deduplicator: Option<Arc<EventDeduplicator>>,
     events: &'a [RawEvent],
+    invalid: Vec<InvalidEvent>,
     context: &'a ProcessingContext,
 ) -> Result<(), CaptureError> {
-    let mut events: Vec<ProcessedEvent> = events
-        .iter()
-        .map(|e| process_single_event(e, context))
-        .collect::<Result<Vec<ProcessedEvent>, CaptureError>>()?;
+    let mut warnings = Vec::with_capacity(invalid.len());
+    for InvalidEvent { payload, error } in invalid {
+        warnings.push(dropped_event_warning(&error, &payload, context)?);
+        report_dropped_events(drop_reason(&error), 1);
+    }
+
+    let (events, claimed) = match &deduplicator {
+        Some(deduplicator) => {
+            let (unique, claimed) = deduplicator.filter_duplicates(&context.token, events).await;
+            let duplicates = events.len() - unique.len();
+            if duplicates > 0 {
+                report_dropped_events("duplicate_uuid", duplicates as u64);
+            }
+            (unique, claimed)
+        }
+        None => (events.iter().collect(), Vec::new()),
+    };
+
+    let mut rejected = Vec::new();
+    let result = send_events(
+        sink,
+        dropper,
+        event_rules,
+        events,
+        warnings,
+        &mut rejected,
+        context,
+    )
+    .await;
+    if let Some(deduplicator) = &deduplicator {
+        match &result {
+            Ok(()) => deduplicator.release(&context.token, &rejected).await,
+            Err(_) => deduplicator.release(&context.token, &claimed).await,
+        }
+    }
+    result
+}
+
+/// Applies the event rules, then processes and sends the events along with the warnings.
+/// The uuids of the events turned into warnings are added to `rejected`.
+async fn send_events(
+    sink: Arc<dyn sinks::Event + Send + Sync>,
+    dropper: Arc<TokenDropper>,
+    event_rules: Arc<EventRules>,
+    events: Vec<&RawEvent>,
+    mut warnings: Vec<ProcessedEvent>,
+    rejected: &mut Vec<Uuid>,
+    context: &ProcessingContext,
+) -> Result<(), CaptureError> {
+    let total = events.len();
+    let events = event_rules.filter(&context.token, events);
+    if events.len() < total {
+        report_dropped_events("event_rule", (total - events.len()) as u64);
+    }
 
-    events.retain(|e| {
+    let mut processed = Vec::with_capacity(events.len() + warnings.len());
+    for event in events {
+        match process_single_event(event, context) {
+            Ok(event) => processed.push(event),
+            Err(error) => {
+                let payload = serde_json::to_value(event)?;
+                warnings.push(dropped_event_warning(&error, &payload, context)?);
+                report_dropped_events(drop_reason(&error), 1);
+                rejected.extend(event.uuid);
+            }
+        }
+    }
+    if !warnings.is_empty() {
+        tracing::log::warn!("dropped {} invalid events from batch", warnings.len());
+    }
+
+    processed.retain(|e| {
         if dropper.should_drop(&e.event.token, &e.event.distinct_id) {
             report_dropped_events("token_dropper", 1);
             false
@@ -330,12 +813,14 @@ pub async fn process_events<'a>(
         }
     });
 
-    tracing::debug!(events=?events, "processed {} events", events.len());
+    processed.extend(warnings);
+
+    tracing::debug!(events=?processed, "processed {} events", processed.len());
 
-    if events.len() == 1 {
-        sink.send(events[0].clone()).await
+    if processed.len() == 1 {
+        sink.send(processed[0].clone()).await
     } else {
-        sink.send_batch(events).await
+        sink.send_batch(processed).await
     }
 }
 
@@ -344,6 +829,7 @@ pub async fn process_replay_events<'a>(
     sink: Arc<dyn sinks::Event + Send + Sync>,
     mut events: Vec<RawEvent>,
     context: &'a ProcessingContext,
+    message_max_bytes: usize,
 ) -> Result<(), CaptureError> {
     // Grab metadata about the whole batch from the first event before
     // we drop all the events as we rip out the snapshot data
@@ -402,29 +888,132 @@ pub async fn process_replay_events<'a>(
         ),
     };
 
-    let event = CapturedEvent {
-        uuid,
-        distinct_id: distinct_id.clone(),
-        ip: context.client_ip.clone(),
-        data: json!({
+    let snapshot_data = |items: Vec<Value>, chunk: Option<(Uuid, usize, usize)>| {
+        let mut data = json!({
             "event": "$snapshot_items",
             "properties": {
                 "distinct_id": distinct_id,
                 "$session_id": session_id,
                 "$window_id": window_id,
                 "$snapshot_source": snapshot_source,
-                "$snapshot_items": snapshot_items,
+                "$snapshot_items": items,
                 "$lib": snapshot_library,
             }
-        })
-        .to_string(),
+        });
+        if let (Some((id, index, count)), Some(properties)) =
+            (chunk, data["properties"].as_object_mut())
+        {
+            properties.insert("$snapshot_chunk_id".to_string(), json!(id));
+            properties.insert("$snapshot_chunk_index".to_string(), json!(index));
+            properties.insert("$snapshot_chunk_count".to_string(), json!(count));
+        }
+        data.to_string()
+    };
+    let captured_event = |uuid: Uuid, data: String| CapturedEvent {
+        uuid,
+        distinct_id: distinct_id.clone(),
+        ip: context.client_ip.clone(),
+        data,
         now: context.now.clone(),
         sent_at: context.sent_at,
         token: context.token.clone(),
         is_cookieless_mode,
     };
 
-    sink.send(ProcessedEvent { metadata, event }).await
+    // Size of a message without its snapshot items, data is escaped as it is a JSON string
+    let envelope_bytes = serde_json::to_string(&captured_event(uuid, String::new()))?.len()
+        + escaped_len(&snapshot_data(
+            Vec::new(),
+            Some((uuid, usize::MAX, usize::MAX)),
+        ))
+        + REPLAY_MESSAGE_MARGIN_BYTES;
+    let mut chunks = split_snapshot_items(
+        snapshot_items,
+        message_max_bytes.saturating_sub(envelope_bytes),
+    );
+
+    if chunks.len() == 1 {
+        let data = snapshot_data(chunks.remove(0), None);
+        let event = captured_event(uuid, data);
+        return sink.send(ProcessedEvent { metadata, event }).await;
+    }
+
+    // All chunks are keyed by session_id, consumers put them back together using the
+    // id of the first chunk, that keeps the uuid of the original event
+    let count = chunks.len();
+    counter!("capture_replay_split_messages_total").increment(count as u64);
+    let events = chunks
+        .into_iter()
+        .enumerate()
+        .map(|(index, items)| {
+            let chunk_uuid = if index == 0 { uuid } else { uuid_v7() };
+            let data = snapshot_data(items, Some((uuid, index, count)));
+            ProcessedEvent {
+                metadata: metadata.clone(),
+                event: captured_event(chunk_uuid, data),
+            }
+        })
+        .collect();
+    sink.send_batch(events).await
+}
+
+/// Splits the snapshot items in ordered chunks holding at most `max_bytes` of serialized items.
+/// An item bigger than `max_bytes` gets a chunk of its own, and will be rejected by the sink.
+fn split_snapshot_items(items: Vec<Value>, max_bytes: usize) -> Vec<Vec<Value>> {
+    let mut chunks = Vec::new();
+    let mut chunk = Vec::new();
+    let mut chunk_bytes = 0;
+    for item in items {
+        let item_bytes = escaped_len(&item.to_string()) + 1; // Separating comma
+        if !chunk.is_empty() && chunk_bytes + item_bytes > max_bytes {
+            chunks.push(std::mem::take(&mut chunk));
+            chunk_bytes = 0;
+        }
+        chunk_bytes += item_bytes;
+        chunk.push(item);
+    }
+    chunks.push(chunk);
+    chunks
+}
+
+/// Length of the string once escaped as a JSON string, without the surrounding quotes.
+fn escaped_len(value: &str) -> usize {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'"' | b'\\' | b'\n' | b'\r' | b'\t' | 0x08 | 0x0c => 2,
+            0x00..=0x1f => 6, // \u00XX
+            _ => 1,
+        })
+        .sum()
+}
+
+/// Timestamp of the event as computed by the plugin-server: `now` minus the `offset` if set,
+/// else the `timestamp` corrected for the client's clock skew using `sent_at`, else `now`.
+/// Timestamps more than 23 hours in the future are replaced by `now`.
+fn ingestion_timestamp(event: &RawEvent, context: &ProcessingContext) -> Option<OffsetDateTime> {
+    let now = OffsetDateTime::parse(&context.now, &Iso8601::DEFAULT).ok()?;
+    let ignore_sent_at = event
+        .properties
+        .get("$ignore_sent_at")
+        .and_then(Value::as_bool)
+        .unwrap_or(false);
+    let sent_at = context.sent_at.filter(|_| !ignore_sent_at);
+
+    let timestamp = event
+        .timestamp
+        .as_deref()
+        .and_then(|t| OffsetDateTime::parse(t, &Iso8601::DEFAULT).ok());
+    let mut computed = match (event.offset, timestamp, sent_at) {
+        (Some(offset), _, _) => now - time::Duration::milliseconds(offset),
+        (None, Some(timestamp), Some(sent_at)) => now + (timestamp - sent_at),
+        (None, Some(timestamp), None) => timestamp,
+        (None, None, _) => now,
+    };
+    if computed - now > time::Duration::hours(23) {
+        computed = now;
+    }
+    Some(computed)
 }
 
 fn snapshot_library_fallback_from(user_agent: Option<&String>) -> Option<String> {
@@ -435,3 +1024,142 @@ fn snapshot_library_fallback_from(user_agent: Option<&String>) -> Option<String>
         .filter(|s| s.contains("posthog"))
         .or(Some("web".to_string()))
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use serde_json::{json, Value};
+
+    use common_types::{CapturedEvent, RawEvent};
+    use time::macros::datetime;
+
+    use super::{escaped_len, ingestion_timestamp, process_single_event, split_snapshot_items};
+    use crate::cookieless::COOKIELESS_SERVER_HASHED_PROPERTY;
+    use crate::utils::uuid_v7;
+    use crate::v0_request::ProcessingContext;
+
+    #[test]
+    fn escaped_len_matches_serde() {
+        for value in [
+            "plain",
+            r#"{"quoted": "a\\b"}"#,
+            "line\nbreak\ttab\u{1}",
+            "émoji 🦔",
+        ] {
+            let escaped = serde_json::to_string(&Value::String(value.to_string())).unwrap();
+            assert_eq!(escaped.len() - 2, escaped_len(value), "{}", value);
+        }
+    }
+
+    #[test]
+    fn computes_ingestion_timestamp() {
+        let context = ProcessingContext {
+            lib_version: None,
+            user_agent: None,
+            sent_at: Some(datetime!(2024-01-01 00:00:10 UTC)),
+            token: "my_token".to_string(),
+            now: "2024-01-01T00:00:00Z".to_string(),
+            client_ip: "127.0.0.1".to_string(),
+            historical_migration: false,
+            server_hashed: HashSet::new(),
+        };
+        let event = |timestamp: Option<&str>, offset: Option<i64>| RawEvent {
+            event: "e".to_string(),
+            timestamp: timestamp.map(String::from),
+            offset,
+            ..Default::default()
+        };
+
+        // The client's clock is 10 seconds ahead
+        assert_eq!(
+            Some(datetime!(2023-12-31 23:59:55 UTC)),
+            ingestion_timestamp(&event(Some("2024-01-01T00:00:05Z"), None), &context)
+        );
+        assert_eq!(
+            Some(datetime!(2023-12-31 23:59:59 UTC)),
+            ingestion_timestamp(&event(Some("2024-01-01T00:00:05Z"), Some(1000)), &context)
+        );
+        assert_eq!(
+            Some(datetime!(2024-01-01 00:00:00 UTC)),
+            ingestion_timestamp(&event(None, None), &context)
+        );
+
+        let context = ProcessingContext {
+            sent_at: None,
+            ..context
+        };
+        assert_eq!(
+            Some(datetime!(2023-12-01 00:00:00 UTC)),
+            ingestion_timestamp(&event(Some("2023-12-01T00:00:00Z"), None), &context)
+        );
+        assert_eq!(
+            Some(datetime!(2024-01-01 00:00:00 UTC)),
+            ingestion_timestamp(&event(Some("2024-02-01T00:00:00Z"), None), &context)
+        );
+    }
+
+    #[test]
+    fn splits_snapshot_items() {
+        let item = |i: usize| json!({"type": 3, "data": "x".repeat(100), "i": i});
+        let item_bytes = escaped_len(&item(0).to_string()) + 1;
+        let items: Vec<Value> = (0..10).map(item).collect();
+
+        // Everything fits in a single chunk
+        let chunks = split_snapshot_items(items.clone(), 10 * item_bytes);
+        assert_eq!(vec![items.clone()], chunks);
+
+        // Three items per chunk, order is kept
+        let chunks = split_snapshot_items(items.clone(), 3 * item_bytes + 1);
+        assert_eq!(
+            vec![3, 3, 3, 1],
+            chunks.iter().map(Vec::len).collect::<Vec<_>>()
+        );
+        assert_eq!(items, chunks.concat());
+
+        // Items over the limit get a chunk of their own
+        let chunks = split_snapshot_items(items.clone(), 10);
+        assert_eq!(10, chunks.len());
+
+        assert_eq!(vec![Vec::<Value>::new()], split_snapshot_items(vec![], 10));
+    }
+
+    #[test]
+    fn only_trusts_events_hashed_by_capture() {
+        let uuid = uuid_v7();
+        let context = ProcessingContext {
+            lib_version: None,
+            user_agent: None,
+            sent_at: None,
+            token: "my_token".to_string(),
+            now: "2024-01-01T00:00:00Z".to_string(),
+            client_ip: "1.2.3.4".to_string(),
+            historical_migration: false,
+            server_hashed: HashSet::from([uuid]),
+        };
+        let event = |uuid| RawEvent {
+            uuid: Some(uuid),
+            event: "e".to_string(),
+            distinct_id: Some(json!("id1")),
+            properties: [(COOKIELESS_SERVER_HASHED_PROPERTY.to_string(), json!(true))].into(),
+            ..Default::default()
+        };
+        let data = |event: &CapturedEvent| {
+            serde_json::from_str::<Value>(&event.data).unwrap()["properties"].clone()
+        };
+
+        let hashed = process_single_event(&event(uuid), &context).unwrap().event;
+        assert_eq!("", hashed.ip);
+        assert_eq!(
+            json!(true),
+            data(&hashed)[COOKIELESS_SERVER_HASHED_PROPERTY]
+        );
+
+        // The client marked the event, it keeps its IP and loses the mark
+        let marked = process_single_event(&event(uuid_v7()), &context)
+            .unwrap()
+            .event;
+        assert_eq!("1.2.3.4", marked.ip);
+        assert_eq!(json!({}), data(&marked));
+    }
+}
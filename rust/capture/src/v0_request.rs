@@ -3,23 +3,60 @@ use std::io::prelude::*;
 
 use bytes::{Buf, Bytes};
 use common_types::{CapturedEvent, RawEvent};
-use flate2::read::GzDecoder;
-use serde::Deserialize;
+use flate2::read::{DeflateDecoder, GzDecoder, ZlibDecoder};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use time::format_description::well_known::Iso8601;
 use time::OffsetDateTime;
 use tracing::instrument;
+use uuid::Uuid;
 
 use crate::api::CaptureError;
+use crate::binary_request::{decode_protobuf_request, BinaryFormat};
+use crate::lzstring;
 use crate::prometheus::report_dropped_events;
 use crate::token::validate_token;
 
-#[derive(Deserialize, Default)]
+#[derive(Deserialize, Default, Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Compression {
     #[default]
     Unsupported,
 
     #[serde(rename = "gzip", alias = "gzip-js")]
     Gzip,
+    #[serde(rename = "zstd")]
+    Zstd,
+    #[serde(rename = "br", alias = "brotli")]
+    Brotli,
+    #[serde(rename = "deflate")]
+    Deflate,
+    #[serde(rename = "lz64", alias = "lz-string")]
+    LZ64,
+}
+
+impl Compression {
+    /// Maps a `Content-Encoding` header value, returns None for identity or unknown encodings.
+    pub fn from_content_encoding(value: &str) -> Option<Compression> {
+        match value.trim().to_lowercase().as_str() {
+            "gzip" | "x-gzip" | "gzip-js" => Some(Compression::Gzip),
+            "zstd" => Some(Compression::Zstd),
+            "br" => Some(Compression::Brotli),
+            "deflate" => Some(Compression::Deflate),
+            "lz64" => Some(Compression::LZ64),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Compression::Unsupported => "unsupported",
+            Compression::Gzip => "gzip",
+            Compression::Zstd => "zstd",
+            Compression::Brotli => "brotli",
+            Compression::Deflate => "deflate",
+            Compression::LZ64 => "lz64",
+        }
+    }
 }
 
 #[derive(Deserialize, Default)]
@@ -31,9 +68,19 @@ pub struct EventQuery {
 
     #[serde(alias = "_")]
     sent_at: Option<i64>,
+
+    // Like posthog-django, the token may be sent as a query parameter, see `token`
+    token: Option<String>,
+    api_key: Option<String>,
 }
 
 impl EventQuery {
+    /// Token sent as a `token` or `api_key` query parameter. The payload's token is still the
+    /// one the events are captured for, this one only allows checking its limits early.
+    pub fn token(&self) -> Option<&str> {
+        self.token.as_deref().or(self.api_key.as_deref())
+    }
+
     /// Returns the parsed value of the sent_at timestamp if present in the query params.
     /// We only support the format sent by recent posthog-js versions, in milliseconds integer.
     /// Values in seconds integer (older SDKs will be ignored).
@@ -54,9 +101,19 @@ impl EventQuery {
 #[derive(Debug, Deserialize)]
 pub struct EventFormData {
     pub data: String,
+    token: Option<String>,
+    api_key: Option<String>,
+}
+
+impl EventFormData {
+    /// Token sent as a `token` or `api_key` form field, like `EventQuery::token`.
+    pub fn token(&self) -> Option<&str> {
+        self.token.as_deref().or(self.api_key.as_deref())
+    }
 }
 
 pub static GZIP_MAGIC_NUMBERS: [u8; 3] = [0x1f, 0x8b, 8];
+pub static ZSTD_MAGIC_NUMBERS: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
 
 #[derive(Deserialize)]
 #[serde(untagged)]
@@ -69,6 +126,73 @@ pub enum RawRequest {
     One(Box<RawEvent>),
 }
 
+/// Same shapes as `RawRequest`, with the events kept unparsed. Used to find out which
+/// events of a request are invalid, see `RawRequest::from_compressed_bytes_lenient`.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum LenientRequest {
+    Array(Vec<Value>),
+    Batch {
+        #[serde(alias = "api_key")]
+        token: String,
+        historical_migration: Option<bool>,
+        sent_at: Option<String>,
+        batch: Vec<Value>,
+    },
+}
+
+impl LenientRequest {
+    // `err` is the error of the strict parsing, returned if no event of an array is valid
+    fn into_request(
+        self,
+        err: CaptureError,
+    ) -> Result<(RawRequest, Vec<InvalidEvent>), CaptureError> {
+        let parse_events = |values: Vec<Value>| {
+            let mut events = Vec::with_capacity(values.len());
+            let mut invalid = Vec::new();
+            for value in values {
+                match RawEvent::deserialize(&value) {
+                    Ok(event) => events.push(event),
+                    Err(e) => invalid.push(InvalidEvent {
+                        payload: value,
+                        error: CaptureError::InvalidEvent(e.to_string()),
+                    }),
+                }
+            }
+            (events, invalid)
+        };
+
+        match self {
+            LenientRequest::Array(values) => match parse_events(values) {
+                (events, _) if events.is_empty() => Err(err),
+                (events, invalid) => Ok((RawRequest::Array(events), invalid)),
+            },
+            LenientRequest::Batch {
+                token,
+                historical_migration,
+                sent_at,
+                batch,
+            } => {
+                let (batch, invalid) = parse_events(batch);
+                let request = BatchedRequest {
+                    token,
+                    historical_migration,
+                    sent_at,
+                    batch,
+                };
+                Ok((RawRequest::Batch(request), invalid))
+            }
+        }
+    }
+}
+
+/// An event that failed to parse, along with its payload.
+#[derive(Debug)]
+pub struct InvalidEvent {
+    pub payload: Value,
+    pub error: CaptureError,
+}
+
 #[derive(Deserialize)]
 pub struct BatchedRequest {
     #[serde(alias = "api_key")]
@@ -82,63 +206,95 @@ impl RawRequest {
     /// Takes a request payload and tries to decompress and unmarshall it.
     /// While posthog-js sends a compression query param, a sizable portion of requests
     /// fail due to it being missing when the body is compressed.
-    /// Instead of trusting the parameter, we peek at the payload's first three bytes to
-    /// detect gzip, fallback to uncompressed utf8 otherwise.
-    #[instrument(skip_all)]
+    /// Instead of trusting the parameter, we peek at the payload's first bytes to
+    /// detect gzip and zstd, fallback to uncompressed utf8 otherwise.
     pub fn from_bytes(bytes: Bytes, limit: usize) -> Result<RawRequest, CaptureError> {
+        Self::from_compressed_bytes(bytes, limit, Compression::Unsupported)
+    }
+
+    /// Same as `from_bytes`, but honours a compression hint sourced from the request's
+    /// `compression` query parameter or `Content-Encoding` header. The hint is required
+    /// for codecs we cannot detect: brotli, deflate and lz64.
+    #[instrument(skip_all)]
+    pub fn from_compressed_bytes(
+        bytes: Bytes,
+        limit: usize,
+        compression: Compression,
+    ) -> Result<RawRequest, CaptureError> {
         tracing::debug!(len = bytes.len(), "decoding new event");
 
-        let payload = if bytes.starts_with(&GZIP_MAGIC_NUMBERS) {
-            let len = bytes.len();
-            let mut zipstream = GzDecoder::new(bytes.reader());
-            let chunk = &mut [0; 1024];
-            let mut buf = Vec::with_capacity(len);
-            loop {
-                let got = match zipstream.read(chunk) {
-                    Ok(got) => got,
-                    Err(e) => {
-                        tracing::error!("failed to read gzip stream: {}", e);
-                        return Err(CaptureError::RequestDecodingError(String::from(
-                            "invalid gzip data",
-                        )));
-                    }
-                };
-                if got == 0 {
-                    break;
-                }
-                buf.extend_from_slice(&chunk[..got]);
-                if buf.len() > limit {
-                    tracing::error!("GZIP decompression limit reached");
-                    report_dropped_events("event_too_big", 1);
-                    return Err(CaptureError::EventTooBig);
-                }
-            }
-            match String::from_utf8(buf) {
-                Ok(s) => s,
-                Err(e) => {
-                    tracing::error!("failed to decode gzip: {}", e);
-                    return Err(CaptureError::RequestDecodingError(String::from(
-                        "invalid gzip data",
-                    )));
-                }
-            }
-        } else {
-            let s = String::from_utf8(bytes.into()).map_err(|e| {
-                tracing::error!("failed to decode body: {}", e);
-                CaptureError::RequestDecodingError(String::from("invalid body encoding"))
-            })?;
-            if s.len() > limit {
-                tracing::error!("Request size limit reached");
-                report_dropped_events("event_too_big", 1);
-                return Err(CaptureError::EventTooBig);
-            }
-            s
-        };
+        let payload = decompress_payload(bytes, limit, compression)?;
 
         tracing::debug!(json = payload, "decoded event data");
         Ok(serde_json::from_str::<RawRequest>(&payload)?)
     }
 
+    /// Same as `from_compressed_bytes`, but invalid events don't fail the whole request:
+    /// they are returned separately, so that the valid events can still be ingested.
+    /// This only applies to batches, and to arrays holding at least one valid event, as
+    /// we could not tell the token of the request otherwise.
+    #[instrument(skip_all)]
+    pub fn from_compressed_bytes_lenient(
+        bytes: Bytes,
+        limit: usize,
+        compression: Compression,
+    ) -> Result<(RawRequest, Vec<InvalidEvent>), CaptureError> {
+        tracing::debug!(len = bytes.len(), "decoding new event");
+
+        let payload = decompress_payload(bytes, limit, compression)?;
+        Self::from_payload_lenient(&payload)
+    }
+
+    /// Same as `from_compressed_bytes_lenient`, for an already decompressed payload.
+    pub fn from_payload_lenient(
+        payload: &str,
+    ) -> Result<(RawRequest, Vec<InvalidEvent>), CaptureError> {
+        tracing::debug!(json = payload, "decoded event data");
+        let err = match serde_json::from_str::<RawRequest>(payload) {
+            Ok(request) => return Ok((request, Vec::new())),
+            Err(err) => CaptureError::from(err),
+        };
+
+        let Ok(lenient) = serde_json::from_str::<LenientRequest>(payload) else {
+            return Err(err);
+        };
+        lenient.into_request(err)
+    }
+
+    /// Same as `from_compressed_bytes_lenient`, for the binary encodings, see `binary_request`.
+    #[instrument(skip_all, fields(format = format.as_str()))]
+    pub fn from_binary_bytes_lenient(
+        bytes: Bytes,
+        limit: usize,
+        compression: Compression,
+        format: BinaryFormat,
+    ) -> Result<(RawRequest, Vec<InvalidEvent>), CaptureError> {
+        tracing::debug!(len = bytes.len(), "decoding new binary event");
+
+        let payload = decompress_bytes(bytes, limit, compression)?;
+        Self::from_binary_payload_lenient(&payload, format)
+    }
+
+    /// Same as `from_binary_bytes_lenient`, for an already decompressed payload.
+    pub fn from_binary_payload_lenient(
+        payload: &[u8],
+        format: BinaryFormat,
+    ) -> Result<(RawRequest, Vec<InvalidEvent>), CaptureError> {
+        match format {
+            BinaryFormat::MessagePack => {
+                let err = match rmp_serde::from_slice::<RawRequest>(payload) {
+                    Ok(request) => return Ok((request, Vec::new())),
+                    Err(err) => CaptureError::RequestParsingError(err.to_string()),
+                };
+                let Ok(lenient) = rmp_serde::from_slice::<LenientRequest>(payload) else {
+                    return Err(err);
+                };
+                lenient.into_request(err)
+            }
+            BinaryFormat::Protobuf => decode_protobuf_request(payload),
+        }
+    }
+
     pub fn events(self) -> Vec<RawEvent> {
         match self {
             RawRequest::Array(events) => events,
@@ -176,6 +332,143 @@ impl RawRequest {
     }
 }
 
+/// Decompresses a request payload, and checks it is valid utf8 and fits within the given
+/// size limit once decompressed.
+///
+/// Gzip and zstd payloads are detected by their magic numbers, whatever the hint says: if
+/// the hint claims one of these but the payload does not start with their magic numbers,
+/// it is read as uncompressed.
+pub fn decompress_payload(
+    bytes: Bytes,
+    limit: usize,
+    compression: Compression,
+) -> Result<String, CaptureError> {
+    let compression = detect_compression(&bytes, compression);
+    if compression == Compression::LZ64 {
+        let input = std::str::from_utf8(&bytes).map_err(|e| {
+            tracing::error!("failed to decode lz64 body: {}", e);
+            CaptureError::RequestDecodingError(String::from("invalid lz64 data"))
+        })?;
+        return lzstring::decompress_from_base64(input, limit).inspect_err(|e| {
+            if let CaptureError::EventTooBig = e {
+                report_dropped_events("event_too_big", 1);
+            }
+        });
+    }
+
+    let buf = decompress_bytes(bytes, limit, compression)?;
+    String::from_utf8(buf).map_err(|e| match compression {
+        Compression::Unsupported => {
+            tracing::error!("failed to decode body: {}", e);
+            CaptureError::RequestDecodingError(String::from("invalid body encoding"))
+        }
+        _ => {
+            tracing::error!("failed to decode {}: {}", compression.as_str(), e);
+            CaptureError::RequestDecodingError(format!("invalid {} data", compression.as_str()))
+        }
+    })
+}
+
+/// Same as `decompress_payload`, for binary payloads. As it is a text encoding, lz64 is
+/// not supported.
+pub fn decompress_bytes(
+    bytes: Bytes,
+    limit: usize,
+    compression: Compression,
+) -> Result<Vec<u8>, CaptureError> {
+    let compression = detect_compression(&bytes, compression);
+    match compression {
+        Compression::Unsupported => {
+            if bytes.len() > limit {
+                tracing::error!("Request size limit reached");
+                report_dropped_events("event_too_big", 1);
+                return Err(CaptureError::EventTooBig);
+            }
+            Ok(bytes.into())
+        }
+        Compression::LZ64 => Err(CaptureError::RequestDecodingError(String::from(
+            "lz64 is not supported for binary payloads",
+        ))),
+        Compression::Gzip => read_with_limit(GzDecoder::new(bytes.reader()), limit, compression),
+        Compression::Zstd => {
+            let decoder =
+                zstd::stream::read::Decoder::with_buffer(bytes.reader()).map_err(|e| {
+                    tracing::error!("failed to create zstd decoder: {}", e);
+                    CaptureError::RequestDecodingError(String::from("invalid zstd data"))
+                })?;
+            read_with_limit(decoder, limit, compression)
+        }
+        Compression::Brotli => read_with_limit(
+            brotli_decompressor::Decompressor::new(bytes.reader(), 4096),
+            limit,
+            compression,
+        ),
+        Compression::Deflate => {
+            // HTTP's deflate is zlib-wrapped, but some clients send raw deflate streams
+            if has_zlib_header(&bytes) {
+                read_with_limit(ZlibDecoder::new(bytes.reader()), limit, compression)
+            } else {
+                read_with_limit(DeflateDecoder::new(bytes.reader()), limit, compression)
+            }
+        }
+    }
+}
+
+fn detect_compression(bytes: &[u8], hint: Compression) -> Compression {
+    if bytes.starts_with(&GZIP_MAGIC_NUMBERS) {
+        Compression::Gzip
+    } else if bytes.starts_with(&ZSTD_MAGIC_NUMBERS) {
+        Compression::Zstd
+    } else {
+        match hint {
+            Compression::Gzip | Compression::Zstd => Compression::Unsupported,
+            c => c,
+        }
+    }
+}
+
+/// Reads a decompression stream to completion, failing as soon as the decompressed
+/// size crosses the limit, to protect against decompression bombs.
+fn read_with_limit<R: Read>(
+    mut stream: R,
+    limit: usize,
+    compression: Compression,
+) -> Result<Vec<u8>, CaptureError> {
+    let chunk = &mut [0; 1024];
+    let mut buf = Vec::new();
+    loop {
+        let got = match stream.read(chunk) {
+            Ok(got) => got,
+            Err(e) => {
+                tracing::error!("failed to read {} stream: {}", compression.as_str(), e);
+                return Err(CaptureError::RequestDecodingError(format!(
+                    "invalid {} data",
+                    compression.as_str()
+                )));
+            }
+        };
+        if got == 0 {
+            break;
+        }
+        buf.extend_from_slice(&chunk[..got]);
+        if buf.len() > limit {
+            tracing::error!("{} decompression limit reached", compression.as_str());
+            report_dropped_events("event_too_big", 1);
+            return Err(CaptureError::EventTooBig);
+        }
+    }
+    Ok(buf)
+}
+
+/// Checks the first two bytes for a valid zlib header (RFC 1950): deflate method and a
+/// valid check value.
+fn has_zlib_header(bytes: &[u8]) -> bool {
+    match bytes {
+        [cmf, flg, ..] => cmf & 0x0f == 8 && (u16::from(*cmf) << 8 | u16::from(*flg)) % 31 == 0,
+        _ => false,
+    }
+}
+
 #[instrument(skip_all, fields(events = events.len()))]
 pub fn extract_token(events: &[RawEvent]) -> Result<String, CaptureError> {
     let distinct_tokens: HashSet<Option<String>> = HashSet::from_iter(
@@ -204,9 +497,13 @@ pub struct ProcessingContext {
     pub now: String,
     pub client_ip: String,
     pub historical_migration: bool,
+    /// Events whose cookieless distinct_id was computed by capture, see `cookieless`.
+    /// They don't carry the client IP, as it was only needed for the hash.
+    pub server_hashed: HashSet<Uuid>,
 }
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum DataType {
     AnalyticsMain,
     AnalyticsHistorical,
@@ -214,15 +511,16 @@ pub enum DataType {
     HeatmapMain,
     ExceptionMain,
     SnapshotMain,
+    LogMain,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ProcessedEvent {
     pub metadata: ProcessedEventMetadata,
     pub event: CapturedEvent,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ProcessedEventMetadata {
     pub data_type: DataType,
     pub session_id: Option<String>,
@@ -242,7 +540,9 @@ mod tests {
     use uuid::Uuid;
 
     use super::CaptureError;
+    use super::Compression;
     use super::RawRequest;
+    use crate::binary_request::BinaryFormat;
 
     fn test_deserialize(json: Value) -> Result<Option<Uuid>, serde_json::Error> {
         #[derive(Deserialize)]
@@ -300,6 +600,95 @@ mod tests {
         );
     }
 
+    fn decode_base64_payload(payload: &str, compression: Compression) -> RawRequest {
+        let compressed_bytes = Bytes::from(
+            base64::engine::general_purpose::STANDARD
+                .decode(payload)
+                .expect("payload is not base64"),
+        );
+        RawRequest::from_compressed_bytes(compressed_bytes, 2048, compression)
+            .expect("failed to parse")
+    }
+
+    fn assert_is_my_event2(request: RawRequest) {
+        let events = request.events();
+        assert_eq!(1, events.len());
+        assert_eq!(Some("my_token2".to_string()), events[0].extract_token());
+        assert_eq!("my_event2".to_string(), events[0].event);
+    }
+
+    #[test]
+    fn decode_zstd_raw_event() {
+        // zstd is detected by its magic numbers, with or without hint
+        let payload = "KLUv/QBYNQMAsoUSFqClbTbbbPMlvwFM7efvu1a9t9MPhTzkIC7oJ+36uVTBYU171AM24+va+5DFUTDMWD2XuCZCF704wAXz1sBmjHk7oFyVaDMzYgQJAFxkA0JeiraRglhRXe1Z8HKBzqwFg3IC";
+        assert_is_my_event2(decode_base64_payload(payload, Compression::Zstd));
+        assert_is_my_event2(decode_base64_payload(payload, Compression::Unsupported));
+    }
+
+    #[test]
+    fn decode_brotli_raw_event() {
+        let payload = "G5IAYCyLd1Cr4agspm7dOofn1Cxpa3sSRKSvRWS+0iZJWFsp6BQZXIt8leXRyYHD12otiq8BHrKQ041hKAPbnZxq9D/ohmBP8mDwgszsUFxORYoLKO2kQx3yAhECztbeuiAWGoX0AA==";
+        assert_is_my_event2(decode_base64_payload(payload, Compression::Brotli));
+    }
+
+    #[test]
+    fn decode_deflate_raw_event() {
+        // zlib-wrapped, as per the HTTP spec
+        let payload = "eJw9jLEKgDAMRPd+hQRHJ0dn/6OIvSEU29AGoYj/bq3FTMd7d7nMUI8cZ+Wwq2VHy0BHqWGm6ZM4EbTjln8jKQqSMnLVV2ONjw4n77BaBO9wRfYahVrj7uNN2HqU/lijR5jJ3OYB9OEpTg==";
+        assert_is_my_event2(decode_base64_payload(payload, Compression::Deflate));
+        // raw deflate stream
+        let payload = "PYyxCoAwDET3foUERydHZ/+jiL0hFNvQBqGI/26txUzHe3e5zFCPHGflsKtlR8tAR6lhpumTOBG045Z/IykKkjJy1VdjjY8OJ++wWgTvcEX2GoVa4+7jTdh6lP5Yo0eYydzmAQ==";
+        assert_is_my_event2(decode_base64_payload(payload, Compression::Deflate));
+    }
+
+    #[test]
+    fn decode_lz64_raw_event() {
+        // Payload from posthog-django's test_lz64_with_emoji
+        let payload = "NoKABBYN4EQKYDc4DsAuMBcYaD4NwyLswA0MADgE4D2JcZqAlnAM6bQwAkFzWMAsgIYBjMAHkAymAAaRdgCNKAd0Y0WMAMIALSgFs40tgICuZMilQB9IwBsV61KhIYA9I4CMAJgDsAOgAMvry4YABw+oY4AJnBaFHrqnOjc7t5+foEhoXokfKjqyHw6KhFRMcRschSKNGZIZIx0FMgsQQBspYwCJihm6nB0AOa2LC4+AKw+bR1wXfJ04TlDzSGllnQyKvJwa8ur1TR1DSou/j56dMhKtGaz6wBeAJ4GQagALPJ8buo3I8iLevQFWBczVGIxGAGYPABONxeMGQlzEcJ0Rj0ZACczXbg3OCQgBCyFxAlxAE1iQBBADSAC0ANYAVT4NIAKmDRC4eAA5AwAMUYABkAJIAcQMPCouOeZCCAFotAA1cLNeR6SIIOgCOBXcKHDwjSFBNyQnzA95BZ7SnxuAQjFwuABmYKCAg8bh8MqBYLgzRcIzc0pcfDgfD4Pn9uv1huNPhkwxGegMFy1KmxeIJRNJlNpDOZrPZXN5gpFYpIEqlsoVStOyDo9D4ljMJjtNBMZBsdgcziSxwCwVCPkclgofTOAH5kHAAB6oAC8jirNbodYbcCbxjOfTM4QoWj4Z0Onm7aT70hI8TiG5q+0aiQCzV80nUfEYZkYlkENLMGxkcQoNJYdrrJRSkEegkDMJtsiMTU7TfPouDAUBIGwED6nOaUDAnaVXWGdwYBAABdYhUF/FAVGpKkqTgAUSDuAQ+QACWlVAKQoGQ+VxABRJk3A5YQ+g8eQ+gAKW5NwKQARwAET5EY7gAdTpMwPFQKllQAX2ICg7TtJQEjAMFQmeNSCKAA==";
+        let events = RawRequest::from_compressed_bytes(payload.into(), 4096, Compression::LZ64)
+            .expect("failed to parse")
+            .events();
+        assert_eq!(1, events.len());
+        assert_eq!("🤓".to_string(), events[0].event);
+    }
+
+    #[test]
+    fn enforce_decompressed_size_limit() {
+        // 10KB of zeroes, compressed to a few bytes
+        let payload = base64::engine::general_purpose::STANDARD
+            .decode("KLUv/QBYTQAAEGFhAQALhwdY")
+            .expect("payload is not base64");
+        assert!(matches!(
+            RawRequest::from_compressed_bytes(payload.into(), 2048, Compression::Zstd),
+            Err(CaptureError::EventTooBig)
+        ));
+    }
+
+    #[test]
+    fn compression_from_content_encoding() {
+        assert_eq!(
+            Some(Compression::Gzip),
+            Compression::from_content_encoding("gzip")
+        );
+        assert_eq!(
+            Some(Compression::Zstd),
+            Compression::from_content_encoding("zstd")
+        );
+        assert_eq!(
+            Some(Compression::Brotli),
+            Compression::from_content_encoding("br")
+        );
+        assert_eq!(
+            Some(Compression::Deflate),
+            Compression::from_content_encoding("deflate")
+        );
+        assert_eq!(
+            Some(Compression::LZ64),
+            Compression::from_content_encoding("lz64")
+        );
+        assert_eq!(None, Compression::from_content_encoding("unknown"));
+    }
+
     #[test]
     fn extract_distinct_id() {
         let parse_and_extract = |input: &'static str| -> Result<String, CaptureError> {
@@ -466,4 +855,66 @@ mod tests {
         let result = test_deserialize(json);
         assert!(result.is_err());
     }
+
+    #[test]
+    fn lenient_parsing_keeps_valid_events() {
+        let parse = |input: &'static str| {
+            RawRequest::from_compressed_bytes_lenient(input.into(), 2048, Compression::Unsupported)
+        };
+
+        let (request, invalid) = parse(
+            r#"{"api_key": "my_token", "batch": [{"event": "e", "distinct_id": "id1"}, {"event": "e", "uuid": "not-a-uuid"}]}"#,
+        )
+        .expect("failed to parse");
+        assert_eq!(1, request.events().len());
+        assert_eq!(1, invalid.len());
+        assert_eq!(Some("not-a-uuid"), invalid[0].payload["uuid"].as_str());
+        assert!(matches!(invalid[0].error, CaptureError::InvalidEvent(_)));
+
+        // Arrays need a valid event to tell the token
+        let (request, invalid) = parse(
+            r#"[{"event": "e", "token": "my_token"}, {"event": "e", "token": "my_token", "uuid": "bad"}]"#,
+        )
+        .expect("failed to parse");
+        assert_eq!(1, request.events().len());
+        assert_eq!(1, invalid.len());
+        assert!(parse(r#"[{"event": "e", "token": "my_token", "uuid": "bad"}]"#).is_err());
+
+        // Other errors still fail the whole request
+        assert!(parse(r#"{"event": "e", "uuid": "bad"}"#).is_err());
+        assert!(parse(r#"{"api_key": "my_token", "batch": "nope"}"#).is_err());
+    }
+
+    #[test]
+    fn msgpack_parsing() {
+        let parse = |input: Value| {
+            let bytes = rmp_serde::to_vec_named(&input).expect("failed to encode");
+            RawRequest::from_binary_bytes_lenient(
+                bytes.into(),
+                2048,
+                Compression::Unsupported,
+                BinaryFormat::MessagePack,
+            )
+        };
+
+        let (request, invalid) = parse(json!({
+            "api_key": "my_token",
+            "batch": [
+                {"event": "e", "distinct_id": "id1", "properties": {"count": 1, "nested": {"a": [true]}}},
+                {"event": "e", "uuid": "not-a-uuid"},
+            ],
+        }))
+        .expect("failed to parse");
+        assert_eq!("my_token", request.extract_and_verify_token().unwrap());
+        let events = request.events();
+        assert_eq!(1, events.len());
+        assert_eq!(json!({"a": [true]}), events[0].properties["nested"]);
+        assert_eq!(1, invalid.len());
+
+        let (request, _) =
+            parse(json!({"event": "e", "token": "my_token"})).expect("failed to parse");
+        assert!(matches!(request, RawRequest::One(_)));
+
+        assert!(parse(json!("nope")).is_err());
+    }
 }
@@ -0,0 +1,335 @@
+use std::collections::HashSet;
+
+use axum::extract::{MatchedPath, Query, State};
+use axum::http::{HeaderMap, Method};
+use axum::{debug_handler, Json};
+use axum_client_ip::InsecureClientIp;
+use bytes::Bytes;
+use common_types::RawEvent;
+use metrics::counter;
+use serde_json::Value;
+use tracing::instrument;
+
+use crate::api::{BatchResponse, CaptureError, CaptureResponseCode, EventResult};
+use crate::prometheus::report_dropped_events;
+use crate::router;
+use crate::token::{request_token, validate_token};
+use crate::v0_endpoint::process_single_event;
+use crate::v0_request::{
+    decompress_payload, Compression, EventQuery, ProcessedEvent, ProcessingContext,
+};
+use crate::v1_request::BatchRequest;
+
+/// Strict batch endpoint: only accepts the `BatchRequest` payload shape.
+///
+/// Every event of the batch is validated on its own, and the response holds
+/// one accepted / rejected / dropped result per event, in submission order.
+/// Invalid events are rejected, the valid ones go through deduplication, the
+/// token's event rules and dropper before being sent to the sink, and are
+/// reported as dropped with the cause if any of them drops them.
+///
+/// Request-level failures (decoding, token, request, rate and billing limits, sink errors) are
+/// still returned as a `CaptureError` for the whole request.
+#[instrument(
+    skip_all,
+    fields(
+        path,
+        token,
+        batch_size,
+        user_agent,
+        content_encoding,
+        content_type,
+        version,
+        compression,
+        historical_migration
+    )
+)]
+#[debug_handler]
+pub async fn batch(
+    state: State<router::State>,
+    InsecureClientIp(ip): InsecureClientIp,
+    meta: Query<EventQuery>,
+    headers: HeaderMap,
+    method: Method,
+    path: MatchedPath,
+    body: Bytes,
+) -> Result<Json<BatchResponse>, CaptureError> {
+    let user_agent = headers
+        .get("user-agent")
+        .map_or("unknown", |v| v.to_str().unwrap_or("unknown"));
+    let content_encoding = headers
+        .get("content-encoding")
+        .map_or("unknown", |v| v.to_str().unwrap_or("unknown"));
+    let content_type = headers
+        .get("content-type")
+        .map_or("", |v| v.to_str().unwrap_or(""));
+
+    tracing::Span::current().record("user_agent", user_agent);
+    tracing::Span::current().record("content_encoding", content_encoding);
+    tracing::Span::current().record("content_type", content_type);
+    tracing::Span::current().record("version", meta.lib_version.clone());
+    tracing::Span::current().record("method", method.as_str());
+    tracing::Span::current().record("path", path.as_str().trim_end_matches('/'));
+
+    let compression = meta
+        .compression
+        .or_else(|| Compression::from_content_encoding(content_encoding))
+        .unwrap_or_default();
+    tracing::Span::current().record("compression", compression.as_str());
+    let body_bytes = body.len();
+    let payload = state.token_limits.decompress(
+        request_token(&headers, meta.token()),
+        router::default_body_limit(path.as_str()),
+        state.event_size_limit,
+        |limit| decompress_payload(body, limit, compression),
+    )?;
+    let decompressed_bytes = payload.len();
+    let request = BatchRequest::from_payload(&payload)?;
+    if let Err(err) = validate_token(&request.token) {
+        report_dropped_events("token_shape_invalid", request.batch.len() as u64);
+        return Err(err.into());
+    }
+
+    tracing::Span::current().record("token", &request.token);
+    tracing::Span::current().record("historical_migration", request.historical_migration());
+    tracing::Span::current().record("batch_size", request.batch.len());
+
+    if request.batch.is_empty() {
+        tracing::log::warn!("rejected empty batch");
+        return Err(CaptureError::EmptyBatch);
+    }
+
+    counter!("capture_events_received_total").increment(request.batch.len() as u64);
+
+    if let Err(err) = state.token_limits.check(
+        &request.token,
+        body_bytes,
+        decompressed_bytes,
+        router::default_body_limit(path.as_str()),
+        &request.batch,
+    ) {
+        report_dropped_events("token_limit_exceeded", request.batch.len() as u64);
+        return Err(err);
+    }
+
+    let mut context = ProcessingContext {
+        lib_version: meta.lib_version.clone(),
+        sent_at: request.sent_at().or(meta.sent_at()),
+        historical_migration: request.historical_migration(),
+        token: request.token,
+        now: state.timesource.current_time(),
+        client_ip: ip.to_string(),
+        user_agent: Some(user_agent.to_string()),
+        server_hashed: HashSet::new(),
+    };
+
+    if let Some(rate_limiter) = &state.rate_limiter {
+        if let Some(retry_after) = rate_limiter.check(&context.token, &context.client_ip).await {
+            report_dropped_events("rate_limited", request.batch.len() as u64);
+            return Err(CaptureError::RateLimited(retry_after));
+        }
+    }
+
+    // Unlike v0, we return a meaningful error code so that clients can stop retrying
+    if state
+        .billing_limiter
+        .is_limited(context.token.as_str())
+        .await
+    {
+        report_dropped_events("over_quota", request.batch.len() as u64);
+        return Err(CaptureError::BillingLimit);
+    }
+
+    let mut parsed: Vec<Result<RawEvent, CaptureError>> =
+        request.batch.into_iter().map(parse_batch_event).collect();
+    if let Some(cookieless) = &state.cookieless {
+        context.server_hashed = cookieless
+            .process_events(parsed.iter_mut().filter_map(|e| e.as_mut().ok()), &context)
+            .await;
+    }
+
+    // Uuids are claimed for all the parsed events, the flags follow their order
+    let (unique, claimed) = match &state.deduplicator {
+        Some(deduplicator) => {
+            let events: Vec<&RawEvent> = parsed.iter().filter_map(|e| e.as_ref().ok()).collect();
+            deduplicator.check_duplicates(&context.token, &events).await
+        }
+        None => (Vec::new(), Vec::new()),
+    };
+    let mut unique = unique.into_iter();
+
+    let mut results = Vec::with_capacity(parsed.len());
+    let mut events = Vec::with_capacity(parsed.len());
+    let mut rejected_uuids = Vec::new();
+    for event in parsed {
+        let duplicate = event.is_ok() && !unique.next().unwrap_or(true);
+        // Rejected events can be fixed and resent, their uuids are released unless claimed
+        // by another event
+        let uuid = event
+            .as_ref()
+            .ok()
+            .and_then(|e| e.uuid)
+            .filter(|_| !duplicate);
+        let outcome = event.and_then(|event| {
+            let processed = process_batch_event(&event, &context)?;
+            Ok(if duplicate {
+                EventOutcome::Drop("duplicate_uuid")
+            } else if !state.event_rules.keep(&context.token, &event) {
+                EventOutcome::Drop("event_rule")
+            } else if state
+                .token_dropper
+                .should_drop(&processed.event.token, &processed.event.distinct_id)
+            {
+                EventOutcome::Drop("token_dropper")
+            } else {
+                EventOutcome::Send(processed)
+            })
+        });
+        match outcome {
+            Ok(EventOutcome::Send(event)) => {
+                events.push(event);
+                results.push(EventResult::Accepted);
+            }
+            Ok(EventOutcome::Drop(cause)) => {
+                report_dropped_events(cause, 1);
+                results.push(EventResult::Dropped {
+                    reason: cause.to_string(),
+                });
+            }
+            Err(err) => {
+                let cause = match err {
+                    CaptureError::InvalidEvent(_) => "invalid_event",
+                    CaptureError::MissingDistinctId => "missing_distinct_id",
+                    CaptureError::MissingEventName => "missing_event_name",
+                    CaptureError::MultipleTokensError => "multiple_tokens",
+                    _ => "process_events_error",
+                };
+                report_dropped_events(cause, 1);
+                rejected_uuids.extend(uuid);
+                results.push(EventResult::Rejected {
+                    reason: err.to_string(),
+                });
+            }
+        }
+    }
+
+    let (mut accepted, mut rejected, mut dropped) = (0, 0, 0);
+    for result in &results {
+        match result {
+            EventResult::Accepted => accepted += 1,
+            EventResult::Rejected { .. } => rejected += 1,
+            EventResult::Dropped { .. } => dropped += 1,
+        }
+    }
+    tracing::debug!(accepted, rejected, dropped, "processed v1 batch");
+
+    let sent = match events.len() {
+        0 => Ok(()),
+        1 => state.sink.send(events.pop().unwrap()).await,
+        _ => state.sink.send_batch(events).await,
+    };
+    if let Some(deduplicator) = &state.deduplicator {
+        match &sent {
+            Ok(()) => deduplicator.release(&context.token, &rejected_uuids).await,
+            Err(_) => deduplicator.release(&context.token, &claimed).await,
+        }
+    }
+    sent?;
+
+    Ok(Json(BatchResponse {
+        status: CaptureResponseCode::Ok,
+        accepted,
+        rejected,
+        dropped,
+        results,
+    }))
+}
+
+enum EventOutcome {
+    Send(ProcessedEvent),
+    // Dropped on purpose, with the cause reported in the metrics
+    Drop(&'static str),
+}
+
+fn parse_batch_event(value: Value) -> Result<RawEvent, CaptureError> {
+    serde_json::from_value(value).map_err(|e| CaptureError::InvalidEvent(e.to_string()))
+}
+
+/// Validates a single event of a v1 batch.
+fn process_batch_event(
+    event: &RawEvent,
+    context: &ProcessingContext,
+) -> Result<ProcessedEvent, CaptureError> {
+    // The batch token is authoritative, refuse events claiming another project
+    if let Some(token) = event.extract_token() {
+        if token != context.token {
+            return Err(CaptureError::MultipleTokensError);
+        }
+    }
+
+    process_single_event(event, context)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use serde_json::json;
+
+    use super::{parse_batch_event, process_batch_event};
+    use crate::api::CaptureError;
+    use crate::v0_request::ProcessingContext;
+
+    fn context() -> ProcessingContext {
+        ProcessingContext {
+            lib_version: None,
+            user_agent: None,
+            sent_at: None,
+            token: "my_token".to_string(),
+            now: "2024-01-01T00:00:00Z".to_string(),
+            client_ip: "127.0.0.1".to_string(),
+            historical_migration: false,
+            server_hashed: HashSet::new(),
+        }
+    }
+
+    #[test]
+    fn validates_each_event() {
+        let context = context();
+        let process_batch_event = |value, context: &ProcessingContext| {
+            parse_batch_event(value).and_then(|event| process_batch_event(&event, context))
+        };
+
+        let event = process_batch_event(json!({"event": "e", "distinct_id": "id1"}), &context)
+            .expect("valid event was rejected");
+        assert_eq!("id1", event.event.distinct_id);
+        assert_eq!("my_token", event.event.token);
+
+        assert!(matches!(
+            process_batch_event(json!({"event": "e"}), &context),
+            Err(CaptureError::MissingDistinctId)
+        ));
+        assert!(matches!(
+            process_batch_event(json!({"event": "", "distinct_id": "id1"}), &context),
+            Err(CaptureError::MissingEventName)
+        ));
+        assert!(matches!(
+            process_batch_event(
+                json!({"event": "e", "distinct_id": "id1", "uuid": "not-a-uuid"}),
+                &context
+            ),
+            Err(CaptureError::InvalidEvent(_))
+        ));
+        assert!(matches!(
+            process_batch_event(json!({"distinct_id": "id1"}), &context),
+            Err(CaptureError::InvalidEvent(_))
+        ));
+        assert!(matches!(
+            process_batch_event(
+                json!({"event": "e", "distinct_id": "id1", "api_key": "other_token"}),
+                &context
+            ),
+            Err(CaptureError::MultipleTokensError)
+        ));
+    }
+}
@@ -0,0 +1,107 @@
+use bytes::Bytes;
+use serde::Deserialize;
+use serde_json::Value;
+use time::format_description::well_known::Iso8601;
+use time::OffsetDateTime;
+use tracing::instrument;
+
+use crate::api::CaptureError;
+use crate::v0_request::{decompress_payload, Compression};
+
+/// Payload accepted by the v1 batch endpoint. Unlike the v0 `RawRequest`, only
+/// the batched shape is supported. Events are kept as raw JSON values so that
+/// each of them can be parsed and validated on its own: one malformed event must
+/// not fail the whole batch.
+#[derive(Deserialize)]
+pub struct BatchRequest {
+    #[serde(alias = "api_key")]
+    pub token: String,
+    pub historical_migration: Option<bool>,
+    pub sent_at: Option<String>,
+    pub batch: Vec<Value>,
+}
+
+impl BatchRequest {
+    /// Takes a request payload and tries to decompress and unmarshall it.
+    /// See `RawRequest::from_compressed_bytes` for how the compression is detected.
+    #[instrument(skip_all)]
+    pub fn from_bytes(
+        bytes: Bytes,
+        limit: usize,
+        compression: Compression,
+    ) -> Result<BatchRequest, CaptureError> {
+        tracing::debug!(len = bytes.len(), "decoding new batch");
+
+        let payload = decompress_payload(bytes, limit, compression)?;
+        Self::from_payload(&payload)
+    }
+
+    /// Same as `from_bytes`, for an already decompressed payload.
+    pub fn from_payload(payload: &str) -> Result<BatchRequest, CaptureError> {
+        Ok(serde_json::from_str::<BatchRequest>(payload)?)
+    }
+
+    pub fn historical_migration(&self) -> bool {
+        self.historical_migration.unwrap_or_default()
+    }
+
+    pub fn sent_at(&self) -> Option<OffsetDateTime> {
+        self.sent_at
+            .as_ref()
+            .and_then(|value| OffsetDateTime::parse(value, &Iso8601::DEFAULT).ok())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::BatchRequest;
+    use crate::api::CaptureError;
+    use crate::v0_request::Compression;
+
+    #[test]
+    fn decode_batch_request() {
+        let input = json!({
+            "api_key": "my_token",
+            "historical_migration": true,
+            "sent_at": "2024-01-01T00:00:00Z",
+            "batch": [
+                {"event": "e1", "distinct_id": "id1"},
+                {"event": "e2", "distinct_id": "id2", "uuid": "not-a-uuid"},
+            ]
+        });
+
+        let request =
+            BatchRequest::from_bytes(input.to_string().into(), 2048, Compression::Unsupported)
+                .expect("failed to parse");
+        assert_eq!("my_token", request.token);
+        assert!(request.historical_migration());
+        assert!(request.sent_at().is_some());
+        // Invalid events are kept as-is, they are validated separately
+        assert_eq!(2, request.batch.len());
+    }
+
+    #[test]
+    fn reject_other_shapes() {
+        let parse = |input: &'static str| {
+            BatchRequest::from_bytes(input.into(), 2048, Compression::Unsupported)
+        };
+
+        // Single event
+        assert!(matches!(
+            parse(r#"{"event": "e", "api_key": "my_token"}"#),
+            Err(CaptureError::RequestParsingError(_))
+        ));
+        // Array of events
+        assert!(matches!(
+            parse(r#"[{"event": "e", "api_key": "my_token"}]"#),
+            Err(CaptureError::RequestParsingError(_))
+        ));
+        // Batch without token
+        assert!(matches!(
+            parse(r#"{"batch": [{"event": "e"}]}"#),
+            Err(CaptureError::RequestParsingError(_))
+        ));
+    }
+}
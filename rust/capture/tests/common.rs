@@ -25,10 +25,12 @@ use tokio::sync::Notify;
 use tokio::time::timeout;
 use tracing::{debug, warn};
 
-use capture::config::{CaptureMode, Config, KafkaConfig};
+use capture::config::{CaptureMode, Config, KafkaConfig, S3Compression};
 use capture::server::serve;
+use capture::sinks::dual::RoutingPolicies;
 use health::HealthStrategy;
 use limiters::redis::{QuotaResource, OVERFLOW_LIMITER_CACHE_KEY, QUOTA_LIMITER_CACHE_KEY};
+use limiters::request_rate::RateLimit;
 
 pub static DEFAULT_CONFIG: Lazy<Config> = Lazy::new(|| Config {
     print_sink: false,
@@ -51,6 +53,7 @@ pub static DEFAULT_CONFIG: Lazy<Config> = Lazy::new(|| Config {
         kafka_client_ingestion_warning_topic: "events_plugin_ingestion".to_string(),
         kafka_exceptions_topic: "events_plugin_ingestion".to_string(),
         kafka_heatmaps_topic: "events_plugin_ingestion".to_string(),
+        kafka_logs_topic: "logs_ingestion".to_string(),
         kafka_replay_overflow_topic: "session_recording_snapshot_item_overflow".to_string(),
         kafka_tls: false,
         kafka_client_id: "".to_string(),
@@ -58,6 +61,9 @@ pub static DEFAULT_CONFIG: Lazy<Config> = Lazy::new(|| Config {
         kafka_producer_max_retries: 2,
         kafka_producer_acks: "all".to_string(),
     },
+    kafka_secondary_hosts: None,
+    kafka_secondary_policies: RoutingPolicies::default(),
+    kafka_secondary_max_copies_in_flight: 1000,
     otel_url: None,
     otel_sampling_rate: 0.0,
     otel_service_name: "capture-testing".to_string(),
@@ -69,7 +75,26 @@ pub static DEFAULT_CONFIG: Lazy<Config> = Lazy::new(|| Config {
     s3_fallback_bucket: None,
     s3_fallback_endpoint: None,
     s3_fallback_prefix: String::new(),
+    s3_fallback_compression: S3Compression::Gzip,
+    s3_fallback_partitioned: false,
+    disk_spill_enabled: false,
+    disk_spill_path: None,
+    disk_spill_max_bytes: 1024 * 1024 * 1024,
+    disk_spill_segment_bytes: 64 * 1024 * 1024,
     healthcheck_strategy: HealthStrategy::All,
+    rate_limit_enabled: false,
+    rate_limit_default: RateLimit::from_str("1000:10000").unwrap(),
+    rate_limit_per_ip: None,
+    cookieless_enabled: false,
+    cookieless_force_stateless_mode: false,
+    cookieless_redis_url: None,
+    dedup_enabled: false,
+    dedup_window_secs: 600,
+    dedup_local_cache_size: 100000,
+    token_limits: None,
+    token_limits_max_body_bytes: 0,
+    shutdown_drain_timeout_secs: 5,
+    shutdown_flush_timeout_secs: 5,
 });
 
 static TRACING_INIT: Once = Once::new();
@@ -136,6 +161,64 @@ impl ServerHandle {
             .expect("failed to send request")
     }
 
+    pub async fn capture_to_v1_batch<T: Into<reqwest::Body>>(&self, body: T) -> reqwest::Response {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_millis(3000))
+            .build()
+            .unwrap();
+        client
+            .post(format!("http://{:?}/i/v1/batch", self.addr))
+            .body(body)
+            .send()
+            .await
+            .expect("failed to send request")
+    }
+
+    pub async fn capture_exception<T: Into<reqwest::Body>>(&self, body: T) -> reqwest::Response {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_millis(3000))
+            .build()
+            .unwrap();
+        client
+            .post(format!("http://{:?}/i/v0/exception", self.addr))
+            .body(body)
+            .send()
+            .await
+            .expect("failed to send request")
+    }
+
+    pub async fn capture_otlp_logs<T: Into<reqwest::Body>>(
+        &self,
+        token: &str,
+        body: T,
+    ) -> reqwest::Response {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_millis(3000))
+            .build()
+            .unwrap();
+        client
+            .post(format!("http://{:?}/i/v1/logs", self.addr))
+            .body(body)
+            .header("Content-Type", "application/json")
+            .bearer_auth(token)
+            .send()
+            .await
+            .expect("failed to send request")
+    }
+
+    pub async fn validate_events<T: Into<reqwest::Body>>(&self, body: T) -> reqwest::Response {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_millis(3000))
+            .build()
+            .unwrap();
+        client
+            .post(format!("http://{:?}/i/v0/validate", self.addr))
+            .body(body)
+            .send()
+            .await
+            .expect("failed to send request")
+    }
+
     pub async fn capture_recording<T: Into<reqwest::Body>>(
         &self,
         body: T,
@@ -6,9 +6,11 @@ use base64::engine::general_purpose;
 use base64::Engine;
 use capture::api::{CaptureError, CaptureResponse, CaptureResponseCode};
 use capture::config::CaptureMode;
+use capture::event_rules::EventRules;
 use capture::router::router;
 use capture::sinks::Event;
 use capture::time::TimeSource;
+use capture::token_limits::TokenLimits;
 use capture::v0_request::{DataType, ProcessedEvent};
 use common_redis::MockRedisClient;
 use health::HealthRegistry;
@@ -119,11 +121,17 @@ async fn it_matches_django_capture_behaviour() -> anyhow::Result<()> {
             sink.clone(),
             redis,
             billing_limiter,
+            None,
             TokenDropper::default(),
+            EventRules::default(),
+            TokenLimits::new(None, 0, 25 * 1024 * 1024).unwrap(),
+            None,
+            None,
             false,
             CaptureMode::Events,
             None,
             25 * 1024 * 1024,
+            1024 * 1024,
         );
 
         let client = TestClient::new(app);
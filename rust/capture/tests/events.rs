@@ -178,6 +178,190 @@ async fn it_captures_a_batch() -> Result<()> {
 
     Ok(())
 }
+#[tokio::test]
+async fn it_reports_per_event_results_on_v1_batch() -> Result<()> {
+    setup_tracing();
+    let token = random_string("token", 16);
+    let distinct_id1 = random_string("id", 16);
+    let distinct_id2 = random_string("id", 16);
+
+    let main_topic = EphemeralTopic::new().await;
+    let histo_topic = EphemeralTopic::new().await;
+    let server = ServerHandle::for_topics(&main_topic, &histo_topic).await;
+
+    let event = json!({
+        "api_key": token,
+        "batch": [{
+            "event": "event1",
+            "distinct_id": distinct_id1
+        },{
+            "event": "event2"
+        },{
+            "event": "event3",
+            "distinct_id": distinct_id2,
+            "uuid": "not-a-uuid"
+        },{
+            "event": "event4",
+            "distinct_id": distinct_id2
+        }]
+    });
+    let res = server.capture_to_v1_batch(event.to_string()).await;
+    assert_eq!(StatusCode::OK, res.status());
+    assert_json_include!(
+        actual: res.json::<serde_json::Value>().await?,
+        expected: json!({
+            "accepted": 2,
+            "rejected": 2,
+            "dropped": 0,
+            "results": [
+                {"status": "accepted"},
+                {"status": "rejected", "reason": "event submitted without a distinct_id"},
+                {"status": "rejected"},
+                {"status": "accepted"},
+            ]
+        })
+    );
+
+    assert_json_include!(
+        actual: main_topic.next_event()?,
+        expected: json!({
+            "token": token,
+            "distinct_id": distinct_id1
+        })
+    );
+    assert_json_include!(
+        actual: main_topic.next_event()?,
+        expected: json!({
+            "token": token,
+            "distinct_id": distinct_id2
+        })
+    );
+    main_topic.assert_empty();
+
+    // Other payload shapes are refused
+    let event = json!([{
+        "token": token,
+        "event": "event1",
+        "distinct_id": distinct_id1
+    }]);
+    let res = server.capture_to_v1_batch(event.to_string()).await;
+    assert_eq!(StatusCode::BAD_REQUEST, res.status());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn it_reports_dropped_events_on_v1_batch() -> Result<()> {
+    setup_tracing();
+    let token = random_string("token", 16);
+    let distinct_id = random_string("id", 16);
+    let dropped_id = random_string("id", 16);
+
+    let main_topic = EphemeralTopic::new().await;
+    let histo_topic = EphemeralTopic::new().await;
+    let mut config = DEFAULT_CONFIG.clone();
+    config.kafka.kafka_topic = main_topic.topic_name().to_string();
+    config.kafka.kafka_historical_topic = histo_topic.topic_name().to_string();
+    config.dropped_keys = Some(format!("{}:{}", token, dropped_id));
+    let server = ServerHandle::for_config(config).await;
+
+    let event = json!({
+        "api_key": token,
+        "batch": [{
+            "event": "event1",
+            "distinct_id": dropped_id
+        },{
+            "event": "event2",
+            "distinct_id": distinct_id
+        }]
+    });
+    let res = server.capture_to_v1_batch(event.to_string()).await;
+    assert_eq!(StatusCode::OK, res.status());
+    assert_json_include!(
+        actual: res.json::<serde_json::Value>().await?,
+        expected: json!({
+            "accepted": 1,
+            "rejected": 0,
+            "dropped": 1,
+            "results": [
+                {"status": "dropped", "reason": "token_dropper"},
+                {"status": "accepted"},
+            ]
+        })
+    );
+
+    assert_json_include!(
+        actual: main_topic.next_event()?,
+        expected: json!({
+            "token": token,
+            "distinct_id": distinct_id
+        })
+    );
+    main_topic.assert_empty();
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn it_validates_events_without_sending_them() -> Result<()> {
+    setup_tracing();
+    let token = random_string("token", 16);
+    let distinct_id = random_string("id", 16);
+
+    let dropped_id = random_string("id", 16);
+
+    let main_topic = EphemeralTopic::new().await;
+    let histo_topic = EphemeralTopic::new().await;
+    let mut config = DEFAULT_CONFIG.clone();
+    config.kafka.kafka_topic = main_topic.topic_name().to_string();
+    config.kafka.kafka_historical_topic = histo_topic.topic_name().to_string();
+    config.dropped_keys = Some(format!("{}:{}", token, dropped_id));
+    let server = ServerHandle::for_config(config).await;
+
+    let event = json!({
+        "api_key": token,
+        "sent_at": "2024-01-01T00:00:10Z",
+        "batch": [{
+            "event": "event1",
+            "distinct_id": distinct_id,
+            "timestamp": "2024-01-01T00:00:05Z"
+        },{
+            "event": "event2"
+        },{
+            "event": "event3",
+            "distinct_id": dropped_id
+        }]
+    });
+    let res = server.validate_events(event.to_string()).await;
+    assert_eq!(StatusCode::OK, res.status());
+    assert_json_include!(
+        actual: res.json::<serde_json::Value>().await?,
+        expected: json!({
+            "token": token,
+            "events": [{
+                "event": "event1",
+                "distinct_id": distinct_id,
+                "data_type": "analytics_main",
+                "topic": main_topic.topic_name(),
+                "partition_key": format!("{}:{}", token, distinct_id),
+                "sent_at": "2024-01-01T00:00:10Z"
+            }],
+            "rejected": [{
+                "payload": {"event": "event2"},
+                "reason": "event submitted without a distinct_id"
+            }],
+            "dropped": [{
+                "payload": {"event": "event3", "distinct_id": dropped_id},
+                "reason": "dropped by the token dropper"
+            }]
+        })
+    );
+
+    main_topic.assert_empty();
+
+    Ok(())
+}
+
 #[tokio::test]
 async fn it_captures_a_historical_batch() -> Result<()> {
     setup_tracing();
@@ -468,6 +652,67 @@ async fn it_applies_billing_limits() -> Result<()> {
     Ok(())
 }
 
+#[tokio::test]
+async fn it_validates_exceptions_on_their_endpoint() -> Result<()> {
+    setup_tracing();
+
+    let token = random_string("token", 16);
+    let distinct_id = random_string("id", 16);
+
+    let main_topic = EphemeralTopic::new().await;
+    let exceptions_topic = EphemeralTopic::new().await;
+
+    let mut config = DEFAULT_CONFIG.clone();
+    config.kafka.kafka_topic = main_topic.topic_name().to_string();
+    config.kafka.kafka_exceptions_topic = exceptions_topic.topic_name().to_string();
+
+    let server = ServerHandle::for_config(config).await;
+
+    let exception = |frame: serde_json::Value| {
+        json!({
+            "token": token,
+            "event": "$exception",
+            "distinct_id": distinct_id,
+            "properties": {
+                "$exception_list": [{
+                    "type": "TypeError",
+                    "value": "undefined is not a function",
+                    "stacktrace": {"type": "raw", "frames": [frame]}
+                }]
+            }
+        })
+    };
+
+    let res = server
+        .capture_exception(exception(json!({"platform": "cobol", "function": "f"})).to_string())
+        .await;
+    assert_eq!(StatusCode::BAD_REQUEST, res.status());
+    assert!(res.text().await?.contains("unknown variant `cobol`"));
+
+    let valid = exception(json!({
+        "platform": "web:javascript",
+        "filename": "https://example.com/app.js",
+        "function": "onClick",
+        "in_app": true,
+        "lineno": 1,
+        "colno": 42
+    }));
+    let res = server.capture_exception(valid.to_string()).await;
+    assert_eq!(StatusCode::OK, res.status());
+
+    assert_json_include!(
+        actual: exceptions_topic.next_event()?,
+        expected: json!({
+            "token": token,
+            "distinct_id": distinct_id
+        })
+    );
+    exceptions_topic.assert_empty();
+    main_topic.assert_empty();
+
+    Ok(())
+}
+
 #[tokio::test]
 async fn it_routes_exceptions_and_heapmaps_to_separate_topics() -> Result<()> {
     setup_tracing();
@@ -647,3 +892,230 @@ async fn it_limits_batch_endpoints_to_20mb() -> Result<()> {
 
     Ok(())
 }
+
+#[tokio::test]
+async fn it_applies_per_token_limits() -> Result<()> {
+    setup_tracing();
+
+    let limited_token = random_string("token", 16);
+    let large_token = random_string("token", 16);
+    let distinct_id = random_string("id", 16);
+
+    let main_topic = EphemeralTopic::new().await;
+    let histo_topic = EphemeralTopic::new().await;
+    let mut config = DEFAULT_CONFIG.clone();
+    config.kafka.kafka_topic = main_topic.topic_name().to_string();
+    config.kafka.kafka_historical_topic = histo_topic.topic_name().to_string();
+    config.token_limits = Some(
+        json!({
+            limited_token.clone(): {"max_batch_events": 1, "max_event_bytes": 1000},
+            large_token.clone(): {"max_body_bytes": 3_000_000},
+        })
+        .to_string(),
+    );
+    config.token_limits_max_body_bytes = 3_000_000;
+    let server = ServerHandle::for_config(config).await;
+
+    let event = |token: &str, size: usize| {
+        json!({
+            "token": token,
+            "event": "testing",
+            "distinct_id": distinct_id,
+            "properties": {"big": "a".repeat(size)}
+        })
+    };
+
+    let batch = json!([event(&limited_token, 10), event(&limited_token, 10)]);
+    let res = server.capture_to_batch(batch.to_string()).await;
+    assert_eq!(StatusCode::PAYLOAD_TOO_LARGE, res.status());
+    let res = server
+        .capture_events(event(&limited_token, 2000).to_string())
+        .await;
+    assert_eq!(StatusCode::PAYLOAD_TOO_LARGE, res.status());
+    let res = server
+        .capture_events(event(&limited_token, 10).to_string())
+        .await;
+    assert_eq!(StatusCode::OK, res.status());
+    assert_json_include!(
+        actual: main_topic.next_event()?,
+        expected: json!({"token": limited_token, "distinct_id": distinct_id})
+    );
+
+    // Other tokens keep the 2MB limit of the route, even if the layer now allows 3MB
+    let res = server
+        .capture_events(event(&random_string("token", 16), 2_500_000).to_string())
+        .await;
+    assert_eq!(StatusCode::PAYLOAD_TOO_LARGE, res.status());
+    // The event is too large to go in kafka, but that's a 400, not a 413
+    let res = server
+        .capture_events(event(&large_token, 2_500_000).to_string())
+        .await;
+    assert_eq!(StatusCode::BAD_REQUEST, res.status());
+
+    main_topic.assert_empty();
+    Ok(())
+}
+
+#[tokio::test]
+async fn it_applies_per_token_limits_to_otlp_logs() -> Result<()> {
+    setup_tracing();
+
+    let small_body_token = random_string("token", 16);
+    let small_batch_token = random_string("token", 16);
+
+    let main_topic = EphemeralTopic::new().await;
+    let histo_topic = EphemeralTopic::new().await;
+    let mut config = DEFAULT_CONFIG.clone();
+    config.kafka.kafka_topic = main_topic.topic_name().to_string();
+    config.kafka.kafka_historical_topic = histo_topic.topic_name().to_string();
+    config.token_limits = Some(
+        json!({
+            small_body_token.clone(): {"max_body_bytes": 1000},
+            small_batch_token.clone(): {"max_batch_events": 1},
+        })
+        .to_string(),
+    );
+    let server = ServerHandle::for_config(config).await;
+
+    let record = |size: usize| json!({"body": {"stringValue": "a".repeat(size)}});
+    let export = |records: Vec<serde_json::Value>| {
+        json!({"resourceLogs": [{"scopeLogs": [{"logRecords": records}]}]}).to_string()
+    };
+
+    let res = server
+        .capture_otlp_logs(&small_body_token, export(vec![record(2000)]))
+        .await;
+    assert_eq!(StatusCode::PAYLOAD_TOO_LARGE, res.status());
+    let res = server
+        .capture_otlp_logs(&small_batch_token, export(vec![record(10), record(10)]))
+        .await;
+    assert_eq!(StatusCode::PAYLOAD_TOO_LARGE, res.status());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn it_drops_otlp_logs_of_billing_limited_teams() -> Result<()> {
+    setup_tracing();
+    let token = random_string("token", 16);
+
+    let redis = PrefixedRedis::new().await;
+    redis.add_billing_limit(QuotaResource::Events, &token, Duration::seconds(60));
+
+    let logs_topic = EphemeralTopic::new().await;
+    let mut config = DEFAULT_CONFIG.clone();
+    config.redis_key_prefix = redis.key_prefix();
+    config.kafka.kafka_logs_topic = logs_topic.topic_name().to_string();
+    let server = ServerHandle::for_config(config).await;
+
+    let export = json!({"resourceLogs": [{"scopeLogs": [{"logRecords": [
+        {"body": {"stringValue": "dropped"}}
+    ]}]}]});
+    let res = server.capture_otlp_logs(&token, export.to_string()).await;
+    // Exporters retry 429s, the success body makes them move on
+    assert_eq!(StatusCode::OK, res.status());
+    assert_eq!(json!({}), res.json::<serde_json::Value>().await?);
+    logs_topic.assert_empty();
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn it_rate_limits_query_tokens_before_decoding() -> Result<()> {
+    setup_tracing();
+    let token = random_string("token", 16);
+
+    let main_topic = EphemeralTopic::new().await;
+    let histo_topic = EphemeralTopic::new().await;
+    let mut config = DEFAULT_CONFIG.clone();
+    config.kafka.kafka_topic = main_topic.topic_name().to_string();
+    config.kafka.kafka_historical_topic = histo_topic.topic_name().to_string();
+    config.rate_limit_enabled = true;
+    config.rate_limit_default = "1:1".parse().unwrap();
+    let server = ServerHandle::for_config(config).await;
+
+    let client = reqwest::Client::new();
+    let send = || {
+        client
+            .post(format!("http://{:?}/i/v0/e?token={}", server.addr, token))
+            .body("not json")
+            .send()
+    };
+    // The payload can't be decoded, but the query token is still counted against its limit
+    let res = send().await?;
+    assert_eq!(StatusCode::BAD_REQUEST, res.status());
+    let res = send().await?;
+    assert_eq!(StatusCode::TOO_MANY_REQUESTS, res.status());
+
+    main_topic.assert_empty();
+    Ok(())
+}
+
+#[tokio::test]
+async fn it_emits_ingestion_warnings_for_invalid_events() -> Result<()> {
+    setup_tracing();
+
+    let token = random_string("token", 16);
+    let distinct_id = random_string("id", 16);
+
+    let main_topic = EphemeralTopic::new().await;
+    let histo_topic = EphemeralTopic::new().await;
+    let warnings_topic = EphemeralTopic::new().await;
+    let mut config = DEFAULT_CONFIG.clone();
+    config.kafka.kafka_topic = main_topic.topic_name().to_string();
+    config.kafka.kafka_historical_topic = histo_topic.topic_name().to_string();
+    config.kafka.kafka_client_ingestion_warning_topic = warnings_topic.topic_name().to_string();
+    let server = ServerHandle::for_config(config).await;
+
+    let batch = json!({
+        "api_key": token,
+        "batch": [
+            {"event": "first", "distinct_id": distinct_id},
+            {"event": "invalid", "distinct_id": distinct_id, "properties": "a".repeat(2000)},
+            {"event": "no_distinct_id"},
+            {"event": "second", "distinct_id": distinct_id},
+        ]
+    });
+    let res = server.capture_to_batch(batch.to_string()).await;
+    assert_eq!(StatusCode::OK, res.status());
+
+    // The valid events of the batch are still captured
+    assert_json_include!(
+        actual: main_topic.next_event()?,
+        expected: json!({"token": token, "distinct_id": distinct_id})
+    );
+    assert_json_include!(
+        actual: main_topic.next_event()?,
+        expected: json!({"token": token, "distinct_id": distinct_id})
+    );
+    main_topic.assert_empty();
+
+    // While each invalid one is replaced by an ingestion warning
+    let mut warnings = Vec::new();
+    for _ in 0..2 {
+        let warning = warnings_topic.next_event()?;
+        assert_json_include!(actual: warning, expected: json!({"token": token}));
+        let data: serde_json::Value = serde_json::from_str(warning["data"].as_str().unwrap())?;
+        assert_eq!("$$client_ingestion_warning", data["event"]);
+        warnings.push((warning["distinct_id"].clone(), data["properties"].clone()));
+    }
+    warnings_topic.assert_empty();
+    warnings.sort_by_key(|(_, properties)| properties["$$capture_drop_reason"].to_string());
+
+    let (warning_distinct_id, properties) = &warnings[0];
+    assert_eq!(&json!(distinct_id), warning_distinct_id);
+    assert_eq!("invalid_event", properties["$$capture_drop_reason"]);
+    let payload = properties["$$capture_dropped_payload"].as_str().unwrap();
+    assert_eq!(1024, payload.chars().count());
+    assert!(payload.contains(r#""event":"invalid""#));
+
+    let (warning_distinct_id, properties) = &warnings[1];
+    assert_eq!("$capture_ingestion_warning", *warning_distinct_id);
+    assert_eq!("missing_distinct_id", properties["$$capture_drop_reason"]);
+    assert!(properties["$$capture_dropped_payload"]
+        .as_str()
+        .unwrap()
+        .contains("no_distinct_id"));
+
+    Ok(())
+}
@@ -7,6 +7,7 @@ mod salt_cache;
 pub use constants::*;
 pub use hash::{do_hash, HashError};
 pub use manager::{
-    CookielessConfig, CookielessManager, CookielessManagerError, EventData, HashParams,
+    get_redis_sessions_key, CookielessConfig, CookielessManager, CookielessManagerError, EventData,
+    HashParams,
 };
 pub use salt_cache::{is_calendar_date_valid, SaltCache, SaltCacheError};
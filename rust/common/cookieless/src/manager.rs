@@ -6,6 +6,7 @@ use chrono::{DateTime, Utc};
 use public_suffix::{EffectiveTLDProvider, DEFAULT_PROVIDER};
 use thiserror::Error;
 use url::Url;
+use uuid::{NoContext, Timestamp, Uuid};
 
 use crate::constants::{
     COOKIELESS_DISTINCT_ID_PREFIX, IDENTIFIES_TTL_SECONDS, SALT_TTL_SECONDS, SESSION_INACTIVITY_MS,
@@ -13,7 +14,7 @@ use crate::constants::{
 };
 use crate::hash::{do_hash, HashError};
 use crate::salt_cache::{SaltCache, SaltCacheError};
-use common_redis::Client as RedisClient;
+use common_redis::{Client as RedisClient, CustomRedisError};
 
 #[derive(Debug, Error)]
 pub enum CookielessManagerError {
@@ -162,6 +163,16 @@ impl CookielessManager {
         &self,
         event_data: EventData<'_>,
     ) -> Result<String, CookielessManagerError> {
+        let hash = self.compute_cookieless_hash(event_data).await?;
+        Ok(Self::hash_to_distinct_id(&hash))
+    }
+
+    /// Compute the hash the cookieless distinct ID of an event is derived from, taking the
+    /// number of identifies into account. Its session is also keyed on it.
+    pub async fn compute_cookieless_hash(
+        &self,
+        event_data: EventData<'_>,
+    ) -> Result<Vec<u8>, CookielessManagerError> {
         // If cookieless mode is disabled, return an error
         if self.config.disabled {
             return Err(CookielessManagerError::Disabled);
@@ -198,7 +209,7 @@ impl CookielessManager {
 
         // If we're in stateless mode, use the base hash directly
         if self.config.force_stateless_mode {
-            return Ok(Self::hash_to_distinct_id(&base_hash));
+            return Ok(base_hash);
         }
 
         // Get the number of identify events for this hash
@@ -208,17 +219,12 @@ impl CookielessManager {
 
         // If n is 0, we can use the base hash
         if n == 0 {
-            return Ok(Self::hash_to_distinct_id(&base_hash));
+            return Ok(base_hash);
         }
 
         // Otherwise, recompute the hash with the correct n value
         let hash_params_with_n = HashParams { n, ..hash_params };
-
-        // Compute the final hash
-        let final_hash = self.do_hash_for_day(hash_params_with_n).await?;
-
-        // Convert the hash to a distinct ID
-        Ok(Self::hash_to_distinct_id(&final_hash))
+        self.do_hash_for_day(hash_params_with_n).await
     }
 
     /// Compute a hash for a specific day
@@ -290,6 +296,87 @@ impl CookielessManager {
             }
         }
     }
+
+    /// Get the session ID for a hash, as of `timestamp_ms`
+    /// A new session is started if there is none, or if the last one has been inactive for
+    /// longer than the inactivity timeout. The session state is shared with the plugin-server,
+    /// in the same binary format.
+    pub async fn get_session_id(
+        &self,
+        hash: &[u8],
+        team_id: u64,
+        timestamp_ms: u64,
+    ) -> Result<Uuid, CookielessManagerError> {
+        let redis_key = get_redis_sessions_key(hash, team_id);
+        let session_state = match self.redis_client.get_raw_bytes(redis_key.clone()).await {
+            Ok(bytes) => SessionState::from_bytes(&bytes),
+            Err(CustomRedisError::NotFound) => None,
+            Err(e) => return Err(CookielessManagerError::RedisError(e.to_string())),
+        };
+
+        // Don't rely on the redis TTL, as ingestion lag could approach the inactivity timeout
+        let session_id = match session_state {
+            Some(state)
+                if timestamp_ms.saturating_sub(state.last_activity_timestamp_ms)
+                    <= self.config.session_inactivity_ms =>
+            {
+                state.session_id
+            }
+            _ => new_session_id(timestamp_ms),
+        };
+
+        // Either way, record the activity
+        let session_state = SessionState {
+            session_id,
+            last_activity_timestamp_ms: timestamp_ms,
+        };
+        self.redis_client
+            .set_bytes(
+                redis_key,
+                session_state.to_bytes(),
+                Some(self.config.session_ttl_seconds),
+            )
+            .await
+            .map_err(|e| CookielessManagerError::RedisError(e.to_string()))?;
+
+        Ok(session_id)
+    }
+}
+
+/// State of a cookieless session, stored as the 16 bytes of the session ID followed by the
+/// last activity timestamp as a little-endian u64, like the plugin-server does
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct SessionState {
+    session_id: Uuid,
+    last_activity_timestamp_ms: u64,
+}
+
+impl SessionState {
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let session_id = Uuid::from_slice(bytes.get(0..16)?).ok()?;
+        let timestamp = bytes.get(16..24)?.try_into().ok()?;
+        Some(Self {
+            session_id,
+            last_activity_timestamp_ms: u64::from_le_bytes(timestamp),
+        })
+    }
+
+    fn to_bytes(self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(24);
+        bytes.extend_from_slice(self.session_id.as_bytes());
+        bytes.extend_from_slice(&self.last_activity_timestamp_ms.to_le_bytes());
+        bytes
+    }
+}
+
+/// A UUIDv7 for the given timestamp, as sessions start with their first event
+fn new_session_id(timestamp_ms: u64) -> Uuid {
+    let timestamp = Timestamp::from_unix(
+        NoContext,
+        timestamp_ms / 1000,
+        ((timestamp_ms % 1000) * 1_000_000) as u32,
+    );
+    Uuid::new_v7(timestamp)
 }
 
 /// Extract the root domain from a host string
@@ -396,6 +483,16 @@ pub fn get_redis_identifies_key(hash: &[u8], team_id: u64) -> String {
     )
 }
 
+/// Get the Redis key for session states
+pub fn get_redis_sessions_key(hash: &[u8], team_id: u64) -> String {
+    // cklss = cookieless sessions
+    format!(
+        "cklss:{}:{}",
+        team_id,
+        general_purpose::STANDARD.encode(hash).trim_end_matches('=')
+    )
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -830,6 +927,58 @@ mod tests {
         assert_eq!(result, expected_distinct_id);
     }
 
+    #[test]
+    fn test_session_state_bytes() {
+        let session_id = Uuid::parse_str("0192e9c0-7a4b-7d2f-9f6e-2c8d1b3a4e5f").unwrap();
+        let state = SessionState {
+            session_id,
+            last_activity_timestamp_ms: 1_700_000_000_123,
+        };
+        let bytes = state.to_bytes();
+        assert_eq!(bytes.len(), 24);
+        assert_eq!(&bytes[..16], session_id.as_bytes());
+        assert_eq!(&bytes[16..], &1_700_000_000_123u64.to_le_bytes());
+        assert_eq!(SessionState::from_bytes(&bytes), Some(state));
+
+        // Truncated states are ignored
+        assert_eq!(SessionState::from_bytes(&bytes[..20]), None);
+    }
+
+    #[tokio::test]
+    async fn test_get_session_id() {
+        let hash = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16];
+        let redis_key = get_redis_sessions_key(&hash, 1);
+        let now_ms = 1_700_000_000_000;
+        let active = SessionState {
+            session_id: new_session_id(now_ms - 60_000),
+            last_activity_timestamp_ms: now_ms - 60_000,
+        };
+        let config = CookielessConfig::default();
+
+        // A new session is started, and recorded
+        let mock_redis = MockRedisClient::new().set_ret(&redis_key, Ok(()));
+        let manager = CookielessManager::new(config.clone(), Arc::new(mock_redis.clone()));
+        let session_id = manager.get_session_id(&hash, 1, now_ms).await.unwrap();
+        assert_eq!(session_id.get_version_num(), 7);
+        assert_ne!(session_id, active.session_id);
+        let calls = mock_redis.get_calls();
+        assert_eq!(calls[1].op, "set_bytes");
+        assert_eq!(calls[1].key, redis_key);
+
+        // An active session is kept
+        let mock_redis = MockRedisClient::new()
+            .get_raw_bytes_ret(&redis_key, Ok(active.to_bytes()))
+            .set_ret(&redis_key, Ok(()));
+        let manager = CookielessManager::new(config.clone(), Arc::new(mock_redis));
+        let session_id = manager.get_session_id(&hash, 1, now_ms).await.unwrap();
+        assert_eq!(session_id, active.session_id);
+
+        // An inactive one isn't
+        let inactive_ms = now_ms + config.session_inactivity_ms;
+        let session_id = manager.get_session_id(&hash, 1, inactive_ms).await.unwrap();
+        assert_ne!(session_id, active.session_id);
+    }
+
     #[tokio::test]
     async fn test_compute_cookieless_distinct_id_stateless_mode() {
         // Create a mock Redis client
@@ -1,3 +1,4 @@
 pub mod overflow;
 pub mod redis;
+pub mod request_rate;
 pub mod token_dropper;
@@ -7,19 +7,28 @@
 /// constraint when bursts are detected. When that happens, the excess traffic will be
 /// spread across all partitions and be processed by the overflow consumer, without
 /// strict ordering guarantees.
+///
+/// Keys can also be forced into overflow, through the `overflow_forced_keys` setting, or
+/// at runtime by adding them to the `OVERFLOW_FORCED_KEYS_CACHE_KEY` Redis set.
 use std::collections::HashSet;
 use std::num::NonZeroU32;
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
 
+use common_redis::{Client, CustomRedisError};
 use governor::{clock, state::keyed::DefaultKeyedStateStore, Quota, RateLimiter};
 use metrics::gauge;
 use rand::Rng;
 
+pub const OVERFLOW_FORCED_KEYS_CACHE_KEY: &str = "@posthog/capture-overflow-forced-keys";
+
 // See: https://docs.rs/governor/latest/governor/_guide/index.html#usage-in-multiple-threads
 #[derive(Clone)]
 pub struct OverflowLimiter {
     limiter: Arc<RateLimiter<String, DefaultKeyedStateStore<String>, clock::DefaultClock>>,
     forced_keys: HashSet<String>,
+    // Loaded from redis by `refresh_forced_keys`, in addition to the configured ones
+    dynamic_forced_keys: Arc<RwLock<HashSet<String>>>,
 }
 
 impl OverflowLimiter {
@@ -35,11 +44,49 @@ impl OverflowLimiter {
         OverflowLimiter {
             limiter,
             forced_keys,
+            dynamic_forced_keys: Arc::new(RwLock::new(HashSet::new())),
         }
     }
 
     pub fn is_limited(&self, key: &String) -> bool {
-        self.forced_keys.contains(key) || self.limiter.check_key(key).is_err()
+        self.forced_keys.contains(key)
+            || self.is_dynamically_forced(key)
+            || self.limiter.check_key(key).is_err()
+    }
+
+    fn is_dynamically_forced(&self, key: &String) -> bool {
+        match self.dynamic_forced_keys.read() {
+            Ok(keys) => keys.contains(key),
+            Err(poisoned) => poisoned.into_inner().contains(key),
+        }
+    }
+
+    /// Reloads the forced keys from the given redis set every `interval`, needs to be spawned
+    /// in a separate task. The last loaded keys are kept if redis is unavailable.
+    pub async fn refresh_forced_keys(
+        &self,
+        redis: Arc<dyn Client + Send + Sync>,
+        key: String,
+        interval: Duration,
+    ) {
+        let mut interval = tokio::time::interval(interval);
+        loop {
+            interval.tick().await;
+
+            let keys = match redis.smembers(key.clone()).await {
+                Ok(keys) => HashSet::from_iter(keys),
+                Err(CustomRedisError::NotFound) => HashSet::new(),
+                Err(e) => {
+                    tracing::error!("Failed to update forced overflow keys from Redis: {:?}", e);
+                    continue;
+                }
+            };
+            gauge!("partition_limits_forced_key_count").set(keys.len() as f64);
+            match self.dynamic_forced_keys.write() {
+                Ok(mut forced) => *forced = keys,
+                Err(poisoned) => *poisoned.into_inner() = keys,
+            }
+        }
     }
 
     /// Reports the number of tracked keys to prometheus every 10 seconds,
@@ -72,8 +119,11 @@ impl OverflowLimiter {
 
 #[cfg(test)]
 mod tests {
-    use super::OverflowLimiter;
+    use super::{OverflowLimiter, OVERFLOW_FORCED_KEYS_CACHE_KEY};
+    use common_redis::MockRedisClient;
     use std::num::NonZeroU32;
+    use std::sync::Arc;
+    use std::time::Duration;
 
     #[tokio::test]
     async fn low_limits() {
@@ -124,4 +174,32 @@ mod tests {
         // Two is limited on the second event
         assert!(limiter.is_limited(&key_two));
     }
+
+    #[tokio::test]
+    async fn forced_keys_from_redis() {
+        let limiter = OverflowLimiter::new(
+            NonZeroU32::new(10).unwrap(),
+            NonZeroU32::new(10).unwrap(),
+            Some(String::from("one")),
+        );
+        let client = MockRedisClient::new()
+            .smembers_ret(OVERFLOW_FORCED_KEYS_CACHE_KEY, vec![String::from("two")]);
+
+        let refreshed = limiter.clone();
+        tokio::spawn(async move {
+            refreshed
+                .refresh_forced_keys(
+                    Arc::new(client),
+                    OVERFLOW_FORCED_KEYS_CACHE_KEY.to_string(),
+                    Duration::from_secs(60),
+                )
+                .await
+        });
+        tokio::time::sleep(Duration::from_millis(30)).await;
+
+        // Both the configured and the dynamic keys are forced
+        assert!(limiter.is_limited(&String::from("one")));
+        assert!(limiter.is_limited(&String::from("two")));
+        assert!(!limiter.is_limited(&String::from("three")));
+    }
 }
@@ -0,0 +1,305 @@
+/// Per-token request rate limiting, with an optional per-IP limit.
+///
+/// Unlike the `OverflowLimiter`, which reroutes bursts to the overflow partitions, this limiter
+/// refuses the excess requests: callers are expected to answer with a 429 status, and a
+/// `Retry-After` header set from the returned wait time.
+///
+/// All tokens share a configured default limit. Limits for specific tokens are read from the
+/// `REQUEST_RATE_LIMITS_KEY` Redis hash, mapping tokens to `<per_second>[:<burst>]`, and are
+/// refreshed in a background task, like the `RedisLimiter`. If Redis is unavailable, the last
+/// known limits are kept.
+use std::collections::HashMap;
+use std::num::NonZeroU32;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use common_redis::{Client, CustomRedisError};
+use governor::clock::{Clock, DefaultClock};
+use governor::{state::keyed::DefaultKeyedStateStore, Quota, RateLimiter};
+use metrics::gauge;
+use tokio::sync::RwLock;
+use tokio::task;
+use tracing::instrument;
+
+pub const REQUEST_RATE_LIMITS_KEY: &str = "@posthog/capture-rate-limits";
+
+type KeyedLimiter = RateLimiter<String, DefaultKeyedStateStore<String>, DefaultClock>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RateLimit {
+    pub per_second: NonZeroU32,
+    pub burst: NonZeroU32,
+}
+
+impl RateLimit {
+    fn limiter(&self) -> Arc<KeyedLimiter> {
+        let quota = Quota::per_second(self.per_second).allow_burst(self.burst);
+        Arc::new(RateLimiter::dashmap(quota))
+    }
+}
+
+impl FromStr for RateLimit {
+    type Err = String;
+
+    // Takes "<per_second>" or "<per_second>:<burst>", the burst defaults to one second of requests
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = |value: &str| {
+            value
+                .trim()
+                .parse::<NonZeroU32>()
+                .map_err(|e| format!("Invalid rate limit {s}: {e}"))
+        };
+        match s.split_once(':') {
+            Some((per_second, burst)) => Ok(RateLimit {
+                per_second: parse(per_second)?,
+                burst: parse(burst)?,
+            }),
+            None => {
+                let per_second = parse(s)?;
+                Ok(RateLimit {
+                    per_second,
+                    burst: per_second,
+                })
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct RequestRateLimiter {
+    default: Arc<KeyedLimiter>,
+    // Tokens with a custom limit. Tokens sharing the same limit share a limiter.
+    overrides: Arc<RwLock<HashMap<String, (RateLimit, Arc<KeyedLimiter>)>>>,
+    per_ip: Option<Arc<KeyedLimiter>>,
+    clock: DefaultClock,
+}
+
+impl RequestRateLimiter {
+    /// Create a new RequestRateLimiter, and spawn the background task refreshing the
+    /// per-token limits from redis every `interval`, which also cleans up the limiter state.
+    pub fn new(
+        default_limit: RateLimit,
+        per_ip_limit: Option<RateLimit>,
+        redis: Arc<dyn Client + Send + Sync>,
+        redis_key_prefix: Option<String>,
+        interval: Duration,
+    ) -> Self {
+        let limiter = RequestRateLimiter {
+            default: default_limit.limiter(),
+            overrides: Arc::new(RwLock::new(HashMap::new())),
+            per_ip: per_ip_limit.map(|limit| limit.limiter()),
+            clock: DefaultClock::default(),
+        };
+
+        let key = format!(
+            "{}{REQUEST_RATE_LIMITS_KEY}",
+            redis_key_prefix.unwrap_or_default()
+        );
+        limiter.spawn_background_update(redis, key, interval);
+
+        limiter
+    }
+
+    fn spawn_background_update(
+        &self,
+        redis: Arc<dyn Client + Send + Sync>,
+        key: String,
+        interval: Duration,
+    ) {
+        let limiter = self.clone();
+        task::spawn(async move {
+            let mut interval = tokio::time::interval(interval);
+            loop {
+                match RequestRateLimiter::fetch_limits(&redis, &key).await {
+                    Ok(limits) => limiter.update_overrides(limits).await,
+                    Err(CustomRedisError::NotFound) => limiter.update_overrides(Vec::new()).await,
+                    Err(e) => {
+                        tracing::error!("Failed to update rate limits from Redis: {:?}", e);
+                    }
+                }
+                limiter.clean_state().await;
+
+                interval.tick().await;
+            }
+        });
+    }
+
+    #[instrument(skip_all)]
+    async fn fetch_limits(
+        client: &Arc<dyn Client + Send + Sync>,
+        key: &str,
+    ) -> Result<Vec<(String, RateLimit)>, CustomRedisError> {
+        let limits = client.hgetall(key.to_string()).await?;
+        Ok(limits
+            .into_iter()
+            .filter_map(|(token, limit)| match limit.parse() {
+                Ok(limit) => Some((token, limit)),
+                Err(e) => {
+                    tracing::warn!("Ignoring rate limit for token {}: {}", token, e);
+                    None
+                }
+            })
+            .collect())
+    }
+
+    async fn update_overrides(&self, limits: Vec<(String, RateLimit)>) {
+        let mut overrides = self.overrides.write().await;
+
+        // Keep the existing limiters, so that tokens don't get a fresh quota on every refresh
+        let mut limiters: HashMap<RateLimit, Arc<KeyedLimiter>> = overrides
+            .values()
+            .map(|(limit, limiter)| (*limit, limiter.clone()))
+            .collect();
+        *overrides = limits
+            .into_iter()
+            .map(|(token, limit)| {
+                let limiter = limiters
+                    .entry(limit)
+                    .or_insert_with(|| limit.limiter())
+                    .clone();
+                (token, (limit, limiter))
+            })
+            .collect();
+
+        gauge!("capture_rate_limits_loaded_tokens").set(overrides.len() as f64);
+    }
+
+    async fn clean_state(&self) {
+        let overrides = self.overrides.read().await;
+        let limiters = overrides
+            .values()
+            .map(|(_, limiter)| limiter)
+            .chain(std::iter::once(&self.default))
+            .chain(self.per_ip.iter());
+        for limiter in limiters {
+            limiter.retain_recent();
+            limiter.shrink_to_fit();
+        }
+    }
+
+    /// Counts one request for the token and client IP. Returns how long the client should
+    /// wait before retrying if either is over its limit, `None` if the request is allowed.
+    ///
+    /// The client IP is checked first, so that a single noisy client doesn't use up the
+    /// quota of the token with requests it's rejected anyway.
+    pub async fn check(&self, token: &str, ip: &str) -> Option<Duration> {
+        if let Some(per_ip) = &self.per_ip {
+            if let Err(not_until) = per_ip.check_key(&ip.to_string()) {
+                return Some(not_until.wait_time_from(self.clock.now()));
+            }
+        }
+
+        let token_limiter = match self.overrides.read().await.get(token) {
+            Some((_, limiter)) => limiter.clone(),
+            None => self.default.clone(),
+        };
+        if let Err(not_until) = token_limiter.check_key(&token.to_string()) {
+            return Some(not_until.wait_time_from(self.clock.now()));
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RateLimit, RequestRateLimiter, REQUEST_RATE_LIMITS_KEY};
+    use common_redis::MockRedisClient;
+    use std::collections::HashMap;
+    use std::num::NonZeroU32;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    fn limit(per_second: u32, burst: u32) -> RateLimit {
+        RateLimit {
+            per_second: NonZeroU32::new(per_second).unwrap(),
+            burst: NonZeroU32::new(burst).unwrap(),
+        }
+    }
+
+    #[test]
+    fn parse_limits() {
+        assert_eq!(Ok(limit(10, 10)), "10".parse());
+        assert_eq!(Ok(limit(10, 50)), "10:50".parse());
+        assert!("0".parse::<RateLimit>().is_err());
+        assert!("10:".parse::<RateLimit>().is_err());
+        assert!("ten".parse::<RateLimit>().is_err());
+    }
+
+    #[tokio::test]
+    async fn default_limit() {
+        let client = Arc::new(MockRedisClient::new());
+        let limiter =
+            RequestRateLimiter::new(limit(1, 2), None, client, None, Duration::from_secs(60));
+
+        assert!(limiter.check("token", "1.2.3.4").await.is_none());
+        assert!(limiter.check("token", "1.2.3.4").await.is_none());
+        let retry_after = limiter.check("token", "1.2.3.4").await;
+        assert!(retry_after.is_some_and(|wait| wait <= Duration::from_secs(1)));
+
+        // Other tokens have their own quota
+        assert!(limiter.check("other", "1.2.3.4").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn token_limits_from_redis() {
+        let client = MockRedisClient::new().hgetall_ret(
+            REQUEST_RATE_LIMITS_KEY,
+            HashMap::from([
+                ("limited".to_string(), "1".to_string()),
+                ("invalid".to_string(), "lots".to_string()),
+            ]),
+        );
+        let limiter = RequestRateLimiter::new(
+            limit(100, 100),
+            None,
+            Arc::new(client),
+            None,
+            Duration::from_secs(60),
+        );
+        tokio::time::sleep(Duration::from_millis(30)).await;
+
+        assert!(limiter.check("limited", "1.2.3.4").await.is_none());
+        assert!(limiter.check("limited", "1.2.3.4").await.is_some());
+
+        // Invalid limits fall back to the default
+        for _ in 0..10 {
+            assert!(limiter.check("invalid", "1.2.3.4").await.is_none());
+        }
+    }
+
+    #[tokio::test]
+    async fn per_ip_limit() {
+        let client = Arc::new(MockRedisClient::new());
+        let limiter = RequestRateLimiter::new(
+            limit(100, 100),
+            Some(limit(1, 1)),
+            client,
+            None,
+            Duration::from_secs(60),
+        );
+
+        assert!(limiter.check("token", "1.2.3.4").await.is_none());
+        assert!(limiter.check("other", "1.2.3.4").await.is_some());
+        assert!(limiter.check("token", "5.6.7.8").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn ip_limited_requests_dont_use_token_quota() {
+        let client = Arc::new(MockRedisClient::new());
+        let limiter = RequestRateLimiter::new(
+            limit(1, 2),
+            Some(limit(1, 1)),
+            client,
+            None,
+            Duration::from_secs(60),
+        );
+
+        assert!(limiter.check("token", "1.2.3.4").await.is_none());
+        for _ in 0..10 {
+            assert!(limiter.check("token", "1.2.3.4").await.is_some());
+        }
+        // The token still has quota left for other clients
+        assert!(limiter.check("token", "5.6.7.8").await.is_none());
+    }
+}
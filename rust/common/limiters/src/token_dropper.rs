@@ -1,10 +1,19 @@
 use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
 
+use common_redis::{Client, CustomRedisError};
+use metrics::gauge;
 use tracing::warn;
 
-#[derive(Default)]
+// Members of this redis set take the same "<token>:<distinct_id or *>,..." format as the config
+pub const TOKEN_DROPPER_CACHE_KEY: &str = "@posthog/capture-dropped-keys";
+
+#[derive(Default, Clone)]
 pub struct TokenDropper {
     to_drop: HashMap<String, Vec<String>>,
+    // Loaded from redis by `refresh`, in addition to the configured ones
+    dynamic_to_drop: Arc<RwLock<HashMap<String, Vec<String>>>>,
 }
 
 impl TokenDropper {
@@ -12,32 +21,78 @@ impl TokenDropper {
     pub fn new(config: &str) -> Self {
         let mut to_drop = HashMap::new();
         for pair in config.split(';') {
-            let mut parts = pair.split(':');
-            let Some(token) = parts.next() else {
-                warn!("No distinct id's configured for pair {}", pair);
-                continue;
-            };
-            let Some(ids) = parts.next() else {
-                warn!("No distinct id's configured for token {}", token);
-                continue;
-            };
-            let ids = ids.split(',').map(|s| s.to_string()).collect();
-            to_drop.insert(token.to_string(), ids);
+            Self::parse_pair(pair, &mut to_drop);
         }
-        Self { to_drop }
+        Self {
+            to_drop,
+            ..Default::default()
+        }
+    }
+
+    fn parse_pair(pair: &str, to_drop: &mut HashMap<String, Vec<String>>) {
+        let mut parts = pair.split(':');
+        let Some(token) = parts.next() else {
+            warn!("No distinct id's configured for pair {}", pair);
+            return;
+        };
+        let Some(ids) = parts.next() else {
+            warn!("No distinct id's configured for token {}", token);
+            return;
+        };
+        to_drop
+            .entry(token.to_string())
+            .or_default()
+            .extend(ids.split(',').map(|s| s.to_string()));
     }
 
     pub fn should_drop(&self, token: &str, distinct_id: &str) -> bool {
-        self.to_drop
-            .get(token)
-            .map(|ids| ids.iter().any(|id| id == distinct_id || id == "*"))
-            .unwrap_or(false)
+        let matches = |ids: &Vec<String>| ids.iter().any(|id| id == distinct_id || id == "*");
+        if self.to_drop.get(token).is_some_and(matches) {
+            return true;
+        }
+        match self.dynamic_to_drop.read() {
+            Ok(to_drop) => to_drop.get(token).is_some_and(matches),
+            Err(poisoned) => poisoned.into_inner().get(token).is_some_and(matches),
+        }
+    }
+
+    /// Reloads the dropped keys from the given redis set every `interval`, needs to be spawned
+    /// in a separate task. The last loaded keys are kept if redis is unavailable.
+    pub async fn refresh(
+        &self,
+        redis: Arc<dyn Client + Send + Sync>,
+        key: String,
+        interval: Duration,
+    ) {
+        let mut interval = tokio::time::interval(interval);
+        loop {
+            interval.tick().await;
+
+            let members = match redis.smembers(key.clone()).await {
+                Ok(members) => members,
+                Err(CustomRedisError::NotFound) => Vec::new(),
+                Err(e) => {
+                    tracing::error!("Failed to update dropped keys from Redis: {:?}", e);
+                    continue;
+                }
+            };
+            let mut to_drop = HashMap::new();
+            for pair in &members {
+                Self::parse_pair(pair, &mut to_drop);
+            }
+            gauge!("capture_token_dropper_loaded_tokens").set(to_drop.len() as f64);
+            match self.dynamic_to_drop.write() {
+                Ok(mut dynamic) => *dynamic = to_drop,
+                Err(poisoned) => *poisoned.into_inner() = to_drop,
+            }
+        }
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use common_redis::MockRedisClient;
 
     #[test]
     fn test_empty_config() {
@@ -73,4 +128,30 @@ mod test {
         assert!(dropper.should_drop("token2", "id2"));
         assert!(!dropper.should_drop("token1", "id2"));
     }
+
+    #[tokio::test]
+    async fn test_refresh_from_redis() {
+        let dropper = TokenDropper::new("token1:id1");
+        let client = MockRedisClient::new().smembers_ret(
+            TOKEN_DROPPER_CACHE_KEY,
+            vec!["token2:id2".to_string(), "token1:id3".to_string()],
+        );
+
+        let refreshed = dropper.clone();
+        tokio::spawn(async move {
+            refreshed
+                .refresh(
+                    Arc::new(client),
+                    TOKEN_DROPPER_CACHE_KEY.to_string(),
+                    Duration::from_secs(60),
+                )
+                .await
+        });
+        tokio::time::sleep(Duration::from_millis(30)).await;
+
+        assert!(dropper.should_drop("token1", "id1"));
+        assert!(dropper.should_drop("token1", "id3"));
+        assert!(dropper.should_drop("token2", "id2"));
+        assert!(!dropper.should_drop("token2", "id1"));
+    }
 }
@@ -55,11 +55,32 @@ pub trait Client {
     ) -> Result<(), CustomRedisError>;
 
     async fn get(&self, k: String) -> Result<String, CustomRedisError>;
+    /// Same as `get`, for values that aren't pickled, like the binary ones of the plugin-server
+    async fn get_raw_bytes(&self, k: String) -> Result<Vec<u8>, CustomRedisError>;
     async fn set(&self, k: String, v: String) -> Result<(), CustomRedisError>;
+    /// Sets the value as is, without pickling it, expiring after `ttl_seconds` if set
+    async fn set_bytes(
+        &self,
+        k: String,
+        v: Vec<u8>,
+        ttl_seconds: Option<u64>,
+    ) -> Result<(), CustomRedisError>;
     async fn set_nx_ex(&self, k: String, v: String, seconds: u64)
         -> Result<bool, CustomRedisError>;
+    /// Same as `set_nx_ex` for each key, in a single pipelined round-trip.
+    /// Returns whether each key was set, in the order of `ks`.
+    async fn set_nx_ex_many(
+        &self,
+        ks: Vec<String>,
+        v: String,
+        seconds: u64,
+    ) -> Result<Vec<bool>, CustomRedisError>;
     async fn del(&self, k: String) -> Result<(), CustomRedisError>;
+    /// Deletes the keys with a single command
+    async fn del_many(&self, ks: Vec<String>) -> Result<(), CustomRedisError>;
     async fn hget(&self, k: String, field: String) -> Result<String, CustomRedisError>;
+    async fn hgetall(&self, k: String) -> Result<HashMap<String, String>, CustomRedisError>;
+    async fn smembers(&self, k: String) -> Result<Vec<String>, CustomRedisError>;
 }
 
 pub struct RedisClient {
@@ -116,6 +137,15 @@ impl Client for RedisClient {
         Ok(string_response)
     }
 
+    async fn get_raw_bytes(&self, k: String) -> Result<Vec<u8>, CustomRedisError> {
+        let mut conn = self.client.get_async_connection().await?;
+        let results = conn.get(k);
+        let fut: Result<Option<Vec<u8>>, RedisError> =
+            timeout(Duration::from_millis(REDIS_TIMEOUT_MILLISECS), results).await?;
+
+        fut?.ok_or(CustomRedisError::NotFound)
+    }
+
     async fn set(&self, k: String, v: String) -> Result<(), CustomRedisError> {
         let bytes = serde_pickle::to_vec(&v, Default::default())?;
         let mut conn = self.client.get_async_connection().await?;
@@ -124,6 +154,21 @@ impl Client for RedisClient {
         Ok(fut?)
     }
 
+    async fn set_bytes(
+        &self,
+        k: String,
+        v: Vec<u8>,
+        ttl_seconds: Option<u64>,
+    ) -> Result<(), CustomRedisError> {
+        let mut conn = self.client.get_async_connection().await?;
+        let results = match ttl_seconds {
+            Some(seconds) => conn.set_ex(k, v, seconds as usize),
+            None => conn.set(k, v),
+        };
+        let fut = timeout(Duration::from_millis(REDIS_TIMEOUT_MILLISECS), results).await?;
+        Ok(fut?)
+    }
+
     async fn set_nx_ex(
         &self,
         k: String,
@@ -154,6 +199,38 @@ impl Client for RedisClient {
         }
     }
 
+    async fn set_nx_ex_many(
+        &self,
+        ks: Vec<String>,
+        v: String,
+        seconds: u64,
+    ) -> Result<Vec<bool>, CustomRedisError> {
+        if ks.is_empty() {
+            return Ok(Vec::new());
+        }
+        let bytes = serde_pickle::to_vec(&v, Default::default())?;
+        let mut conn = self.client.get_async_connection().await?;
+        let seconds_usize = seconds as usize;
+
+        let mut pipe = redis::pipe();
+        for k in &ks {
+            pipe.cmd("SET")
+                .arg(k)
+                .arg(&bytes)
+                .arg("EX")
+                .arg(seconds_usize)
+                .arg("NX");
+        }
+        let result: Result<Vec<Option<String>>, RedisError> = timeout(
+            Duration::from_millis(REDIS_TIMEOUT_MILLISECS),
+            pipe.query_async(&mut conn),
+        )
+        .await?;
+
+        // A key is only set if it didn't exist
+        Ok(result?.iter().map(Option::is_some).collect())
+    }
+
     async fn del(&self, k: String) -> Result<(), CustomRedisError> {
         let mut conn = self.client.get_async_connection().await?;
         let results = conn.del(k);
@@ -161,6 +238,16 @@ impl Client for RedisClient {
         fut.map_err(|e| CustomRedisError::Other(e.to_string()))
     }
 
+    async fn del_many(&self, ks: Vec<String>) -> Result<(), CustomRedisError> {
+        if ks.is_empty() {
+            return Ok(());
+        }
+        let mut conn = self.client.get_async_connection().await?;
+        let results = conn.del(ks);
+        let fut = timeout(Duration::from_millis(REDIS_TIMEOUT_MILLISECS), results).await?;
+        fut.map_err(|e| CustomRedisError::Other(e.to_string()))
+    }
+
     async fn hget(&self, k: String, field: String) -> Result<String, CustomRedisError> {
         let mut conn = self.client.get_async_connection().await?;
         let results = conn.hget(k, field);
@@ -172,6 +259,20 @@ impl Client for RedisClient {
             None => Err(CustomRedisError::NotFound),
         }
     }
+
+    async fn hgetall(&self, k: String) -> Result<HashMap<String, String>, CustomRedisError> {
+        let mut conn = self.client.get_async_connection().await?;
+        let results = conn.hgetall(k);
+        let fut = timeout(Duration::from_millis(REDIS_TIMEOUT_MILLISECS), results).await?;
+        Ok(fut?)
+    }
+
+    async fn smembers(&self, k: String) -> Result<Vec<String>, CustomRedisError> {
+        let mut conn = self.client.get_async_connection().await?;
+        let results = conn.smembers(k);
+        let fut = timeout(Duration::from_millis(REDIS_TIMEOUT_MILLISECS), results).await?;
+        Ok(fut?)
+    }
 }
 
 #[derive(Clone)]
@@ -179,10 +280,13 @@ pub struct MockRedisClient {
     zrangebyscore_ret: HashMap<String, Vec<String>>,
     hincrby_ret: HashMap<String, Result<(), CustomRedisError>>,
     get_ret: HashMap<String, Result<String, CustomRedisError>>,
+    get_raw_bytes_ret: HashMap<String, Result<Vec<u8>, CustomRedisError>>,
     set_ret: HashMap<String, Result<(), CustomRedisError>>,
     set_nx_ex_ret: HashMap<String, Result<bool, CustomRedisError>>,
     del_ret: HashMap<String, Result<(), CustomRedisError>>,
     hget_ret: HashMap<String, Result<String, CustomRedisError>>,
+    hgetall_ret: HashMap<String, HashMap<String, String>>,
+    smembers_ret: HashMap<String, Vec<String>>,
     calls: Arc<Mutex<Vec<MockRedisCall>>>,
 }
 
@@ -192,10 +296,13 @@ impl Default for MockRedisClient {
             zrangebyscore_ret: HashMap::new(),
             hincrby_ret: HashMap::new(),
             get_ret: HashMap::new(),
+            get_raw_bytes_ret: HashMap::new(),
             set_ret: HashMap::new(),
             set_nx_ex_ret: HashMap::new(),
             del_ret: HashMap::new(),
             hget_ret: HashMap::new(),
+            hgetall_ret: HashMap::new(),
+            smembers_ret: HashMap::new(),
             calls: Arc::new(Mutex::new(Vec::new())),
         }
     }
@@ -230,6 +337,11 @@ impl MockRedisClient {
         self.clone()
     }
 
+    pub fn get_raw_bytes_ret(&mut self, key: &str, ret: Result<Vec<u8>, CustomRedisError>) -> Self {
+        self.get_raw_bytes_ret.insert(key.to_owned(), ret);
+        self.clone()
+    }
+
     pub fn set_ret(&mut self, key: &str, ret: Result<(), CustomRedisError>) -> Self {
         self.set_ret.insert(key.to_owned(), ret);
         self.clone()
@@ -245,6 +357,16 @@ impl MockRedisClient {
         self.clone()
     }
 
+    pub fn hgetall_ret(&mut self, key: &str, ret: HashMap<String, String>) -> Self {
+        self.hgetall_ret.insert(key.to_owned(), ret);
+        self.clone()
+    }
+
+    pub fn smembers_ret(&mut self, key: &str, ret: Vec<String>) -> Self {
+        self.smembers_ret.insert(key.to_owned(), ret);
+        self.clone()
+    }
+
     pub fn get_calls(&self) -> Vec<MockRedisCall> {
         self.lock_calls().clone()
     }
@@ -315,6 +437,42 @@ impl Client for MockRedisClient {
         }
     }
 
+    async fn get_raw_bytes(&self, key: String) -> Result<Vec<u8>, CustomRedisError> {
+        // Record the call
+        let mut calls = self.lock_calls();
+        calls.push(MockRedisCall {
+            op: "get_raw_bytes".to_string(),
+            key: key.clone(),
+            value: MockRedisValue::None,
+        });
+
+        match self.get_raw_bytes_ret.get(&key) {
+            Some(result) => result.clone(),
+            None => Err(CustomRedisError::NotFound),
+        }
+    }
+
+    async fn set_bytes(
+        &self,
+        key: String,
+        value: Vec<u8>,
+        ttl_seconds: Option<u64>,
+    ) -> Result<(), CustomRedisError> {
+        // Record the call
+        let mut calls = self.lock_calls();
+        calls.push(MockRedisCall {
+            op: "set_bytes".to_string(),
+            key: key.clone(),
+            value: MockRedisValue::BytesWithTTL(value, ttl_seconds),
+        });
+
+        // Like `set`, using its returns
+        match self.set_ret.get(&key) {
+            Some(result) => result.clone(),
+            None => Err(CustomRedisError::NotFound),
+        }
+    }
+
     async fn set(&self, key: String, value: String) -> Result<(), CustomRedisError> {
         // Record the call
         let mut calls = self.lock_calls();
@@ -350,6 +508,29 @@ impl Client for MockRedisClient {
         }
     }
 
+    async fn set_nx_ex_many(
+        &self,
+        keys: Vec<String>,
+        value: String,
+        seconds: u64,
+    ) -> Result<Vec<bool>, CustomRedisError> {
+        // Record the call, once for the whole pipeline
+        let mut calls = self.lock_calls();
+        calls.push(MockRedisCall {
+            op: "set_nx_ex_many".to_string(),
+            key: keys.join(","),
+            value: MockRedisValue::StringWithTTL(value.clone(), seconds),
+        });
+
+        // Uses the set_nx_ex returns, the pipeline failing if any key does
+        keys.iter()
+            .map(|key| match self.set_nx_ex_ret.get(key) {
+                Some(result) => result.clone(),
+                None => Err(CustomRedisError::NotFound),
+            })
+            .collect()
+    }
+
     async fn del(&self, key: String) -> Result<(), CustomRedisError> {
         // Record the call
         let mut calls = self.lock_calls();
@@ -365,6 +546,23 @@ impl Client for MockRedisClient {
         }
     }
 
+    async fn del_many(&self, keys: Vec<String>) -> Result<(), CustomRedisError> {
+        // Record the call, once for all the keys
+        let mut calls = self.lock_calls();
+        calls.push(MockRedisCall {
+            op: "del_many".to_string(),
+            key: keys.join(","),
+            value: MockRedisValue::None,
+        });
+
+        // Uses the del returns, failing if any key does
+        keys.iter()
+            .try_for_each(|key| match self.del_ret.get(key) {
+                Some(result) => result.clone(),
+                None => Err(CustomRedisError::NotFound),
+            })
+    }
+
     async fn hget(&self, key: String, field: String) -> Result<String, CustomRedisError> {
         // Record the call
         let mut calls = self.lock_calls();
@@ -379,6 +577,36 @@ impl Client for MockRedisClient {
             None => Err(CustomRedisError::NotFound),
         }
     }
+
+    async fn hgetall(&self, key: String) -> Result<HashMap<String, String>, CustomRedisError> {
+        // Record the call
+        let mut calls = self.lock_calls();
+        calls.push(MockRedisCall {
+            op: "hgetall".to_string(),
+            key: key.clone(),
+            value: MockRedisValue::None,
+        });
+
+        match self.hgetall_ret.get(&key) {
+            Some(val) => Ok(val.clone()),
+            None => Err(CustomRedisError::NotFound),
+        }
+    }
+
+    async fn smembers(&self, key: String) -> Result<Vec<String>, CustomRedisError> {
+        // Record the call
+        let mut calls = self.lock_calls();
+        calls.push(MockRedisCall {
+            op: "smembers".to_string(),
+            key: key.clone(),
+            value: MockRedisValue::None,
+        });
+
+        match self.smembers_ret.get(&key) {
+            Some(val) => Ok(val.clone()),
+            None => Err(CustomRedisError::NotFound),
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -387,6 +615,7 @@ pub enum MockRedisValue {
     Error(CustomRedisError),
     String(String),
     StringWithTTL(String, u64),
+    BytesWithTTL(Vec<u8>, Option<u64>),
     VecString(Vec<String>),
     I32(i32),
     I64(i64),
@@ -394,9 +623,8 @@ pub enum MockRedisValue {
 }
 
 #[derive(Clone)]
-#[allow(dead_code)]
 pub struct MockRedisCall {
-    op: String,
-    key: String,
-    value: MockRedisValue,
+    pub op: String,
+    pub key: String,
+    pub value: MockRedisValue,
 }
@@ -12,4 +12,5 @@ pub use event::RawEvent;
 pub use team::Team;
 
 // Utils
+pub mod mixpanel;
 pub mod util;
@@ -0,0 +1,10 @@
+// Shared by the Mixpanel importers and the Mixpanel compatible capture endpoints
+
+// Maps mixpanel event names to posthog event names
+pub fn map_event_names(event: String) -> String {
+    // TODO - add more as you find them
+    match event.as_str() {
+        "$mp_web_page_view" => "$pageview".to_string(),
+        _ => event,
+    }
+}
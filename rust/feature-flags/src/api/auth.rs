@@ -0,0 +1,281 @@
+use std::time::Duration;
+
+use axum::http::{header, HeaderMap};
+use moka::sync::Cache;
+use sha2::{Digest, Sha256};
+use sqlx::Row;
+
+use crate::{
+    api::errors::{ClientFacingError, FlagError},
+    client::database::Client as DatabaseClient,
+    team::team_models::TeamId,
+};
+
+/// PersonalApiKeyCache keeps the results of recent personal API key lookups, keyed on the hashed
+/// key and the team, so that requests with the same key don't each query the database.
+#[derive(Clone)]
+pub struct PersonalApiKeyCache {
+    /// Scopes of the keys found to have access to the team. Kept briefly, so that revoked keys
+    /// and removed members lose access quickly.
+    authenticated: Cache<(String, TeamId), Option<Vec<String>>>,
+    /// Keys found to have no access to the team, so that retries with an unknown or revoked key
+    /// are rejected without a database query. Kept even more briefly, as keys can be created or
+    /// granted access at any time.
+    rejected: Cache<(String, TeamId), ()>,
+}
+
+impl Default for PersonalApiKeyCache {
+    fn default() -> Self {
+        Self {
+            authenticated: Cache::builder()
+                .max_capacity(10_000)
+                .time_to_live(Duration::from_secs(60))
+                .build(),
+            rejected: Cache::builder()
+                .max_capacity(10_000)
+                .time_to_live(Duration::from_secs(10))
+                .build(),
+        }
+    }
+}
+
+/// Reads the personal API key from the `Authorization: Bearer <key>` header.
+pub fn extract_personal_api_key(headers: &HeaderMap) -> Result<String, FlagError> {
+    let value = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    match value.trim().split_once(' ') {
+        Some((scheme, key)) if scheme.eq_ignore_ascii_case("bearer") && !key.trim().is_empty() => {
+            Ok(key.trim().to_string())
+        }
+        _ => Err(FlagError::ClientFacing(ClientFacingError::Unauthorized(
+            "A personal API key is required, as an `Authorization: Bearer <key>` header"
+                .to_string(),
+        ))),
+    }
+}
+
+/// Same hashing as Django's `hash_key_value`, in the default sha256 mode.
+/// Keys still stored with the legacy pbkdf2 hashing are migrated by Django on their next use.
+pub fn hash_personal_api_key(key: &str) -> String {
+    format!("sha256${:x}", Sha256::digest(key.as_bytes()))
+}
+
+/// Checks that the personal API key belongs to an active member of the team's organization,
+/// and that its scopes allow each of `scopes` (a `<object>:read` scope is also granted by
+/// `<object>:write`) on the team.
+pub async fn authenticate_personal_api_key(
+    reader: &(dyn DatabaseClient + Send + Sync),
+    key_cache: &PersonalApiKeyCache,
+    key: &str,
+    team_id: TeamId,
+    scopes: &[&str],
+) -> Result<(), FlagError> {
+    let unauthorized = |msg: &str| {
+        Err(FlagError::ClientFacing(ClientFacingError::Unauthorized(
+            msg.to_string(),
+        )))
+    };
+
+    let no_access = "The personal API key is invalid or has no access to this project";
+    let cache_key = (hash_personal_api_key(key), team_id);
+    if key_cache.rejected.contains_key(&cache_key) {
+        return unauthorized(no_access);
+    }
+    let key_scopes = match key_cache.authenticated.get(&cache_key) {
+        Some(key_scopes) => key_scopes,
+        None => {
+            let Some(key_scopes) = fetch_personal_api_key_scopes(reader, &cache_key).await? else {
+                key_cache.rejected.insert(cache_key, ());
+                return unauthorized(no_access);
+            };
+            key_cache
+                .authenticated
+                .insert(cache_key, key_scopes.clone());
+            key_scopes
+        }
+    };
+
+    let Some(key_scopes) = key_scopes else {
+        return Ok(()); // Legacy keys, with full access
+    };
+    match scopes
+        .iter()
+        .find(|scope| !scopes_allow(&key_scopes, scope))
+    {
+        None => Ok(()),
+        Some(scope) => unauthorized(&format!(
+            "The personal API key is missing the {scope} scope"
+        )),
+    }
+}
+
+/// Returns the scopes of the key, `None` for legacy keys, if the key has access to the team.
+async fn fetch_personal_api_key_scopes(
+    reader: &(dyn DatabaseClient + Send + Sync),
+    (secure_value, team_id): &(String, TeamId),
+) -> Result<Option<Option<Vec<String>>>, FlagError> {
+    let mut conn = reader.get_connection().await?;
+
+    let query = r#"
+        SELECT k.scopes
+          FROM posthog_personalapikey AS k
+          JOIN posthog_user AS u ON (k.user_id = u.id)
+          JOIN posthog_organizationmembership AS m ON (m.user_id = u.id)
+          JOIN posthog_team AS t ON (t.organization_id = m.organization_id)
+        WHERE k.secure_value = $1
+          AND t.id = $2
+          AND u.is_active
+          AND (k.scoped_teams IS NULL OR cardinality(k.scoped_teams) = 0 OR t.id = ANY(k.scoped_teams))
+          AND (k.scoped_organizations IS NULL OR cardinality(k.scoped_organizations) = 0
+               OR t.organization_id::text = ANY(k.scoped_organizations))
+    "#;
+    let row = sqlx::query(query)
+        .bind(secure_value)
+        .bind(team_id)
+        .fetch_optional(&mut *conn)
+        .await?;
+
+    match row {
+        Some(row) => Ok(Some(row.try_get("scopes")?)),
+        None => Ok(None),
+    }
+}
+
+fn scopes_allow(scopes: &[String], scope: &str) -> bool {
+    let write_scope = scope
+        .strip_suffix(":read")
+        .map(|object| format!("{object}:write"));
+    scopes
+        .iter()
+        .any(|s| s == "*" || s == scope || Some(s) == write_scope.as_ref())
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+
+    use super::*;
+    use crate::utils::test_utils::{
+        insert_new_team_in_pg, insert_personal_api_key_for_team_in_pg, setup_pg_reader_client,
+    };
+
+    #[test]
+    fn test_extract_personal_api_key() {
+        let mut headers = HeaderMap::new();
+        assert!(extract_personal_api_key(&headers).is_err());
+
+        headers.insert(header::AUTHORIZATION, HeaderValue::from_static("Basic abc"));
+        assert!(extract_personal_api_key(&headers).is_err());
+
+        headers.insert(
+            header::AUTHORIZATION,
+            HeaderValue::from_static("Bearer phx_123"),
+        );
+        assert_eq!(extract_personal_api_key(&headers).unwrap(), "phx_123");
+    }
+
+    #[test]
+    fn test_hash_personal_api_key() {
+        assert_eq!(
+            hash_personal_api_key("phx_123"),
+            "sha256$d8324f285b4fe2207f0896f13dbde6d7e893aef70e81f7901ae62d6858a3a64f"
+        );
+    }
+
+    #[test]
+    fn test_scopes_allow() {
+        let scopes = |s: &[&str]| s.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        assert!(scopes_allow(&scopes(&["*"]), "feature_flag:read"));
+        assert!(scopes_allow(
+            &scopes(&["feature_flag:read"]),
+            "feature_flag:read"
+        ));
+        assert!(scopes_allow(
+            &scopes(&["feature_flag:write"]),
+            "feature_flag:read"
+        ));
+        assert!(!scopes_allow(
+            &scopes(&["cohort:read"]),
+            "feature_flag:read"
+        ));
+        assert!(!scopes_allow(
+            &scopes(&["feature_flag:read"]),
+            "feature_flag:write"
+        ));
+        assert!(!scopes_allow(&[], "feature_flag:read"));
+    }
+
+    #[tokio::test]
+    async fn test_authenticate_personal_api_key() {
+        let reader = setup_pg_reader_client(None).await;
+        let team = insert_new_team_in_pg(reader.clone(), None)
+            .await
+            .expect("Failed to insert team");
+        let other_team = insert_new_team_in_pg(reader.clone(), None)
+            .await
+            .expect("Failed to insert team");
+
+        let key = insert_personal_api_key_for_team_in_pg(
+            reader.clone(),
+            team.id,
+            Some(vec!["feature_flag:read"]),
+        )
+        .await
+        .expect("Failed to insert personal API key");
+        let scoped_key = insert_personal_api_key_for_team_in_pg(
+            reader.clone(),
+            team.id,
+            Some(vec!["cohort:read"]),
+        )
+        .await
+        .expect("Failed to insert personal API key");
+
+        let key_cache = PersonalApiKeyCache::default();
+        let authenticate = |key: String, team_id: TeamId| {
+            let reader = reader.clone();
+            let key_cache = key_cache.clone();
+            async move {
+                authenticate_personal_api_key(
+                    reader.as_ref(),
+                    &key_cache,
+                    &key,
+                    team_id,
+                    &["feature_flag:read"],
+                )
+                .await
+            }
+        };
+        assert!(authenticate(key.clone(), team.id).await.is_ok());
+        // Every scope is required
+        assert!(authenticate_personal_api_key(
+            reader.as_ref(),
+            &key_cache,
+            &key,
+            team.id,
+            &["feature_flag:read", "person:read"],
+        )
+        .await
+        .is_err());
+        // Keys are scoped to the team they were created for
+        assert!(authenticate(key.clone(), other_team.id).await.is_err());
+        assert!(authenticate(scoped_key, team.id).await.is_err());
+        assert!(authenticate("phx_unknown".to_string(), team.id)
+            .await
+            .is_err());
+        // Rejected lookups are cached too, so retries don't query the database
+        assert!(key_cache
+            .rejected
+            .contains_key(&(hash_personal_api_key("phx_unknown"), team.id)));
+
+        // Successful lookups are cached, even once the key is deleted
+        let mut conn = reader.get_connection().await.unwrap();
+        sqlx::query("DELETE FROM posthog_personalapikey WHERE secure_value = $1")
+            .bind(hash_personal_api_key(&key))
+            .execute(&mut *conn)
+            .await
+            .unwrap();
+        assert!(authenticate(key, team.id).await.is_ok());
+    }
+}
@@ -2,8 +2,13 @@ use std::net::IpAddr;
 
 use crate::{
     api::errors::FlagError,
+    api::explain::{process_explain_request, ExplainQueryParams},
+    api::local_evaluation::{process_local_evaluation_request, LocalEvaluationQueryParams},
     api::request_handler::{process_request, FlagsQueryParams, RequestContext},
-    api::types::{FlagsOptionsResponse, FlagsResponseCode, LegacyFlagsResponse, ServiceResponse},
+    api::types::{
+        ExplainResponse, FlagsOptionsResponse, FlagsResponseCode, LegacyFlagsResponse,
+        LocalEvaluationResponse, ServiceResponse,
+    },
     router,
 };
 // TODO: stream this instead
@@ -70,6 +75,49 @@ pub async fn flags(
     Ok(Json(versioned_response?))
 }
 
+/// Flag definitions endpoint, polled by server SDKs to evaluate flags locally.
+/// Authenticated with a personal API key, for the team of the `token` query parameter.
+#[instrument(skip_all, fields(path, token, user_agent))]
+#[debug_handler]
+pub async fn local_evaluation(
+    state: State<router::State>,
+    Query(query_params): Query<LocalEvaluationQueryParams>,
+    headers: HeaderMap,
+    path: MatchedPath,
+) -> Result<Json<LocalEvaluationResponse>, FlagError> {
+    let user_agent = headers
+        .get("user-agent")
+        .map_or("unknown", |v| v.to_str().unwrap_or("unknown"));
+    tracing::Span::current().record("user_agent", user_agent);
+    tracing::Span::current().record("path", path.as_str().trim_end_matches('/'));
+    tracing::Span::current().record("token", query_params.token.as_deref().unwrap_or("none"));
+
+    let response = process_local_evaluation_request(&state, &headers, &query_params).await?;
+    Ok(Json(response))
+}
+
+/// Flag evaluation trace endpoint, explaining why a person gets the flag values they get.
+/// Authenticated with a personal API key, for the team of the token in the body.
+#[instrument(skip_all, fields(path, user_agent))]
+#[debug_handler]
+pub async fn explain(
+    state: State<router::State>,
+    InsecureClientIp(ip): InsecureClientIp,
+    Query(query_params): Query<ExplainQueryParams>,
+    headers: HeaderMap,
+    path: MatchedPath,
+    body: Bytes,
+) -> Result<Json<ExplainResponse>, FlagError> {
+    let user_agent = headers
+        .get("user-agent")
+        .map_or("unknown", |v| v.to_str().unwrap_or("unknown"));
+    tracing::Span::current().record("user_agent", user_agent);
+    tracing::Span::current().record("path", path.as_str().trim_end_matches('/'));
+
+    let response = process_explain_request(&state, ip, &headers, &query_params, body).await?;
+    Ok(Json(response))
+}
+
 pub async fn options() -> Result<Json<FlagsOptionsResponse>, FlagError> {
     Ok(Json(FlagsOptionsResponse {
         status: FlagsResponseCode::Ok,
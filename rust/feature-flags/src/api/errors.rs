@@ -58,6 +58,10 @@ pub enum FlagError {
     CohortFiltersParsingError,
     #[error("Cohort dependency cycle")]
     CohortDependencyCycle(String),
+    #[error("Flag dependency cycle")]
+    FlagDependencyCycle(String),
+    #[error("Flag dependency failed")]
+    FlagDependencyFailed(String),
     #[error("Person not found")]
     PersonNotFound,
 }
@@ -165,6 +169,14 @@ impl IntoResponse for FlagError {
                 tracing::error!("Cohort dependency cycle: {}", msg);
                 (StatusCode::BAD_REQUEST, msg)
             }
+            FlagError::FlagDependencyCycle(msg) => {
+                tracing::error!("Flag dependency cycle: {}", msg);
+                (StatusCode::BAD_REQUEST, msg)
+            }
+            FlagError::FlagDependencyFailed(msg) => {
+                tracing::error!("Flag dependency failed: {}", msg);
+                (StatusCode::INTERNAL_SERVER_ERROR, msg)
+            }
             FlagError::PersonNotFound => {
                 (StatusCode::BAD_REQUEST, "Person not found. Please check your distinct_id and try again.".to_string())
             }
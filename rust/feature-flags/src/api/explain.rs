@@ -0,0 +1,117 @@
+use crate::{
+    api::{
+        auth::{authenticate_personal_api_key, extract_personal_api_key},
+        errors::FlagError,
+        request_handler::{
+            fetch_and_filter_flags, get_person_property_overrides, process_group_property_overrides,
+        },
+        types::ExplainResponse,
+    },
+    flags::{
+        flag_matching::{FeatureFlagMatcher, GroupTypeMappingCache},
+        flag_request::FlagRequest,
+        flag_service::FlagService,
+    },
+    router,
+};
+use axum::http::HeaderMap;
+use bytes::Bytes;
+use serde::Deserialize;
+use std::{collections::HashSet, net::IpAddr};
+
+/// Personal API keys need these scopes to explain the flags: the flag definitions,
+/// and the person properties they're matched against, are in the trace
+pub const EXPLAIN_SCOPES: [&str; 2] = ["feature_flag:read", "person:read"];
+
+#[derive(Clone, Deserialize, Default)]
+pub struct ExplainQueryParams {
+    /// IP address to add the GeoIP properties of, instead of the one of the request,
+    /// which usually isn't the one of the person the flags are explained for
+    pub ip: Option<IpAddr>,
+}
+
+/// Evaluates the team's flags for a distinct_id like a `/flags` request with the same body would,
+/// returning with each flag a trace of how its conditions were matched.
+///
+/// Authenticated with a personal API key with access to the flags of the team of the token,
+/// as the trace exposes the person's properties.
+pub async fn process_explain_request(
+    state: &router::State,
+    ip: IpAddr,
+    headers: &HeaderMap,
+    query: &ExplainQueryParams,
+    body: Bytes,
+) -> Result<ExplainResponse, FlagError> {
+    let request = FlagRequest::from_bytes(body)?;
+    let token = request.extract_token()?;
+    let distinct_id = request.extract_distinct_id()?;
+    let personal_api_key = extract_personal_api_key(headers)?;
+
+    let flag_service = FlagService::new(state.redis.clone(), state.reader.clone());
+    let verified_token = flag_service.verify_token(&token).await?;
+    let team = flag_service
+        .get_team_from_cache_or_pg(&verified_token)
+        .await?;
+    authenticate_personal_api_key(
+        state.reader.as_ref(),
+        &state.personal_api_key_cache,
+        &personal_api_key,
+        team.id,
+        &EXPLAIN_SCOPES,
+    )
+    .await?;
+
+    let flags = fetch_and_filter_flags(
+        &flag_service,
+        &state.evaluation_plan_cache,
+        team.project_id,
+        &request,
+    )
+    .await?;
+
+    let ip = query.ip.unwrap_or(ip);
+    let geoip_enabled = !request.geoip_disable.unwrap_or(false);
+    let geoip_property_keys: HashSet<String> = if geoip_enabled {
+        state
+            .geoip
+            .get_geoip_properties(Some(&ip.to_string()))
+            .into_keys()
+            .collect()
+    } else {
+        HashSet::new()
+    };
+    let person_property_overrides = get_person_property_overrides(
+        geoip_enabled,
+        request.person_properties.clone(),
+        &ip,
+        &state.geoip,
+    );
+    let group_property_overrides =
+        process_group_property_overrides(request.groups.clone(), request.group_properties.clone());
+
+    let mut matcher = FeatureFlagMatcher::new(
+        distinct_id,
+        team.id,
+        team.project_id,
+        state.reader.clone(),
+        state.writer.clone(),
+        state.cohort_cache_manager.clone(),
+        Some(GroupTypeMappingCache::new(
+            team.project_id,
+            state.reader.clone(),
+        )),
+        request.groups.clone(),
+    );
+    let explanations = matcher
+        .explain_flags(
+            flags,
+            person_property_overrides,
+            group_property_overrides,
+            geoip_property_keys,
+        )
+        .await;
+
+    Ok(ExplainResponse {
+        flags: explanations,
+    })
+}
@@ -0,0 +1,222 @@
+use crate::{
+    api::{
+        auth::{authenticate_personal_api_key, extract_personal_api_key},
+        errors::{ClientFacingError, FlagError},
+        types::LocalEvaluationResponse,
+    },
+    cohort::cohort_models::{Cohort, CohortId},
+    flags::{
+        flag_analytics::increment_request_count, flag_matching::GroupTypeMappingCache,
+        flag_models::FeatureFlagList, flag_request::FlagRequestType, flag_service::FlagService,
+    },
+    router,
+};
+use axum::http::HeaderMap;
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+
+/// Personal API keys need this scope to fetch the flag definitions
+pub const LOCAL_EVALUATION_SCOPE: &str = "feature_flag:read";
+
+#[derive(Clone, Deserialize, Default)]
+pub struct LocalEvaluationQueryParams {
+    /// Project API token of the team to fetch the flags for
+    pub token: Option<String>,
+}
+
+/// Returns the flag definitions of the team for server SDKs evaluating flags locally,
+/// with the same shape as Django's `/api/feature_flag/local_evaluation`.
+///
+/// The referenced cohorts are always returned, as with the `send_cohorts` parameter,
+/// rather than being inlined into the flags that only target a single cohort.
+pub async fn process_local_evaluation_request(
+    state: &router::State,
+    headers: &HeaderMap,
+    query: &LocalEvaluationQueryParams,
+) -> Result<LocalEvaluationResponse, FlagError> {
+    let token = match &query.token {
+        Some(token) if !token.is_empty() => token.clone(),
+        _ => return Err(FlagError::NoTokenError),
+    };
+    let personal_api_key = extract_personal_api_key(headers)?;
+
+    let flag_service = FlagService::new(state.redis.clone(), state.reader.clone());
+    let verified_token = flag_service.verify_token(&token).await?;
+    let team = flag_service
+        .get_team_from_cache_or_pg(&verified_token)
+        .await?;
+    authenticate_personal_api_key(
+        state.reader.as_ref(),
+        &state.personal_api_key_cache,
+        &personal_api_key,
+        team.id,
+        &[LOCAL_EVALUATION_SCOPE],
+    )
+    .await?;
+
+    if state
+        .billing_limiter
+        .is_limited(verified_token.as_str())
+        .await
+    {
+        return Err(FlagError::ClientFacing(ClientFacingError::BillingLimit));
+    }
+
+    let flags = flag_service
+        .get_flags_from_cache_or_pg(team.project_id, &state.evaluation_plan_cache)
+        .await?;
+    let cohorts = state
+        .cohort_cache_manager
+        .get_cohorts(team.project_id)
+        .await?;
+    let group_type_mapping = match GroupTypeMappingCache::new(team.project_id, state.reader.clone())
+        .group_type_index_to_group_type_map()
+        .await
+    {
+        Ok(mapping) => mapping,
+        Err(FlagError::NoGroupTypeMappings) => HashMap::new(),
+        Err(e) => return Err(e),
+    };
+
+    // Add request for analytics
+    if let Err(e) = increment_request_count(
+        state.redis.clone(),
+        team.id,
+        1,
+        FlagRequestType::LocalEvaluation,
+    )
+    .await
+    {
+        tracing::warn!("Failed to increment local evaluation request count: {}", e);
+    }
+
+    Ok(LocalEvaluationResponse {
+        cohorts: referenced_cohorts(&flags, cohorts),
+        flags: flags.flags,
+        group_type_mapping: group_type_mapping
+            .into_iter()
+            .map(|(index, group_type)| (index.to_string(), group_type))
+            .collect(),
+    })
+}
+
+/// Collects the properties of the cohorts targeted by the flags, and of the cohorts they depend on.
+/// Static cohorts are skipped, as their members can't be evaluated locally.
+fn referenced_cohorts(flags: &FeatureFlagList, cohorts: Vec<Cohort>) -> HashMap<String, Value> {
+    let cohorts: HashMap<CohortId, Cohort> = cohorts
+        .into_iter()
+        .filter(|cohort| !cohort.deleted)
+        .map(|cohort| (cohort.id, cohort))
+        .collect();
+
+    let mut pending: Vec<CohortId> = flags
+        .flags
+        .iter()
+        .flat_map(|flag| flag.get_conditions())
+        .flat_map(|condition| condition.properties.iter().flatten())
+        .filter_map(|filter| filter.get_cohort_id())
+        .collect();
+    let mut seen = HashSet::new();
+    let mut referenced = HashMap::new();
+    while let Some(cohort_id) = pending.pop() {
+        if !seen.insert(cohort_id) {
+            continue;
+        }
+        let Some(cohort) = cohorts.get(&cohort_id) else {
+            continue;
+        };
+        if cohort.is_static {
+            continue;
+        }
+        match cohort.extract_dependencies() {
+            Ok(dependencies) => pending.extend(dependencies),
+            Err(e) => tracing::warn!(
+                "Failed to extract dependencies of cohort {}: {}",
+                cohort_id,
+                e
+            ),
+        }
+        if let Some(properties) = cohort.filters.as_ref().and_then(|f| f.get("properties")) {
+            referenced.insert(cohort_id.to_string(), properties.clone());
+        }
+    }
+    referenced
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test_utils::create_flag_from_json;
+    use serde_json::json;
+
+    fn cohort(id: CohortId, filters: Value, is_static: bool, deleted: bool) -> Cohort {
+        Cohort {
+            id,
+            name: format!("cohort {}", id),
+            description: None,
+            team_id: 1,
+            deleted,
+            filters: Some(filters),
+            query: None,
+            version: None,
+            pending_version: None,
+            count: None,
+            is_calculating: false,
+            is_static,
+            errors_calculating: 0,
+            groups: json!([]),
+            created_by_id: None,
+        }
+    }
+
+    fn cohort_filter(cohort_id: CohortId) -> Value {
+        json!({"key": "id", "type": "cohort", "value": cohort_id, "operator": "in"})
+    }
+
+    #[test]
+    fn test_referenced_cohorts() {
+        let flags = FeatureFlagList::new(create_flag_from_json(Some(
+            json!([{
+                "id": 1,
+                "key": "flag",
+                "team_id": 1,
+                "active": true,
+                "filters": {
+                    "groups": [
+                        {"properties": [cohort_filter(1)], "rollout_percentage": 100},
+                        {"properties": [cohort_filter(3), cohort_filter(4)]},
+                    ],
+                },
+            }])
+            .to_string(),
+        )));
+        let properties = |values: Value| json!({"properties": {"type": "OR", "values": values}});
+        let cohorts = vec![
+            // Depends on cohort 2
+            cohort(
+                1,
+                properties(json!([{"type": "AND", "values": [cohort_filter(2)]}])),
+                false,
+                false,
+            ),
+            cohort(
+                2,
+                properties(json!([{"type": "AND", "values": [
+                    {"key": "email", "type": "person", "value": "@posthog.com", "operator": "icontains"}
+                ]}])),
+                false,
+                false,
+            ),
+            cohort(3, properties(json!([])), true, false),
+            cohort(4, properties(json!([])), false, true),
+            cohort(5, properties(json!([])), false, false),
+        ];
+
+        let referenced = referenced_cohorts(&flags, cohorts);
+        let mut ids: Vec<&String> = referenced.keys().collect();
+        ids.sort();
+        assert_eq!(ids, vec!["1", "2"]);
+        assert_eq!(referenced["2"]["values"][0]["values"][0]["key"], "email");
+    }
+}
@@ -1,5 +1,8 @@
+pub mod auth;
 pub mod endpoint;
 pub mod errors;
+pub mod explain;
+pub mod local_evaluation;
 pub mod request_handler;
 pub mod test_endpoint;
 pub mod types;
@@ -5,6 +5,7 @@ use crate::{
     flags::{
         flag_matching::{FeatureFlagMatcher, GroupTypeMappingCache},
         flag_models::FeatureFlagList,
+        flag_plan_cache::FlagEvaluationPlanCache,
         flag_request::FlagRequest,
         flag_service::FlagService,
     },
@@ -126,7 +127,13 @@ pub async fn process_request(context: RequestContext) -> Result<FlagsResponse, F
     let team_id = team.id;
     let project_id = team.project_id;
 
-    let filtered_flags = fetch_and_filter_flags(&flag_service, project_id, &request).await?;
+    let filtered_flags = fetch_and_filter_flags(
+        &flag_service,
+        &context.state.evaluation_plan_cache,
+        project_id,
+        &request,
+    )
+    .await?;
 
     let (person_prop_overrides, group_prop_overrides, groups, hash_key_override) =
         prepare_property_overrides(&context, &request)?;
@@ -168,12 +175,15 @@ async fn parse_and_authenticate_request(
 }
 
 /// Fetches flags from cache/DB and filters them based on requested keys, if any.
-async fn fetch_and_filter_flags(
+pub async fn fetch_and_filter_flags(
     flag_service: &FlagService,
+    plan_cache: &FlagEvaluationPlanCache,
     project_id: i64,
     request: &FlagRequest,
 ) -> Result<FeatureFlagList, FlagError> {
-    let all_flags = flag_service.get_flags_from_cache_or_pg(project_id).await?;
+    let all_flags = flag_service
+        .get_flags_from_cache_or_pg(project_id, plan_cache)
+        .await?;
     if let Some(flag_keys) = &request.flag_keys {
         let keys: HashSet<String> = flag_keys.iter().cloned().collect();
         let filtered = all_flags
@@ -181,7 +191,11 @@ async fn fetch_and_filter_flags(
             .into_iter()
             .filter(|f| keys.contains(&f.key))
             .collect();
-        Ok(FeatureFlagList::new(filtered))
+        // The plan of all the flags also covers the filtered ones, no need to compile them again
+        Ok(FeatureFlagList {
+            flags: filtered,
+            plan: all_flags.plan,
+        })
     } else {
         Ok(all_flags)
     }
@@ -589,7 +603,7 @@ mod tests {
             version: Some(1),
         };
 
-        let feature_flag_list = FeatureFlagList { flags: vec![flag] };
+        let feature_flag_list = FeatureFlagList::new(vec![flag]);
 
         let mut person_properties = HashMap::new();
         person_properties.insert("country".to_string(), json!("US"));
@@ -660,7 +674,7 @@ mod tests {
             version: Some(1),
         }];
 
-        let feature_flag_list = FeatureFlagList { flags };
+        let feature_flag_list = FeatureFlagList::new(flags);
 
         // Set up evaluation context
         let evaluation_context = FeatureFlagEvaluationContext {
@@ -871,7 +885,7 @@ mod tests {
             },
         ];
 
-        let feature_flag_list = FeatureFlagList { flags };
+        let feature_flag_list = FeatureFlagList::new(flags);
 
         let evaluation_context = FeatureFlagEvaluationContext {
             team_id: 1,
@@ -954,7 +968,7 @@ mod tests {
             },
         ];
 
-        let feature_flag_list = FeatureFlagList { flags };
+        let feature_flag_list = FeatureFlagList::new(flags);
 
         let evaluation_context = FeatureFlagEvaluationContext {
             team_id: 1,
@@ -1087,7 +1101,7 @@ mod tests {
             ensure_experience_continuity: false,
             version: Some(1),
         };
-        let feature_flag_list = FeatureFlagList { flags: vec![flag] };
+        let feature_flag_list = FeatureFlagList::new(vec![flag]);
 
         let groups = HashMap::from([("project".to_string(), json!("project_123"))]);
         let group_property_overrides = HashMap::from([(
@@ -1170,7 +1184,7 @@ mod tests {
             version: Some(1),
         };
 
-        let feature_flag_list = FeatureFlagList { flags: vec![flag] };
+        let feature_flag_list = FeatureFlagList::new(vec![flag]);
 
         let evaluation_context = FeatureFlagEvaluationContext {
             team_id: 1,
@@ -1,6 +1,7 @@
 use crate::flags::flag_match_reason::FeatureFlagMatchReason;
 use crate::flags::flag_matching::FeatureFlagMatch;
 use crate::flags::flag_models::FeatureFlag;
+use crate::properties::property_models::OperatorType;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::collections::HashMap;
@@ -81,6 +82,14 @@ impl FlagsResponse {
     }
 }
 
+/// Flag definitions served to server SDKs, keeping the shape of the Django endpoint
+#[derive(Debug, Deserialize, Serialize)]
+pub struct LocalEvaluationResponse {
+    pub flags: Vec<FeatureFlag>,
+    pub group_type_mapping: HashMap<String, String>, // group_type_index -> group_type
+    pub cohorts: HashMap<String, Value>,             // cohort id -> cohort properties
+}
+
 #[derive(Debug, PartialEq, Eq, Deserialize, Serialize)]
 pub struct FlagsOptionsResponse {
     pub status: FlagsResponseCode,
@@ -121,6 +130,75 @@ pub struct FlagEvaluationReason {
     pub description: Option<String>,
 }
 
+/// Flag evaluations traced by the explain endpoint, keyed by flag key
+#[derive(Debug, PartialEq, Serialize)]
+pub struct ExplainResponse {
+    pub flags: HashMap<String, FlagExplanation>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct FlagExplanation {
+    #[serde(flatten)]
+    pub details: FlagDetails,
+    /// None if the flag couldn't be evaluated, see the reason code
+    pub trace: Option<FlagEvaluationTrace>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FlagEvaluationTrace {
+    /// Distinct ID, group key or hash key override that the hashes are computed from
+    pub hashed_identifier: String,
+    /// Between 0 and 1, in the rollout of the conditions with a rollout percentage above it
+    pub rollout_hash: f64,
+    /// Between 0 and 1, picks the variant whose cumulative rollout percentage is above it
+    pub variant_hash: Option<f64>,
+    pub super_condition: Option<ConditionTrace>,
+    pub conditions: Vec<ConditionTrace>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ConditionTrace {
+    pub condition_index: usize,
+    pub rollout_percentage: f64,
+    pub variant: Option<String>,
+    pub filters: Vec<FilterTrace>,
+    pub filters_match: bool,
+    pub in_rollout: bool,
+    pub matches: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FilterTrace {
+    pub key: String,
+    #[serde(rename = "type")]
+    pub prop_type: String,
+    pub operator: Option<OperatorType>,
+    pub value: Value,
+    /// Property value or flag result the filter was matched against, None if it's missing
+    pub compared_value: Option<Value>,
+    pub source: Option<ValueSource>,
+    pub matches: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Where the value a filter was matched against came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ValueSource {
+    /// Properties sent with the request
+    Override,
+    /// Person properties added from the request's IP address
+    #[serde(rename = "geoip")]
+    GeoIp,
+    /// Person or group properties stored in the database
+    Database,
+    /// Result of another flag evaluated in the request
+    Flag,
+    /// Cohort membership of the person
+    Cohort,
+}
+
 pub trait FromFeatureAndMatch {
     fn create(flag: &FeatureFlag, flag_match: &FeatureFlagMatch) -> Self;
     fn create_error(flag: &FeatureFlag, error_reason: &str) -> Self;
@@ -1,22 +1,28 @@
 use crate::api::errors::FlagError;
-use crate::api::types::{FlagDetails, FlagsResponse, FromFeatureAndMatch};
+use crate::api::types::{
+    ConditionTrace, FilterTrace, FlagDetails, FlagEvaluationTrace, FlagExplanation, FlagsResponse,
+    FromFeatureAndMatch, ValueSource,
+};
 use crate::client::database::Client as DatabaseClient;
 use crate::cohort::cohort_cache_manager::CohortCacheManager;
 use crate::cohort::cohort_models::{Cohort, CohortId};
 use crate::flags::flag_match_reason::FeatureFlagMatchReason;
-use crate::flags::flag_models::{FeatureFlag, FeatureFlagList, FlagGroupType};
+use crate::flags::flag_models::{
+    CohortMembershipFilter, CompiledCondition, CompiledFlag, FeatureFlag, FeatureFlagList,
+    FlagEvaluationPlan,
+};
 use crate::metrics::metrics_consts::{
     DB_GROUP_PROPERTIES_READS_COUNTER, DB_PERSON_AND_GROUP_PROPERTIES_READS_COUNTER,
     DB_PERSON_PROPERTIES_READS_COUNTER, FLAG_EVALUATION_ERROR_COUNTER,
     FLAG_HASH_KEY_WRITES_COUNTER, PROPERTY_CACHE_HITS_COUNTER, PROPERTY_CACHE_MISSES_COUNTER,
 };
 use crate::metrics::metrics_utils::parse_exception_for_prometheus_label;
-use crate::properties::property_matching::match_property;
+use crate::properties::property_matching::{match_compiled_property, match_property};
 use crate::properties::property_models::{OperatorType, PropertyFilter};
 use crate::team::team_models::{ProjectId, TeamId};
 use anyhow::Result;
 use common_metrics::inc;
-use petgraph::algo::{is_cyclic_directed, toposort};
+use petgraph::algo::{is_cyclic_directed, tarjan_scc, toposort};
 use petgraph::graph::DiGraph;
 use serde_json::Value;
 use sha1::{Digest, Sha1};
@@ -220,6 +226,21 @@ pub struct FeatureFlagMatcher {
     group_type_mapping_cache: GroupTypeMappingCache,
     properties_cache: PropertiesCache,
     groups: HashMap<String, Value>,
+    /// Results of the flags evaluated so far in the request, keyed by flag key,
+    /// which the flags depending on them are matched against
+    flag_evaluation_results: HashMap<String, FeatureFlagMatch>,
+    /// Compiled conditions of the flags being evaluated, from the flag list
+    evaluation_plan: Arc<FlagEvaluationPlan>,
+    /// Set while explaining the flags, to record their traces as they are evaluated
+    flag_traces: Option<FlagTraces>,
+}
+
+/// Traces of the flags evaluated so far, recorded by `get_match` when explaining the flags.
+#[derive(Clone, Debug)]
+struct FlagTraces {
+    /// Person property overrides which were added from the GeoIP lookup
+    geoip_property_keys: HashSet<String>,
+    traces: HashMap<String, FlagEvaluationTrace>,
 }
 
 const LONG_SCALE: u64 = 0xfffffffffffffff;
@@ -247,6 +268,9 @@ impl FeatureFlagMatcher {
                 .unwrap_or_else(|| GroupTypeMappingCache::new(project_id, reader.clone())),
             groups: groups.unwrap_or_default(),
             properties_cache: PropertiesCache::default(),
+            flag_evaluation_results: HashMap::new(),
+            evaluation_plan: Arc::default(),
+            flag_traces: None,
         }
     }
 
@@ -422,10 +446,13 @@ impl FeatureFlagMatcher {
 
     /// Evaluates feature flags with property and hash key overrides.
     ///
+    /// Flags are evaluated in dependency order, so that the flags other flags depend on are
+    /// evaluated (once) before them. Flags in a dependency cycle are returned as errors.
+    ///
     /// This function evaluates feature flags in two steps:
     /// 1. First, it evaluates flags that can be computed using only the provided property overrides
     /// 2. Then, for remaining flags that need database properties, it fetches and caches those properties
-    ///    before evaluating those flags
+    ///    before evaluating those flags, along with the flags depending on them
     pub async fn evaluate_flags_with_overrides(
         &mut self,
         feature_flags: FeatureFlagList,
@@ -436,25 +463,71 @@ impl FeatureFlagMatcher {
         let mut errors_while_computing_flags = false;
         let mut flag_details_map = HashMap::new();
         let mut flags_needing_db_properties = Vec::new();
+        // Flags which failed to evaluate, their dependents fail too
+        let mut failed_flags = HashSet::new();
+        self.evaluation_plan = feature_flags.plan.clone();
+
+        let (sorted_flags, flags_with_cyclic_dependencies) =
+            sort_flags_by_dependencies(&feature_flags.flags);
+        for flag in flags_with_cyclic_dependencies {
+            if !flag.active || flag.deleted {
+                continue;
+            }
+            errors_while_computing_flags = true;
+            let e = FlagError::FlagDependencyCycle(format!(
+                "Flag '{}' is part of or depends on a dependency cycle",
+                flag.key
+            ));
+            error!(
+                "Error evaluating feature flag '{}' for distinct_id '{}': {:?}",
+                flag.key, self.distinct_id, e
+            );
+            let reason = parse_exception_for_prometheus_label(&e);
+            inc(
+                FLAG_EVALUATION_ERROR_COUNTER,
+                &[("reason".to_string(), reason.to_string())],
+                1,
+            );
+            flag_details_map.insert(flag.key.clone(), FlagDetails::create_error(flag, reason));
+        }
 
         // Step 1: Evaluate flags with locally computable property overrides first
-        for flag in &feature_flags.flags {
+        for flag in sorted_flags {
             if !flag.active || flag.deleted {
                 continue;
             }
 
-            match self
-                .match_flag_with_property_overrides(
-                    flag,
-                    &person_property_overrides,
-                    &group_property_overrides,
-                    hash_key_overrides.clone(),
-                )
-                .await
+            // Flags depending on a flag that needs database properties need to wait for it
+            let failed_dependency = failed_dependency(flag, &failed_flags);
+            if failed_dependency.is_none()
+                && flag.get_flag_dependencies().iter().any(|key| {
+                    flags_needing_db_properties
+                        .iter()
+                        .any(|f: &FeatureFlag| &f.key == key)
+                })
             {
+                flags_needing_db_properties.push(flag.clone());
+                continue;
+            }
+
+            let result = match failed_dependency {
+                Some(e) => Err(e),
+                None => {
+                    self.match_flag_with_property_overrides(
+                        flag,
+                        &person_property_overrides,
+                        &group_property_overrides,
+                        hash_key_overrides.clone(),
+                    )
+                    .await
+                }
+            };
+            match result {
                 Ok(Some(flag_match)) => {
                     flag_details_map
                         .insert(flag.key.clone(), FlagDetails::create(flag, &flag_match));
+                    self.flag_evaluation_results
+                        .insert(flag.key.clone(), flag_match);
                 }
                 Ok(None) => {
                     flags_needing_db_properties.push(flag.clone());
@@ -471,6 +544,9 @@ impl FeatureFlagMatcher {
                         &[("reason".to_string(), reason.to_string())],
                         1,
                     );
+                    flag_details_map
+                        .insert(flag.key.clone(), FlagDetails::create_error(flag, reason));
+                    failed_flags.insert(flag.key.clone());
                 }
             }
         }
@@ -547,13 +623,19 @@ impl FeatureFlagMatcher {
 
             // Step 3: Evaluate remaining flags with cached properties
             for flag in flags_needing_db_properties {
-                match self
-                    .get_match(&flag, None, hash_key_overrides.clone())
-                    .await
-                {
+                let result = match failed_dependency(&flag, &failed_flags) {
+                    Some(e) => Err(e),
+                    None => {
+                        self.get_match(&flag, None, hash_key_overrides.clone())
+                            .await
+                    }
+                };
+                match result {
                     Ok(flag_match) => {
                         flag_details_map
                             .insert(flag.key.clone(), FlagDetails::create(&flag, &flag_match));
+                        self.flag_evaluation_results
+                            .insert(flag.key.clone(), flag_match);
                     }
                     Err(e) => {
                         errors_while_computing_flags = true;
@@ -570,6 +652,7 @@ impl FeatureFlagMatcher {
                         );
                         flag_details_map
                             .insert(flag.key.clone(), FlagDetails::create_error(&flag, reason));
+                        failed_flags.insert(flag.key.clone());
                     }
                 }
             }
@@ -578,6 +661,223 @@ impl FeatureFlagMatcher {
         FlagsResponse::new(errors_while_computing_flags, flag_details_map, None)
     }
 
+    /// Evaluates the flags with `evaluate_flags_with_overrides`, tracing for each flag how its
+    /// conditions were matched: the value each filter was compared against and where it came from,
+    /// and the rollout and variant hashes. The traces are recorded by `get_match` as the flags are
+    /// evaluated, see `FlagTraces`.
+    ///
+    /// Hash key overrides for experience continuity are only read, never written.
+    /// `geoip_property_keys` are the person property overrides which were added from the GeoIP lookup.
+    pub async fn explain_flags(
+        &mut self,
+        feature_flags: FeatureFlagList,
+        person_property_overrides: Option<HashMap<String, Value>>,
+        group_property_overrides: Option<HashMap<String, HashMap<String, Value>>>,
+        geoip_property_keys: HashSet<String>,
+    ) -> HashMap<String, FlagExplanation> {
+        let hash_key_overrides = if feature_flags
+            .flags
+            .iter()
+            .any(|flag| flag.ensure_experience_continuity)
+        {
+            match get_feature_flag_hash_key_overrides(
+                self.reader.clone(),
+                self.team_id,
+                vec![self.distinct_id.clone()],
+            )
+            .await
+            {
+                Ok(overrides) => Some(overrides),
+                Err(e) => {
+                    error!("Failed to get feature flag hash key overrides: {:?}", e);
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        self.flag_traces = Some(FlagTraces {
+            geoip_property_keys,
+            traces: HashMap::new(),
+        });
+        let flags_response = self
+            .evaluate_flags_with_overrides(
+                feature_flags,
+                person_property_overrides,
+                group_property_overrides,
+                hash_key_overrides,
+            )
+            .await;
+        let mut traces = self
+            .flag_traces
+            .take()
+            .map(|flag_traces| flag_traces.traces)
+            .unwrap_or_default();
+
+        // Flags which failed to evaluate have no trace
+        flags_response
+            .flags
+            .into_iter()
+            .map(|(key, details)| {
+                let trace = traces.remove(&key);
+                (key, FlagExplanation { details, trace })
+            })
+            .collect()
+    }
+
+    /// Starts the trace of a flag, with the hashes its conditions and variants are matched with.
+    async fn start_flag_trace(
+        &mut self,
+        flag: &FeatureFlag,
+        hash_key_overrides: Option<HashMap<String, String>>,
+    ) -> Result<FlagEvaluationTrace, FlagError> {
+        let hashed_identifier = self
+            .hashed_identifier(flag, hash_key_overrides.clone())
+            .await?;
+        let rollout_hash = self.get_hash(flag, "", hash_key_overrides.clone()).await?;
+        let variant_hash = if flag.get_variants().is_empty() {
+            None
+        } else {
+            Some(self.get_hash(flag, "variant", hash_key_overrides).await?)
+        };
+
+        Ok(FlagEvaluationTrace {
+            hashed_identifier,
+            rollout_hash,
+            variant_hash,
+            super_condition: None,
+            conditions: Vec::new(),
+        })
+    }
+
+    /// Matches a condition like `is_condition_match`, tracing every filter of the condition rather
+    /// than stopping at the first one which doesn't match. Errors are only returned where
+    /// `is_condition_match` would return them, otherwise they're recorded in the filter traces.
+    ///
+    /// `from_overrides` is whether the properties of the condition are the overrides sent with the
+    /// request, which the sources of the property values are traced from.
+    async fn trace_condition(
+        &mut self,
+        feature_flag: &FeatureFlag,
+        condition_index: usize,
+        condition: &CompiledCondition,
+        property_overrides: Option<HashMap<String, Value>>,
+        from_overrides: bool,
+        rollout_hash: f64,
+    ) -> Result<ConditionTrace, FlagError> {
+        let properties = if condition.has_no_filters() {
+            HashMap::new()
+        } else {
+            self.get_properties_to_check(
+                feature_flag,
+                property_overrides,
+                &condition.property_filters,
+            )
+            .await?
+        };
+        let mut filters = Vec::new();
+
+        for filter in &condition.flag_filters {
+            let flag_match = self.flag_evaluation_results.get(&filter.key);
+            filters.push(FilterTrace {
+                key: filter.key.clone(),
+                prop_type: filter.prop_type.clone(),
+                operator: filter.operator,
+                value: filter.value.clone(),
+                compared_value: flag_match.map(|flag_match| match &flag_match.variant {
+                    Some(variant) if flag_match.matches => Value::String(variant.clone()),
+                    _ => Value::Bool(flag_match.matches),
+                }),
+                source: Some(ValueSource::Flag),
+                matches: flag_evaluates_to(flag_match, &filter.value),
+                error: None,
+            });
+        }
+
+        // GeoIP properties are only added to the person property overrides
+        let geoip_property_keys = match feature_flag.get_group_type_index() {
+            Some(_) => None,
+            None => self
+                .flag_traces
+                .as_ref()
+                .map(|flag_traces| &flag_traces.geoip_property_keys),
+        };
+        for (filter, operand) in condition
+            .property_filters
+            .iter()
+            .zip(&condition.property_operands)
+        {
+            let compared_value = properties.get(&filter.key).cloned();
+            let result = match_compiled_property(filter, operand, &properties, false);
+            filters.push(FilterTrace {
+                key: filter.key.clone(),
+                prop_type: filter.prop_type.clone(),
+                operator: filter.operator,
+                value: filter.value.clone(),
+                source: compared_value
+                    .as_ref()
+                    .map(|_| property_source(&filter.key, from_overrides, geoip_property_keys)),
+                compared_value,
+                matches: matches!(result, Ok(true)),
+                error: result.err().map(|e| format!("{:?}", e)),
+            });
+        }
+
+        // Cohorts are only matched once the other filters match, so they only fail the
+        // evaluation from there
+        let other_filters_match = filters.iter().all(|filter| filter.matches);
+        if other_filters_match && condition.has_invalid_cohort_filter {
+            return Err(FlagError::CohortFiltersParsingError);
+        }
+        if !condition.cohort_filters.is_empty() {
+            let person_id = match self.get_person_id().await {
+                Ok(person_id) => Ok(person_id),
+                Err(e) if other_filters_match => return Err(e),
+                Err(e) => Err(e.to_string()),
+            };
+            for cohort_filter in &condition.cohort_filters {
+                let result = match &person_id {
+                    Ok(person_id) => match self
+                        .evaluate_cohort_filters(
+                            std::slice::from_ref(cohort_filter),
+                            &properties,
+                            *person_id,
+                        )
+                        .await
+                    {
+                        Ok(matches) => Ok(matches),
+                        Err(e) if other_filters_match => return Err(e),
+                        Err(e) => Err(e.to_string()),
+                    },
+                    Err(e) => Err(e.clone()),
+                };
+                filters.push(FilterTrace {
+                    key: "id".to_string(),
+                    prop_type: "cohort".to_string(),
+                    operator: Some(cohort_filter.operator),
+                    value: Value::from(cohort_filter.cohort_id),
+                    compared_value: None,
+                    source: Some(ValueSource::Cohort),
+                    matches: matches!(result, Ok(true)),
+                    error: result.err(),
+                });
+            }
+        }
+
+        let filters_match = filters.iter().all(|filter| filter.matches);
+        let in_rollout = is_in_rollout(rollout_hash, condition.rollout_percentage);
+        Ok(ConditionTrace {
+            condition_index,
+            rollout_percentage: condition.rollout_percentage,
+            variant: condition.variant.clone(),
+            filters,
+            filters_match,
+            in_rollout,
+            matches: filters_match && in_rollout,
+        })
+    }
+
     /// Matches a feature flag with property overrides.
     ///
     /// This function attempts to match a feature flag using either group or person property overrides,
@@ -591,30 +891,47 @@ impl FeatureFlagMatcher {
         group_property_overrides: &Option<HashMap<String, HashMap<String, Value>>>,
         hash_key_overrides: Option<HashMap<String, String>>,
     ) -> Result<Option<FeatureFlagMatch>, FlagError> {
+        let overrides = self
+            .get_flag_property_overrides(flag, person_property_overrides, group_property_overrides)
+            .await?;
+
+        match overrides {
+            Some(props) => self
+                .get_match(flag, Some(props), hash_key_overrides)
+                .await
+                .map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the group or person property overrides the flag can be matched with,
+    /// or None if they don't cover all of its property filters.
+    async fn get_flag_property_overrides(
+        &mut self,
+        flag: &FeatureFlag,
+        person_property_overrides: &Option<HashMap<String, Value>>,
+        group_property_overrides: &Option<HashMap<String, HashMap<String, Value>>>,
+    ) -> Result<Option<HashMap<String, Value>>, FlagError> {
+        // Flag filters are matched against the results of the flags evaluated before, not overrides
         let flag_property_filters: Vec<PropertyFilter> = flag
             .get_conditions()
             .iter()
             .flat_map(|c| c.properties.clone().unwrap_or_default())
+            .filter(|prop| !prop.is_flag())
             .collect();
 
-        let overrides = match flag.get_group_type_index() {
+        match flag.get_group_type_index() {
             Some(group_type_index) => {
                 self.get_group_overrides(
                     group_type_index,
                     group_property_overrides,
                     &flag_property_filters,
                 )
-                .await?
-            }
-            None => self.get_person_overrides(person_property_overrides, &flag_property_filters),
-        };
-
-        match overrides {
-            Some(props) => self
-                .get_match(flag, Some(props), hash_key_overrides)
                 .await
-                .map(Some),
-            None => Ok(None),
+            }
+            None => {
+                Ok(self.get_person_overrides(person_property_overrides, &flag_property_filters))
+            }
         }
     }
 
@@ -676,11 +993,44 @@ impl FeatureFlagMatcher {
     ///
     /// The method also keeps track of the highest priority match reason and index,
     /// which are used even if no conditions ultimately match.
+    ///
+    /// When explaining the flags, the trace of the flag is recorded along the way, see `explain_flags`.
     pub async fn get_match(
         &mut self,
         flag: &FeatureFlag,
         property_overrides: Option<HashMap<String, Value>>,
         hash_key_overrides: Option<HashMap<String, String>>,
+    ) -> Result<FeatureFlagMatch, FlagError> {
+        let mut trace = if self.flag_traces.is_some() {
+            Some(
+                self.start_flag_trace(flag, hash_key_overrides.clone())
+                    .await?,
+            )
+        } else {
+            None
+        };
+
+        let flag_match = self
+            .match_flag(flag, property_overrides, hash_key_overrides, &mut trace)
+            .await?;
+
+        if let (Some(mut trace), Some(flag_traces)) = (trace, self.flag_traces.as_mut()) {
+            trace
+                .conditions
+                .sort_by_key(|condition| condition.condition_index);
+            flag_traces.traces.insert(flag.key.clone(), trace);
+        }
+        Ok(flag_match)
+    }
+
+    /// Matches the flag for `get_match`, adding its conditions to the trace if there is one.
+    /// Conditions after the matching one are only evaluated for the trace.
+    async fn match_flag(
+        &mut self,
+        flag: &FeatureFlag,
+        property_overrides: Option<HashMap<String, Value>>,
+        hash_key_overrides: Option<HashMap<String, String>>,
+        trace: &mut Option<FlagEvaluationTrace>,
     ) -> Result<FeatureFlagMatch, FlagError> {
         if self
             .hashed_identifier(flag, hash_key_overrides.clone())
@@ -698,47 +1048,75 @@ impl FeatureFlagMatcher {
 
         let mut highest_match = FeatureFlagMatchReason::NoConditionMatch;
         let mut highest_index = None;
+        let compiled_flag = self.get_compiled_flag(flag);
 
         // Evaluate any super conditions first
-        if let Some(super_groups) = &flag.filters.super_groups {
-            if !super_groups.is_empty() {
-                let super_condition_evaluation = self
-                    .is_super_condition_match(
-                        flag,
-                        property_overrides.clone(),
-                        hash_key_overrides.clone(),
-                    )
-                    .await?;
+        if let Some(super_condition) = compiled_flag.super_conditions.first() {
+            let super_condition_evaluation = self
+                .is_super_condition_match(
+                    flag,
+                    super_condition,
+                    property_overrides.clone(),
+                    hash_key_overrides.clone(),
+                    trace,
+                )
+                .await?;
 
-                if super_condition_evaluation.should_evaluate {
-                    let payload = self.get_matching_payload(None, flag);
-                    return Ok(FeatureFlagMatch {
-                        matches: super_condition_evaluation.is_match,
-                        variant: None,
-                        reason: super_condition_evaluation.reason,
-                        condition_index: Some(0),
-                        payload,
-                    });
-                } // if no match, continue to normal conditions
-            }
+            if super_condition_evaluation.should_evaluate {
+                let payload = self.get_matching_payload(None, flag);
+                return Ok(FeatureFlagMatch {
+                    matches: super_condition_evaluation.is_match,
+                    variant: None,
+                    reason: super_condition_evaluation.reason,
+                    condition_index: Some(0),
+                    payload,
+                });
+            } // if no match, continue to normal conditions
         }
 
         // Sort conditions with variant overrides to the top so that we can evaluate them first
-        let mut sorted_conditions: Vec<(usize, &FlagGroupType)> =
-            flag.get_conditions().iter().enumerate().collect();
+        let mut sorted_conditions: Vec<(usize, &CompiledCondition)> =
+            compiled_flag.conditions.iter().enumerate().collect();
 
         sorted_conditions
             .sort_by_key(|(_, condition)| if condition.variant.is_some() { 0 } else { 1 });
 
+        let mut flag_match = None;
         for (index, condition) in sorted_conditions {
-            let (is_match, reason) = self
-                .is_condition_match(
-                    flag,
-                    condition,
-                    property_overrides.clone(),
-                    hash_key_overrides.clone(),
-                )
-                .await?;
+            let (is_match, reason) = match trace {
+                Some(trace) => {
+                    let from_overrides = locally_computable_property_overrides(
+                        &property_overrides,
+                        &condition.property_filters,
+                    )
+                    .is_some();
+                    let condition_trace = self
+                        .trace_condition(
+                            flag,
+                            index,
+                            condition,
+                            property_overrides.clone(),
+                            from_overrides,
+                            trace.rollout_hash,
+                        )
+                        .await?;
+                    let result = condition_trace_match(&condition_trace);
+                    trace.conditions.push(condition_trace);
+                    result
+                }
+                None => {
+                    self.is_condition_match(
+                        flag,
+                        condition,
+                        property_overrides.clone(),
+                        hash_key_overrides.clone(),
+                    )
+                    .await?
+                }
+            };
+            if flag_match.is_some() {
+                continue;
+            }
 
             // Update highest_match and highest_index
             let (new_highest_match, new_highest_index) = self
@@ -777,24 +1155,35 @@ impl FeatureFlagMatcher {
                 };
                 let payload = self.get_matching_payload(variant.as_deref(), flag);
 
-                return Ok(FeatureFlagMatch {
+                flag_match = Some(FeatureFlagMatch {
                     matches: true,
                     variant,
-                    reason: highest_match,
+                    reason: highest_match.clone(),
                     condition_index: highest_index,
                     payload,
                 });
+                if trace.is_none() {
+                    break;
+                }
             }
         }
 
         // Return with the highest_match reason and index even if no conditions matched
-        Ok(FeatureFlagMatch {
+        Ok(flag_match.unwrap_or(FeatureFlagMatch {
             matches: false,
             variant: None,
             reason: highest_match,
             condition_index: highest_index,
             payload: None,
-        })
+        }))
+    }
+
+    /// Returns the compiled conditions of the flag from the evaluation plan of the flag list,
+    /// or compiles them if the flag isn't part of the plan.
+    fn get_compiled_flag(&self, flag: &FeatureFlag) -> Arc<CompiledFlag> {
+        self.evaluation_plan
+            .get(flag)
+            .unwrap_or_else(|| Arc::new(CompiledFlag::new(flag)))
     }
 
     /// This function determines the highest priority match evaluation for feature flag conditions.
@@ -825,43 +1214,42 @@ impl FeatureFlagMatcher {
     async fn is_condition_match(
         &mut self,
         feature_flag: &FeatureFlag,
-        condition: &FlagGroupType,
+        condition: &CompiledCondition,
         property_overrides: Option<HashMap<String, Value>>,
         hash_key_overrides: Option<HashMap<String, String>>,
     ) -> Result<(bool, FeatureFlagMatchReason), FlagError> {
-        let rollout_percentage = condition.rollout_percentage.unwrap_or(100.0);
+        let rollout_percentage = condition.rollout_percentage;
 
-        if let Some(flag_property_filters) = &condition.properties {
-            if flag_property_filters.is_empty() {
-                return self
-                    .check_rollout(feature_flag, rollout_percentage, hash_key_overrides)
-                    .await;
+        if !condition.has_no_filters() {
+            // Check the filters on other flags first, since they have already been evaluated
+            if !self.all_flag_filters_match(&condition.flag_filters) {
+                return Ok((false, FeatureFlagMatchReason::NoConditionMatch));
             }
 
-            // Separate cohort and non-cohort filters
-            let (cohort_filters, non_cohort_filters): (Vec<PropertyFilter>, Vec<PropertyFilter>) =
-                flag_property_filters
-                    .iter()
-                    .cloned()
-                    .partition(|prop| prop.is_cohort());
-
             // Get the properties we need to check for in this condition match from the flag + any overrides
             let person_or_group_properties = self
-                .get_properties_to_check(feature_flag, property_overrides, &non_cohort_filters)
+                .get_properties_to_check(
+                    feature_flag,
+                    property_overrides,
+                    &condition.property_filters,
+                )
                 .await?;
 
             // Evaluate non-cohort filters first, since they're cheaper to evaluate and we can return early if they don't match
-            if !all_properties_match(&non_cohort_filters, &person_or_group_properties) {
+            if !all_properties_match(condition, &person_or_group_properties) {
                 return Ok((false, FeatureFlagMatchReason::NoConditionMatch));
             }
 
             // Evaluate cohort filters, if any.
-            if !cohort_filters.is_empty() {
+            if condition.has_invalid_cohort_filter {
+                return Err(FlagError::CohortFiltersParsingError);
+            }
+            if !condition.cohort_filters.is_empty() {
                 // Get the person ID for the current distinct ID – this value should be cached at this point, but as a fallback we fetch from the database
                 let person_id = self.get_person_id().await?;
                 if !self
                     .evaluate_cohort_filters(
-                        &cohort_filters,
+                        &condition.cohort_filters,
                         &person_or_group_properties,
                         person_id,
                     )
@@ -876,6 +1264,14 @@ impl FeatureFlagMatcher {
             .await
     }
 
+    /// Checks the filters on other flags against the results of those flags, which have been evaluated
+    /// earlier in the request since flags are evaluated in dependency order.
+    fn all_flag_filters_match(&self, flag_filters: &[PropertyFilter]) -> bool {
+        flag_filters.iter().all(|filter| {
+            flag_evaluates_to(self.flag_evaluation_results.get(&filter.key), &filter.value)
+        })
+    }
+
     /// Get properties to check for a feature flag.
     ///
     /// This function determines which properties to check based on the feature flag's group type index.
@@ -987,7 +1383,7 @@ impl FeatureFlagMatcher {
     /// hitting the database for each cohort filter.
     pub async fn evaluate_cohort_filters(
         &self,
-        cohort_property_filters: &[PropertyFilter],
+        cohort_filters: &[CohortMembershipFilter],
         target_properties: &HashMap<String, Value>,
         person_id: PersonId,
     ) -> Result<bool, FlagError> {
@@ -1017,18 +1413,18 @@ impl FeatureFlagMatcher {
         }
 
         if !dynamic_cohorts.is_empty() {
-            for filter in cohort_property_filters {
-                let cohort_id = filter
-                    .get_cohort_id()
-                    .ok_or(FlagError::CohortFiltersParsingError)?;
+            for filter in cohort_filters {
                 let match_result =
-                    evaluate_dynamic_cohorts(cohort_id, target_properties, &cohorts)?;
-                cohort_matches.insert(cohort_id, match_result);
+                    evaluate_dynamic_cohorts(filter.cohort_id, target_properties, &cohorts)?;
+                cohort_matches.insert(filter.cohort_id, match_result);
             }
         }
 
         // Apply cohort membership logic (IN|NOT_IN) to the cohort match results
-        apply_cohort_membership_logic(cohort_property_filters, &cohort_matches)
+        Ok(apply_cohort_membership_logic(
+            cohort_filters,
+            &cohort_matches,
+        ))
     }
 
     /// Check if a super condition matches for a feature flag.
@@ -1040,8 +1436,10 @@ impl FeatureFlagMatcher {
     async fn is_super_condition_match(
         &mut self,
         feature_flag: &FeatureFlag,
+        compiled_super_condition: &CompiledCondition,
         property_overrides: Option<HashMap<String, Value>>,
         hash_key_overrides: Option<HashMap<String, String>>,
+        trace: &mut Option<FlagEvaluationTrace>,
     ) -> Result<SuperConditionEvaluation, FlagError> {
         if let Some(first_condition) = feature_flag
             .filters
@@ -1051,11 +1449,11 @@ impl FeatureFlagMatcher {
         {
             // Need to fetch person properties to check super conditions.  If these properties are already locally computable,
             // we don't need to fetch from the database, but if they aren't we need to fetch from the database and then we'll cache them.
+            let properties = first_condition.properties.as_deref().unwrap_or(&[]);
+            let from_overrides = trace.is_some()
+                && locally_computable_property_overrides(&property_overrides, properties).is_some();
             let person_properties = self
-                .get_person_properties(
-                    property_overrides,
-                    first_condition.properties.as_deref().unwrap_or(&[]),
-                )
+                .get_person_properties(property_overrides, properties)
                 .await?;
 
             let has_relevant_super_condition_properties =
@@ -1065,14 +1463,33 @@ impl FeatureFlagMatcher {
                         .any(|prop| person_properties.contains_key(&prop.key))
                 });
 
-            let (is_match, _) = self
-                .is_condition_match(
-                    feature_flag,
-                    first_condition,
-                    Some(person_properties),
-                    hash_key_overrides,
-                )
-                .await?;
+            let is_match = match trace {
+                Some(trace) => {
+                    let condition_trace = self
+                        .trace_condition(
+                            feature_flag,
+                            0,
+                            compiled_super_condition,
+                            Some(person_properties),
+                            from_overrides,
+                            trace.rollout_hash,
+                        )
+                        .await?;
+                    let is_match = condition_trace.matches;
+                    trace.super_condition = Some(condition_trace);
+                    is_match
+                }
+                None => {
+                    self.is_condition_match(
+                        feature_flag,
+                        compiled_super_condition,
+                        Some(person_properties),
+                        hash_key_overrides,
+                    )
+                    .await?
+                    .0
+                }
+            };
 
             if has_relevant_super_condition_properties {
                 return Ok(SuperConditionEvaluation {
@@ -1312,7 +1729,7 @@ impl FeatureFlagMatcher {
         hash_key_overrides: Option<HashMap<String, String>>,
     ) -> Result<(bool, FeatureFlagMatchReason), FlagError> {
         let hash = self.get_hash(feature_flag, "", hash_key_overrides).await?;
-        if rollout_percentage == 100.0 || hash <= (rollout_percentage / 100.0) {
+        if is_in_rollout(hash, rollout_percentage) {
             Ok((true, FeatureFlagMatchReason::ConditionMatch))
         } else {
             Ok((false, FeatureFlagMatchReason::OutOfRolloutBound))
@@ -1353,6 +1770,35 @@ impl FeatureFlagMatcher {
     }
 }
 
+/// Whether a rollout hash is within the rollout percentage of a condition.
+fn is_in_rollout(hash: f64, rollout_percentage: f64) -> bool {
+    rollout_percentage == 100.0 || hash <= (rollout_percentage / 100.0)
+}
+
+/// Whether the traced condition matches, and the reason `is_condition_match` would give.
+fn condition_trace_match(condition_trace: &ConditionTrace) -> (bool, FeatureFlagMatchReason) {
+    match (condition_trace.filters_match, condition_trace.in_rollout) {
+        (true, true) => (true, FeatureFlagMatchReason::ConditionMatch),
+        (true, false) => (false, FeatureFlagMatchReason::OutOfRolloutBound),
+        (false, _) => (false, FeatureFlagMatchReason::NoConditionMatch),
+    }
+}
+
+/// Where the value of a property came from, given whether the properties of the condition were
+/// the overrides sent with the request.
+fn property_source(
+    key: &str,
+    from_overrides: bool,
+    geoip_property_keys: Option<&HashSet<String>>,
+) -> ValueSource {
+    let from_geoip = geoip_property_keys.is_some_and(|keys| keys.contains(key));
+    match (from_overrides, from_geoip) {
+        (true, true) => ValueSource::GeoIp,
+        (true, false) => ValueSource::Override,
+        (false, _) => ValueSource::Database,
+    }
+}
+
 /// Evaluate static cohort filters by checking if the person is in each cohort.
 async fn evaluate_static_cohorts(
     reader: PostgresReader,
@@ -1455,18 +1901,17 @@ fn evaluate_dynamic_cohorts(
 
 /// Apply cohort membership logic (i.e., IN|NOT_IN)
 fn apply_cohort_membership_logic(
-    cohort_filters: &[PropertyFilter],
+    cohort_filters: &[CohortMembershipFilter],
     cohort_matches: &HashMap<CohortId, bool>,
-) -> Result<bool, FlagError> {
+) -> bool {
     for filter in cohort_filters {
-        let cohort_id = filter
-            .get_cohort_id()
-            .ok_or(FlagError::CohortFiltersParsingError)?;
-        let matches = cohort_matches.get(&cohort_id).copied().unwrap_or(false);
-        let operator = filter.operator.unwrap_or(OperatorType::In);
+        let matches = cohort_matches
+            .get(&filter.cohort_id)
+            .copied()
+            .unwrap_or(false);
 
         // Combine the operator logic directly within this method
-        let membership_match = match operator {
+        let membership_match = match filter.operator {
             OperatorType::In => matches,
             OperatorType::NotIn => !matches,
             // Currently supported operators are IN and NOT IN
@@ -1476,11 +1921,11 @@ fn apply_cohort_membership_logic(
 
         // If any filter does not match, return false early
         if !membership_match {
-            return Ok(false);
+            return false;
         }
     }
     // All filters matched
-    Ok(true)
+    true
 }
 
 /// Constructs a dependency graph for cohorts.
@@ -1566,6 +2011,90 @@ fn build_cohort_dependency_graph(
     Ok(graph)
 }
 
+/// Returns the error of a flag depending on a flag which failed to evaluate. As for dependency
+/// cycles, the flag isn't matched: a failed flag has no result, which would count as disabled.
+fn failed_dependency(flag: &FeatureFlag, failed_flags: &HashSet<String>) -> Option<FlagError> {
+    flag.get_flag_dependencies()
+        .into_iter()
+        .find(|key| failed_flags.contains(key))
+        .map(|key| {
+            FlagError::FlagDependencyFailed(format!(
+                "Flag '{}' depends on flag '{}', which failed to evaluate",
+                flag.key, key
+            ))
+        })
+}
+
+/// Checks the result of a flag against the value of a flag filter: `true` or `false` for whether
+/// the flag is enabled, or the key of the variant the flag should evaluate to.
+/// Flags without a result (e.g. inactive or unknown flags) count as disabled.
+fn flag_evaluates_to(flag_match: Option<&FeatureFlagMatch>, value: &Value) -> bool {
+    let (enabled, variant) = match flag_match {
+        Some(flag_match) => (flag_match.matches, flag_match.variant.as_deref()),
+        None => (false, None),
+    };
+    match value {
+        Value::Bool(expected) => enabled == *expected,
+        Value::String(expected) if expected.eq_ignore_ascii_case("true") => enabled,
+        Value::String(expected) if expected.eq_ignore_ascii_case("false") => !enabled,
+        Value::String(expected) => enabled && variant == Some(expected.as_str()),
+        _ => false,
+    }
+}
+
+/// Constructs a dependency graph for flags, with an edge from each flag to the flags its
+/// conditions depend on. Dependencies on flags that aren't in the list are ignored.
+fn build_flag_dependency_graph(flags: &[FeatureFlag]) -> DiGraph<usize, ()> {
+    let mut graph = DiGraph::new();
+    let nodes: Vec<_> = (0..flags.len()).map(|i| graph.add_node(i)).collect();
+    let mut node_map = HashMap::new();
+    for (flag, node) in flags.iter().zip(&nodes) {
+        node_map.entry(flag.key.as_str()).or_insert(*node);
+    }
+
+    for (flag, node) in flags.iter().zip(&nodes) {
+        for dependency in flag.get_flag_dependencies() {
+            if let Some(dep_node) = node_map.get(dependency.as_str()) {
+                graph.add_edge(*node, *dep_node, ());
+            }
+        }
+    }
+
+    graph
+}
+
+/// Sorts the flags so that each flag comes after the flags it depends on.
+///
+/// Returns the sorted flags, and the flags that can't be evaluated because they're part of a
+/// dependency cycle, or depend on a flag that is.
+fn sort_flags_by_dependencies(flags: &[FeatureFlag]) -> (Vec<&FeatureFlag>, Vec<&FeatureFlag>) {
+    let graph = build_flag_dependency_graph(flags);
+    let mut sorted = Vec::new();
+    let mut cyclic = Vec::new();
+    let mut cyclic_nodes = HashSet::new();
+
+    // Tarjan's algorithm returns the strongly connected components in reverse topological order,
+    // so the dependencies come first. A component with more than one flag, or with a flag depending
+    // on itself, is a cycle.
+    for component in tarjan_scc(&graph) {
+        let is_cycle = component.len() > 1 || graph.contains_edge(component[0], component[0]);
+        for node in &component {
+            if is_cycle
+                || graph
+                    .neighbors(*node)
+                    .any(|dep_node| cyclic_nodes.contains(&dep_node))
+            {
+                cyclic_nodes.insert(*node);
+                cyclic.push(&flags[graph[*node]]);
+            } else {
+                sorted.push(&flags[graph[*node]]);
+            }
+        }
+    }
+
+    (sorted, cyclic)
+}
+
 /// Fetch and locally cache all properties for a given distinct ID and team ID.
 ///
 /// This function fetches both person and group properties for a specified distinct ID and team ID.
@@ -1769,12 +2298,17 @@ fn locally_computable_property_overrides(
 
 /// Check if all properties match the given filters
 fn all_properties_match(
-    flag_condition_properties: &[PropertyFilter],
+    condition: &CompiledCondition,
     matching_property_values: &HashMap<String, Value>,
 ) -> bool {
-    flag_condition_properties
+    condition
+        .property_filters
         .iter()
-        .all(|property| match_property(property, matching_property_values, false).unwrap_or(false))
+        .zip(&condition.property_operands)
+        .all(|(property, operand)| {
+            match_compiled_property(property, operand, matching_property_values, false)
+                .unwrap_or(false)
+        })
 }
 
 async fn get_feature_flag_hash_key_overrides(
@@ -2000,13 +2534,14 @@ mod tests {
     use super::*;
     use crate::{
         flags::flag_models::{
-            FeatureFlagRow, FlagFilters, MultivariateFlagOptions, MultivariateFlagVariant,
+            FeatureFlagRow, FlagFilters, FlagGroupType, MultivariateFlagOptions,
+            MultivariateFlagVariant,
         },
         properties::property_models::OperatorType,
         utils::test_utils::{
-            add_person_to_cohort, get_person_id_by_distinct_id, insert_cohort_for_team_in_pg,
-            insert_flag_for_team_in_pg, insert_new_team_in_pg, insert_person_for_team_in_pg,
-            setup_pg_reader_client, setup_pg_writer_client,
+            add_person_to_cohort, create_flag_from_json, get_person_id_by_distinct_id,
+            insert_cohort_for_team_in_pg, insert_flag_for_team_in_pg, insert_new_team_in_pg,
+            insert_person_for_team_in_pg, setup_pg_reader_client, setup_pg_writer_client,
         },
     };
 
@@ -2188,9 +2723,7 @@ mod tests {
             None,
         );
 
-        let flags = FeatureFlagList {
-            flags: vec![flag.clone()],
-        };
+        let flags = FeatureFlagList::new(vec![flag.clone()]);
         let result = matcher
             .evaluate_all_feature_flags(flags, Some(overrides), None, None)
             .await;
@@ -2264,9 +2797,7 @@ mod tests {
             Some(groups),
         );
 
-        let flags = FeatureFlagList {
-            flags: vec![flag.clone()],
-        };
+        let flags = FeatureFlagList::new(vec![flag.clone()]);
         let result = matcher
             .evaluate_all_feature_flags(flags, None, Some(group_overrides), None)
             .await;
@@ -2381,7 +2912,7 @@ mod tests {
             None,
         );
         let (is_match, reason) = matcher
-            .is_condition_match(&flag, &condition, None, None)
+            .is_condition_match(&flag, &CompiledCondition::new(&condition), None, None)
             .await
             .unwrap();
         assert!(is_match);
@@ -2481,9 +3012,7 @@ mod tests {
 
         let result = matcher
             .evaluate_all_feature_flags(
-                FeatureFlagList {
-                    flags: vec![flag.clone()],
-                },
+                FeatureFlagList::new(vec![flag.clone()]),
                 Some(person_property_overrides),
                 None,
                 None,
@@ -3290,7 +3819,12 @@ mod tests {
         );
 
         let (is_match, reason) = matcher
-            .is_condition_match(&flag, &flag.filters.groups[0], None, None)
+            .is_condition_match(
+                &flag,
+                &CompiledCondition::new(&flag.filters.groups[0]),
+                None,
+                None,
+            )
             .await
             .unwrap();
 
@@ -4740,9 +5274,7 @@ mod tests {
         .await
         .unwrap();
 
-        let flags = FeatureFlagList {
-            flags: vec![flag.clone()],
-        };
+        let flags = FeatureFlagList::new(vec![flag.clone()]);
 
         let result = FeatureFlagMatcher::new(
             distinct_id.clone(),
@@ -4815,9 +5347,7 @@ mod tests {
             Some(true),
         );
 
-        let flags = FeatureFlagList {
-            flags: vec![flag.clone()],
-        };
+        let flags = FeatureFlagList::new(vec![flag.clone()]);
 
         let result = FeatureFlagMatcher::new(
             distinct_id.clone(),
@@ -4932,9 +5462,7 @@ mod tests {
         .await
         .unwrap();
 
-        let flags = FeatureFlagList {
-            flags: vec![flag_continuity.clone(), flag_no_continuity.clone()],
-        };
+        let flags = FeatureFlagList::new(vec![flag_continuity.clone(), flag_no_continuity.clone()]);
 
         let result = FeatureFlagMatcher::new(
             distinct_id.clone(),
@@ -5106,4 +5634,314 @@ mod tests {
         assert!(result_invalid.matches);
         assert!(result_invalid.variant.is_some()); // Will be either "control" or "test" based on hash
     }
+
+    fn flag_with_dependencies(id: i32, key: &str, dependencies: &[(&str, Value)]) -> Value {
+        let properties: Vec<Value> = dependencies
+            .iter()
+            .map(|(dependency, value)| {
+                json!({"key": dependency, "type": "flag", "value": value, "operator": "flag_evaluates_to"})
+            })
+            .collect();
+        json!({
+            "id": id,
+            "key": key,
+            "team_id": 1,
+            "active": true,
+            "filters": {"groups": [{"properties": properties, "rollout_percentage": 100}]},
+        })
+    }
+
+    #[test]
+    fn test_sort_flags_by_dependencies() {
+        let flags = create_flag_from_json(Some(
+            json!([
+                flag_with_dependencies(1, "a", &[("b", json!(true))]),
+                flag_with_dependencies(2, "b", &[("c", json!(true)), ("unknown", json!(false))]),
+                flag_with_dependencies(3, "c", &[]),
+                flag_with_dependencies(4, "d", &[("e", json!(true))]),
+                flag_with_dependencies(5, "e", &[("d", json!(true))]),
+                flag_with_dependencies(6, "f", &[("d", json!(true)), ("c", json!(true))]),
+                flag_with_dependencies(7, "g", &[("g", json!(true))]),
+            ])
+            .to_string(),
+        ));
+
+        let (sorted, cyclic) = sort_flags_by_dependencies(&flags);
+        let sorted: Vec<&str> = sorted.iter().map(|f| f.key.as_str()).collect();
+        let position = |key: &str| sorted.iter().position(|k| *k == key).unwrap();
+        assert_eq!(sorted.len(), 3);
+        assert!(position("c") < position("b"));
+        assert!(position("b") < position("a"));
+
+        let mut cyclic: Vec<&str> = cyclic.iter().map(|f| f.key.as_str()).collect();
+        cyclic.sort();
+        assert_eq!(cyclic, vec!["d", "e", "f", "g"]);
+    }
+
+    #[test]
+    fn test_flag_evaluates_to() {
+        let flag_match = |matches: bool, variant: Option<&str>| FeatureFlagMatch {
+            matches,
+            variant: variant.map(|v| v.to_string()),
+            reason: FeatureFlagMatchReason::ConditionMatch,
+            condition_index: Some(0),
+            payload: None,
+        };
+
+        let enabled = flag_match(true, None);
+        assert!(flag_evaluates_to(Some(&enabled), &json!(true)));
+        assert!(flag_evaluates_to(Some(&enabled), &json!("true")));
+        assert!(!flag_evaluates_to(Some(&enabled), &json!(false)));
+
+        let disabled = flag_match(false, None);
+        assert!(flag_evaluates_to(Some(&disabled), &json!(false)));
+        assert!(!flag_evaluates_to(Some(&disabled), &json!(true)));
+        assert!(flag_evaluates_to(None, &json!(false)));
+        assert!(!flag_evaluates_to(None, &json!(true)));
+
+        let variant = flag_match(true, Some("control"));
+        assert!(flag_evaluates_to(Some(&variant), &json!("control")));
+        assert!(flag_evaluates_to(Some(&variant), &json!(true)));
+        assert!(!flag_evaluates_to(Some(&variant), &json!("test")));
+        assert!(!flag_evaluates_to(None, &json!("control")));
+    }
+
+    #[tokio::test]
+    async fn test_evaluate_flags_with_flag_dependencies() {
+        let reader = setup_pg_reader_client(None).await;
+        let writer = setup_pg_writer_client(None).await;
+        let cohort_cache = Arc::new(CohortCacheManager::new(reader.clone(), None, None));
+        let team = insert_new_team_in_pg(reader.clone(), None).await.unwrap();
+
+        let mut base_flag = flag_with_dependencies(1, "base", &[]);
+        base_flag["filters"]["groups"][0]["properties"] =
+            json!([{"key": "email", "type": "person", "value": "test@example.com"}]);
+        base_flag["filters"]["multivariate"] =
+            json!({"variants": [{"key": "control", "rollout_percentage": 100}]});
+        // Dependent flags come first, to check that they're evaluated after their dependencies
+        let flags = FeatureFlagList::new(create_flag_from_json(Some(
+            json!([
+                flag_with_dependencies(2, "on_base", &[("base", json!(true))]),
+                flag_with_dependencies(3, "on_base_variant", &[("base", json!("control"))]),
+                flag_with_dependencies(4, "on_base_other_variant", &[("base", json!("test"))]),
+                flag_with_dependencies(5, "on_base_disabled", &[("base", json!(false))]),
+                flag_with_dependencies(6, "on_on_base", &[("on_base", json!(true))]),
+                flag_with_dependencies(7, "cycle_a", &[("cycle_b", json!(true))]),
+                flag_with_dependencies(8, "cycle_b", &[("cycle_a", json!(true))]),
+                base_flag,
+            ])
+            .to_string(),
+        )));
+
+        let result = FeatureFlagMatcher::new(
+            "test_user".to_string(),
+            team.id,
+            team.project_id,
+            reader.clone(),
+            writer.clone(),
+            cohort_cache,
+            None,
+            None,
+        )
+        .evaluate_all_feature_flags(
+            flags,
+            Some(HashMap::from([(
+                "email".to_string(),
+                json!("test@example.com"),
+            )])),
+            None,
+            None,
+        )
+        .await;
+
+        assert!(result.errors_while_computing_flags);
+        assert_eq!(result.flags["base"].variant, "control");
+        assert!(result.flags["on_base"].enabled);
+        assert!(result.flags["on_base_variant"].enabled);
+        assert!(!result.flags["on_base_other_variant"].enabled);
+        assert!(!result.flags["on_base_disabled"].enabled);
+        assert!(result.flags["on_on_base"].enabled);
+        for key in ["cycle_a", "cycle_b"] {
+            assert!(!result.flags[key].enabled);
+            assert_eq!(result.flags[key].reason.code, "flag_dependency_cycle");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_evaluate_flags_depending_on_a_failed_flag() {
+        let reader = setup_pg_reader_client(None).await;
+        let writer = setup_pg_writer_client(None).await;
+        let cohort_cache = Arc::new(CohortCacheManager::new(reader.clone(), None, None));
+        let team = insert_new_team_in_pg(reader.clone(), None).await.unwrap();
+        insert_person_for_team_in_pg(reader.clone(), team.id, "test_user".to_string(), None)
+            .await
+            .unwrap();
+        // Matching the cohort fails, as its filters can't be parsed
+        let cohort = insert_cohort_for_team_in_pg(
+            reader.clone(),
+            team.id,
+            None,
+            json!({"properties": "invalid"}),
+            false,
+        )
+        .await
+        .unwrap();
+
+        let mut failing_flag = flag_with_dependencies(1, "failing", &[]);
+        failing_flag["filters"]["groups"][0]["properties"] =
+            json!([{"key": "id", "type": "cohort", "value": cohort.id}]);
+        let flags = FeatureFlagList::new(create_flag_from_json(Some(
+            json!([
+                flag_with_dependencies(2, "on_failing", &[("failing", json!(true))]),
+                flag_with_dependencies(3, "on_failing_disabled", &[("failing", json!(false))]),
+                flag_with_dependencies(4, "on_on_failing", &[("on_failing", json!(false))]),
+                failing_flag,
+            ])
+            .to_string(),
+        )));
+
+        let result = FeatureFlagMatcher::new(
+            "test_user".to_string(),
+            team.id,
+            team.project_id,
+            reader.clone(),
+            writer.clone(),
+            cohort_cache,
+            None,
+            None,
+        )
+        .evaluate_all_feature_flags(flags, None, None, None)
+        .await;
+
+        assert!(result.errors_while_computing_flags);
+        assert!(!result.flags["failing"].enabled);
+        for key in ["on_failing", "on_failing_disabled", "on_on_failing"] {
+            assert!(!result.flags[key].enabled);
+            assert_eq!(result.flags[key].reason.code, "flag_dependency_failed");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_explain_flags() {
+        let reader = setup_pg_reader_client(None).await;
+        let writer = setup_pg_writer_client(None).await;
+        let cohort_cache = Arc::new(CohortCacheManager::new(reader.clone(), None, None));
+        let team = insert_new_team_in_pg(reader.clone(), None).await.unwrap();
+        let distinct_id = "explained_user".to_string();
+        insert_person_for_team_in_pg(
+            reader.clone(),
+            team.id,
+            distinct_id.clone(),
+            Some(json!({"plan": "enterprise"})),
+        )
+        .await
+        .unwrap();
+
+        let flags = create_flag_from_json(Some(
+            json!([
+                {
+                    "id": 1,
+                    "key": "overrides_flag",
+                    "team_id": team.id,
+                    "active": true,
+                    "filters": {
+                        "groups": [
+                            {
+                                "properties": [
+                                    {"key": "email", "type": "person", "value": "@posthog.com", "operator": "icontains"},
+                                    {"key": "$geoip_country_code", "type": "person", "value": "US", "operator": "exact"},
+                                ],
+                                "rollout_percentage": 100,
+                            },
+                            {
+                                "properties": [
+                                    {"key": "email", "type": "person", "value": "other@example.com", "operator": "exact"},
+                                ],
+                                "rollout_percentage": 100,
+                            },
+                        ],
+                        "multivariate": {
+                            "variants": [
+                                {"key": "control", "rollout_percentage": 50},
+                                {"key": "test", "rollout_percentage": 50},
+                            ],
+                        },
+                    },
+                },
+                {
+                    "id": 2,
+                    "key": "database_flag",
+                    "team_id": team.id,
+                    "active": true,
+                    "filters": {
+                        "groups": [
+                            {
+                                "properties": [
+                                    {"key": "plan", "type": "person", "value": "enterprise", "operator": "exact"},
+                                    {"key": "overrides_flag", "type": "flag", "value": true, "operator": "flag_evaluates_to"},
+                                ],
+                                "rollout_percentage": 0,
+                            },
+                        ],
+                    },
+                },
+            ])
+            .to_string(),
+        ));
+
+        let mut matcher = FeatureFlagMatcher::new(
+            distinct_id,
+            team.id,
+            team.project_id,
+            reader.clone(),
+            writer.clone(),
+            cohort_cache.clone(),
+            None,
+            None,
+        );
+        let explanations = matcher
+            .explain_flags(
+                FeatureFlagList::new(flags),
+                Some(HashMap::from([
+                    ("email".to_string(), json!("user@posthog.com")),
+                    ("$geoip_country_code".to_string(), json!("US")),
+                ])),
+                None,
+                HashSet::from(["$geoip_country_code".to_string()]),
+            )
+            .await;
+
+        let explanation = &explanations["overrides_flag"];
+        assert!(explanation.details.enabled);
+        assert_eq!(explanation.details.reason.condition_index, Some(0));
+        let trace = explanation.trace.as_ref().unwrap();
+        assert_eq!(trace.hashed_identifier, "explained_user");
+        assert!(trace.variant_hash.is_some());
+        let condition = &trace.conditions[0];
+        assert!(condition.matches);
+        assert_eq!(
+            condition.filters[0].compared_value,
+            Some(json!("user@posthog.com"))
+        );
+        assert_eq!(condition.filters[0].source, Some(ValueSource::Override));
+        assert_eq!(condition.filters[1].source, Some(ValueSource::GeoIp));
+        // Every condition is traced, even after one matched
+        assert!(!trace.conditions[1].matches);
+        assert!(!trace.conditions[1].filters[0].matches);
+
+        // The overrides don't cover `plan`, so the flag is matched with the database properties
+        let explanation = &explanations["database_flag"];
+        assert!(!explanation.details.enabled);
+        assert_eq!(explanation.details.reason.code, "out_of_rollout_bound");
+        let condition = &explanation.trace.as_ref().unwrap().conditions[0];
+        assert!(condition.filters_match);
+        assert!(!condition.in_rollout);
+        assert_eq!(condition.filters[0].key, "overrides_flag");
+        assert_eq!(condition.filters[0].source, Some(ValueSource::Flag));
+        assert_eq!(
+            condition.filters[1].compared_value,
+            Some(json!("enterprise"))
+        );
+        assert_eq!(condition.filters[1].source, Some(ValueSource::Database));
+    }
 }
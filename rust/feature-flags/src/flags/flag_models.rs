@@ -1,6 +1,10 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
 use serde::{Deserialize, Serialize};
 
-use crate::properties::property_models::PropertyFilter;
+use crate::cohort::cohort_models::CohortId;
+use crate::properties::property_models::{CompiledOperand, OperatorType, PropertyFilter};
 
 // TRICKY: This cache data is coming from django-redis. If it ever goes out of sync, we'll bork.
 // TODO: Add integration tests across repos to ensure this doesn't happen.
@@ -70,10 +74,54 @@ pub struct FeatureFlagRow {
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct FeatureFlagList {
     pub flags: Vec<FeatureFlag>,
+    /// Compiled from the flags when the list is created. Flags missing from the plan
+    /// are compiled when they're evaluated.
+    #[serde(skip)]
+    pub plan: Arc<FlagEvaluationPlan>,
 }
 
 impl FeatureFlagList {
     pub fn new(flags: Vec<FeatureFlag>) -> Self {
-        Self { flags }
+        let plan = Arc::new(FlagEvaluationPlan::new(&flags));
+        Self { flags, plan }
     }
 }
+
+/// A cohort filter of a condition, reduced to the cohort and whether the person
+/// should (`in`) or shouldn't (`not_in`) be a member of it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CohortMembershipFilter {
+    pub cohort_id: CohortId,
+    pub operator: OperatorType,
+}
+
+/// A flag condition prepared for evaluation, with its filters split by kind
+/// and the operands of its property filters compiled
+#[derive(Debug, Clone)]
+pub struct CompiledCondition {
+    /// Filters on person or group properties
+    pub property_filters: Vec<PropertyFilter>,
+    /// Compiled operands of the property filters, in the same order
+    pub property_operands: Vec<CompiledOperand>,
+    pub cohort_filters: Vec<CohortMembershipFilter>,
+    /// Whether a cohort filter doesn't have a valid cohort id
+    pub has_invalid_cohort_filter: bool,
+    /// Filters on the values of other flags
+    pub flag_filters: Vec<PropertyFilter>,
+    pub rollout_percentage: f64,
+    pub variant: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct CompiledFlag {
+    pub id: i32,
+    pub key: String,
+    pub conditions: Vec<CompiledCondition>,
+    pub super_conditions: Vec<CompiledCondition>,
+}
+
+/// The compiled flags of a flag list, by flag id
+#[derive(Debug, Clone, Default)]
+pub struct FlagEvaluationPlan {
+    pub flags: HashMap<i32, Arc<CompiledFlag>>,
+}
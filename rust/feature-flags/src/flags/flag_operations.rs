@@ -2,8 +2,11 @@ use crate::api::errors::FlagError;
 use crate::client::database::Client as DatabaseClient;
 use crate::cohort::cohort_models::CohortId;
 use crate::flags::flag_models::*;
-use crate::properties::property_models::PropertyFilter;
+use crate::flags::flag_plan_cache::FlagEvaluationPlanCache;
+use crate::properties::property_models::{CompiledOperand, OperatorType, PropertyFilter};
+use crate::team::team_models::ProjectId;
 use common_redis::Client as RedisClient;
+use std::collections::HashSet;
 use std::sync::Arc;
 use tracing::instrument;
 
@@ -21,6 +24,11 @@ impl PropertyFilter {
         }
         self.value.as_i64().map(|id| id as CohortId)
     }
+
+    /// Returns true if the filter is on the value of another flag, whose key is the filter's key
+    pub fn is_flag(&self) -> bool {
+        self.prop_type == "flag"
+    }
 }
 
 impl FeatureFlag {
@@ -32,6 +40,18 @@ impl FeatureFlag {
         &self.filters.groups
     }
 
+    /// Returns the keys of the flags that this flag's conditions (including super conditions) depend on
+    pub fn get_flag_dependencies(&self) -> HashSet<String> {
+        self.filters
+            .groups
+            .iter()
+            .chain(self.filters.super_groups.iter().flatten())
+            .flat_map(|condition| condition.properties.iter().flatten())
+            .filter(|filter| filter.is_flag())
+            .map(|filter| filter.key.clone())
+            .collect()
+    }
+
     pub fn get_variants(&self) -> Vec<MultivariateFlagVariant> {
         self.filters
             .multivariate
@@ -48,12 +68,108 @@ impl FeatureFlag {
     }
 }
 
+impl CompiledCondition {
+    pub fn new(condition: &FlagGroupType) -> Self {
+        let mut compiled = CompiledCondition {
+            property_filters: vec![],
+            property_operands: vec![],
+            cohort_filters: vec![],
+            has_invalid_cohort_filter: false,
+            flag_filters: vec![],
+            rollout_percentage: condition.rollout_percentage.unwrap_or(100.0),
+            variant: condition.variant.clone(),
+        };
+
+        for filter in condition.properties.iter().flatten() {
+            if filter.is_cohort() {
+                match filter.get_cohort_id() {
+                    Some(cohort_id) => compiled.cohort_filters.push(CohortMembershipFilter {
+                        cohort_id,
+                        operator: filter.operator.unwrap_or(OperatorType::In),
+                    }),
+                    None => compiled.has_invalid_cohort_filter = true,
+                }
+            } else if filter.is_flag() {
+                compiled.flag_filters.push(filter.clone());
+            } else {
+                compiled
+                    .property_operands
+                    .push(CompiledOperand::compile(filter));
+                compiled.property_filters.push(filter.clone());
+            }
+        }
+
+        compiled
+    }
+
+    /// Returns true if the condition has no filters, and only depends on the rollout percentage
+    pub fn has_no_filters(&self) -> bool {
+        self.property_filters.is_empty()
+            && self.cohort_filters.is_empty()
+            && !self.has_invalid_cohort_filter
+            && self.flag_filters.is_empty()
+    }
+}
+
+impl CompiledFlag {
+    pub fn new(flag: &FeatureFlag) -> Self {
+        CompiledFlag {
+            id: flag.id,
+            key: flag.key.clone(),
+            conditions: flag
+                .get_conditions()
+                .iter()
+                .map(CompiledCondition::new)
+                .collect(),
+            super_conditions: flag
+                .filters
+                .super_groups
+                .iter()
+                .flatten()
+                .map(CompiledCondition::new)
+                .collect(),
+        }
+    }
+}
+
+impl FlagEvaluationPlan {
+    pub fn new(flags: &[FeatureFlag]) -> Self {
+        FlagEvaluationPlan {
+            flags: flags
+                .iter()
+                .map(|flag| (flag.id, Arc::new(CompiledFlag::new(flag))))
+                .collect(),
+        }
+    }
+
+    /// Returns the compiled flag, if the plan has one for this flag
+    pub fn get(&self, flag: &FeatureFlag) -> Option<Arc<CompiledFlag>> {
+        self.flags
+            .get(&flag.id)
+            .filter(|compiled| compiled.key == flag.key)
+            .cloned()
+    }
+}
+
 impl FeatureFlagList {
+    /// Creates the list with the cached plan of the project's flags if they're unchanged,
+    /// `payload` being the flags serialized
+    fn with_cached_plan(
+        project_id: ProjectId,
+        flags: Vec<FeatureFlag>,
+        payload: &[u8],
+        plan_cache: &FlagEvaluationPlanCache,
+    ) -> Self {
+        let plan = plan_cache.get_or_compile(project_id, &flags, payload);
+        Self { flags, plan }
+    }
+
     /// Returns feature flags from redis given a team_id
     #[instrument(skip_all)]
     pub async fn from_redis(
         client: Arc<dyn RedisClient + Send + Sync>,
         project_id: i64,
+        plan_cache: &FlagEvaluationPlanCache,
     ) -> Result<FeatureFlagList, FlagError> {
         let serialized_flags = client
             .get(format!("{TEAM_FLAGS_CACHE_PREFIX}{}", project_id))
@@ -67,7 +183,12 @@ impl FeatureFlagList {
                 FlagError::RedisDataParsingError
             })?;
 
-        Ok(FeatureFlagList { flags: flags_list })
+        Ok(FeatureFlagList::with_cached_plan(
+            project_id,
+            flags_list,
+            serialized_flags.as_bytes(),
+            plan_cache,
+        ))
     }
 
     /// Returns feature flags from postgres given a team_id
@@ -75,6 +196,7 @@ impl FeatureFlagList {
     pub async fn from_pg(
         client: Arc<dyn DatabaseClient + Send + Sync>,
         project_id: i64,
+        plan_cache: &FlagEvaluationPlanCache,
     ) -> Result<FeatureFlagList, FlagError> {
         let mut conn = client.get_connection().await.map_err(|e| {
             tracing::error!("Failed to get database connection: {}", e);
@@ -127,7 +249,13 @@ impl FeatureFlagList {
             })
             .collect::<Result<Vec<FeatureFlag>, FlagError>>()?;
 
-        Ok(FeatureFlagList { flags: flags_list })
+        let payload = serde_json::to_vec(&flags_list).map_err(|e| {
+            tracing::error!("Failed to serialize flags: {}", e);
+            FlagError::RedisDataParsingError
+        })?;
+        Ok(FeatureFlagList::with_cached_plan(
+            project_id, flags_list, &payload, plan_cache,
+        ))
     }
 
     pub async fn update_flags_in_redis(
@@ -180,9 +308,13 @@ mod tests {
             .await
             .expect("Failed to insert flags");
 
-        let flags_from_redis = FeatureFlagList::from_redis(redis_client.clone(), team.project_id)
-            .await
-            .expect("Failed to fetch flags from redis");
+        let flags_from_redis = FeatureFlagList::from_redis(
+            redis_client.clone(),
+            team.project_id,
+            &FlagEvaluationPlanCache::default(),
+        )
+        .await
+        .expect("Failed to fetch flags from redis");
         assert_eq!(flags_from_redis.flags.len(), 1);
         let flag = flags_from_redis
             .flags
@@ -205,7 +337,13 @@ mod tests {
     async fn test_fetch_invalid_team_from_redis() {
         let redis_client = setup_redis_client(None);
 
-        match FeatureFlagList::from_redis(redis_client.clone(), 1234).await {
+        match FeatureFlagList::from_redis(
+            redis_client.clone(),
+            1234,
+            &FlagEvaluationPlanCache::default(),
+        )
+        .await
+        {
             Err(FlagError::TokenValidationError) => (),
             _ => panic!("Expected TokenValidationError"),
         };
@@ -215,7 +353,9 @@ mod tests {
     async fn test_cant_connect_to_redis_error_is_not_token_validation_error() {
         let client = setup_redis_client(Some("redis://localhost:1111/".to_string()));
 
-        match FeatureFlagList::from_redis(client.clone(), 1234).await {
+        match FeatureFlagList::from_redis(client.clone(), 1234, &FlagEvaluationPlanCache::default())
+            .await
+        {
             Err(FlagError::RedisUnavailable) => (),
             _ => panic!("Expected RedisUnavailable"),
         };
@@ -233,9 +373,13 @@ mod tests {
             .await
             .expect("Failed to insert flags");
 
-        let flags_from_pg = FeatureFlagList::from_pg(reader.clone(), team.project_id)
-            .await
-            .expect("Failed to fetch flags from pg");
+        let flags_from_pg = FeatureFlagList::from_pg(
+            reader.clone(),
+            team.project_id,
+            &FlagEvaluationPlanCache::default(),
+        )
+        .await
+        .expect("Failed to fetch flags from pg");
 
         assert_eq!(flags_from_pg.flags.len(), 1);
         let flag = flags_from_pg.flags.first().expect("Flags should be in pg");
@@ -364,9 +508,10 @@ mod tests {
     async fn test_fetch_empty_team_from_pg() {
         let reader = setup_pg_reader_client(None).await;
 
-        let FeatureFlagList { flags } = FeatureFlagList::from_pg(reader.clone(), 1234)
-            .await
-            .expect("Failed to fetch flags from pg");
+        let FeatureFlagList { flags, .. } =
+            FeatureFlagList::from_pg(reader.clone(), 1234, &FlagEvaluationPlanCache::default())
+                .await
+                .expect("Failed to fetch flags from pg");
         {
             assert_eq!(flags.len(), 0);
         }
@@ -376,7 +521,9 @@ mod tests {
     async fn test_fetch_nonexistent_team_from_pg() {
         let reader = setup_pg_reader_client(None).await;
 
-        match FeatureFlagList::from_pg(reader.clone(), -1).await {
+        match FeatureFlagList::from_pg(reader.clone(), -1, &FlagEvaluationPlanCache::default())
+            .await
+        {
             Ok(flags) => assert_eq!(flags.flags.len(), 0),
             Err(err) => panic!("Expected empty result, got error: {:?}", err),
         }
@@ -387,7 +534,8 @@ mod tests {
         // Simulate a database connection failure by using an invalid client setup
         let invalid_client = setup_invalid_pg_client().await;
 
-        match FeatureFlagList::from_pg(invalid_client, 1).await {
+        match FeatureFlagList::from_pg(invalid_client, 1, &FlagEvaluationPlanCache::default()).await
+        {
             Err(FlagError::DatabaseUnavailable) => (),
             other => panic!("Expected DatabaseUnavailable error, got: {:?}", other),
         }
@@ -437,9 +585,13 @@ mod tests {
             .await
             .expect("Failed to insert flags");
 
-        let flags_from_pg = FeatureFlagList::from_pg(reader.clone(), team.project_id)
-            .await
-            .expect("Failed to fetch flags from pg");
+        let flags_from_pg = FeatureFlagList::from_pg(
+            reader.clone(),
+            team.project_id,
+            &FlagEvaluationPlanCache::default(),
+        )
+        .await
+        .expect("Failed to fetch flags from pg");
 
         assert_eq!(flags_from_pg.flags.len(), 2);
         for flag in &flags_from_pg.flags {
@@ -465,6 +617,16 @@ mod tests {
             ("is_date_exact", OperatorType::IsDateExact),
             ("is_date_after", OperatorType::IsDateAfter),
             ("is_date_before", OperatorType::IsDateBefore),
+            ("in", OperatorType::In),
+            ("not_in", OperatorType::NotIn),
+            ("flag_evaluates_to", OperatorType::FlagEvaluatesTo),
+            ("semver_eq", OperatorType::SemverEq),
+            ("semver_gt", OperatorType::SemverGt),
+            ("semver_gte", OperatorType::SemverGte),
+            ("semver_lt", OperatorType::SemverLt),
+            ("semver_lte", OperatorType::SemverLte),
+            ("semver_tilde", OperatorType::SemverTilde),
+            ("semver_caret", OperatorType::SemverCaret),
         ];
 
         for (op_str, op_type) in operators {
@@ -482,6 +644,119 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_compile_flag_evaluation_plan() {
+        let flag: FeatureFlag = serde_json::from_value(json!({
+            "id": 1,
+            "team_id": 1,
+            "key": "compiled_flag",
+            "filters": {
+                "groups": [
+                    {
+                        "properties": [
+                            {"key": "email", "type": "person", "value": ".*@posthog.com", "operator": "regex"},
+                            {"key": "id", "type": "cohort", "value": 7, "operator": "not_in"},
+                            {"key": "id", "type": "cohort", "value": "invalid"},
+                            {"key": "other_flag", "type": "flag", "value": true, "operator": "flag_evaluates_to"},
+                        ],
+                        "rollout_percentage": 50,
+                    },
+                    {"properties": [], "variant": "test"},
+                ],
+                "super_groups": [{"properties": [], "rollout_percentage": 100}],
+            },
+        }))
+        .unwrap();
+
+        let list = FeatureFlagList::new(vec![flag.clone()]);
+        let compiled = list.plan.get(&flag).expect("flag should be compiled");
+        assert_eq!(compiled.conditions.len(), 2);
+        assert_eq!(compiled.super_conditions.len(), 1);
+
+        let condition = &compiled.conditions[0];
+        assert_eq!(condition.property_filters.len(), 1);
+        assert!(matches!(
+            condition.property_operands[0],
+            CompiledOperand::Regex(Some(_))
+        ));
+        assert_eq!(
+            condition.cohort_filters,
+            vec![CohortMembershipFilter {
+                cohort_id: 7,
+                operator: OperatorType::NotIn,
+            }]
+        );
+        assert!(condition.has_invalid_cohort_filter);
+        assert_eq!(condition.flag_filters[0].key, "other_flag");
+        assert_eq!(condition.rollout_percentage, 50.0);
+        assert!(!condition.has_no_filters());
+
+        let condition = &compiled.conditions[1];
+        assert!(condition.has_no_filters());
+        assert_eq!(condition.rollout_percentage, 100.0);
+        assert_eq!(condition.variant.as_deref(), Some("test"));
+
+        // Flags which aren't part of the plan are compiled when evaluated
+        let mut other_flag = flag.clone();
+        other_flag.key = "other_flag".to_string();
+        assert!(list.plan.get(&other_flag).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_reuses_evaluation_plan_of_unchanged_flags() {
+        let redis_client = setup_redis_client(None);
+        let plan_cache = FlagEvaluationPlanCache::default();
+        let team = insert_new_team_in_redis(redis_client.clone())
+            .await
+            .expect("Failed to insert team");
+        insert_flags_for_team_in_redis(redis_client.clone(), team.id, team.project_id, None)
+            .await
+            .expect("Failed to insert flags");
+
+        let first = FeatureFlagList::from_redis(redis_client.clone(), team.project_id, &plan_cache)
+            .await
+            .expect("Failed to fetch flags from redis");
+        let second =
+            FeatureFlagList::from_redis(redis_client.clone(), team.project_id, &plan_cache)
+                .await
+                .expect("Failed to fetch flags from redis");
+        // Same plan, so the same compiled regexes and operands
+        assert!(Arc::ptr_eq(&first.plan, &second.plan));
+
+        let mut flag = first.flags[0].clone();
+        flag.key = "updated_flag".to_string();
+        insert_flags_for_team_in_redis(
+            redis_client.clone(),
+            team.id,
+            team.project_id,
+            Some(serde_json::to_string(&vec![flag.clone()]).unwrap()),
+        )
+        .await
+        .expect("Failed to insert flags");
+
+        let updated =
+            FeatureFlagList::from_redis(redis_client.clone(), team.project_id, &plan_cache)
+                .await
+                .expect("Failed to fetch flags from redis");
+        assert!(!Arc::ptr_eq(&first.plan, &updated.plan));
+        assert!(updated.plan.get(&flag).is_some());
+
+        let reader = setup_pg_reader_client(None).await;
+        let team = insert_new_team_in_pg(reader.clone(), None)
+            .await
+            .expect("Failed to insert team in pg");
+        insert_flag_for_team_in_pg(reader.clone(), team.id, None)
+            .await
+            .expect("Failed to insert flags");
+        let first = FeatureFlagList::from_pg(reader.clone(), team.project_id, &plan_cache)
+            .await
+            .expect("Failed to fetch flags from pg");
+        let second = FeatureFlagList::from_pg(reader.clone(), team.project_id, &plan_cache)
+            .await
+            .expect("Failed to fetch flags from pg");
+        assert!(Arc::ptr_eq(&first.plan, &second.plan));
+    }
+
     #[tokio::test]
     async fn test_multivariate_flag_parsing() {
         let redis_client = setup_redis_client(None);
@@ -557,9 +832,13 @@ mod tests {
         .expect("Failed to insert flag in Postgres");
 
         // Fetch and verify from Redis
-        let redis_flags = FeatureFlagList::from_redis(redis_client, team.project_id)
-            .await
-            .expect("Failed to fetch flags from Redis");
+        let redis_flags = FeatureFlagList::from_redis(
+            redis_client,
+            team.project_id,
+            &FlagEvaluationPlanCache::default(),
+        )
+        .await
+        .expect("Failed to fetch flags from Redis");
 
         assert_eq!(redis_flags.flags.len(), 1);
         let redis_flag = &redis_flags.flags[0];
@@ -567,9 +846,10 @@ mod tests {
         assert_eq!(redis_flag.get_variants().len(), 3);
 
         // Fetch and verify from Postgres
-        let pg_flags = FeatureFlagList::from_pg(reader, team.project_id)
-            .await
-            .expect("Failed to fetch flags from Postgres");
+        let pg_flags =
+            FeatureFlagList::from_pg(reader, team.project_id, &FlagEvaluationPlanCache::default())
+                .await
+                .expect("Failed to fetch flags from Postgres");
 
         assert_eq!(pg_flags.flags.len(), 1);
         let pg_flag = &pg_flags.flags[0];
@@ -657,18 +937,23 @@ mod tests {
         .expect("Failed to insert flag in Postgres");
 
         // Fetch and verify from Redis
-        let redis_flags = FeatureFlagList::from_redis(redis_client, team.project_id)
-            .await
-            .expect("Failed to fetch flags from Redis");
+        let redis_flags = FeatureFlagList::from_redis(
+            redis_client,
+            team.project_id,
+            &FlagEvaluationPlanCache::default(),
+        )
+        .await
+        .expect("Failed to fetch flags from Redis");
 
         assert_eq!(redis_flags.flags.len(), 1);
         let redis_flag = &redis_flags.flags[0];
         assert_eq!(redis_flag.key, "multivariate_flag_with_payloads");
 
         // Fetch and verify from Postgres
-        let pg_flags = FeatureFlagList::from_pg(reader, team.project_id)
-            .await
-            .expect("Failed to fetch flags from Postgres");
+        let pg_flags =
+            FeatureFlagList::from_pg(reader, team.project_id, &FlagEvaluationPlanCache::default())
+                .await
+                .expect("Failed to fetch flags from Postgres");
 
         assert_eq!(pg_flags.flags.len(), 1);
         let pg_flag = &pg_flags.flags[0];
@@ -791,9 +1076,13 @@ mod tests {
         .expect("Failed to insert flag in Postgres");
 
         // Fetch and verify from Redis
-        let redis_flags = FeatureFlagList::from_redis(redis_client, team.project_id)
-            .await
-            .expect("Failed to fetch flags from Redis");
+        let redis_flags = FeatureFlagList::from_redis(
+            redis_client,
+            team.project_id,
+            &FlagEvaluationPlanCache::default(),
+        )
+        .await
+        .expect("Failed to fetch flags from Redis");
 
         assert_eq!(redis_flags.flags.len(), 1);
         let redis_flag = &redis_flags.flags[0];
@@ -802,9 +1091,10 @@ mod tests {
         assert_eq!(redis_flag.filters.super_groups.as_ref().unwrap().len(), 1);
 
         // Fetch and verify from Postgres
-        let pg_flags = FeatureFlagList::from_pg(reader, team.project_id)
-            .await
-            .expect("Failed to fetch flags from Postgres");
+        let pg_flags =
+            FeatureFlagList::from_pg(reader, team.project_id, &FlagEvaluationPlanCache::default())
+                .await
+                .expect("Failed to fetch flags from Postgres");
 
         assert_eq!(pg_flags.flags.len(), 1);
         let pg_flag = &pg_flags.flags[0];
@@ -888,9 +1178,13 @@ mod tests {
         .expect("Failed to insert flag in Postgres");
 
         // Fetch and verify from Redis
-        let redis_flags = FeatureFlagList::from_redis(redis_client, team.project_id)
-            .await
-            .expect("Failed to fetch flags from Redis");
+        let redis_flags = FeatureFlagList::from_redis(
+            redis_client,
+            team.project_id,
+            &FlagEvaluationPlanCache::default(),
+        )
+        .await
+        .expect("Failed to fetch flags from Redis");
 
         assert_eq!(redis_flags.flags.len(), 1);
         let redis_flag = &redis_flags.flags[0];
@@ -902,9 +1196,10 @@ mod tests {
         assert_eq!(redis_properties[2].prop_type, "event");
 
         // Fetch and verify from Postgres
-        let pg_flags = FeatureFlagList::from_pg(reader, team.project_id)
-            .await
-            .expect("Failed to fetch flags from Postgres");
+        let pg_flags =
+            FeatureFlagList::from_pg(reader, team.project_id, &FlagEvaluationPlanCache::default())
+                .await
+                .expect("Failed to fetch flags from Postgres");
 
         assert_eq!(pg_flags.flags.len(), 1);
         let pg_flag = &pg_flags.flags[0];
@@ -993,9 +1288,13 @@ mod tests {
         .expect("Failed to insert inactive flag in Postgres");
 
         // Fetch and verify from Redis
-        let redis_flags = FeatureFlagList::from_redis(redis_client, team.project_id)
-            .await
-            .expect("Failed to fetch flags from Redis");
+        let redis_flags = FeatureFlagList::from_redis(
+            redis_client,
+            team.project_id,
+            &FlagEvaluationPlanCache::default(),
+        )
+        .await
+        .expect("Failed to fetch flags from Redis");
 
         assert_eq!(redis_flags.flags.len(), 2);
         assert!(redis_flags.flags.iter().any(|f| f.deleted));
@@ -1005,9 +1304,10 @@ mod tests {
             .any(|f| f.key == "inactive_flag" && !f.active));
 
         // Fetch and verify from Postgres
-        let pg_flags = FeatureFlagList::from_pg(reader, team.project_id)
-            .await
-            .expect("Failed to fetch flags from Postgres");
+        let pg_flags =
+            FeatureFlagList::from_pg(reader, team.project_id, &FlagEvaluationPlanCache::default())
+                .await
+                .expect("Failed to fetch flags from Postgres");
 
         assert_eq!(pg_flags.flags.len(), 1);
         assert!(!pg_flags.flags.iter().any(|f| f.deleted)); // no deleted flags
@@ -1024,7 +1324,9 @@ mod tests {
 
         // Test Redis connection error
         let bad_redis_client = setup_redis_client(Some("redis://localhost:1111/".to_string()));
-        let result = FeatureFlagList::from_redis(bad_redis_client, 1).await;
+        let result =
+            FeatureFlagList::from_redis(bad_redis_client, 1, &FlagEvaluationPlanCache::default())
+                .await;
         assert!(matches!(result, Err(FlagError::RedisUnavailable)));
 
         // Test malformed JSON in Redis
@@ -1040,7 +1342,12 @@ mod tests {
             .await
             .expect("Failed to set malformed JSON in Redis");
 
-        let result = FeatureFlagList::from_redis(redis_client, team.project_id).await;
+        let result = FeatureFlagList::from_redis(
+            redis_client,
+            team.project_id,
+            &FlagEvaluationPlanCache::default(),
+        )
+        .await;
         assert!(matches!(result, Err(FlagError::RedisDataParsingError)));
 
         // Test database query error (using a non-existent table)
@@ -1103,10 +1410,20 @@ mod tests {
             let project_id = team.project_id;
 
             let handle = task::spawn(async move {
-                let redis_flags = FeatureFlagList::from_redis(redis_client, project_id)
-                    .await
-                    .unwrap();
-                let pg_flags = FeatureFlagList::from_pg(reader, project_id).await.unwrap();
+                let redis_flags = FeatureFlagList::from_redis(
+                    redis_client,
+                    project_id,
+                    &FlagEvaluationPlanCache::default(),
+                )
+                .await
+                .unwrap();
+                let pg_flags = FeatureFlagList::from_pg(
+                    reader,
+                    project_id,
+                    &FlagEvaluationPlanCache::default(),
+                )
+                .await
+                .unwrap();
                 (redis_flags, pg_flags)
             });
 
@@ -1178,15 +1495,20 @@ mod tests {
         }
 
         let start = Instant::now();
-        let redis_flags = FeatureFlagList::from_redis(redis_client, team.project_id)
-            .await
-            .expect("Failed to fetch flags from Redis");
+        let redis_flags = FeatureFlagList::from_redis(
+            redis_client,
+            team.project_id,
+            &FlagEvaluationPlanCache::default(),
+        )
+        .await
+        .expect("Failed to fetch flags from Redis");
         let redis_duration = start.elapsed();
 
         let start = Instant::now();
-        let pg_flags = FeatureFlagList::from_pg(reader, team.project_id)
-            .await
-            .expect("Failed to fetch flags from Postgres");
+        let pg_flags =
+            FeatureFlagList::from_pg(reader, team.project_id, &FlagEvaluationPlanCache::default())
+                .await
+                .expect("Failed to fetch flags from Postgres");
         let pg_duration = start.elapsed();
 
         println!("Redis fetch time: {:?}", redis_duration);
@@ -1269,12 +1591,17 @@ mod tests {
         }
 
         // Fetch and verify edge case flags
-        let redis_flags = FeatureFlagList::from_redis(redis_client, team.project_id)
-            .await
-            .expect("Failed to fetch flags from Redis");
-        let pg_flags = FeatureFlagList::from_pg(reader, team.project_id)
-            .await
-            .expect("Failed to fetch flags from Postgres");
+        let redis_flags = FeatureFlagList::from_redis(
+            redis_client,
+            team.project_id,
+            &FlagEvaluationPlanCache::default(),
+        )
+        .await
+        .expect("Failed to fetch flags from Redis");
+        let pg_flags =
+            FeatureFlagList::from_pg(reader, team.project_id, &FlagEvaluationPlanCache::default())
+                .await
+                .expect("Failed to fetch flags from Postgres");
 
         assert_eq!(redis_flags.flags.len(), 3);
         assert_eq!(pg_flags.flags.len(), 3);
@@ -1355,12 +1682,17 @@ mod tests {
         }
 
         // Fetch flags from both sources
-        let mut redis_flags = FeatureFlagList::from_redis(redis_client, team.project_id)
-            .await
-            .expect("Failed to fetch flags from Redis");
-        let mut pg_flags = FeatureFlagList::from_pg(reader, team.project_id)
-            .await
-            .expect("Failed to fetch flags from Postgres");
+        let mut redis_flags = FeatureFlagList::from_redis(
+            redis_client,
+            team.project_id,
+            &FlagEvaluationPlanCache::default(),
+        )
+        .await
+        .expect("Failed to fetch flags from Redis");
+        let mut pg_flags =
+            FeatureFlagList::from_pg(reader, team.project_id, &FlagEvaluationPlanCache::default())
+                .await
+                .expect("Failed to fetch flags from Postgres");
 
         // Sort flags by key to ensure consistent order
         redis_flags.flags.sort_by(|a, b| a.key.cmp(&b.key));
@@ -1469,12 +1801,17 @@ mod tests {
         }
 
         // Fetch flags from both sources
-        let redis_flags = FeatureFlagList::from_redis(redis_client, team.project_id)
-            .await
-            .expect("Failed to fetch flags from Redis");
-        let pg_flags = FeatureFlagList::from_pg(reader, team.project_id)
-            .await
-            .expect("Failed to fetch flags from Postgres");
+        let redis_flags = FeatureFlagList::from_redis(
+            redis_client,
+            team.project_id,
+            &FlagEvaluationPlanCache::default(),
+        )
+        .await
+        .expect("Failed to fetch flags from Redis");
+        let pg_flags =
+            FeatureFlagList::from_pg(reader, team.project_id, &FlagEvaluationPlanCache::default())
+                .await
+                .expect("Failed to fetch flags from Postgres");
 
         // Verify rollout percentages
         for flags in &[redis_flags, pg_flags] {
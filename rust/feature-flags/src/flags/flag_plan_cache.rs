@@ -0,0 +1,46 @@
+use crate::flags::flag_models::{FeatureFlag, FlagEvaluationPlan};
+use crate::team::team_models::ProjectId;
+use moka::sync::Cache;
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// FlagEvaluationPlanCache keeps the plans compiled for the flag lists recently loaded, so that
+/// regexes aren't compiled again on every request.
+///
+/// Plans are keyed on the project and the SHA-256 of the serialized flags, so a changed flag list
+/// gets a new plan. Entries are weighted by the number of flags, and the plans of outdated lists
+/// expire once they're no longer read.
+#[derive(Clone)]
+pub struct FlagEvaluationPlanCache {
+    cache: Cache<(ProjectId, [u8; 32]), Arc<FlagEvaluationPlan>>,
+}
+
+impl Default for FlagEvaluationPlanCache {
+    fn default() -> Self {
+        let cache = Cache::builder()
+            .weigher(|_, plan: &Arc<FlagEvaluationPlan>| {
+                u32::try_from(plan.flags.len()).unwrap_or(u32::MAX).max(1)
+            })
+            .max_capacity(100_000) // Up to 100,000 flags
+            .time_to_idle(Duration::from_secs(600))
+            .build();
+
+        Self { cache }
+    }
+}
+
+impl FlagEvaluationPlanCache {
+    /// Returns the cached plan of the project's flags if they're unchanged, or compiles it,
+    /// `payload` being the flags serialized
+    pub fn get_or_compile(
+        &self,
+        project_id: ProjectId,
+        flags: &[FeatureFlag],
+        payload: &[u8],
+    ) -> Arc<FlagEvaluationPlan> {
+        let key = (project_id, Sha256::digest(payload).into());
+        self.cache
+            .get_with(key, || Arc::new(FlagEvaluationPlan::new(flags)))
+    }
+}
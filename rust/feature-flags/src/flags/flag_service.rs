@@ -1,7 +1,7 @@
 use crate::{
     api::errors::FlagError,
     client::database::Client as DatabaseClient,
-    flags::flag_models::FeatureFlagList,
+    flags::{flag_models::FeatureFlagList, flag_plan_cache::FlagEvaluationPlanCache},
     metrics::metrics_consts::{
         DB_FLAG_READS_COUNTER, DB_TEAM_READS_COUNTER, FLAG_CACHE_ERRORS_COUNTER,
         FLAG_CACHE_HIT_COUNTER, TEAM_CACHE_ERRORS_COUNTER, TEAM_CACHE_HIT_COUNTER,
@@ -128,15 +128,21 @@ impl FlagService {
     /// Fetches the flags from the cache or the database. Returns a tuple containing
     /// the flags and a boolean indicating whether the flags came from cache.  Also, it
     /// tracks cache hits and misses for a given project_id.
+    /// The evaluation plan of the flags is taken from `plan_cache` if they're unchanged.
     pub async fn get_flags_from_cache_or_pg(
         &self,
         project_id: i64,
+        plan_cache: &FlagEvaluationPlanCache,
     ) -> Result<FeatureFlagList, FlagError> {
         let (flags_result, cache_hit) =
-            match FeatureFlagList::from_redis(self.redis_client.clone(), project_id).await {
+            match FeatureFlagList::from_redis(self.redis_client.clone(), project_id, plan_cache)
+                .await
+            {
                 Ok(flags) => (Ok(flags), true),
                 Err(_) => {
-                    match FeatureFlagList::from_pg(self.pg_client.clone(), project_id).await {
+                    match FeatureFlagList::from_pg(self.pg_client.clone(), project_id, plan_cache)
+                        .await
+                    {
                         Ok(flags) => {
                             inc(
                                 DB_FLAG_READS_COUNTER,
@@ -236,83 +242,81 @@ mod tests {
             .expect("Failed to insert new team in Redis");
 
         // Insert some mock flags into Redis
-        let mock_flags = FeatureFlagList {
-            flags: vec![
-                FeatureFlag {
-                    id: 1,
-                    team_id: team.id,
-                    name: Some("Beta Feature".to_string()),
-                    key: "beta_feature".to_string(),
-                    filters: FlagFilters {
-                        groups: vec![FlagGroupType {
-                            properties: Some(vec![PropertyFilter {
-                                key: "country".to_string(),
-                                value: json!("US"),
-                                operator: Some(OperatorType::Exact),
-                                prop_type: "person".to_string(),
-                                group_type_index: None,
-                                negation: None,
-                            }]),
-                            rollout_percentage: Some(50.0),
-                            variant: None,
-                        }],
-                        multivariate: None,
-                        aggregation_group_type_index: None,
-                        payloads: None,
-                        super_groups: None,
-                    },
-                    deleted: false,
-                    active: true,
-                    ensure_experience_continuity: false,
-                    version: Some(1),
+        let mock_flags = FeatureFlagList::new(vec![
+            FeatureFlag {
+                id: 1,
+                team_id: team.id,
+                name: Some("Beta Feature".to_string()),
+                key: "beta_feature".to_string(),
+                filters: FlagFilters {
+                    groups: vec![FlagGroupType {
+                        properties: Some(vec![PropertyFilter {
+                            key: "country".to_string(),
+                            value: json!("US"),
+                            operator: Some(OperatorType::Exact),
+                            prop_type: "person".to_string(),
+                            group_type_index: None,
+                            negation: None,
+                        }]),
+                        rollout_percentage: Some(50.0),
+                        variant: None,
+                    }],
+                    multivariate: None,
+                    aggregation_group_type_index: None,
+                    payloads: None,
+                    super_groups: None,
                 },
-                FeatureFlag {
-                    id: 2,
-                    team_id: team.id,
-                    name: Some("New User Interface".to_string()),
-                    key: "new_ui".to_string(),
-                    filters: FlagFilters {
-                        groups: vec![],
-                        multivariate: None,
-                        aggregation_group_type_index: None,
-                        payloads: None,
-                        super_groups: None,
-                    },
-                    deleted: false,
-                    active: false,
-                    ensure_experience_continuity: false,
-                    version: Some(1),
+                deleted: false,
+                active: true,
+                ensure_experience_continuity: false,
+                version: Some(1),
+            },
+            FeatureFlag {
+                id: 2,
+                team_id: team.id,
+                name: Some("New User Interface".to_string()),
+                key: "new_ui".to_string(),
+                filters: FlagFilters {
+                    groups: vec![],
+                    multivariate: None,
+                    aggregation_group_type_index: None,
+                    payloads: None,
+                    super_groups: None,
                 },
-                FeatureFlag {
-                    id: 3,
-                    team_id: team.id,
-                    name: Some("Premium Feature".to_string()),
-                    key: "premium_feature".to_string(),
-                    filters: FlagFilters {
-                        groups: vec![FlagGroupType {
-                            properties: Some(vec![PropertyFilter {
-                                key: "is_premium".to_string(),
-                                value: json!(true),
-                                operator: Some(OperatorType::Exact),
-                                prop_type: "person".to_string(),
-                                group_type_index: None,
-                                negation: None,
-                            }]),
-                            rollout_percentage: Some(100.0),
-                            variant: None,
-                        }],
-                        multivariate: None,
-                        aggregation_group_type_index: None,
-                        payloads: None,
-                        super_groups: None,
-                    },
-                    deleted: false,
-                    active: true,
-                    ensure_experience_continuity: false,
-                    version: Some(1),
+                deleted: false,
+                active: false,
+                ensure_experience_continuity: false,
+                version: Some(1),
+            },
+            FeatureFlag {
+                id: 3,
+                team_id: team.id,
+                name: Some("Premium Feature".to_string()),
+                key: "premium_feature".to_string(),
+                filters: FlagFilters {
+                    groups: vec![FlagGroupType {
+                        properties: Some(vec![PropertyFilter {
+                            key: "is_premium".to_string(),
+                            value: json!(true),
+                            operator: Some(OperatorType::Exact),
+                            prop_type: "person".to_string(),
+                            group_type_index: None,
+                            negation: None,
+                        }]),
+                        rollout_percentage: Some(100.0),
+                        variant: None,
+                    }],
+                    multivariate: None,
+                    aggregation_group_type_index: None,
+                    payloads: None,
+                    super_groups: None,
                 },
-            ],
-        };
+                deleted: false,
+                active: true,
+                ensure_experience_continuity: false,
+                version: Some(1),
+            },
+        ]);
 
         FeatureFlagList::update_flags_in_redis(redis_client.clone(), team.project_id, &mock_flags)
             .await
@@ -322,7 +326,7 @@ mod tests {
 
         // Test fetching from Redis
         let result = flag_service
-            .get_flags_from_cache_or_pg(team.project_id)
+            .get_flags_from_cache_or_pg(team.project_id, &FlagEvaluationPlanCache::default())
             .await;
         assert!(result.is_ok());
         let fetched_flags = result.unwrap();
@@ -379,11 +383,16 @@ mod tests {
             .expect("Failed to remove flags from Redis");
 
         let result = flag_service
-            .get_flags_from_cache_or_pg(team.project_id)
+            .get_flags_from_cache_or_pg(team.project_id, &FlagEvaluationPlanCache::default())
             .await;
         assert!(result.is_ok());
         // Verify that the flags were re-added to Redis
-        let redis_flags = FeatureFlagList::from_redis(redis_client.clone(), team.project_id).await;
+        let redis_flags = FeatureFlagList::from_redis(
+            redis_client.clone(),
+            team.project_id,
+            &FlagEvaluationPlanCache::default(),
+        )
+        .await;
         assert!(redis_flags.is_ok());
         assert_eq!(redis_flags.unwrap().flags.len(), mock_flags.flags.len());
     }
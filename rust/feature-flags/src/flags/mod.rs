@@ -3,5 +3,6 @@ pub mod flag_match_reason;
 pub mod flag_matching;
 pub mod flag_models;
 pub mod flag_operations;
+pub mod flag_plan_cache;
 pub mod flag_request;
 pub mod flag_service;
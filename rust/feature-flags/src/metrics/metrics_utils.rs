@@ -55,6 +55,8 @@ pub fn parse_exception_for_prometheus_label(err: &FlagError) -> &'static str {
         FlagError::RedisUnavailable => "redis_unavailable",
         FlagError::TimeoutError => "timeout_error",
         FlagError::NoGroupTypeMappings => "no_group_type_mappings",
+        FlagError::FlagDependencyCycle(_) => "flag_dependency_cycle",
+        FlagError::FlagDependencyFailed(_) => "flag_dependency_failed",
         _ => "unknown",
     }
 }
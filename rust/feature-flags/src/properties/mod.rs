@@ -1,2 +1,3 @@
 pub mod property_matching;
 pub mod property_models;
+pub mod semantic_version;
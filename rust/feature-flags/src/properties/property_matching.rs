@@ -1,7 +1,10 @@
 use std::collections::HashMap;
 
-use crate::properties::property_models::{OperatorType, PropertyFilter};
-use chrono::{DateTime, NaiveDateTime, Utc};
+use crate::properties::property_models::{
+    CompiledOperand, DateOperand, OperatorType, PropertyFilter, RelativeDate,
+};
+use crate::properties::semantic_version::SemanticVersion;
+use chrono::{DateTime, Duration, Months, NaiveDateTime, Utc};
 use regex::Regex;
 use serde_json::Value;
 
@@ -34,6 +37,86 @@ pub fn match_property(
     property: &PropertyFilter,
     matching_property_values: &HashMap<String, Value>,
     partial_props: bool,
+) -> Result<bool, FlagMatchingError> {
+    match_compiled_property(
+        property,
+        &CompiledOperand::compile(property),
+        matching_property_values,
+        partial_props,
+    )
+}
+
+impl CompiledOperand {
+    pub fn compile(property: &PropertyFilter) -> Self {
+        let value = &property.value;
+        match property.operator.unwrap_or(OperatorType::Exact) {
+            OperatorType::Exact | OperatorType::IsNot => {
+                if is_truthy_or_falsy_property_value(value) {
+                    // Do boolean handling, such that passing in "true" or "True" or "false" or "False" as matching value is equivalent
+                    CompiledOperand::Boolean(is_truthy_property_value(value))
+                } else if let Some(values) = value.as_array() {
+                    CompiledOperand::Set(
+                        values
+                            .iter()
+                            .map(|v| to_string_representation(v).to_lowercase())
+                            .collect(),
+                    )
+                } else {
+                    CompiledOperand::Text(to_string_representation(value).to_lowercase())
+                }
+            }
+            // Using to_ascii_lowercase() since we only care about ASCII case insensitivity
+            // This is more performant than to_lowercase() which handles full Unicode
+            OperatorType::Icontains | OperatorType::NotIcontains => {
+                CompiledOperand::Text(to_string_representation(value).to_ascii_lowercase())
+            }
+            //TODO: Should we return Err for invalid patterns and handle elsewhere?
+            // python just returns false here
+            OperatorType::Regex | OperatorType::NotRegex => {
+                CompiledOperand::Regex(Regex::new(&to_string_representation(value)).ok())
+            }
+            OperatorType::Gt | OperatorType::Gte | OperatorType::Lt | OperatorType::Lte => {
+                CompiledOperand::Number(to_f64_representation(value))
+            }
+            // Like Python, relative dates are only compared with before/after: a date is
+            // never "exactly" 7 days ago, so `is_date_exact` only takes absolute dates
+            OperatorType::IsDateExact => CompiledOperand::Date(
+                value
+                    .as_str()
+                    .and_then(parse_date_operand)
+                    .filter(|date| matches!(date, DateOperand::Absolute(_))),
+            ),
+            OperatorType::IsDateAfter | OperatorType::IsDateBefore => {
+                CompiledOperand::Date(value.as_str().and_then(parse_date_operand))
+            }
+            OperatorType::SemverEq
+            | OperatorType::SemverGt
+            | OperatorType::SemverGte
+            | OperatorType::SemverLt
+            | OperatorType::SemverLte => {
+                CompiledOperand::Version(SemanticVersion::parse(&to_string_representation(value)))
+            }
+            OperatorType::SemverTilde => CompiledOperand::VersionRange(
+                SemanticVersion::tilde_range(&to_string_representation(value)),
+            ),
+            OperatorType::SemverCaret => CompiledOperand::VersionRange(
+                SemanticVersion::caret_range(&to_string_representation(value)),
+            ),
+            OperatorType::IsSet
+            | OperatorType::IsNotSet
+            | OperatorType::In
+            | OperatorType::NotIn
+            | OperatorType::FlagEvaluatesTo => CompiledOperand::None,
+        }
+    }
+}
+
+/// Same as `match_property`, with the operand of the filter compiled ahead of time.
+pub fn match_compiled_property(
+    property: &PropertyFilter,
+    operand: &CompiledOperand,
+    matching_property_values: &HashMap<String, Value>,
+    partial_props: bool,
 ) -> Result<bool, FlagMatchingError> {
     // only looks for matches where key exists in override_property_values
     // doesn't support operator is_not_set with partial_props
@@ -46,40 +129,30 @@ pub fn match_property(
 
     let key = &property.key;
     let operator = property.operator.unwrap_or(OperatorType::Exact);
-    let value = &property.value;
     let match_value = matching_property_values.get(key);
 
     match operator {
         OperatorType::Exact | OperatorType::IsNot => {
-            let compute_exact_match = |value: &Value, override_value: &Value| -> bool {
-                if is_truthy_or_falsy_property_value(value) {
-                    // Do boolean handling, such that passing in "true" or "True" or "false" or "False" as matching value is equivalent
-                    let (truthy_value, truthy_override_value) = (
-                        is_truthy_property_value(value),
-                        is_truthy_property_value(override_value),
-                    );
-                    return truthy_override_value.to_string().to_lowercase()
-                        == truthy_value.to_string().to_lowercase();
-                }
-
-                if value.is_array() {
-                    return value
-                        .as_array()
-                        .expect("expected array value")
-                        .iter()
-                        .map(|v| to_string_representation(v).to_lowercase())
-                        .collect::<Vec<String>>()
-                        .contains(&to_string_representation(override_value).to_lowercase());
+            let compute_exact_match = |override_value: &Value| -> bool {
+                match operand {
+                    CompiledOperand::Boolean(truthy_value) => {
+                        is_truthy_property_value(override_value) == *truthy_value
+                    }
+                    CompiledOperand::Set(values) => {
+                        values.contains(&to_string_representation(override_value).to_lowercase())
+                    }
+                    CompiledOperand::Text(value) => {
+                        to_string_representation(override_value).to_lowercase() == *value
+                    }
+                    _ => false,
                 }
-                to_string_representation(value).to_lowercase()
-                    == to_string_representation(override_value).to_lowercase()
             };
 
             if let Some(match_value) = match_value {
                 if operator == OperatorType::Exact {
-                    Ok(compute_exact_match(value, match_value))
+                    Ok(compute_exact_match(match_value))
                 } else {
-                    Ok(!compute_exact_match(value, match_value))
+                    Ok(!compute_exact_match(match_value))
                 }
             } else {
                 // When value doesn't exist:
@@ -102,11 +175,12 @@ pub fn match_property(
         }
         OperatorType::Icontains | OperatorType::NotIcontains => {
             if let Some(match_value) = match_value {
-                // Using to_ascii_lowercase() since we only care about ASCII case insensitivity
-                // This is more performant than to_lowercase() which handles full Unicode
-                let is_contained = to_string_representation(match_value)
-                    .to_ascii_lowercase()
-                    .contains(&to_string_representation(value).to_ascii_lowercase());
+                let is_contained = match operand {
+                    CompiledOperand::Text(value) => to_string_representation(match_value)
+                        .to_ascii_lowercase()
+                        .contains(value.as_str()),
+                    _ => false,
+                };
 
                 if operator == OperatorType::Icontains {
                     Ok(is_contained)
@@ -127,12 +201,9 @@ pub fn match_property(
                 // - for NotRegex: it is a match (true)
                 return Ok(operator == OperatorType::NotRegex);
             }
-            let pattern = match Regex::new(&to_string_representation(value)) {
-                Ok(pattern) => pattern,
-                Err(_) => return Ok(false),
-                //TODO: Should we return Err here and handle elsewhere?
-                //Err(FlagMatchingError::InvalidRegexPattern)
-                // python just returns false here
+            let pattern = match operand {
+                CompiledOperand::Regex(Some(pattern)) => pattern,
+                _ => return Ok(false),
             };
             let haystack = to_string_representation(match_value.unwrap_or(&Value::Null));
             let match_ = pattern.find(&haystack);
@@ -170,8 +241,8 @@ pub fn match_property(
                 }
             };
 
-            if let Some(override_value) = to_f64_representation(value) {
-                Ok(compare(parsed_value, override_value, operator))
+            if let CompiledOperand::Number(Some(override_value)) = operand {
+                Ok(compare(parsed_value, *override_value, operator))
             } else {
                 Err(FlagMatchingError::ValidationError(
                     "override value is not a number".to_string(),
@@ -187,14 +258,11 @@ pub fn match_property(
                 return Ok(false);
             }
 
-            if let Some(override_value) = value.as_str() {
-                let override_date = match parse_date_string(override_value) {
-                    Some(date) => date,
-                    None => {
-                        return Ok(false);
-                    }
-                };
-
+            let override_date = match operand {
+                CompiledOperand::Date(Some(date)) => date.resolve(Utc::now()),
+                _ => None,
+            };
+            if let Some(override_date) = override_date {
                 match operator {
                     OperatorType::IsDateBefore => Ok(parsed_date.unwrap() < override_date),
                     OperatorType::IsDateAfter => Ok(parsed_date.unwrap() > override_date),
@@ -205,6 +273,42 @@ pub fn match_property(
                 Ok(false)
             }
         }
+        OperatorType::SemverEq
+        | OperatorType::SemverGt
+        | OperatorType::SemverGte
+        | OperatorType::SemverLt
+        | OperatorType::SemverLte
+        | OperatorType::SemverTilde
+        | OperatorType::SemverCaret => {
+            // When value doesn't exist, or isn't a version, it's not a match (false)
+            let Some(version) =
+                match_value.and_then(|v| SemanticVersion::parse(&to_string_representation(v)))
+            else {
+                return Ok(false);
+            };
+
+            match (operator, operand) {
+                (OperatorType::SemverEq, CompiledOperand::Version(Some(target))) => {
+                    Ok(version == *target)
+                }
+                (OperatorType::SemverGt, CompiledOperand::Version(Some(target))) => {
+                    Ok(version > *target)
+                }
+                (OperatorType::SemverGte, CompiledOperand::Version(Some(target))) => {
+                    Ok(version >= *target)
+                }
+                (OperatorType::SemverLt, CompiledOperand::Version(Some(target))) => {
+                    Ok(version < *target)
+                }
+                (OperatorType::SemverLte, CompiledOperand::Version(Some(target))) => {
+                    Ok(version <= *target)
+                }
+                (_, CompiledOperand::VersionRange(Some((lower, upper)))) => {
+                    Ok(*lower <= version && version < *upper)
+                }
+                _ => Ok(false),
+            }
+        }
         // NB: In/NotIn operators are only for Cohorts,
         // and should be handled by cohort matching code because
         // by the time we match properties, we've already decomposed the cohort
@@ -212,6 +316,11 @@ pub fn match_property(
         OperatorType::In | OperatorType::NotIn => Err(FlagMatchingError::ValidationError(
             "In/NotIn operators should be handled by cohort matching".to_string(),
         )),
+        // NB: FlagEvaluatesTo is only for flag dependencies, which are matched against the
+        // results of the flags evaluated earlier in the request, not against properties
+        OperatorType::FlagEvaluatesTo => Err(FlagMatchingError::ValidationError(
+            "FlagEvaluatesTo operator should be handled by flag dependency matching".to_string(),
+        )),
     }
 }
 
@@ -291,6 +400,58 @@ fn parse_date_string(date_str: &str) -> Option<DateTime<Utc>> {
     None
 }
 
+/// Parses the operand of a date filter, which can also be a relative date like `-7d`.
+fn parse_date_operand(date_str: &str) -> Option<DateOperand> {
+    parse_relative_date(date_str)
+        .map(DateOperand::Relative)
+        .or_else(|| parse_date_string(date_str).map(DateOperand::Absolute))
+}
+
+/// Parses relative dates like `-24h`, `-7d`, `-2w`, `-3m` or `-1y`, with the same rules as
+/// Python's `relative_date_parse_for_feature_flag_matching`: the leading `-` is optional,
+/// and amounts of 10000 or more aren't relative dates.
+fn parse_relative_date(date_str: &str) -> Option<RelativeDate> {
+    let date_str = date_str.strip_prefix('-').unwrap_or(date_str);
+    let (number, interval) = date_str.split_at_checked(date_str.len().checked_sub(1)?)?;
+    if number.is_empty() || !number.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let number = number.parse::<u32>().ok().filter(|n| *n < 10_000)?;
+
+    match interval {
+        "h" => Some(RelativeDate::Hours(number)),
+        "d" => Some(RelativeDate::Days(number)),
+        "w" => Some(RelativeDate::Weeks(number)),
+        "m" => Some(RelativeDate::Months(number)),
+        "y" => Some(RelativeDate::Years(number)),
+        _ => None,
+    }
+}
+
+impl DateOperand {
+    /// Returns the date to compare against, resolving relative dates against `now`.
+    pub fn resolve(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        match *self {
+            DateOperand::Absolute(date) => Some(date),
+            DateOperand::Relative(RelativeDate::Hours(n)) => {
+                now.checked_sub_signed(Duration::hours(n.into()))
+            }
+            DateOperand::Relative(RelativeDate::Days(n)) => {
+                now.checked_sub_signed(Duration::days(n.into()))
+            }
+            DateOperand::Relative(RelativeDate::Weeks(n)) => {
+                now.checked_sub_signed(Duration::weeks(n.into()))
+            }
+            DateOperand::Relative(RelativeDate::Months(n)) => {
+                now.checked_sub_months(Months::new(n))
+            }
+            DateOperand::Relative(RelativeDate::Years(n)) => {
+                now.checked_sub_months(Months::new(n * 12))
+            }
+        }
+    }
+}
+
 fn determine_parsed_date_for_property_matching(value: Option<&Value>) -> Option<DateTime<Utc>> {
     let value = value?;
 
@@ -1555,4 +1716,227 @@ mod test_match_properties {
         )
         .expect("expected match to exist"));
     }
+
+    #[test]
+    fn test_match_properties_relative_date_operators() {
+        let filter = |value: &str, operator: OperatorType| PropertyFilter {
+            key: "joined_at".to_string(),
+            value: json!(value),
+            operator: Some(operator),
+            prop_type: "person".to_string(),
+            group_type_index: None,
+            negation: None,
+        };
+        let days_ago = |days: i64| {
+            json!((Utc::now() - Duration::days(days))
+                .format("%Y-%m-%dT%H:%M:%S")
+                .to_string())
+        };
+        let evaluate = |property: &PropertyFilter, value: Value| {
+            match_property(
+                property,
+                &HashMap::from([("joined_at".to_string(), value)]),
+                true,
+            )
+            .expect("expected match to exist")
+        };
+
+        let before = filter("-7d", OperatorType::IsDateBefore);
+        assert!(evaluate(&before, days_ago(8)));
+        assert!(!evaluate(&before, days_ago(6)));
+        assert!(evaluate(&before, json!("2020-01-01")));
+
+        let after = filter("-2w", OperatorType::IsDateAfter);
+        assert!(evaluate(&after, days_ago(13)));
+        assert!(!evaluate(&after, days_ago(15)));
+
+        let after = filter("24h", OperatorType::IsDateAfter);
+        assert!(evaluate(&after, days_ago(0)));
+        assert!(!evaluate(&after, days_ago(2)));
+
+        let after = filter("-1y", OperatorType::IsDateAfter);
+        assert!(evaluate(&after, days_ago(300)));
+        assert!(!evaluate(&after, days_ago(400)));
+
+        // Relative dates aren't operands of is_date_exact, even when the person's date is it
+        let exact = filter("-3m", OperatorType::IsDateExact);
+        assert!(!evaluate(&exact, days_ago(90)));
+        let exact = filter("-1d", OperatorType::IsDateExact);
+        assert!(!evaluate(&exact, days_ago(1)));
+        assert!(matches!(
+            CompiledOperand::compile(&exact),
+            CompiledOperand::Date(None)
+        ));
+
+        // Invalid intervals and too large amounts aren't relative dates, nor valid dates
+        assert!(!evaluate(
+            &filter("-7x", OperatorType::IsDateBefore),
+            json!("2020-01-01")
+        ));
+        assert!(!evaluate(
+            &filter("-10000d", OperatorType::IsDateBefore),
+            json!("2020-01-01")
+        ));
+    }
+
+    #[test]
+    fn test_parse_relative_date() {
+        assert_eq!(parse_relative_date("-24h"), Some(RelativeDate::Hours(24)));
+        assert_eq!(parse_relative_date("-7d"), Some(RelativeDate::Days(7)));
+        assert_eq!(parse_relative_date("2w"), Some(RelativeDate::Weeks(2)));
+        assert_eq!(parse_relative_date("-03m"), Some(RelativeDate::Months(3)));
+        assert_eq!(
+            parse_relative_date("-9999y"),
+            Some(RelativeDate::Years(9999))
+        );
+        for invalid in [
+            "",
+            "-",
+            "-d",
+            "-10000d",
+            "--7d",
+            "-7D",
+            "-7dd",
+            "-1.5d",
+            "2024-03-21",
+        ] {
+            assert_eq!(parse_relative_date(invalid), None, "{}", invalid);
+        }
+
+        let now = DateTime::parse_from_rfc3339("2024-03-31T12:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let resolve = |date: &str| parse_date_operand(date).and_then(|d| d.resolve(now));
+        let date = |date: &str| {
+            DateTime::parse_from_rfc3339(date)
+                .unwrap()
+                .with_timezone(&Utc)
+        };
+        assert_eq!(resolve("-24h"), Some(date("2024-03-30T12:00:00Z")));
+        assert_eq!(resolve("-7d"), Some(date("2024-03-24T12:00:00Z")));
+        assert_eq!(resolve("-2w"), Some(date("2024-03-17T12:00:00Z")));
+        // Calendar months, clamped to the end of shorter months
+        assert_eq!(resolve("-1m"), Some(date("2024-02-29T12:00:00Z")));
+        assert_eq!(resolve("-13m"), Some(date("2023-02-28T12:00:00Z")));
+        assert_eq!(resolve("-1y"), Some(date("2023-03-31T12:00:00Z")));
+        assert_eq!(resolve("2024-03-21"), Some(date("2024-03-21T00:00:00Z")));
+        assert_eq!(resolve("not a date"), None);
+    }
+
+    #[test]
+    fn test_match_properties_semver() {
+        let filter = |value: &str, operator: OperatorType| PropertyFilter {
+            key: "$app_version".to_string(),
+            value: json!(value),
+            operator: Some(operator),
+            prop_type: "person".to_string(),
+            group_type_index: None,
+            negation: None,
+        };
+        let matches = |property: &PropertyFilter, version: Value| {
+            match_property(
+                property,
+                &HashMap::from([("$app_version".to_string(), version)]),
+                true,
+            )
+            .expect("expected match to exist")
+        };
+
+        // Numeric and string comparisons get this wrong
+        let gte = filter("2.10.0", OperatorType::SemverGte);
+        assert!(matches(&gte, json!("2.10.0")));
+        assert!(matches(&gte, json!("2.10")));
+        assert!(matches(&gte, json!("10.0.0")));
+        assert!(!matches(&gte, json!("2.9.9")));
+        assert!(!matches(&gte, json!("2.10.0-beta.1")));
+        assert!(!matches(&gte, json!("not a version")));
+
+        let eq = filter("v2.10", OperatorType::SemverEq);
+        assert!(matches(&eq, json!("2.10.0")));
+        assert!(matches(&eq, json!("2.10.0+build.7")));
+        assert!(!matches(&eq, json!("2.10.1")));
+
+        let gt = filter("1.0.0-beta.2", OperatorType::SemverGt);
+        assert!(matches(&gt, json!("1.0.0-beta.11")));
+        assert!(matches(&gt, json!("1.0.0")));
+        assert!(!matches(&gt, json!("1.0.0-alpha")));
+
+        let lt = filter("2", OperatorType::SemverLt);
+        assert!(matches(&lt, json!("1.99.99")));
+        assert!(matches(&lt, json!("2.0.0-rc.1")));
+        assert!(!matches(&lt, json!("2.0.0")));
+
+        let lte = filter("2.1.3", OperatorType::SemverLte);
+        assert!(matches(&lte, json!("2.1.3")));
+        assert!(!matches(&lte, json!("2.1.4")));
+        // Numbers are compared as versions too
+        assert!(matches(&lte, json!(2.1)));
+
+        let tilde = filter("1.2.3", OperatorType::SemverTilde);
+        assert!(matches(&tilde, json!("1.2.10")));
+        assert!(!matches(&tilde, json!("1.3.0")));
+
+        let caret = filter("1.2", OperatorType::SemverCaret);
+        assert!(matches(&caret, json!("1.9.0")));
+        assert!(!matches(&caret, json!("2.0.0-alpha")));
+        assert!(!matches(&caret, json!("1.1.9")));
+
+        // Invalid operands never match
+        let invalid = filter("latest", OperatorType::SemverCaret);
+        assert!(!matches(&invalid, json!("1.0.0")));
+
+        assert!(!match_property(&gte, &HashMap::new(), false).unwrap());
+    }
+
+    #[test]
+    fn test_match_compiled_property() {
+        let filter = |value: Value, operator: OperatorType| PropertyFilter {
+            key: "key".to_string(),
+            value,
+            operator: Some(operator),
+            prop_type: "person".to_string(),
+            group_type_index: None,
+            negation: None,
+        };
+        let evaluate = |property: &PropertyFilter, operand: &CompiledOperand, value: Value| {
+            match_compiled_property(
+                property,
+                operand,
+                &HashMap::from([("key".to_string(), value)]),
+                false,
+            )
+        };
+
+        let list = filter(json!(["Value", 5, "other"]), OperatorType::Exact);
+        let operand = CompiledOperand::compile(&list);
+        assert!(
+            matches!(&operand, CompiledOperand::Set(values) if values.contains("value") && values.contains("5"))
+        );
+        assert_eq!(evaluate(&list, &operand, json!("VALUE")), Ok(true));
+        assert_eq!(evaluate(&list, &operand, json!(5)), Ok(true));
+        assert_eq!(evaluate(&list, &operand, json!("missing")), Ok(false));
+
+        let boolean = filter(json!("True"), OperatorType::IsNot);
+        let operand = CompiledOperand::compile(&boolean);
+        assert!(matches!(operand, CompiledOperand::Boolean(true)));
+        assert_eq!(evaluate(&boolean, &operand, json!(false)), Ok(true));
+
+        let invalid_regex = filter(json!("(unclosed"), OperatorType::Regex);
+        let operand = CompiledOperand::compile(&invalid_regex);
+        assert!(matches!(operand, CompiledOperand::Regex(None)));
+        assert_eq!(
+            evaluate(&invalid_regex, &operand, json!("(unclosed")),
+            Ok(false)
+        );
+
+        let number = filter(json!("10"), OperatorType::Gt);
+        let operand = CompiledOperand::compile(&number);
+        assert!(matches!(operand, CompiledOperand::Number(Some(n)) if n == 10.0));
+        assert_eq!(evaluate(&number, &operand, json!(11)), Ok(true));
+
+        let date = filter(json!("2024-03-21"), OperatorType::IsDateBefore);
+        let operand = CompiledOperand::compile(&date);
+        assert!(matches!(operand, CompiledOperand::Date(Some(_))));
+        assert_eq!(evaluate(&date, &operand, json!("2024-03-20")), Ok(true));
+    }
 }
@@ -1,5 +1,11 @@
+use std::collections::HashSet;
+
+use chrono::{DateTime, Utc};
+use regex::Regex;
 use serde::{Deserialize, Serialize};
 
+use crate::properties::semantic_version::SemanticVersion;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
 #[serde(rename_all = "snake_case")]
 pub enum OperatorType {
@@ -20,6 +26,14 @@ pub enum OperatorType {
     IsDateBefore,
     In,
     NotIn,
+    FlagEvaluatesTo,
+    SemverEq,
+    SemverGt,
+    SemverGte,
+    SemverLt,
+    SemverLte,
+    SemverTilde,
+    SemverCaret,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -36,3 +50,46 @@ pub struct PropertyFilter {
     pub negation: Option<bool>,
     pub group_type_index: Option<i32>,
 }
+
+/// The operand of a property filter, parsed ahead of matching so that evaluating the filter
+/// doesn't recompile regexes or reparse numbers and dates from its JSON value.
+/// See `CompiledOperand::compile`.
+#[derive(Debug, Clone)]
+pub enum CompiledOperand {
+    /// Operand of `exact`/`is_not` filters on boolean values, like `true` or `"False"`
+    Boolean(bool),
+    /// Lowercased values of `exact`/`is_not` filters on lists
+    Set(HashSet<String>),
+    /// Lowercased value of `exact`/`is_not` and `icontains`/`not_icontains` filters
+    Text(String),
+    /// None if the pattern is invalid, in which case the filter never matches
+    Regex(Option<Regex>),
+    Number(Option<f64>),
+    /// None if the operand isn't a valid date, in which case the filter never matches
+    Date(Option<DateOperand>),
+    /// None if the operand isn't a valid version, in which case the filter never matches
+    Version(Option<SemanticVersion>),
+    /// Inclusive lower and exclusive upper bounds of `semver_tilde`/`semver_caret` filters
+    VersionRange(Option<(SemanticVersion, SemanticVersion)>),
+    /// Operators which don't need an operand, or aren't matched against properties
+    None,
+}
+
+/// Operand of the date filters. Relative dates are resolved against the time the filter is
+/// matched, i.e. the request time, rather than when the operand is compiled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DateOperand {
+    Absolute(DateTime<Utc>),
+    Relative(RelativeDate),
+}
+
+/// A date in the past relative to now, like `-7d` for 7 days ago. Months and years are calendar
+/// ones, so `-1m` on March 31st is the last day of February.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RelativeDate {
+    Hours(u32),
+    Days(u32),
+    Weeks(u32),
+    Months(u32),
+    Years(u32),
+}
@@ -0,0 +1,271 @@
+use std::cmp::Ordering;
+
+/// A semantic version, as used by the `semver_*` property operators.
+///
+/// Versions are parsed leniently: a leading `v` is ignored, missing minor and patch
+/// components default to 0 (so `2.10` is `2.10.0`), and build metadata is ignored.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SemanticVersion {
+    pub major: u64,
+    pub minor: u64,
+    pub patch: u64,
+    pub pre_release: Vec<PreReleaseIdentifier>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PreReleaseIdentifier {
+    Numeric(u64),
+    AlphaNumeric(String),
+}
+
+impl SemanticVersion {
+    pub fn new(major: u64, minor: u64, patch: u64) -> Self {
+        SemanticVersion {
+            major,
+            minor,
+            patch,
+            pre_release: vec![],
+        }
+    }
+
+    /// Parses the version, returning it along with the number of components (1 to 3) it had,
+    /// which the tilde and caret ranges depend on.
+    pub fn parse_with_components(version: &str) -> Option<(Self, usize)> {
+        let version = version.trim();
+        let version = version.strip_prefix(['v', 'V']).unwrap_or(version);
+        let version = version.split_once('+').map_or(version, |(v, _)| v);
+        let (core, pre_release) = match version.split_once('-') {
+            Some((core, pre_release)) => (core, Some(pre_release)),
+            None => (version, None),
+        };
+
+        let components = core
+            .split('.')
+            .map(|c| {
+                if c.is_empty() || !c.bytes().all(|b| b.is_ascii_digit()) {
+                    None
+                } else {
+                    c.parse::<u64>().ok()
+                }
+            })
+            .collect::<Option<Vec<u64>>>()?;
+        if components.is_empty() || components.len() > 3 {
+            return None;
+        }
+
+        let pre_release = match pre_release {
+            Some(pre_release) => pre_release
+                .split('.')
+                .map(|identifier| {
+                    if identifier.is_empty()
+                        || !identifier
+                            .bytes()
+                            .all(|b| b.is_ascii_alphanumeric() || b == b'-')
+                    {
+                        None
+                    } else if identifier.bytes().all(|b| b.is_ascii_digit()) {
+                        identifier.parse().ok().map(PreReleaseIdentifier::Numeric)
+                    } else {
+                        Some(PreReleaseIdentifier::AlphaNumeric(identifier.to_string()))
+                    }
+                })
+                .collect::<Option<Vec<_>>>()?,
+            None => vec![],
+        };
+
+        let version = SemanticVersion {
+            major: components[0],
+            minor: components.get(1).copied().unwrap_or(0),
+            patch: components.get(2).copied().unwrap_or(0),
+            pre_release,
+        };
+        Some((version, components.len()))
+    }
+
+    pub fn parse(version: &str) -> Option<Self> {
+        Self::parse_with_components(version).map(|(version, _)| version)
+    }
+
+    /// Returns the bounds of the `~version` range: patch updates, or minor updates if only
+    /// the major version is given. The lower bound is inclusive, the upper one exclusive.
+    /// There's no range if the upper bound would overflow.
+    pub fn tilde_range(version: &str) -> Option<(Self, Self)> {
+        let (lower, components) = Self::parse_with_components(version)?;
+        let upper = if components == 1 {
+            Self::new(lower.major.checked_add(1)?, 0, 0)
+        } else {
+            Self::new(lower.major, lower.minor.checked_add(1)?, 0)
+        };
+        Some((lower, upper.lowest_pre_release()))
+    }
+
+    /// Returns the bounds of the `^version` range: updates that don't change the left-most
+    /// non-zero component. The lower bound is inclusive, the upper one exclusive.
+    /// There's no range if the upper bound would overflow.
+    pub fn caret_range(version: &str) -> Option<(Self, Self)> {
+        let (lower, components) = Self::parse_with_components(version)?;
+        let upper = if lower.major > 0 || components == 1 {
+            Self::new(lower.major.checked_add(1)?, 0, 0)
+        } else if lower.minor > 0 || components == 2 {
+            Self::new(0, lower.minor.checked_add(1)?, 0)
+        } else {
+            Self::new(0, 0, lower.patch.checked_add(1)?)
+        };
+        Some((lower, upper.lowest_pre_release()))
+    }
+
+    /// The lowest version with the same major, minor and patch, so that an exclusive upper
+    /// bound also excludes its pre-releases, e.g. `2.0.0-beta` isn't part of `^1.2.3`.
+    fn lowest_pre_release(self) -> Self {
+        SemanticVersion {
+            pre_release: vec![PreReleaseIdentifier::Numeric(0)],
+            ..self
+        }
+    }
+}
+
+impl Ord for PreReleaseIdentifier {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (PreReleaseIdentifier::Numeric(a), PreReleaseIdentifier::Numeric(b)) => a.cmp(b),
+            (PreReleaseIdentifier::AlphaNumeric(a), PreReleaseIdentifier::AlphaNumeric(b)) => {
+                a.cmp(b)
+            }
+            // Numeric identifiers have lower precedence than alphanumeric ones
+            (PreReleaseIdentifier::Numeric(_), PreReleaseIdentifier::AlphaNumeric(_)) => {
+                Ordering::Less
+            }
+            (PreReleaseIdentifier::AlphaNumeric(_), PreReleaseIdentifier::Numeric(_)) => {
+                Ordering::Greater
+            }
+        }
+    }
+}
+
+impl PartialOrd for PreReleaseIdentifier {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for SemanticVersion {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.major, self.minor, self.patch)
+            .cmp(&(other.major, other.minor, other.patch))
+            .then_with(|| {
+                // A pre-release version has lower precedence than the release itself
+                match (self.pre_release.is_empty(), other.pre_release.is_empty()) {
+                    (true, true) => Ordering::Equal,
+                    (true, false) => Ordering::Greater,
+                    (false, true) => Ordering::Less,
+                    (false, false) => self.pre_release.cmp(&other.pre_release),
+                }
+            })
+    }
+}
+
+impl PartialOrd for SemanticVersion {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version(v: &str) -> SemanticVersion {
+        SemanticVersion::parse(v).unwrap_or_else(|| panic!("failed to parse {}", v))
+    }
+
+    #[test]
+    fn test_parse_semantic_version() {
+        assert_eq!(version("2.10.1"), SemanticVersion::new(2, 10, 1));
+        assert_eq!(version("v2.10"), SemanticVersion::new(2, 10, 0));
+        assert_eq!(version(" 3 "), SemanticVersion::new(3, 0, 0));
+        assert_eq!(version("1.2.3+build.5"), SemanticVersion::new(1, 2, 3));
+        assert_eq!(
+            version("1.2.3-beta.2").pre_release,
+            vec![
+                PreReleaseIdentifier::AlphaNumeric("beta".to_string()),
+                PreReleaseIdentifier::Numeric(2)
+            ]
+        );
+
+        for invalid in [
+            "",
+            "v",
+            "1..2",
+            "1.2.3.4",
+            "1.x",
+            "a.b.c",
+            "1.2.3-",
+            "1.2.3-beta..1",
+        ] {
+            assert_eq!(SemanticVersion::parse(invalid), None, "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_semantic_version_ordering() {
+        // From https://semver.org/#spec-item-11
+        let ordered = [
+            "1.0.0-alpha",
+            "1.0.0-alpha.1",
+            "1.0.0-alpha.beta",
+            "1.0.0-beta",
+            "1.0.0-beta.2",
+            "1.0.0-beta.11",
+            "1.0.0-rc.1",
+            "1.0.0",
+            "1.9.0",
+            "1.10.0",
+            "2.0.0",
+        ];
+        for pair in ordered.windows(2) {
+            assert!(version(pair[0]) < version(pair[1]), "{:?}", pair);
+        }
+        assert_eq!(version("2.10"), version("2.10.0"));
+    }
+
+    #[test]
+    fn test_tilde_and_caret_ranges() {
+        let in_range = |range: Option<(SemanticVersion, SemanticVersion)>, v: &str| {
+            let (lower, upper) = range.unwrap();
+            let v = version(v);
+            lower <= v && v < upper
+        };
+
+        let tilde = |r: &str| SemanticVersion::tilde_range(r);
+        assert!(in_range(tilde("1.2.3"), "1.2.9"));
+        assert!(!in_range(tilde("1.2.3"), "1.3.0"));
+        assert!(!in_range(tilde("1.2.3"), "1.3.0-beta"));
+        assert!(!in_range(tilde("1.2.3"), "1.2.2"));
+        assert!(in_range(tilde("1.2"), "1.2.0"));
+        assert!(in_range(tilde("1"), "1.9.0"));
+        assert!(!in_range(tilde("1"), "2.0.0"));
+
+        let caret = |r: &str| SemanticVersion::caret_range(r);
+        assert!(in_range(caret("1.2.3"), "1.9.0"));
+        assert!(!in_range(caret("1.2.3"), "2.0.0"));
+        assert!(!in_range(caret("1.2.3"), "2.0.0-alpha"));
+        assert!(in_range(caret("0.2.3"), "0.2.9"));
+        assert!(!in_range(caret("0.2.3"), "0.3.0"));
+        assert!(in_range(caret("0.0.3"), "0.0.3"));
+        assert!(!in_range(caret("0.0.3"), "0.0.4"));
+        assert!(in_range(caret("0.0"), "0.0.9"));
+        assert!(!in_range(caret("0.0"), "0.1.0"));
+        assert!(in_range(caret("1.2.3-beta.1"), "1.2.3-beta.2"));
+
+        assert!(tilde("not a version").is_none());
+        assert!(caret("").is_none());
+
+        // Upper bounds which would overflow aren't ranges
+        let max = u64::MAX;
+        assert!(tilde(&max.to_string()).is_none());
+        assert!(tilde(&format!("1.{max}.0")).is_none());
+        assert!(caret(&format!("{max}.0.0")).is_none());
+        assert!(caret(&format!("0.{max}")).is_none());
+        assert!(caret(&format!("0.0.{max}")).is_none());
+    }
+}
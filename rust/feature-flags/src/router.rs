@@ -16,10 +16,11 @@ use tower_http::{
 };
 
 use crate::{
-    api::{endpoint, test_endpoint},
+    api::{auth::PersonalApiKeyCache, endpoint, test_endpoint},
     client::{database::Client as DatabaseClient, geoip::GeoIpClient},
     cohort::cohort_cache_manager::CohortCacheManager,
     config::{Config, TeamIdsToTrack},
+    flags::flag_plan_cache::FlagEvaluationPlanCache,
     metrics::metrics_utils::team_id_label_filter,
 };
 
@@ -29,6 +30,8 @@ pub struct State {
     pub reader: Arc<dyn DatabaseClient + Send + Sync>,
     pub writer: Arc<dyn DatabaseClient + Send + Sync>,
     pub cohort_cache_manager: Arc<CohortCacheManager>,
+    pub evaluation_plan_cache: Arc<FlagEvaluationPlanCache>,
+    pub personal_api_key_cache: Arc<PersonalApiKeyCache>,
     pub geoip: Arc<GeoIpClient>,
     pub team_ids_to_track: TeamIdsToTrack,
     pub billing_limiter: RedisLimiter,
@@ -40,6 +43,8 @@ pub fn router<R, D>(
     reader: Arc<D>,
     writer: Arc<D>,
     cohort_cache: Arc<CohortCacheManager>,
+    evaluation_plan_cache: Arc<FlagEvaluationPlanCache>,
+    personal_api_key_cache: Arc<PersonalApiKeyCache>,
     geoip: Arc<GeoIpClient>,
     liveness: HealthRegistry,
     billing_limiter: RedisLimiter,
@@ -54,6 +59,8 @@ where
         reader,
         writer,
         cohort_cache_manager: cohort_cache,
+        evaluation_plan_cache,
+        personal_api_key_cache,
         geoip,
         team_ids_to_track: config.team_ids_to_track.clone(),
         billing_limiter,
@@ -95,9 +102,35 @@ where
         .route("/flags/", post(endpoint::flags).get(endpoint::flags))
         .layer(ConcurrencyLimitLayer::new(config.max_concurrency));
 
+    // flag definitions endpoint, for server SDKs evaluating flags locally
+    let local_evaluation_router = Router::new()
+        .route(
+            "/api/feature_flag/local_evaluation",
+            get(endpoint::local_evaluation).options(endpoint::options),
+        )
+        .route(
+            "/api/feature_flag/local_evaluation/",
+            get(endpoint::local_evaluation).options(endpoint::options),
+        )
+        .layer(ConcurrencyLimitLayer::new(config.max_concurrency));
+
+    // flag evaluation traces, for debugging why a person gets a flag value
+    let explain_router = Router::new()
+        .route(
+            "/flags/explain",
+            post(endpoint::explain).options(endpoint::options),
+        )
+        .route(
+            "/flags/explain/",
+            post(endpoint::explain).options(endpoint::options),
+        )
+        .layer(ConcurrencyLimitLayer::new(config.max_concurrency));
+
     let router = Router::new()
         .merge(status_router)
         .merge(flags_router)
+        .merge(local_evaluation_router)
+        .merge(explain_router)
         .merge(test_router)
         .layer(TraceLayer::new_for_http())
         .layer(cors)
@@ -8,10 +8,12 @@ use health::{HealthHandle, HealthRegistry};
 use limiters::redis::{QuotaResource, RedisLimiter, ServiceName, QUOTA_LIMITER_CACHE_KEY};
 use tokio::net::TcpListener;
 
+use crate::api::auth::PersonalApiKeyCache;
 use crate::client::database::get_pool;
 use crate::client::geoip::GeoIpClient;
 use crate::cohort::cohort_cache_manager::CohortCacheManager;
 use crate::config::Config;
+use crate::flags::flag_plan_cache::FlagEvaluationPlanCache;
 use crate::router;
 
 pub async fn serve<F>(config: Config, listener: TcpListener, shutdown: F)
@@ -76,6 +78,8 @@ where
         Some(config.cache_max_cohort_entries),
         Some(config.cache_ttl_seconds),
     ));
+    let evaluation_plan_cache = Arc::new(FlagEvaluationPlanCache::default());
+    let personal_api_key_cache = Arc::new(PersonalApiKeyCache::default());
 
     let health = HealthRegistry::new("liveness");
 
@@ -106,6 +110,8 @@ where
         reader,
         writer,
         cohort_cache,
+        evaluation_plan_cache,
+        personal_api_key_cache,
         geoip_service,
         health,
         billing_limiter,
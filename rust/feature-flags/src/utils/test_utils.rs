@@ -1,4 +1,5 @@
 use crate::{
+    api::auth::hash_personal_api_key,
     client::database::{get_pool, Client, CustomDatabaseError},
     cohort::cohort_models::Cohort,
     config::{Config, DEFAULT_TEST_CONFIG},
@@ -303,6 +304,56 @@ pub async fn insert_flag_for_team_in_pg(
     Ok(payload_flag)
 }
 
+/// Creates a user in the team's organization, with a personal API key scoped to the team.
+/// Returns the key's raw value.
+pub async fn insert_personal_api_key_for_team_in_pg(
+    client: Arc<dyn Client + Send + Sync>,
+    team_id: i32,
+    scopes: Option<Vec<&str>>,
+) -> Result<String, Error> {
+    let key = random_string("phx_", 32);
+    let email = format!("{}@posthog.com", random_string("user_", 12));
+
+    let mut conn = client.get_connection().await?;
+    let res = sqlx::query(
+        r#"
+        WITH inserted_user AS (
+            INSERT INTO posthog_user (
+                password, first_name, last_name, is_staff, is_active, date_joined, uuid, email,
+                events_column_config
+            )
+            VALUES ('', 'Test', 'User', false, true, '2024-06-17', $1, $2, '{"active": "DEFAULT"}')
+            RETURNING id
+        ),
+        inserted_membership AS (
+            INSERT INTO posthog_organizationmembership (
+                id, organization_id, user_id, level, joined_at, updated_at
+            )
+            SELECT $3, t.organization_id, (SELECT id FROM inserted_user), 1, '2024-06-17', '2024-06-17'
+              FROM posthog_team AS t WHERE t.id = $4
+        )
+        INSERT INTO posthog_personalapikey (
+            id, user_id, label, mask_value, secure_value, created_at, scopes, scoped_teams
+        )
+        VALUES ($5, (SELECT id FROM inserted_user), 'Test key', $6, $7, '2024-06-17', $8, $9)
+        "#,
+    )
+    .bind(Uuid::now_v7())
+    .bind(&email)
+    .bind(Uuid::now_v7())
+    .bind(team_id)
+    .bind(random_string("", 32))
+    .bind(format!("{}...{}", &key[..4], &key[key.len() - 4..]))
+    .bind(hash_personal_api_key(&key))
+    .bind(scopes)
+    .bind(vec![team_id])
+    .execute(&mut *conn)
+    .await?;
+    assert_eq!(res.rows_affected(), 1);
+
+    Ok(key)
+}
+
 pub async fn insert_person_for_team_in_pg(
     client: Arc<dyn Client + Send + Sync>,
     team_id: i32,
@@ -111,6 +111,10 @@ impl ServerHandle {
                     Some(config.cache_ttl_seconds),
                 ),
             );
+            let evaluation_plan_cache =
+                Arc::new(feature_flags::flags::flag_plan_cache::FlagEvaluationPlanCache::default());
+            let personal_api_key_cache =
+                Arc::new(feature_flags::api::auth::PersonalApiKeyCache::default());
 
             let health = health::HealthRegistry::new("liveness");
             let simple_loop = health
@@ -133,6 +137,8 @@ impl ServerHandle {
                 reader,
                 writer,
                 cohort_cache,
+                evaluation_plan_cache,
+                personal_api_key_cache,
                 geoip_service,
                 health,
                 billing_limiter,
@@ -172,6 +178,38 @@ impl ServerHandle {
             .expect("failed to send request")
     }
 
+    pub async fn send_local_evaluation_request(
+        &self,
+        token: &str,
+        personal_api_key: Option<&str>,
+    ) -> reqwest::Response {
+        let client = reqwest::Client::new();
+        let mut request = client.get(format!(
+            "http://{:?}/api/feature_flag/local_evaluation?token={}&send_cohorts",
+            self.addr, token
+        ));
+        if let Some(key) = personal_api_key {
+            request = request.bearer_auth(key);
+        }
+        request.send().await.expect("failed to send request")
+    }
+
+    pub async fn send_explain_request<T: Into<reqwest::Body>>(
+        &self,
+        body: T,
+        personal_api_key: Option<&str>,
+    ) -> reqwest::Response {
+        let client = reqwest::Client::new();
+        let mut request = client
+            .post(format!("http://{:?}/flags/explain", self.addr))
+            .body(body)
+            .header(CONTENT_TYPE, "application/json");
+        if let Some(key) = personal_api_key {
+            request = request.bearer_auth(key);
+        }
+        request.send().await.expect("failed to send request")
+    }
+
     pub async fn send_invalid_header_for_flags_request<T: Into<reqwest::Body>>(
         &self,
         body: T,
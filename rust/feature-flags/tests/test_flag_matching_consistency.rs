@@ -1,4 +1,6 @@
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc};
+
+use chrono::{Duration, Utc};
 
 /// These tests are common between all libraries doing local evaluation of feature flags.
 /// This ensures there are no mismatches between implementations.
@@ -1241,3 +1243,83 @@ async fn it_is_consistent_with_rollout_calculation_for_multivariate_flags() {
         }
     }
 }
+
+#[tokio::test]
+async fn it_is_consistent_with_relative_date_matching() {
+    let ago = |duration: Duration| {
+        (Utc::now() - duration)
+            .format("%Y-%m-%dT%H:%M:%S")
+            .to_string()
+    };
+
+    // Filter value, operator, the person's `joined_at`, and whether it matches
+    let cases = vec![
+        ("-7d", "is_date_before", ago(Duration::days(8)), true),
+        ("-7d", "is_date_before", ago(Duration::days(6)), false),
+        ("-7d", "is_date_after", ago(Duration::days(6)), true),
+        ("-7d", "is_date_after", ago(Duration::days(8)), false),
+        ("-24h", "is_date_after", ago(Duration::hours(23)), true),
+        ("-24h", "is_date_after", ago(Duration::hours(25)), false),
+        ("-2w", "is_date_before", ago(Duration::days(15)), true),
+        ("-2w", "is_date_before", ago(Duration::days(13)), false),
+        ("-3m", "is_date_after", ago(Duration::days(80)), true),
+        ("-3m", "is_date_after", ago(Duration::days(100)), false),
+        ("-1y", "is_date_before", ago(Duration::days(400)), true),
+        ("-1y", "is_date_before", ago(Duration::days(300)), false),
+        // The leading `-` is optional
+        ("7d", "is_date_before", ago(Duration::days(8)), true),
+        // Invalid intervals and amounts of 10000 or more aren't relative dates
+        ("-7x", "is_date_before", "2020-01-01".to_string(), false),
+        ("-10000d", "is_date_before", "2020-01-01".to_string(), false),
+        ("-1d", "is_date_after", "not a date".to_string(), false),
+        // Relative dates are only compared with before/after
+        ("-1d", "is_date_exact", ago(Duration::days(1)), false),
+        ("-7d", "is_date_exact", ago(Duration::days(7)), false),
+    ];
+
+    for (i, (value, operator, joined_at, expected)) in cases.into_iter().enumerate() {
+        let flags = create_flag_from_json(Some(
+            json!([{
+                "id": 1,
+                "key": "relative-date-flag",
+                "name": "Relative date flag",
+                "active": true,
+                "deleted": false,
+                "team_id": 1,
+                "filters": {
+                    "groups": [
+                        {
+                            "properties": [
+                                {
+                                    "key": "joined_at",
+                                    "type": "person",
+                                    "value": value,
+                                    "operator": operator,
+                                },
+                            ],
+                            "rollout_percentage": 100,
+                        },
+                    ],
+                },
+            }])
+            .to_string(),
+        ));
+        let reader = setup_pg_reader_client(None).await;
+        let writer = setup_pg_writer_client(None).await;
+        let cohort_cache = Arc::new(CohortCacheManager::new(reader.clone(), None, None));
+        let distinct_id = format!("distinct_id_{}", i);
+        let overrides = HashMap::from([("joined_at".to_string(), json!(joined_at))]);
+
+        let feature_flag_match =
+            FeatureFlagMatcher::new(distinct_id, 1, 1, reader, writer, cohort_cache, None, None)
+                .get_match(&flags[0], Some(overrides), None)
+                .await
+                .unwrap();
+
+        assert_eq!(
+            feature_flag_match.matches, expected,
+            "{} {} {}",
+            operator, value, joined_at
+        );
+    }
+}
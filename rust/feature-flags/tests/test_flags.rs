@@ -11,9 +11,11 @@ use serde_json::{json, Value};
 use crate::common::*;
 
 use feature_flags::config::DEFAULT_TEST_CONFIG;
+use feature_flags::flags::flag_models::FeatureFlagRow;
 use feature_flags::utils::test_utils::{
-    create_group_in_pg, insert_flags_for_team_in_redis, insert_new_team_in_pg,
-    insert_new_team_in_redis, insert_person_for_team_in_pg, setup_pg_reader_client,
+    create_group_in_pg, insert_cohort_for_team_in_pg, insert_flag_for_team_in_pg,
+    insert_flags_for_team_in_redis, insert_new_team_in_pg, insert_new_team_in_redis,
+    insert_person_for_team_in_pg, insert_personal_api_key_for_team_in_pg, setup_pg_reader_client,
     setup_redis_client,
 };
 
@@ -1114,3 +1116,178 @@ async fn it_handles_not_equal_and_not_regex_property_filters() -> Result<()> {
 
     Ok(())
 }
+
+#[tokio::test]
+async fn it_serves_flag_definitions_for_local_evaluation() -> Result<()> {
+    let config = DEFAULT_TEST_CONFIG.clone();
+
+    let pg_client = setup_pg_reader_client(None).await;
+    let team = insert_new_team_in_pg(pg_client.clone(), None)
+        .await
+        .unwrap();
+    let cohort = insert_cohort_for_team_in_pg(
+        pg_client.clone(),
+        team.id,
+        None,
+        json!({"properties": {"type": "OR", "values": [{"type": "OR", "values": [
+            {"key": "email", "value": "@posthog.com", "type": "person", "operator": "icontains"}
+        ]}]}}),
+        false,
+    )
+    .await
+    .unwrap();
+    insert_flag_for_team_in_pg(
+        pg_client.clone(),
+        team.id,
+        Some(FeatureFlagRow {
+            id: 0,
+            team_id: team.id,
+            name: Some("Cohort flag".to_string()),
+            key: "cohort-flag".to_string(),
+            filters: json!({"groups": [{
+                "properties": [{"key": "id", "value": cohort.id, "type": "cohort"}],
+                "rollout_percentage": 100,
+            }]}),
+            deleted: false,
+            active: true,
+            ensure_experience_continuity: false,
+            version: Some(1),
+        }),
+    )
+    .await
+    .unwrap();
+    let key = insert_personal_api_key_for_team_in_pg(
+        pg_client.clone(),
+        team.id,
+        Some(vec!["feature_flag:read"]),
+    )
+    .await
+    .unwrap();
+
+    let server = ServerHandle::for_config(config).await;
+
+    let res = server
+        .send_local_evaluation_request(&team.api_token, None)
+        .await;
+    assert_eq!(StatusCode::UNAUTHORIZED, res.status());
+    let res = server
+        .send_local_evaluation_request(&team.api_token, Some("phx_invalid"))
+        .await;
+    assert_eq!(StatusCode::UNAUTHORIZED, res.status());
+
+    let res = server
+        .send_local_evaluation_request(&team.api_token, Some(&key))
+        .await;
+    assert_eq!(StatusCode::OK, res.status());
+    let json_data = res.json::<Value>().await?;
+    assert_json_include!(
+        actual: json_data,
+        expected: json!({
+            "flags": [{
+                "key": "cohort-flag",
+                "team_id": team.id,
+                "active": true,
+                "filters": {"groups": [{"rollout_percentage": 100.0}]},
+            }],
+            "group_type_mapping": {"0": "project", "1": "organization"},
+            "cohorts": {
+                cohort.id.to_string(): {"type": "OR", "values": [{"type": "OR", "values": [
+                    {"key": "email", "value": "@posthog.com"}
+                ]}]}
+            },
+        })
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn it_explains_flags_to_keys_with_flag_and_person_access() -> Result<()> {
+    let config = DEFAULT_TEST_CONFIG.clone();
+
+    let pg_client = setup_pg_reader_client(None).await;
+    let team = insert_new_team_in_pg(pg_client.clone(), None)
+        .await
+        .unwrap();
+    insert_flag_for_team_in_pg(
+        pg_client.clone(),
+        team.id,
+        Some(FeatureFlagRow {
+            id: 0,
+            team_id: team.id,
+            name: Some("Property flag".to_string()),
+            key: "property-flag".to_string(),
+            filters: json!({"groups": [{
+                "properties": [
+                    {"key": "email", "value": "test@example.com", "operator": "exact", "type": "person"}
+                ],
+                "rollout_percentage": 100,
+            }]}),
+            deleted: false,
+            active: true,
+            ensure_experience_continuity: false,
+            version: Some(1),
+        }),
+    )
+    .await
+    .unwrap();
+    let flags_only_key = insert_personal_api_key_for_team_in_pg(
+        pg_client.clone(),
+        team.id,
+        Some(vec!["feature_flag:read"]),
+    )
+    .await
+    .unwrap();
+    let key = insert_personal_api_key_for_team_in_pg(
+        pg_client.clone(),
+        team.id,
+        Some(vec!["feature_flag:read", "person:read"]),
+    )
+    .await
+    .unwrap();
+
+    let server = ServerHandle::for_config(config).await;
+
+    let payload = json!({
+        "token": team.api_token,
+        "distinct_id": "user_distinct_id",
+        "person_properties": {"email": "test@example.com"},
+    })
+    .to_string();
+
+    let res = server.send_explain_request(payload.clone(), None).await;
+    assert_eq!(StatusCode::UNAUTHORIZED, res.status());
+    let res = server
+        .send_explain_request(payload.clone(), Some("phx_invalid"))
+        .await;
+    assert_eq!(StatusCode::UNAUTHORIZED, res.status());
+    let res = server
+        .send_explain_request(payload.clone(), Some(&flags_only_key))
+        .await;
+    assert_eq!(StatusCode::UNAUTHORIZED, res.status());
+
+    let res = server.send_explain_request(payload, Some(&key)).await;
+    assert_eq!(StatusCode::OK, res.status());
+    let json_data = res.json::<Value>().await?;
+    assert_json_include!(
+        actual: json_data,
+        expected: json!({
+            "flags": {
+                "property-flag": {
+                    "enabled": true,
+                    "trace": {"conditions": [{
+                        "matches": true,
+                        "filters": [{
+                            "key": "email",
+                            "compared_value": "test@example.com",
+                            "source": "override",
+                            "matches": true,
+                        }],
+                    }]},
+                },
+            },
+        })
+    );
+
+    Ok(())
+}